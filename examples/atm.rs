@@ -0,0 +1,62 @@
+//! Chapter 1: Drive the ATM state machine interactively from the terminal.
+//!
+//! Run with `cargo run --example atm`. Every card in this demo has the pin 1234.
+
+use blockchain_from_scratch::c1_state_machine::{
+	p3_atm::{pin_hash, Action, Atm, Key},
+	MealyStateMachine, TryStateMachine,
+};
+use std::io::{self, BufRead, Write};
+
+fn parse_key(input: &str) -> Option<Key> {
+	let mut chars = input.chars();
+	match (input, chars.next(), chars.next()) {
+		("enter", _, _) => Some(Key::Enter),
+		("cancel", _, _) => Some(Key::Cancel),
+		("backspace", _, _) => Some(Key::Backspace),
+		("clear", _, _) => Some(Key::Clear),
+		(_, Some(label), None) => Key::from_label(label),
+		_ => None,
+	}
+}
+
+fn main() {
+	let card = pin_hash(&[Key::One, Key::Two, Key::Three, Key::Four]);
+	let mut atm = Atm::new(1000);
+
+	println!("Commands: `swipe`, a key (0 to 9, enter, cancel, backspace, or clear), or `quit`.");
+	println!("The ATM holds {} and your pin is 1234.", atm.cash_inside());
+
+	let stdin = io::stdin();
+	loop {
+		print!("> ");
+		io::stdout().flush().expect("stdout is writable");
+
+		let mut line = String::new();
+		if stdin.lock().read_line(&mut line).expect("stdin is readable") == 0 {
+			break
+		}
+		let action = match line.trim() {
+			"quit" => break,
+			"swipe" => Action::SwipeCard(card),
+			other => match parse_key(other) {
+				Some(key) => Action::PressKey(key),
+				None => {
+					println!("Unknown command {:?}", other);
+					continue
+				},
+			},
+		};
+
+		if let Err(e) = Atm::try_next_state(&atm, &action) {
+			println!("Rejected: {:?}", e);
+			continue
+		}
+		let (next, events) = Atm::next_state_with_output(&atm, &action);
+		for event in events {
+			println!("{:?}", event);
+		}
+		atm = next;
+		println!("{:?}", atm);
+	}
+}
@@ -0,0 +1,46 @@
+//! Chapter 2: Build a blockchain that forks part way through and print the resulting block tree.
+//!
+//! Run with `cargo run --example fork_tree`.
+
+use blockchain_from_scratch::c2_blockchain::{
+	block_tree::{render_fork_tree, BlockTree, Ghost, HeaviestChain, LongestChain, TreeForkChoice},
+	p4_batched_extrinsics::Block,
+};
+
+/// Extend the given block with one child per batch of extrinsics.
+fn extend(from: &Block, batches: &[Vec<u64>]) -> Vec<Block> {
+	let mut chain: Vec<Block> = Vec::new();
+	for batch in batches {
+		let parent = chain.last().unwrap_or(from);
+		chain.push(parent.child(batch.clone()));
+	}
+	chain
+}
+
+fn print_best<F: TreeForkChoice>(tree: &BlockTree) {
+	let best = tree.get(F::best_tip(tree)).expect("the best tip is in the tree");
+	println!("{}: #{} {:016x} state {}", F::human_name(), best.height(), best.hash(), best.state());
+}
+
+fn main() {
+	let genesis = Block::genesis();
+	let common = extend(&genesis, &[vec![1, 2], vec![3]]);
+	let fork_point = common.last().expect("common history is not empty");
+	let left = extend(fork_point, &[vec![4], vec![5, 6], vec![7]]);
+	let right = extend(fork_point, &[vec![10], vec![20]]);
+
+	let mut tree = BlockTree::new(genesis.header().clone());
+	for block in common.iter().chain(&left).chain(&right) {
+		tree.import(block.header().clone())
+			.expect("every block builds on a known parent");
+	}
+	println!("{}", render_fork_tree(&tree));
+	println!();
+
+	for (name, branch) in [("left", &left), ("right", &right)] {
+		println!("{} branch valid: {}", name, fork_point.verify_sub_chain(branch));
+	}
+	print_best::<LongestChain>(&tree);
+	print_best::<HeaviestChain>(&tree);
+	print_best::<Ghost>(&tree);
+}
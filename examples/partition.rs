@@ -0,0 +1,100 @@
+//! Chapter 2: Split a network of three nodes in two, let both sides keep authoring, then heal the
+//! partition and watch every node settle on the same chain.
+//!
+//! Run with `cargo run --example partition`.
+
+use blockchain_from_scratch::c2_blockchain::{
+	block_tree::{render_fork_tree, BlockTree, LongestChain, TreeForkChoice},
+	p4_batched_extrinsics::Block,
+};
+use std::collections::HashMap;
+
+/// A node knows the blocks it has seen, and builds on the best one.
+struct Node {
+	name: &'static str,
+	tree: BlockTree,
+	blocks: HashMap<u64, Block>,
+}
+
+impl Node {
+	fn new(name: &'static str, genesis: &Block) -> Self {
+		let hash = genesis.header().hash();
+		Node {
+			name,
+			tree: BlockTree::new(genesis.header().clone()),
+			blocks: HashMap::from([(hash, genesis.clone())]),
+		}
+	}
+
+	fn best(&self) -> &Block {
+		&self.blocks[&LongestChain::best_tip(&self.tree)]
+	}
+
+	/// Import the given block, whose parent this node must already know.
+	fn import(&mut self, block: &Block) {
+		let hash = self.tree.import(block.header().clone()).expect("parents arrive first");
+		self.blocks.insert(hash, block.clone());
+	}
+
+	/// Author a block on top of the best one, and return it.
+	fn author(&mut self, extrinsics: Vec<u64>) -> Block {
+		let block = self.best().child(extrinsics);
+		self.import(&block);
+		block
+	}
+
+	/// Every block this node knows, parents before children.
+	fn known_blocks(&self) -> Vec<Block> {
+		let mut blocks: Vec<Block> = self.blocks.values().cloned().collect();
+		blocks.sort_by_key(|block| block.header().height());
+		blocks
+	}
+
+	fn report(&self) {
+		let best = self.best().header();
+		println!("{} follows #{} {:016x}", self.name, best.height(), best.hash());
+		println!("{}", render_fork_tree(&self.tree));
+	}
+}
+
+/// Author a block on the given node, and gossip it to the peers the node can reach.
+fn author_and_gossip(nodes: &mut [Node], author: usize, reachable: &[usize], extrinsics: Vec<u64>) {
+	let block = nodes[author].author(extrinsics);
+	for &peer in reachable {
+		nodes[peer].import(&block);
+	}
+}
+
+fn main() {
+	let genesis = Block::genesis();
+	let mut nodes =
+		[Node::new("alice", &genesis), Node::new("bob", &genesis), Node::new("charlie", &genesis)];
+
+	println!("== Everybody is connected");
+	author_and_gossip(&mut nodes, 0, &[1, 2], vec![1]);
+	author_and_gossip(&mut nodes, 2, &[0, 1], vec![2]);
+
+	println!("== Charlie is cut off from Alice and Bob");
+	for round in 0..3 {
+		author_and_gossip(&mut nodes, round % 2, &[1 - round % 2], vec![10 + round as u64]);
+	}
+	author_and_gossip(&mut nodes, 2, &[], vec![20]);
+	for node in &nodes {
+		node.report();
+	}
+
+	println!("== The partition heals, and everybody shares what they have");
+	let everything: Vec<Block> = nodes.iter().flat_map(Node::known_blocks).collect();
+	for node in &mut nodes {
+		let mut blocks = everything.clone();
+		blocks.sort_by_key(|block| block.header().height());
+		for block in &blocks {
+			node.import(block);
+		}
+	}
+	for node in &nodes {
+		node.report();
+	}
+	let best = nodes[0].best().header().hash();
+	println!("All nodes agree: {}", nodes.iter().all(|node| node.best().header().hash() == best));
+}
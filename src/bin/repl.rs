@@ -0,0 +1,58 @@
+//! Interact with any of the chapter 1 state machines from the terminal.
+//!
+//! Usage: `cargo run --bin repl -- <machine>`
+
+use blockchain_from_scratch::c1_state_machine::{
+	p10_tcp_handshake::{TcpConnection, TcpState},
+	p1_switches::{LightSwitch, TwoSwitches, WeirdSwitchMachine},
+	p2_laundry_machine::{ClothesMachine, ClothesState},
+	p3_atm::Atm,
+	p7_vending_machine::{Product, Slot, VendingMachine},
+	p8_traffic_intersection::{Intersection, TrafficController},
+	p9_elevator::Elevator,
+	repl::run,
+};
+use std::{collections::BTreeMap, env, io, process};
+
+/// The machines that can be driven from the repl, along with a short description.
+const MACHINES: [(&str, &str); 8] = [
+	("light-switch", "a single light switch"),
+	("weird-switches", "two switches where turning off the first turns off the second"),
+	("clothes", "a brand new shirt that lasts for ten actions"),
+	("atm", "an ATM with 1000 inside"),
+	("vending", "a vending machine selling cola, chips, and candy"),
+	("traffic", "the traffic lights where two roads cross"),
+	("elevator", "an elevator in a ten storey building"),
+	("tcp", "one end of a TCP connection"),
+];
+
+fn main() -> io::Result<()> {
+	let (stdin, stdout) = (io::stdin().lock(), io::stdout().lock());
+	match env::args().nth(1).as_deref() {
+		Some("light-switch") => run::<LightSwitch>(false, stdin, stdout).map(drop),
+		Some("weird-switches") =>
+			run::<WeirdSwitchMachine>(TwoSwitches::default(), stdin, stdout).map(drop),
+		Some("clothes") => run::<ClothesMachine>(ClothesState::Clean(10), stdin, stdout).map(drop),
+		Some("atm") => run::<Atm>(Atm::new(1000), stdin, stdout).map(drop),
+		Some("vending") => {
+			let slots = BTreeMap::from([
+				(Product::Cola, Slot { price: 75, stock: 3 }),
+				(Product::Chips, Slot { price: 50, stock: 3 }),
+				(Product::Candy, Slot { price: 65, stock: 3 }),
+			]);
+			let coin_box = BTreeMap::from([(5, 4), (10, 4), (25, 4)]);
+			run::<VendingMachine>(VendingMachine::new(slots, coin_box), stdin, stdout).map(drop)
+		},
+		Some("traffic") =>
+			run::<TrafficController>(Intersection::default(), stdin, stdout).map(drop),
+		Some("elevator") => run::<Elevator>(Elevator::new(10), stdin, stdout).map(drop),
+		Some("tcp") => run::<TcpConnection>(TcpState::Closed, stdin, stdout).map(drop),
+		_ => {
+			eprintln!("usage: repl <machine>\n\nmachines:");
+			for (name, description) in MACHINES {
+				eprintln!("  {:16}{}", name, description);
+			}
+			process::exit(2);
+		},
+	}
+}
@@ -0,0 +1,52 @@
+//! Re-execute a replay file from genesis and print the final state root. With `--grade`, also
+//! check that the part 6 chain commits to the same root.
+//!
+//! Usage: `cargo run --bin replay -- [--grade] path/to/file.replay`
+
+use blockchain_from_scratch::c2_blockchain::replay::Replay;
+use std::{env, fs, panic, process};
+
+fn main() {
+	let mut args: Vec<String> = env::args().skip(1).collect();
+	let grade = args.first().is_some_and(|arg| arg == "--grade");
+	if grade {
+		args.remove(0);
+	}
+	let path = match &args[..] {
+		[path] => path.clone(),
+		_ => {
+			eprintln!("usage: replay [--grade] <file.replay>");
+			process::exit(2);
+		},
+	};
+
+	let replay = fs::read_to_string(&path)
+		.map_err(|e| e.to_string())
+		.and_then(|text| Replay::parse(&text))
+		.unwrap_or_else(|e| {
+			eprintln!("{}: {}", path, e);
+			process::exit(1);
+		});
+
+	let (state, state_root) = replay.execute();
+	println!("final state: {:?}", state);
+	println!("state root: {:016x}", state_root);
+	if !grade {
+		return
+	}
+
+	// Grading runs the answers to the part 6 exercises, which panic until they are done. That is
+	// reported like any other failed grade, rather than as a crash.
+	panic::set_hook(Box::new(|_| {}));
+	match panic::catch_unwind(|| replay.grade()) {
+		Ok(true) => println!("the part 6 chain commits to the same root"),
+		Ok(false) => {
+			eprintln!("the part 6 chain doesn't commit to the same root");
+			process::exit(1);
+		},
+		Err(_) => {
+			eprintln!("the part 6 exercises aren't finished yet, so there is nothing to grade");
+			process::exit(1);
+		},
+	}
+}
@@ -0,0 +1,161 @@
+//! Clocks for driving timed state machines.
+//!
+//! A `Ticker` reads a clock and turns the time that has passed into tick transitions. In real
+//! programs it reads the system clock. In tests it reads a `MockClock`, which only moves when the
+//! test says so, keeping timeouts completely deterministic.
+
+use super::TimedStateMachine;
+use std::{
+	cell::Cell,
+	time::{Duration, Instant, SystemTime},
+};
+
+/// A source of the current time.
+pub trait Clock {
+	/// The time elapsed since some fixed starting point. It never goes backwards.
+	fn now(&self) -> Duration;
+}
+
+/// The real clock, measuring time since it was created, or since some moment before that.
+pub struct SystemClock {
+	/// When the clock was created
+	start: Instant,
+	/// How long before the clock was created its starting point was
+	offset: Duration,
+}
+
+impl SystemClock {
+	pub fn new() -> Self {
+		SystemClock { start: Instant::now(), offset: Duration::ZERO }
+	}
+
+	/// A real clock measuring time since the given moment, such as the launch of a chain. A
+	/// moment in the future counts as now.
+	pub fn since(epoch: SystemTime) -> Self {
+		let offset = SystemTime::now().duration_since(epoch).unwrap_or_default();
+		SystemClock { start: Instant::now(), offset }
+	}
+}
+
+impl Default for SystemClock {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Clock for SystemClock {
+	fn now(&self) -> Duration {
+		self.offset + self.start.elapsed()
+	}
+}
+
+/// A clock that only moves when it is told to.
+#[derive(Debug, Default)]
+pub struct MockClock {
+	now: Cell<Duration>,
+}
+
+impl MockClock {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Move the clock forward by the given amount.
+	pub fn advance(&self, by: Duration) {
+		self.now.set(self.now.get() + by);
+	}
+}
+
+impl Clock for MockClock {
+	fn now(&self) -> Duration {
+		self.now.get()
+	}
+}
+
+/// Turns the time passing on a clock into ticks for a timed state machine.
+pub struct Ticker<C> {
+	clock: C,
+	last_tick: Duration,
+}
+
+impl<C: Clock> Ticker<C> {
+	/// Start ticking from the clock's current time.
+	pub fn new(clock: C) -> Self {
+		let last_tick = clock.now();
+		Ticker { clock, last_tick }
+	}
+
+	/// The clock this ticker reads.
+	pub fn clock(&self) -> &C {
+		&self.clock
+	}
+
+	/// Tell the machine how much time has passed since the previous tick, and return the
+	/// resulting state.
+	pub fn tick<SM: TimedStateMachine>(&mut self, state: &SM::State) -> SM::State {
+		let now = self.clock.now();
+		let elapsed = now - self.last_tick;
+		self.last_tick = now;
+		SM::next_state(state, &SM::tick(elapsed))
+	}
+}
+
+/// A kitchen timer that counts down and rings at zero. It only exists to exercise the ticker.
+#[cfg(test)]
+struct KitchenTimer;
+
+#[cfg(test)]
+impl super::StateMachine for KitchenTimer {
+	/// The time remaining before it rings.
+	type State = Duration;
+	type Transition = Duration;
+
+	fn next_state(remaining: &Duration, elapsed: &Duration) -> Duration {
+		remaining.saturating_sub(*elapsed)
+	}
+}
+
+#[cfg(test)]
+impl TimedStateMachine for KitchenTimer {
+	fn tick(elapsed: Duration) -> Duration {
+		elapsed
+	}
+}
+
+#[test]
+fn sm_clock_mock_only_moves_when_advanced() {
+	let clock = MockClock::new();
+	assert_eq!(clock.now(), Duration::ZERO);
+
+	clock.advance(Duration::from_secs(3));
+	clock.advance(Duration::from_secs(4));
+	assert_eq!(clock.now(), Duration::from_secs(7));
+}
+
+#[test]
+fn sm_clock_ticker_reports_time_since_last_tick() {
+	let mut ticker = Ticker::new(MockClock::new());
+	let mut remaining = Duration::from_secs(60);
+
+	ticker.clock().advance(Duration::from_secs(20));
+	remaining = ticker.tick::<KitchenTimer>(&remaining);
+	assert_eq!(remaining, Duration::from_secs(40));
+
+	// Nothing has happened since the last tick.
+	remaining = ticker.tick::<KitchenTimer>(&remaining);
+	assert_eq!(remaining, Duration::from_secs(40));
+
+	ticker.clock().advance(Duration::from_secs(45));
+	remaining = ticker.tick::<KitchenTimer>(&remaining);
+	assert_eq!(remaining, Duration::ZERO);
+}
+
+#[test]
+fn sm_clock_ticker_starts_from_current_time() {
+	let clock = MockClock::new();
+	clock.advance(Duration::from_secs(100));
+	let mut ticker = Ticker::new(clock);
+
+	ticker.clock().advance(Duration::from_secs(1));
+	assert_eq!(ticker.tick::<KitchenTimer>(&Duration::from_secs(5)), Duration::from_secs(4));
+}
@@ -0,0 +1,132 @@
+//! A journal records every transition applied to a state machine, in order.
+//!
+//! Because state machines are deterministic, the initial state plus the journal is all it takes
+//! to reproduce the current state, or indeed any state the machine has ever been in. Keep this in
+//! mind in the next chapter. A blockchain is, in a sense, "just" a journal that many parties
+//! agree on.
+
+use super::StateMachine;
+
+/// A single transition recorded in a journal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry<T> {
+	/// The position of this entry in the journal. The first transition has sequence number 0.
+	pub sequence: u64,
+	/// The transition that was applied.
+	pub transition: T,
+}
+
+/// A state machine's initial state, its current state, and every transition applied in between.
+pub struct Journal<SM: StateMachine> {
+	initial: SM::State,
+	current: SM::State,
+	entries: Vec<Entry<SM::Transition>>,
+}
+
+impl<SM: StateMachine> Journal<SM>
+where
+	SM::State: Clone,
+{
+	/// Start an empty journal from the given initial state.
+	pub fn new(initial: SM::State) -> Self {
+		Journal { current: initial.clone(), initial, entries: Vec::new() }
+	}
+
+	/// Apply the given transition to the current state, record it, and return its sequence number.
+	pub fn apply(&mut self, transition: SM::Transition) -> u64 {
+		let sequence = self.entries.len() as u64;
+		self.current = SM::next_state(&self.current, &transition);
+		self.entries.push(Entry { sequence, transition });
+		sequence
+	}
+
+	/// The state the journal started from.
+	pub fn initial(&self) -> &SM::State {
+		&self.initial
+	}
+
+	/// The state after every recorded transition has been applied.
+	pub fn current(&self) -> &SM::State {
+		&self.current
+	}
+
+	/// Every recorded transition, in the order they were applied.
+	pub fn entries(&self) -> &[Entry<SM::Transition>] {
+		&self.entries
+	}
+
+	/// Reproduce the current state by replaying every recorded transition from the initial state.
+	pub fn replay(&self) -> SM::State {
+		self.replay_through(self.entries.len() as u64)
+	}
+
+	/// Reproduce the state the machine was in just after the transition with the given sequence
+	/// number was applied, or the initial state when `sequence` is `None`.
+	///
+	/// Returns `None` if no transition with that sequence number has been recorded.
+	pub fn state_after(&self, sequence: Option<u64>) -> Option<SM::State> {
+		match sequence {
+			None => Some(self.initial.clone()),
+			Some(sequence) if sequence < self.entries.len() as u64 =>
+				Some(self.replay_through(sequence + 1)),
+			Some(_) => None,
+		}
+	}
+
+	/// Replay the first `count` recorded transitions from the initial state.
+	fn replay_through(&self, count: u64) -> SM::State {
+		self.entries[..count as usize]
+			.iter()
+			.fold(self.initial.clone(), |state, entry| SM::next_state(&state, &entry.transition))
+	}
+}
+
+#[test]
+fn sm_journal_records_sequence_numbers() {
+	use super::p1_switches::LightSwitch;
+
+	let mut journal = Journal::<LightSwitch>::new(false);
+
+	assert_eq!(journal.apply(()), 0);
+	assert_eq!(journal.apply(()), 1);
+	assert_eq!(journal.apply(()), 2);
+	assert_eq!(
+		journal.entries(),
+		&[
+			Entry { sequence: 0, transition: () },
+			Entry { sequence: 1, transition: () },
+			Entry { sequence: 2, transition: () },
+		]
+	);
+	assert!(*journal.current());
+}
+
+#[test]
+fn sm_journal_replay_reproduces_current_state() {
+	use super::p3_atm::{pin_hash, Action, Atm, Key};
+
+	let mut journal = Journal::<Atm>::new(Atm::new(100));
+	journal.apply(Action::SwipeCard(pin_hash(&[Key::One])));
+	for key in [Key::One, Key::Enter, Key::Four, Key::Zero, Key::Enter] {
+		journal.apply(Action::PressKey(key));
+	}
+
+	assert_eq!(journal.replay(), *journal.current());
+	assert_eq!(journal.current().cash_inside(), 60);
+}
+
+#[test]
+fn sm_journal_reproduces_historic_states() {
+	use super::p2_laundry_machine::{ClothesAction, ClothesMachine, ClothesState};
+
+	let mut journal = Journal::<ClothesMachine>::new(ClothesState::Clean(10));
+	journal.apply(ClothesAction::Wear);
+	journal.apply(ClothesAction::Wash);
+	journal.apply(ClothesAction::Dry);
+
+	assert_eq!(journal.state_after(None), Some(ClothesState::Clean(10)));
+	assert_eq!(journal.state_after(Some(0)), Some(ClothesState::Dirty(9)));
+	assert_eq!(journal.state_after(Some(1)), Some(ClothesState::Wet(8)));
+	assert_eq!(journal.state_after(Some(2)), Some(ClothesState::Clean(7)));
+	assert_eq!(journal.state_after(Some(3)), None);
+}
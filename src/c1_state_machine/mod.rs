@@ -2,12 +2,33 @@
 //! We begin with a few simple examples, and then proceed to build bigger and more complex state
 //! machines all implementing the same simple interface.
 
-mod p1_switches;
-mod p2_laundry_machine;
-mod p3_atm;
-mod p4_accounted_currency;
-mod p5_digital_cash;
+pub mod clock;
+pub mod journal;
+pub mod model_checker;
+pub mod nondeterministic;
+pub mod p10_tcp_handshake;
+pub mod p11_multisig;
+pub mod p12_escrow;
+pub mod p13_order_book;
+pub mod p14_parking_garage;
+pub mod p15_nim;
+pub mod p16_token_bridge;
+pub mod p17_rate_limiter;
+pub mod p1_switches;
+pub mod p2_laundry_machine;
+pub mod p3_atm;
+pub mod p4_accounted_currency;
+pub mod p5_digital_cash;
 mod p6_open_ended;
+pub mod p7_vending_machine;
+pub mod p8_traffic_intersection;
+pub mod p9_elevator;
+pub mod repl;
+pub mod state_graph;
+
+use crate::crypto::{Pair, SchnorrPair};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, marker::PhantomData, time::Duration};
 
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
@@ -21,20 +42,255 @@ pub trait StateMachine {
 	fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
 
 	/// A human-readable name for this state machine. This may be used in user-facing
-	/// programs such as the repl described below. This is not in any way related to
+	/// programs such as the repl. This is not in any way related to
 	/// the correctness of the state machine.
 	fn human_name() -> String {
 		"Unnamed state machine".into()
 	}
+
+	/// Calculate the resulting state when this state undergoes each of the given transitions
+	/// in order.
+	fn apply_all(starting_state: &Self::State, transitions: &[Self::Transition]) -> Self::State
+	where
+		Self::State: Clone,
+	{
+		transitions
+			.iter()
+			.fold(starting_state.clone(), |state, t| Self::next_state(&state, t))
+	}
+
+	/// Like `apply_all`, but returns every state visited along the way. The first element is the
+	/// starting state and the last is the final state, so there is always one more state than
+	/// there are transitions.
+	fn trace(starting_state: &Self::State, transitions: &[Self::Transition]) -> Vec<Self::State>
+	where
+		Self::State: Clone,
+	{
+		let mut states = vec![starting_state.clone()];
+		for t in transitions {
+			let next = Self::next_state(states.last().expect("states is never empty"), t);
+			states.push(next);
+		}
+		states
+	}
+}
+
+/// A state machine whose transitions can all be listed up front.
+///
+/// This is what allows tooling to explore every state a machine can reach. Machines whose
+/// transitions carry arbitrary data, like the ATM's card swipes, may list a representative
+/// sample instead.
+pub trait EnumerableTransitions: StateMachine {
+	/// Every transition this machine can undergo.
+	fn transitions() -> Vec<Self::Transition>;
+}
+
+/// A state machine whose transitions can be undone.
+///
+/// Undoing a transition means working out which state the machine was in before it. That isn't
+/// always possible because some transitions destroy information. Many different starting states
+/// may lead to the very same ending state, in which case there is no way to know which one it was.
+pub trait ReversibleStateMachine: StateMachine {
+	/// Calculate the state this machine was in before undergoing the given transition to reach
+	/// the given ending state. Returns `None` if that state can't be uniquely determined.
+	///
+	/// Whenever `prev_state(&next_state(s, t), t)` returns a state, it must be `s`.
+	fn prev_state(ending_state: &Self::State, t: &Self::Transition) -> Option<Self::State>;
+}
+
+/// A state machine that is able to reject transitions.
+///
+/// Plain `StateMachine`s have no way to say "no". When a transition doesn't make sense from the
+/// current state, the best `next_state` can do is return some state anyway, often the starting
+/// state unchanged. A caller looking at the result can't tell whether the transition was a
+/// legitimate no-op or whether it was refused. This trait lets machines report the difference.
+pub trait TryStateMachine: StateMachine {
+	/// The reasons a transition may be rejected.
+	type Error;
+
+	/// Calculate the resulting state when this state undergoes the given transition, or explain
+	/// why the transition is not allowed from this state.
+	///
+	/// Whenever this returns `Ok`, the state must be the same one `next_state` would return.
+	fn try_next_state(
+		starting_state: &Self::State,
+		t: &Self::Transition,
+	) -> Result<Self::State, Self::Error>;
+}
+
+/// A state machine whose transitions may also emit outputs, in the style of a Mealy machine.
+///
+/// Outputs are not part of the state. They describe what happened during a transition, like cash
+/// coming out of an ATM, so that callers don't have to work it out by comparing states.
+pub trait MealyStateMachine: StateMachine {
+	/// The events that may be emitted while transitioning.
+	type Output;
+
+	/// Calculate the resulting state when this state undergoes the given transition, along with
+	/// every output emitted on the way. Many transitions emit nothing at all.
+	///
+	/// The state must be the same one `next_state` would return.
+	fn next_state_with_output(
+		starting_state: &Self::State,
+		t: &Self::Transition,
+	) -> (Self::State, Vec<Self::Output>);
+
+	/// Like `apply_all`, but also returns every output emitted along the way, in order.
+	fn apply_all_with_output(
+		starting_state: &Self::State,
+		transitions: &[Self::Transition],
+	) -> (Self::State, Vec<Self::Output>)
+	where
+		Self::State: Clone,
+	{
+		let mut outputs = Vec::new();
+		let end = transitions.iter().fold(starting_state.clone(), |state, t| {
+			let (next, emitted) = Self::next_state_with_output(&state, t);
+			outputs.extend(emitted);
+			next
+		});
+		(end, outputs)
+	}
+}
+
+/// A state machine that reacts to the passage of time, for example by expiring a session.
+///
+/// State machines never read a clock themselves, because that would make them nondeterministic.
+/// Instead, time passing is just another transition. Something outside the machine, like a
+/// `clock::Ticker`, measures how much time has passed and hands it to the machine as a tick.
+pub trait TimedStateMachine: StateMachine {
+	/// The transition telling the machine that the given amount of time has passed.
+	fn tick(elapsed: Duration) -> Self::Transition;
+}
+
+/// Properties that must hold in every state a machine can reach.
+///
+/// Unit tests check that particular transitions land in particular states. Invariants complement
+/// them by describing what every state must look like, no matter how it was reached.
+pub trait Invariant: StateMachine {
+	/// A description of how a state breaks the invariants.
+	type Violation: Debug;
+
+	/// Check that the given state upholds all of this machine's invariants.
+	fn check(state: &Self::State) -> Result<(), Self::Violation>;
+}
+
+/// Wraps a state machine and, in debug builds, asserts its invariants after every transition.
+/// In release builds it behaves exactly like the machine it wraps.
+pub struct InvariantChecked<SM>(PhantomData<SM>);
+
+impl<SM: Invariant> StateMachine for InvariantChecked<SM> {
+	type State = SM::State;
+	type Transition = SM::Transition;
+
+	fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+		let state = SM::next_state(starting_state, t);
+		if cfg!(debug_assertions) {
+			if let Err(violation) = SM::check(&state) {
+				panic!(
+					"{} reached a state that violates its invariants: {:?}",
+					SM::human_name(),
+					violation
+				);
+			}
+		}
+		state
+	}
+
+	fn human_name() -> String {
+		SM::human_name()
+	}
+}
+
+/// A state machine that may end up in any one of several states after the same transition.
+///
+/// Deterministic machines are the right model for most of this course, but some systems are
+/// better described by everything that *might* happen. A message may be delivered or lost. A miner
+/// may extend either side of a fork. An adversary may do whatever hurts the most. A
+/// nondeterministic machine lists all of those possibilities instead of picking one.
+pub trait NdStateMachine {
+	/// The states that can be occupied by this machine
+	type State;
+
+	/// The transitions that can be made between states
+	type Transition;
+
+	/// Calculate every state the machine could be in after this state undergoes the given
+	/// transition. An empty list means the transition cannot happen from this state at all.
+	fn next_states(starting_state: &Self::State, t: &Self::Transition) -> Vec<Self::State>;
+
+	/// A human-readable name for this state machine. This may be used in user-facing
+	/// programs. This is not in any way related to the correctness of the state machine.
+	fn human_name() -> String {
+		"Unnamed state machine".into()
+	}
+
+	/// Check whether a claimed execution could really have happened. The first state is the
+	/// starting state, and each following state must be a possible result of applying the
+	/// corresponding transition to the state before it. Just like the output of
+	/// `StateMachine::trace`, there must be one more state than there are transitions.
+	fn is_admissible(states: &[Self::State], transitions: &[Self::Transition]) -> bool
+	where
+		Self::State: PartialEq,
+	{
+		states.len() == transitions.len() + 1 &&
+			states
+				.windows(2)
+				.zip(transitions)
+				.all(|(pair, t)| Self::next_states(&pair[0], t).contains(&pair[1]))
+	}
+
+	/// Calculate every state the machine could end up in when this state undergoes each of the
+	/// given transitions in order. Each possible state is listed only once.
+	fn possible_outcomes(
+		starting_state: &Self::State,
+		transitions: &[Self::Transition],
+	) -> Vec<Self::State>
+	where
+		Self::State: Clone + PartialEq,
+	{
+		transitions.iter().fold(vec![starting_state.clone()], |states, t| {
+			let mut next = Vec::new();
+			for state in states.iter().flat_map(|s| Self::next_states(s, t)) {
+				if !next.contains(&state) {
+					next.push(state);
+				}
+			}
+			next
+		})
+	}
+}
+
+/// Views a deterministic state machine as a nondeterministic one that always has exactly one
+/// possible next state.
+pub struct Deterministic<SM>(PhantomData<SM>);
+
+impl<SM: StateMachine> NdStateMachine for Deterministic<SM> {
+	type State = SM::State;
+	type Transition = SM::Transition;
+
+	fn next_states(starting_state: &Self::State, t: &Self::Transition) -> Vec<Self::State> {
+		vec![SM::next_state(starting_state, t)]
+	}
+
+	fn human_name() -> String {
+		SM::human_name()
+	}
 }
 
 /// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum User {
 	Alice,
 	Bob,
 	Charlie,
 }
 
-// TODO Some kind of main program that allows users to interact with their state machine in a
-// repl-like way. Might require From<String> implementation for the transition type.
+impl User {
+	/// The well known key pair of this user. Anybody can recreate it from the user's name, so it
+	/// is only good for experiments, never for protecting anything.
+	pub fn pair(&self) -> SchnorrPair {
+		SchnorrPair::from_string(&format!("blockchain from scratch//{:?}", self))
+			.expect("the secret URI is well formed")
+	}
+}
@@ -0,0 +1,131 @@
+//! A bounded model checker. Rather than testing a handful of hand-picked scenarios, it tries every
+//! possible sequence of transitions up to some length and checks that a property holds in every
+//! state it reaches. When the property fails, it reports the shortest sequence of transitions that
+//! leads there.
+//!
+//! The number of states grows very quickly with the length of the sequences, so this is only
+//! feasible for small machines and short sequences. But it is surprising how many bugs are
+//! lurking just a few transitions away from the starting state.
+
+use super::{EnumerableTransitions, Invariant};
+
+/// A sequence of transitions that leads from the starting state to a state violating a property.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counterexample<S, T> {
+	/// The transitions, in the order they are applied.
+	pub transitions: Vec<T>,
+	/// Every state visited. The first is the starting state, and the last is the one that
+	/// violates the property. There is always one more state than there are transitions.
+	pub states: Vec<S>,
+}
+
+/// Every state discovered, along with the index of its parent and the transition leading from that
+/// parent to it. The starting state has no parent.
+type Discovered<S, T> = Vec<(S, Option<(usize, T)>)>;
+
+/// Check that the given property holds in every state reachable from the starting state within
+/// `depth` transitions. Returns the number of distinct states checked, or the shortest
+/// counterexample if the property fails.
+pub fn check<SM>(
+	starting_state: &SM::State,
+	depth: usize,
+	property: impl Fn(&SM::State) -> bool,
+) -> Result<usize, Counterexample<SM::State, SM::Transition>>
+where
+	SM: EnumerableTransitions,
+	SM::State: Clone + PartialEq,
+	SM::Transition: Clone,
+{
+	let mut discovered: Discovered<SM::State, SM::Transition> =
+		vec![(starting_state.clone(), None)];
+
+	let mut level = 0..1;
+	for current_depth in 0..=depth {
+		for index in level.clone() {
+			if !property(&discovered[index].0) {
+				return Err(counterexample(&discovered, index))
+			}
+			if current_depth == depth {
+				continue
+			}
+			for t in SM::transitions() {
+				let next = SM::next_state(&discovered[index].0, &t);
+				if !discovered.iter().any(|(s, _)| *s == next) {
+					discovered.push((next, Some((index, t))));
+				}
+			}
+		}
+		level = level.end..discovered.len();
+	}
+
+	Ok(discovered.len())
+}
+
+/// Check that a machine's invariants hold in every state reachable from the starting state within
+/// `depth` transitions.
+pub fn check_invariants<SM>(
+	starting_state: &SM::State,
+	depth: usize,
+) -> Result<usize, Counterexample<SM::State, SM::Transition>>
+where
+	SM: EnumerableTransitions + Invariant,
+	SM::State: Clone + PartialEq,
+	SM::Transition: Clone,
+{
+	check::<SM>(starting_state, depth, |state| SM::check(state).is_ok())
+}
+
+/// Walk back from the state at the given index to the starting state, collecting the path.
+fn counterexample<S: Clone, T: Clone>(
+	discovered: &[(S, Option<(usize, T)>)],
+	mut index: usize,
+) -> Counterexample<S, T> {
+	let mut transitions = Vec::new();
+	let mut states = vec![discovered[index].0.clone()];
+	while let Some((parent, t)) = &discovered[index].1 {
+		transitions.push(t.clone());
+		states.push(discovered[*parent].0.clone());
+		index = *parent;
+	}
+	transitions.reverse();
+	states.reverse();
+	Counterexample { transitions, states }
+}
+
+#[test]
+fn sm_check_light_switch_explores_both_states() {
+	use super::p1_switches::LightSwitch;
+
+	assert_eq!(check::<LightSwitch>(&false, 10, |_| true), Ok(2));
+}
+
+#[test]
+fn sm_check_finds_shortest_counterexample() {
+	use super::{
+		p1_switches::{Toggle, TwoSwitches, WeirdSwitchMachine},
+		StateMachine,
+	};
+
+	// Both switches can be on at once. It takes two toggles to get there, but not one.
+	let start = TwoSwitches::default();
+	let both_on =
+		WeirdSwitchMachine::apply_all(&start, &[Toggle::FirstSwitch, Toggle::SecondSwitch]);
+
+	assert_eq!(
+		check::<WeirdSwitchMachine>(&start, 5, |s| *s != both_on).map_err(|c| c.transitions),
+		Err(vec![Toggle::FirstSwitch, Toggle::SecondSwitch])
+	);
+	assert!(check::<WeirdSwitchMachine>(&start, 1, |s| *s != both_on).is_ok());
+}
+
+#[test]
+fn sm_check_counterexample_states_follow_transitions() {
+	use super::{p2_laundry_machine::*, StateMachine};
+
+	let start = ClothesState::Clean(3);
+	let counterexample =
+		check::<ClothesMachine>(&start, 10, |s| *s != ClothesState::Tattered).unwrap_err();
+
+	assert_eq!(counterexample.transitions.len(), 3);
+	assert_eq!(counterexample.states, ClothesMachine::trace(&start, &counterexample.transitions));
+}
@@ -0,0 +1,66 @@
+//! Nondeterministic state machines, and a small example of one.
+//!
+//! With a deterministic machine we can ask "where does this sequence of transitions lead?" With a
+//! nondeterministic one, the better questions are "where *could* it lead?" and "could this claimed
+//! execution really have happened?" The `NdStateMachine` trait answers both. We will use the same
+//! ideas later to reason about forks and misbehaving participants.
+
+use super::NdStateMachine;
+
+/// A light switch with worn out contacts. Flicking it might toggle the light, or it might do
+/// nothing at all. There is no telling which.
+pub struct FlakySwitch;
+
+impl NdStateMachine for FlakySwitch {
+	type State = bool;
+	type Transition = ();
+
+	fn next_states(starting_state: &bool, _: &()) -> Vec<bool> {
+		vec![!starting_state, *starting_state]
+	}
+
+	fn human_name() -> String {
+		"Flaky switch".into()
+	}
+}
+
+#[test]
+fn sm_nd_flaky_switch_admits_either_outcome() {
+	assert!(FlakySwitch::is_admissible(&[false, true, true, false], &[(), (), ()]));
+	assert!(FlakySwitch::is_admissible(&[false, false, false], &[(), ()]));
+	assert!(FlakySwitch::is_admissible(&[true], &[]));
+}
+
+#[test]
+fn sm_nd_rejects_traces_of_the_wrong_length() {
+	assert!(!FlakySwitch::is_admissible(&[false, true], &[(), ()]));
+	assert!(!FlakySwitch::is_admissible(&[], &[]));
+}
+
+#[test]
+fn sm_nd_possible_outcomes_are_deduplicated() {
+	assert_eq!(FlakySwitch::possible_outcomes(&false, &[]), vec![false]);
+	assert_eq!(FlakySwitch::possible_outcomes(&false, &[(), (), ()]), vec![true, false]);
+}
+
+#[test]
+fn sm_nd_deterministic_machine_admits_only_its_own_trace() {
+	use super::{
+		p2_laundry_machine::{ClothesAction::*, ClothesMachine, ClothesState},
+		Deterministic, StateMachine,
+	};
+
+	let start = ClothesState::Clean(4);
+	let transitions = [Wear, Wash, Dry];
+	let trace = ClothesMachine::trace(&start, &transitions);
+
+	assert!(Deterministic::<ClothesMachine>::is_admissible(&trace, &transitions));
+	assert!(!Deterministic::<ClothesMachine>::is_admissible(
+		&[start.clone(), ClothesState::Wet(3), ClothesState::Wet(2), ClothesState::Clean(1)],
+		&transitions
+	));
+	assert_eq!(
+		Deterministic::<ClothesMachine>::possible_outcomes(&start, &transitions),
+		vec![ClothesMachine::apply_all(&start, &transitions)]
+	);
+}
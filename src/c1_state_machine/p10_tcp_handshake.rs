@@ -0,0 +1,364 @@
+//! Before two computers can talk over TCP, they agree to open a connection with a three-way
+//! handshake, and when they are done they agree to close it again. Each end of the connection is
+//! a state machine driven by its user's commands and by the segments that arrive from the other
+//! end. Whatever the machine sends in reply is its output.
+//!
+//! This is a simplified version of the machine described in RFC 793. Sequence numbers, windows,
+//! and the data itself are left out. Only the flags that drive the states are modelled. The
+//! blockchain nodes in later chapters talk to each other over connections just like these.
+
+use super::{repl::ParseTransition, EnumerableTransitions, MealyStateMachine, StateMachine};
+use serde::{Deserialize, Serialize};
+
+/// A segment travelling between the two ends of a connection, identified by its flags
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Segment {
+	/// Asks to open a connection
+	Syn,
+	/// Agrees to open a connection, and acknowledges the other end's request
+	SynAck,
+	/// Acknowledges the last segment
+	Ack,
+	/// Asks to close the connection
+	Fin,
+	/// Asks to close the connection, and acknowledges the other end's request
+	FinAck,
+	/// Aborts the connection immediately
+	Rst,
+}
+
+/// The states of one end of a TCP connection
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum TcpState {
+	/// There is no connection at all.
+	Closed,
+	/// Waiting for the other end to ask for a connection.
+	Listen,
+	/// Asked for a connection, and waiting for the other end to agree.
+	SynSent,
+	/// The other end asked for a connection, and we agreed. Waiting for them to acknowledge.
+	/// Remembers whether we got here by listening, because a reset sends us back to listening.
+	SynReceived { passive: bool },
+	/// The connection is open and data can flow both ways.
+	Established,
+	/// We asked to close the connection, and are waiting for the other end to acknowledge.
+	FinWait1,
+	/// The other end acknowledged our request to close, and we are waiting for theirs.
+	FinWait2,
+	/// The other end asked to close the connection. Waiting for our user to close it too.
+	CloseWait,
+	/// Both ends asked to close at the same time. Waiting for our request to be acknowledged.
+	Closing,
+	/// We closed after the other end did, and are waiting for our request to be acknowledged.
+	LastAck,
+	/// Both ends have closed. Waiting a while in case the other end didn't get our last
+	/// acknowledgement and asks again.
+	TimeWait,
+}
+
+/// Something that happens to one end of a connection
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum TcpEvent {
+	/// The user wants to wait for connections from the other end.
+	PassiveOpen,
+	/// The user wants to connect to the other end.
+	ActiveOpen,
+	/// The user is done with the connection.
+	Close,
+	/// A segment arrived from the other end.
+	Receive(Segment),
+	/// The time to wait after closing is over.
+	Timeout,
+}
+
+/// One end of a TCP connection
+pub struct TcpConnection;
+
+impl MealyStateMachine for TcpConnection {
+	type Output = Segment;
+
+	fn next_state_with_output(starting_state: &TcpState, t: &TcpEvent) -> (TcpState, Vec<Segment>) {
+		use Segment::*;
+		use TcpEvent::*;
+		use TcpState::*;
+
+		let (end, sent) = match (starting_state, t) {
+			(Closed, PassiveOpen) => (Listen, None),
+			(Closed | Listen, ActiveOpen) => (SynSent, Some(Syn)),
+			// Nobody is listening, so anything other than a reset is refused with one.
+			(Closed, Receive(Rst)) => (Closed, None),
+			(Closed, Receive(_)) => (Closed, Some(Rst)),
+
+			(Listen, Receive(Syn)) => (SynReceived { passive: true }, Some(SynAck)),
+			(Listen | SynSent, Close) => (Closed, None),
+
+			(SynSent, Receive(SynAck)) => (Established, Some(Ack)),
+			// Both ends asked for a connection at the same time.
+			(SynSent, Receive(Syn)) => (SynReceived { passive: false }, Some(SynAck)),
+
+			(SynReceived { .. }, Receive(Ack | SynAck)) => (Established, None),
+			(SynReceived { passive: true }, Receive(Rst)) => (Listen, None),
+			(SynReceived { .. } | Established, Close) => (FinWait1, Some(Fin)),
+
+			(Established, Receive(Fin)) => (CloseWait, Some(Ack)),
+
+			(FinWait1, Receive(Ack)) => (FinWait2, None),
+			(FinWait1, Receive(FinAck)) => (TimeWait, Some(Ack)),
+			// Both ends asked to close at the same time.
+			(FinWait1, Receive(Fin)) => (Closing, Some(Ack)),
+			(FinWait2, Receive(Fin | FinAck)) => (TimeWait, Some(Ack)),
+
+			(CloseWait, Close) => (LastAck, Some(Fin)),
+			(Closing, Receive(Ack)) => (TimeWait, None),
+			(LastAck, Receive(Ack)) => (Closed, None),
+			(TimeWait, Timeout) => (Closed, None),
+
+			// A reset aborts any connection that is being set up or is open. In time wait the
+			// connection is already over, so there is nothing left to abort.
+			(
+				SynSent |
+				SynReceived { .. } |
+				Established |
+				FinWait1 |
+				FinWait2 |
+				CloseWait |
+				Closing |
+				LastAck,
+				Receive(Rst),
+			) => (Closed, None),
+
+			// Everything else makes no sense in this state, and is ignored.
+			(state, _) => (state.clone(), None),
+		};
+		(end, sent.into_iter().collect())
+	}
+}
+
+impl StateMachine for TcpConnection {
+	type State = TcpState;
+	type Transition = TcpEvent;
+
+	fn next_state(starting_state: &TcpState, t: &TcpEvent) -> TcpState {
+		Self::next_state_with_output(starting_state, t).0
+	}
+
+	fn human_name() -> String {
+		"TCP connection".into()
+	}
+}
+
+impl EnumerableTransitions for TcpConnection {
+	fn transitions() -> Vec<TcpEvent> {
+		let segments = [
+			Segment::Syn,
+			Segment::SynAck,
+			Segment::Ack,
+			Segment::Fin,
+			Segment::FinAck,
+			Segment::Rst,
+		];
+		[TcpEvent::PassiveOpen, TcpEvent::ActiveOpen, TcpEvent::Close, TcpEvent::Timeout]
+			.into_iter()
+			.chain(segments.into_iter().map(TcpEvent::Receive))
+			.collect()
+	}
+}
+
+impl ParseTransition for TcpConnection {
+	fn transition_help() -> String {
+		"`listen`, `connect`, `close`, `timeout`, or `recv <segment>` where the segment is one of `syn`, `synack`, `ack`, `fin`, `finack`, or `rst`".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<TcpEvent, String> {
+		if let Some(segment) = text.strip_prefix("recv ") {
+			let segment = match segment.trim() {
+				"syn" => Segment::Syn,
+				"synack" => Segment::SynAck,
+				"ack" => Segment::Ack,
+				"fin" => Segment::Fin,
+				"finack" => Segment::FinAck,
+				"rst" => Segment::Rst,
+				other => return Err(format!("Unknown segment {:?}", other)),
+			};
+			return Ok(TcpEvent::Receive(segment))
+		}
+		match text {
+			"listen" => Ok(TcpEvent::PassiveOpen),
+			"connect" => Ok(TcpEvent::ActiveOpen),
+			"close" => Ok(TcpEvent::Close),
+			"timeout" => Ok(TcpEvent::Timeout),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
+}
+
+/// Apply the given events to the two ends of a connection, then deliver every segment each end
+/// sends to the other end, until no more segments are in flight. Segments sent at the same time
+/// cross on the wire, so each round delivers everything both ends sent in the previous round.
+#[cfg(test)]
+fn exchange(
+	(a, b): (TcpState, TcpState),
+	(a_event, b_event): (Option<TcpEvent>, Option<TcpEvent>),
+) -> (TcpState, TcpState) {
+	let step = |state: TcpState, events: Vec<TcpEvent>| {
+		TcpConnection::apply_all_with_output(&state, &events)
+	};
+	let (mut a, mut to_b) = step(a, a_event.into_iter().collect());
+	let (mut b, mut to_a) = step(b, b_event.into_iter().collect());
+	while !to_a.is_empty() || !to_b.is_empty() {
+		let (next_a, from_a) = step(a, to_a.into_iter().map(TcpEvent::Receive).collect());
+		let (next_b, from_b) = step(b, to_b.into_iter().map(TcpEvent::Receive).collect());
+		(a, b, to_b, to_a) = (next_a, next_b, from_a, from_b);
+	}
+	(a, b)
+}
+
+#[test]
+fn sm_10_listen() {
+	let end = TcpConnection::next_state(&TcpState::Closed, &TcpEvent::PassiveOpen);
+
+	assert_eq!(end, TcpState::Listen);
+}
+
+#[test]
+fn sm_10_active_open_sends_syn() {
+	let (end, sent) =
+		TcpConnection::next_state_with_output(&TcpState::Closed, &TcpEvent::ActiveOpen);
+
+	assert_eq!(end, TcpState::SynSent);
+	assert_eq!(sent, vec![Segment::Syn]);
+}
+
+#[test]
+fn sm_10_listener_answers_syn() {
+	let (end, sent) =
+		TcpConnection::next_state_with_output(&TcpState::Listen, &TcpEvent::Receive(Segment::Syn));
+
+	assert_eq!(end, TcpState::SynReceived { passive: true });
+	assert_eq!(sent, vec![Segment::SynAck]);
+}
+
+#[test]
+fn sm_10_three_way_handshake() {
+	let client = TcpConnection::apply_all_with_output(
+		&TcpState::Closed,
+		&[TcpEvent::ActiveOpen, TcpEvent::Receive(Segment::SynAck)],
+	);
+	let server = TcpConnection::apply_all_with_output(
+		&TcpState::Listen,
+		&[TcpEvent::Receive(Segment::Syn), TcpEvent::Receive(Segment::Ack)],
+	);
+
+	assert_eq!(client, (TcpState::Established, vec![Segment::Syn, Segment::Ack]));
+	assert_eq!(server, (TcpState::Established, vec![Segment::SynAck]));
+}
+
+#[test]
+fn sm_10_connect_to_listener() {
+	let end = exchange((TcpState::Closed, TcpState::Listen), (Some(TcpEvent::ActiveOpen), None));
+
+	assert_eq!(end, (TcpState::Established, TcpState::Established));
+}
+
+#[test]
+fn sm_10_simultaneous_open() {
+	let syn_crossed = TcpConnection::apply_all_with_output(
+		&TcpState::Closed,
+		&[TcpEvent::ActiveOpen, TcpEvent::Receive(Segment::Syn)],
+	);
+	assert_eq!(
+		syn_crossed,
+		(TcpState::SynReceived { passive: false }, vec![Segment::Syn, Segment::SynAck])
+	);
+
+	let end = exchange(
+		(TcpState::Closed, TcpState::Closed),
+		(Some(TcpEvent::ActiveOpen), Some(TcpEvent::ActiveOpen)),
+	);
+	assert_eq!(end, (TcpState::Established, TcpState::Established));
+}
+
+#[test]
+fn sm_10_connect_to_closed_port_is_reset() {
+	let (end, sent) =
+		TcpConnection::next_state_with_output(&TcpState::Closed, &TcpEvent::Receive(Segment::Syn));
+	assert_eq!(end, TcpState::Closed);
+	assert_eq!(sent, vec![Segment::Rst]);
+
+	let end = exchange((TcpState::Closed, TcpState::Closed), (Some(TcpEvent::ActiveOpen), None));
+	assert_eq!(end, (TcpState::Closed, TcpState::Closed));
+}
+
+#[test]
+fn sm_10_reset_while_opening() {
+	let rst = TcpEvent::Receive(Segment::Rst);
+
+	assert_eq!(TcpConnection::next_state(&TcpState::SynSent, &rst), TcpState::Closed);
+	assert_eq!(
+		TcpConnection::next_state(&TcpState::SynReceived { passive: true }, &rst),
+		TcpState::Listen
+	);
+	assert_eq!(
+		TcpConnection::next_state(&TcpState::SynReceived { passive: false }, &rst),
+		TcpState::Closed
+	);
+}
+
+#[test]
+fn sm_10_reset_aborts_open_connection() {
+	let rst = TcpEvent::Receive(Segment::Rst);
+	for state in [
+		TcpState::Established,
+		TcpState::FinWait1,
+		TcpState::FinWait2,
+		TcpState::CloseWait,
+		TcpState::Closing,
+		TcpState::LastAck,
+	] {
+		assert_eq!(TcpConnection::next_state_with_output(&state, &rst), (TcpState::Closed, vec![]));
+	}
+	assert_eq!(TcpConnection::next_state(&TcpState::TimeWait, &rst), TcpState::TimeWait);
+}
+
+#[test]
+fn sm_10_closing_handshake() {
+	let end =
+		exchange((TcpState::Established, TcpState::Established), (Some(TcpEvent::Close), None));
+	assert_eq!(end, (TcpState::FinWait2, TcpState::CloseWait));
+
+	let (a, b) = exchange(end, (None, Some(TcpEvent::Close)));
+	assert_eq!((a.clone(), b), (TcpState::TimeWait, TcpState::Closed));
+	assert_eq!(TcpConnection::next_state(&a, &TcpEvent::Timeout), TcpState::Closed);
+}
+
+#[test]
+fn sm_10_simultaneous_close() {
+	let end = exchange(
+		(TcpState::Established, TcpState::Established),
+		(Some(TcpEvent::Close), Some(TcpEvent::Close)),
+	);
+
+	assert_eq!(end, (TcpState::TimeWait, TcpState::TimeWait));
+}
+
+#[test]
+fn sm_10_nonsense_segments_are_ignored() {
+	let (end, sent) = TcpConnection::next_state_with_output(
+		&TcpState::Established,
+		&TcpEvent::Receive(Segment::SynAck),
+	);
+
+	assert_eq!(end, TcpState::Established);
+	assert_eq!(sent, vec![]);
+}
+
+#[test]
+fn sm_10_parse_transitions() {
+	assert_eq!(TcpConnection::parse_transition("connect"), Ok(TcpEvent::ActiveOpen));
+	assert_eq!(
+		TcpConnection::parse_transition("recv synack"),
+		Ok(TcpEvent::Receive(Segment::SynAck))
+	);
+	assert!(TcpConnection::parse_transition("recv urg").is_err());
+	assert!(TcpConnection::parse_transition("ping").is_err());
+}
@@ -0,0 +1,544 @@
+//! A multisig wallet holds money on behalf of a group. No single member may spend it. Instead,
+//! members propose what to do with it, and a proposal only goes through once enough members
+//! have approved it. A wallet that needs k approvals out of n members is called k-of-n.
+//!
+//! The same proposals can change the group itself, adding or removing members or changing how
+//! many approvals are needed. This is a small taste of on-chain governance, where the rules of a
+//! system are changed by the very process they govern.
+
+use super::{Invariant, StateMachine, TryStateMachine, User};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Something a proposal may do once it is approved
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum ProposalAction {
+	/// Pay the given amount out of the wallet to the given user
+	Pay { to: User, amount: u64 },
+	/// Make the given user a signer
+	AddSigner(User),
+	/// Stop the given user from being a signer. Their approvals of pending proposals no longer
+	/// count.
+	RemoveSigner(User),
+	/// Change how many approvals a proposal needs
+	ChangeThreshold(u32),
+}
+
+/// A proposal waiting for approvals
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Proposal {
+	/// The signer who made the proposal. Only they may cancel it.
+	pub proposer: User,
+	/// What happens when the proposal is approved
+	pub action: ProposalAction,
+	/// The signers who approved the proposal so far, including the proposer
+	pub approvals: Vec<User>,
+}
+
+/// The wallet, along with every proposal that is still pending
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Wallet {
+	/// The users who may make and approve proposals
+	signers: Vec<User>,
+	/// How many signers must approve a proposal before it is executed
+	threshold: u32,
+	/// How much money the wallet holds
+	balance: u64,
+	/// The proposals that have been neither executed nor cancelled, by id
+	proposals: BTreeMap<u64, Proposal>,
+	/// The id of the next proposal. Ids are never reused, so an approval can't accidentally
+	/// apply to a different proposal than the one the signer saw.
+	next_proposal: u64,
+}
+
+impl Wallet {
+	/// An empty wallet where the given signers need `threshold` approvals to do anything. Fails if
+	/// the threshold is zero or more than the number of signers.
+	pub fn new(signers: Vec<User>, threshold: u32) -> Result<Self, MultisigError> {
+		let mut unique = Vec::new();
+		for signer in signers {
+			if !unique.contains(&signer) {
+				unique.push(signer);
+			}
+		}
+		if threshold == 0 || threshold as usize > unique.len() {
+			return Err(MultisigError::InvalidThreshold)
+		}
+		Ok(Wallet {
+			signers: unique,
+			threshold,
+			balance: 0,
+			proposals: BTreeMap::new(),
+			next_proposal: 0,
+		})
+	}
+
+	/// The users who may make and approve proposals
+	pub fn signers(&self) -> &[User] {
+		&self.signers
+	}
+
+	/// How many signers must approve a proposal before it is executed
+	pub fn threshold(&self) -> u32 {
+		self.threshold
+	}
+
+	/// How much money the wallet holds
+	pub fn balance(&self) -> u64 {
+		self.balance
+	}
+
+	/// The proposal with the given id, if it is still pending
+	pub fn proposal(&self, id: u64) -> Option<&Proposal> {
+		self.proposals.get(&id)
+	}
+
+	/// Carry out an approved proposal.
+	fn execute(&mut self, action: &ProposalAction) -> Result<(), MultisigError> {
+		match action {
+			ProposalAction::Pay { amount, .. } => {
+				self.balance =
+					self.balance.checked_sub(*amount).ok_or(MultisigError::InsufficientFunds)?;
+			},
+			ProposalAction::AddSigner(user) => {
+				if self.signers.contains(user) {
+					return Err(MultisigError::AlreadyASigner)
+				}
+				self.signers.push(*user);
+			},
+			ProposalAction::RemoveSigner(user) => {
+				if !self.signers.contains(user) {
+					return Err(MultisigError::NotASigner)
+				}
+				if self.signers.len() - 1 < self.threshold as usize {
+					return Err(MultisigError::InvalidThreshold)
+				}
+				self.signers.retain(|signer| signer != user);
+				for proposal in self.proposals.values_mut() {
+					proposal.approvals.retain(|approver| approver != user);
+				}
+			},
+			ProposalAction::ChangeThreshold(threshold) => {
+				if *threshold == 0 || *threshold as usize > self.signers.len() {
+					return Err(MultisigError::InvalidThreshold)
+				}
+				self.threshold = *threshold;
+			},
+		}
+		Ok(())
+	}
+
+	/// Execute the given proposal if it has enough approvals, removing it from the pending ones.
+	fn execute_if_approved(&mut self, id: u64) -> Result<(), MultisigError> {
+		let proposal = &self.proposals[&id];
+		if proposal.approvals.len() < self.threshold as usize {
+			return Ok(())
+		}
+		let proposal = self.proposals.remove(&id).expect("proposal was just looked up");
+		self.execute(&proposal.action)
+	}
+}
+
+/// Something a user can do to the wallet
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum MultisigTransaction {
+	/// Anyone may put money into the wallet.
+	Deposit { amount: u64 },
+	/// A signer proposes an action, which also counts as their approval. The proposal gets the
+	/// next free id.
+	Propose { proposer: User, action: ProposalAction },
+	/// A signer approves a pending proposal. If that brings it up to the threshold, it is
+	/// executed right away.
+	Approve { signer: User, proposal: u64 },
+	/// The signer who made a pending proposal withdraws it.
+	Cancel { proposer: User, proposal: u64 },
+}
+
+/// The reasons a multisig transaction may be rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MultisigError {
+	/// Only signers may propose or approve, and only signers can be removed.
+	NotASigner,
+	/// The user is a signer already, so they can't be added again.
+	AlreadyASigner,
+	/// There is no pending proposal with that id.
+	UnknownProposal,
+	/// The signer approved this proposal already.
+	AlreadyApproved,
+	/// Only the signer who made a proposal may cancel it.
+	NotProposer,
+	/// The wallet doesn't hold enough money for the payment.
+	InsufficientFunds,
+	/// The threshold would be zero, or more than the number of signers.
+	InvalidThreshold,
+	/// The balance is too large to be represented.
+	Overflow,
+}
+
+/// A multi-signature wallet
+pub struct Multisig;
+
+impl StateMachine for Multisig {
+	type State = Wallet;
+	type Transition = MultisigTransaction;
+
+	fn next_state(starting_state: &Wallet, t: &MultisigTransaction) -> Wallet {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+
+	fn human_name() -> String {
+		"Multisig wallet".into()
+	}
+}
+
+/// Every invalid transaction is rejected, leaving the wallet exactly as it was. That includes an
+/// approval that would execute a proposal that can't be carried out, like a payment the wallet
+/// can't afford. The approval can simply be made again later, once the wallet has the money.
+impl TryStateMachine for Multisig {
+	type Error = MultisigError;
+
+	fn try_next_state(
+		starting_state: &Wallet,
+		t: &MultisigTransaction,
+	) -> Result<Wallet, MultisigError> {
+		let mut wallet = starting_state.clone();
+		match t {
+			MultisigTransaction::Deposit { amount } => {
+				wallet.balance =
+					wallet.balance.checked_add(*amount).ok_or(MultisigError::Overflow)?;
+			},
+			MultisigTransaction::Propose { proposer, action } => {
+				if !wallet.signers.contains(proposer) {
+					return Err(MultisigError::NotASigner)
+				}
+				let id = wallet.next_proposal;
+				wallet.next_proposal += 1;
+				let proposal = Proposal {
+					proposer: *proposer,
+					action: action.clone(),
+					approvals: vec![*proposer],
+				};
+				wallet.proposals.insert(id, proposal);
+				wallet.execute_if_approved(id)?;
+			},
+			MultisigTransaction::Approve { signer, proposal } => {
+				if !wallet.signers.contains(signer) {
+					return Err(MultisigError::NotASigner)
+				}
+				let pending =
+					wallet.proposals.get_mut(proposal).ok_or(MultisigError::UnknownProposal)?;
+				if pending.approvals.contains(signer) {
+					return Err(MultisigError::AlreadyApproved)
+				}
+				pending.approvals.push(*signer);
+				wallet.execute_if_approved(*proposal)?;
+			},
+			MultisigTransaction::Cancel { proposer, proposal } => {
+				let pending =
+					wallet.proposals.get(proposal).ok_or(MultisigError::UnknownProposal)?;
+				if pending.proposer != *proposer {
+					return Err(MultisigError::NotProposer)
+				}
+				wallet.proposals.remove(proposal);
+			},
+		}
+		Ok(wallet)
+	}
+}
+
+/// The ways a wallet can be in an impossible state
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MultisigViolation {
+	/// The threshold is zero, or more than the number of signers, so nothing could ever be done.
+	ThresholdOutOfRange,
+	/// The same user is listed as a signer twice.
+	DuplicateSigner,
+	/// A pending proposal counts an approval from somebody who isn't a signer.
+	ApprovalFromNonSigner,
+}
+
+impl Invariant for Multisig {
+	type Violation = MultisigViolation;
+
+	fn check(wallet: &Wallet) -> Result<(), MultisigViolation> {
+		if wallet.threshold == 0 || wallet.threshold as usize > wallet.signers.len() {
+			return Err(MultisigViolation::ThresholdOutOfRange)
+		}
+		for (i, signer) in wallet.signers.iter().enumerate() {
+			if wallet.signers[..i].contains(signer) {
+				return Err(MultisigViolation::DuplicateSigner)
+			}
+		}
+		let approvals = wallet.proposals.values().flat_map(|proposal| &proposal.approvals);
+		if approvals.into_iter().any(|approver| !wallet.signers.contains(approver)) {
+			return Err(MultisigViolation::ApprovalFromNonSigner)
+		}
+		Ok(())
+	}
+}
+
+/// A 2-of-3 wallet holding 100, shared by Alice, Bob, and Charlie.
+#[cfg(test)]
+fn funded_wallet() -> Wallet {
+	let wallet = Wallet::new(vec![User::Alice, User::Bob, User::Charlie], 2).unwrap();
+	Wallet { balance: 100, ..wallet }
+}
+
+#[test]
+fn sm_11_new_wallet_checks_threshold() {
+	assert_eq!(Wallet::new(vec![User::Alice], 0), Err(MultisigError::InvalidThreshold));
+	assert_eq!(
+		Wallet::new(vec![User::Alice, User::Alice], 2),
+		Err(MultisigError::InvalidThreshold)
+	);
+	assert_eq!(Wallet::new(vec![User::Alice, User::Bob], 2).unwrap().threshold(), 2);
+}
+
+#[test]
+fn sm_11_deposit() {
+	let end = Multisig::next_state(&funded_wallet(), &MultisigTransaction::Deposit { amount: 50 });
+
+	assert_eq!(end, Wallet { balance: 150, ..funded_wallet() });
+}
+
+#[test]
+fn sm_11_propose_counts_as_approval() {
+	let pay = ProposalAction::Pay { to: User::Charlie, amount: 30 };
+	let end = Multisig::next_state(
+		&funded_wallet(),
+		&MultisigTransaction::Propose { proposer: User::Alice, action: pay.clone() },
+	);
+	let expected = Wallet {
+		proposals: BTreeMap::from([(
+			0,
+			Proposal { proposer: User::Alice, action: pay, approvals: vec![User::Alice] },
+		)]),
+		next_proposal: 1,
+		..funded_wallet()
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_11_threshold_executes_payment() {
+	let end = Multisig::apply_all(
+		&funded_wallet(),
+		&[
+			MultisigTransaction::Propose {
+				proposer: User::Alice,
+				action: ProposalAction::Pay { to: User::Charlie, amount: 30 },
+			},
+			MultisigTransaction::Approve { signer: User::Bob, proposal: 0 },
+		],
+	);
+
+	assert_eq!(end, Wallet { balance: 70, next_proposal: 1, ..funded_wallet() });
+}
+
+#[test]
+fn sm_11_one_of_n_executes_immediately() {
+	let wallet = Wallet { balance: 100, ..Wallet::new(vec![User::Alice, User::Bob], 1).unwrap() };
+	let end = Multisig::next_state(
+		&wallet,
+		&MultisigTransaction::Propose {
+			proposer: User::Bob,
+			action: ProposalAction::Pay { to: User::Bob, amount: 100 },
+		},
+	);
+
+	assert_eq!(end.balance(), 0);
+	assert_eq!(end.proposal(0), None);
+}
+
+#[test]
+fn sm_11_approvals_must_come_from_distinct_signers() {
+	let proposed = Multisig::next_state(
+		&funded_wallet(),
+		&MultisigTransaction::Propose {
+			proposer: User::Alice,
+			action: ProposalAction::Pay { to: User::Charlie, amount: 30 },
+		},
+	);
+
+	assert_eq!(
+		Multisig::try_next_state(
+			&proposed,
+			&MultisigTransaction::Approve { signer: User::Alice, proposal: 0 }
+		),
+		Err(MultisigError::AlreadyApproved)
+	);
+	assert_eq!(
+		Multisig::try_next_state(
+			&proposed,
+			&MultisigTransaction::Approve { signer: User::Bob, proposal: 1 }
+		),
+		Err(MultisigError::UnknownProposal)
+	);
+}
+
+#[test]
+fn sm_11_non_signer_cannot_propose_or_approve() {
+	let wallet = Wallet { balance: 100, ..Wallet::new(vec![User::Alice, User::Bob], 2).unwrap() };
+	let pay = ProposalAction::Pay { to: User::Charlie, amount: 30 };
+
+	assert_eq!(
+		Multisig::try_next_state(
+			&wallet,
+			&MultisigTransaction::Propose { proposer: User::Charlie, action: pay.clone() }
+		),
+		Err(MultisigError::NotASigner)
+	);
+
+	let proposed = Multisig::next_state(
+		&wallet,
+		&MultisigTransaction::Propose { proposer: User::Alice, action: pay },
+	);
+	assert_eq!(
+		Multisig::try_next_state(
+			&proposed,
+			&MultisigTransaction::Approve { signer: User::Charlie, proposal: 0 }
+		),
+		Err(MultisigError::NotASigner)
+	);
+}
+
+#[test]
+fn sm_11_unaffordable_payment_waits_for_funds() {
+	let proposed = Multisig::next_state(
+		&funded_wallet(),
+		&MultisigTransaction::Propose {
+			proposer: User::Alice,
+			action: ProposalAction::Pay { to: User::Charlie, amount: 150 },
+		},
+	);
+	let approve = MultisigTransaction::Approve { signer: User::Bob, proposal: 0 };
+
+	assert_eq!(
+		Multisig::try_next_state(&proposed, &approve),
+		Err(MultisigError::InsufficientFunds)
+	);
+
+	let funded = Multisig::next_state(&proposed, &MultisigTransaction::Deposit { amount: 50 });
+	let end = Multisig::next_state(&funded, &approve);
+	assert_eq!(end.balance(), 0);
+	assert_eq!(end.proposal(0), None);
+}
+
+#[test]
+fn sm_11_only_proposer_can_cancel() {
+	let proposed = Multisig::next_state(
+		&funded_wallet(),
+		&MultisigTransaction::Propose {
+			proposer: User::Alice,
+			action: ProposalAction::Pay { to: User::Charlie, amount: 30 },
+		},
+	);
+
+	assert_eq!(
+		Multisig::try_next_state(
+			&proposed,
+			&MultisigTransaction::Cancel { proposer: User::Bob, proposal: 0 }
+		),
+		Err(MultisigError::NotProposer)
+	);
+
+	let cancelled = Multisig::next_state(
+		&proposed,
+		&MultisigTransaction::Cancel { proposer: User::Alice, proposal: 0 },
+	);
+	assert_eq!(cancelled, Wallet { next_proposal: 1, ..funded_wallet() });
+	assert_eq!(
+		Multisig::try_next_state(
+			&cancelled,
+			&MultisigTransaction::Approve { signer: User::Bob, proposal: 0 }
+		),
+		Err(MultisigError::UnknownProposal)
+	);
+}
+
+#[test]
+fn sm_11_add_signer_and_raise_threshold() {
+	let wallet = Wallet::new(vec![User::Alice, User::Bob], 2).unwrap();
+	let end = Multisig::apply_all(
+		&wallet,
+		&[
+			MultisigTransaction::Propose {
+				proposer: User::Alice,
+				action: ProposalAction::AddSigner(User::Charlie),
+			},
+			MultisigTransaction::Approve { signer: User::Bob, proposal: 0 },
+			MultisigTransaction::Propose {
+				proposer: User::Charlie,
+				action: ProposalAction::ChangeThreshold(3),
+			},
+			MultisigTransaction::Approve { signer: User::Alice, proposal: 1 },
+		],
+	);
+
+	assert_eq!(end.signers(), &[User::Alice, User::Bob, User::Charlie]);
+	assert_eq!(end.threshold(), 3);
+}
+
+#[test]
+fn sm_11_removed_signer_approvals_stop_counting() {
+	let end = Multisig::apply_all(
+		&funded_wallet(),
+		&[
+			MultisigTransaction::Propose {
+				proposer: User::Charlie,
+				action: ProposalAction::Pay { to: User::Charlie, amount: 100 },
+			},
+			MultisigTransaction::Propose {
+				proposer: User::Alice,
+				action: ProposalAction::RemoveSigner(User::Charlie),
+			},
+			MultisigTransaction::Approve { signer: User::Bob, proposal: 1 },
+		],
+	);
+
+	assert_eq!(end.signers(), &[User::Alice, User::Bob]);
+	assert_eq!(end.proposal(0).unwrap().approvals, vec![]);
+	assert_eq!(Multisig::check(&end), Ok(()));
+}
+
+#[test]
+fn sm_11_cannot_remove_signers_below_threshold() {
+	let wallet = Wallet::new(vec![User::Alice, User::Bob], 2).unwrap();
+	let proposed = Multisig::next_state(
+		&wallet,
+		&MultisigTransaction::Propose {
+			proposer: User::Alice,
+			action: ProposalAction::RemoveSigner(User::Bob),
+		},
+	);
+
+	assert_eq!(
+		Multisig::try_next_state(
+			&proposed,
+			&MultisigTransaction::Approve { signer: User::Bob, proposal: 0 }
+		),
+		Err(MultisigError::InvalidThreshold)
+	);
+}
+
+#[test]
+fn sm_11_invariant_catches_broken_wallets() {
+	let too_high = Wallet { threshold: 4, ..funded_wallet() };
+	let stale_approval = Wallet {
+		signers: vec![User::Alice, User::Bob],
+		proposals: BTreeMap::from([(
+			0,
+			Proposal {
+				proposer: User::Alice,
+				action: ProposalAction::ChangeThreshold(1),
+				approvals: vec![User::Charlie],
+			},
+		)]),
+		..funded_wallet()
+	};
+
+	assert_eq!(Multisig::check(&too_high), Err(MultisigViolation::ThresholdOutOfRange));
+	assert_eq!(Multisig::check(&stale_approval), Err(MultisigViolation::ApprovalFromNonSigner));
+	assert_eq!(Multisig::check(&funded_wallet()), Ok(()));
+}
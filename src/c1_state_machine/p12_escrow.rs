@@ -0,0 +1,389 @@
+//! An escrow lets two parties who don't trust each other trade safely. The buyer pays into the
+//! escrow instead of paying the seller directly. Once the buyer has what they paid for, they
+//! release the money to the seller. If the seller can't deliver, they refund the buyer. And if
+//! the two can't agree, a neutral arbiter decides who gets the money.
+//!
+//! However the trade goes, money must never appear out of thin air or vanish. Every transition
+//! only moves money between the parties' wallets and the escrow, and the invariant checks that
+//! the total never changes.
+
+use super::{EnumerableTransitions, Invariant, StateMachine, TryStateMachine, User};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How far along the trade is
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum EscrowStatus {
+	/// The terms are agreed, but the buyer hasn't paid yet.
+	Created,
+	/// The buyer paid, and the escrow holds the money.
+	Funded,
+	/// The buyer and seller disagree. The escrow holds the money until the arbiter rules.
+	Disputed,
+	/// The seller got the money. The trade is over.
+	Released,
+	/// The buyer got their money back. The trade is over.
+	Refunded,
+}
+
+/// How the arbiter settles a dispute
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Ruling {
+	/// The seller delivered, so they get the money.
+	Release,
+	/// The seller didn't deliver, so the buyer gets their money back.
+	Refund,
+}
+
+/// Something one of the parties can do
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum EscrowAction {
+	/// The buyer pays the price into the escrow.
+	Fund { by: User },
+	/// The buyer is happy with what they got, and the money goes to the seller.
+	Release { by: User },
+	/// The seller can't deliver, and the money goes back to the buyer.
+	Refund { by: User },
+	/// The buyer or the seller asks the arbiter to settle the trade.
+	Dispute { by: User },
+	/// The arbiter settles a dispute.
+	Resolve { by: User, ruling: Ruling },
+}
+
+/// A single trade, along with the wallets of everyone involved
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Escrow {
+	/// The user paying for something
+	buyer: User,
+	/// The user being paid
+	seller: User,
+	/// The user who settles disputes
+	arbiter: User,
+	/// How much the buyer pays
+	price: u64,
+	/// How far along the trade is
+	status: EscrowStatus,
+	/// How much money the escrow holds
+	held: u64,
+	/// How much money each user holds outside of the escrow. Users with nothing may be left out.
+	wallets: HashMap<User, u64>,
+	/// How much money there was when the escrow was created. No transition may change it.
+	supply: u64,
+}
+
+impl Escrow {
+	/// A new trade between the given buyer and seller, where the given arbiter settles disputes.
+	/// The buyer, seller, and arbiter should be three different users.
+	pub fn new(
+		buyer: User,
+		seller: User,
+		arbiter: User,
+		price: u64,
+		wallets: HashMap<User, u64>,
+	) -> Self {
+		let supply = wallets.values().sum();
+		Escrow {
+			buyer,
+			seller,
+			arbiter,
+			price,
+			status: EscrowStatus::Created,
+			held: 0,
+			wallets,
+			supply,
+		}
+	}
+
+	/// How far along the trade is
+	pub fn status(&self) -> EscrowStatus {
+		self.status
+	}
+
+	/// How much money the given user holds outside of the escrow
+	pub fn wallet(&self, user: User) -> u64 {
+		self.wallets.get(&user).copied().unwrap_or_default()
+	}
+
+	/// How much money the escrow holds
+	pub fn held(&self) -> u64 {
+		self.held
+	}
+
+	/// Move all the money in the escrow into the given user's wallet, ending the trade.
+	fn pay_out(&mut self, to: User, status: EscrowStatus) {
+		*self.wallets.entry(to).or_default() += self.held;
+		self.held = 0;
+		self.status = status;
+	}
+}
+
+/// The reasons an escrow action may be rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EscrowError {
+	/// The action can only be taken by another party to the trade.
+	NotAuthorized,
+	/// The action doesn't make sense at this point in the trade.
+	WrongStatus,
+	/// The buyer doesn't have enough money to pay the price.
+	InsufficientBalance,
+}
+
+/// The state machine governing a single escrowed trade
+pub struct EscrowMachine;
+
+impl StateMachine for EscrowMachine {
+	type State = Escrow;
+	type Transition = EscrowAction;
+
+	fn next_state(starting_state: &Escrow, t: &EscrowAction) -> Escrow {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+
+	fn human_name() -> String {
+		"Escrow".into()
+	}
+}
+
+/// Every invalid action is rejected, leaving the trade exactly as it was.
+impl TryStateMachine for EscrowMachine {
+	type Error = EscrowError;
+
+	fn try_next_state(starting_state: &Escrow, t: &EscrowAction) -> Result<Escrow, EscrowError> {
+		let mut escrow = starting_state.clone();
+		let (by, allowed, expected_status) = match t {
+			EscrowAction::Fund { by } => (by, escrow.buyer == *by, EscrowStatus::Created),
+			EscrowAction::Release { by } => (by, escrow.buyer == *by, EscrowStatus::Funded),
+			EscrowAction::Refund { by } => (by, escrow.seller == *by, EscrowStatus::Funded),
+			EscrowAction::Dispute { by } =>
+				(by, escrow.buyer == *by || escrow.seller == *by, EscrowStatus::Funded),
+			EscrowAction::Resolve { by, .. } => (by, escrow.arbiter == *by, EscrowStatus::Disputed),
+		};
+		if !allowed {
+			return Err(EscrowError::NotAuthorized)
+		}
+		if escrow.status != expected_status {
+			return Err(EscrowError::WrongStatus)
+		}
+
+		match t {
+			EscrowAction::Fund { .. } => {
+				let wallet = escrow.wallets.entry(*by).or_default();
+				*wallet =
+					wallet.checked_sub(escrow.price).ok_or(EscrowError::InsufficientBalance)?;
+				escrow.held = escrow.price;
+				escrow.status = EscrowStatus::Funded;
+			},
+			EscrowAction::Release { .. } |
+			EscrowAction::Resolve { ruling: Ruling::Release, .. } =>
+				escrow.pay_out(escrow.seller, EscrowStatus::Released),
+			EscrowAction::Refund { .. } | EscrowAction::Resolve { ruling: Ruling::Refund, .. } =>
+				escrow.pay_out(escrow.buyer, EscrowStatus::Refunded),
+			EscrowAction::Dispute { .. } => escrow.status = EscrowStatus::Disputed,
+		}
+		Ok(escrow)
+	}
+}
+
+/// The ways an escrow can go wrong
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum EscrowViolation {
+	/// Money was created or destroyed. The money in the escrow and in every wallet no longer adds
+	/// up to what there was to begin with.
+	FundsNotConserved,
+	/// The escrow holds the wrong amount for this point in the trade. It should hold exactly the
+	/// price while funded or disputed, and nothing otherwise.
+	WrongAmountHeld,
+}
+
+impl Invariant for EscrowMachine {
+	type Violation = EscrowViolation;
+
+	fn check(escrow: &Escrow) -> Result<(), EscrowViolation> {
+		let total = escrow
+			.wallets
+			.values()
+			.try_fold(escrow.held, |total, wallet| total.checked_add(*wallet));
+		if total != Some(escrow.supply) {
+			return Err(EscrowViolation::FundsNotConserved)
+		}
+		let expected_held = match escrow.status {
+			EscrowStatus::Funded | EscrowStatus::Disputed => escrow.price,
+			EscrowStatus::Created | EscrowStatus::Released | EscrowStatus::Refunded => 0,
+		};
+		if escrow.held != expected_held {
+			return Err(EscrowViolation::WrongAmountHeld)
+		}
+		Ok(())
+	}
+}
+
+/// Every action, taken by every user, including the ones they aren't allowed to take.
+impl EnumerableTransitions for EscrowMachine {
+	fn transitions() -> Vec<EscrowAction> {
+		[User::Alice, User::Bob, User::Charlie]
+			.into_iter()
+			.flat_map(|by| {
+				[
+					EscrowAction::Fund { by },
+					EscrowAction::Release { by },
+					EscrowAction::Refund { by },
+					EscrowAction::Dispute { by },
+					EscrowAction::Resolve { by, ruling: Ruling::Release },
+					EscrowAction::Resolve { by, ruling: Ruling::Refund },
+				]
+			})
+			.collect()
+	}
+}
+
+/// Alice buys something from Bob for 30, and Charlie arbitrates. Alice has 100 and Bob has 10.
+#[cfg(test)]
+fn trade() -> Escrow {
+	Escrow::new(
+		User::Alice,
+		User::Bob,
+		User::Charlie,
+		30,
+		HashMap::from([(User::Alice, 100), (User::Bob, 10)]),
+	)
+}
+
+#[test]
+fn sm_12_buyer_funds_escrow() {
+	let end = EscrowMachine::next_state(&trade(), &EscrowAction::Fund { by: User::Alice });
+	let expected = Escrow {
+		status: EscrowStatus::Funded,
+		held: 30,
+		wallets: HashMap::from([(User::Alice, 70), (User::Bob, 10)]),
+		..trade()
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_12_only_buyer_can_fund() {
+	assert_eq!(
+		EscrowMachine::try_next_state(&trade(), &EscrowAction::Fund { by: User::Bob }),
+		Err(EscrowError::NotAuthorized)
+	);
+}
+
+#[test]
+fn sm_12_buyer_cannot_fund_without_money() {
+	let poor = Escrow { price: 101, ..trade() };
+
+	assert_eq!(
+		EscrowMachine::try_next_state(&poor, &EscrowAction::Fund { by: User::Alice }),
+		Err(EscrowError::InsufficientBalance)
+	);
+}
+
+#[test]
+fn sm_12_cannot_fund_twice() {
+	let funded = EscrowMachine::next_state(&trade(), &EscrowAction::Fund { by: User::Alice });
+
+	assert_eq!(
+		EscrowMachine::try_next_state(&funded, &EscrowAction::Fund { by: User::Alice }),
+		Err(EscrowError::WrongStatus)
+	);
+}
+
+#[test]
+fn sm_12_release_pays_seller() {
+	let end = EscrowMachine::apply_all(
+		&trade(),
+		&[EscrowAction::Fund { by: User::Alice }, EscrowAction::Release { by: User::Alice }],
+	);
+
+	assert_eq!(end.status(), EscrowStatus::Released);
+	assert_eq!((end.wallet(User::Alice), end.wallet(User::Bob), end.held()), (70, 40, 0));
+}
+
+#[test]
+fn sm_12_seller_cannot_release_to_themselves() {
+	let funded = EscrowMachine::next_state(&trade(), &EscrowAction::Fund { by: User::Alice });
+
+	assert_eq!(
+		EscrowMachine::try_next_state(&funded, &EscrowAction::Release { by: User::Bob }),
+		Err(EscrowError::NotAuthorized)
+	);
+}
+
+#[test]
+fn sm_12_refund_pays_buyer_back() {
+	let end = EscrowMachine::apply_all(
+		&trade(),
+		&[EscrowAction::Fund { by: User::Alice }, EscrowAction::Refund { by: User::Bob }],
+	);
+
+	assert_eq!(end.status(), EscrowStatus::Refunded);
+	assert_eq!((end.wallet(User::Alice), end.wallet(User::Bob), end.held()), (100, 10, 0));
+}
+
+#[test]
+fn sm_12_arbiter_resolves_dispute() {
+	let disputed = EscrowMachine::apply_all(
+		&trade(),
+		&[EscrowAction::Fund { by: User::Alice }, EscrowAction::Dispute { by: User::Bob }],
+	);
+	assert_eq!(disputed.status(), EscrowStatus::Disputed);
+
+	// Once disputed, neither party can settle the trade on their own.
+	assert_eq!(
+		EscrowMachine::try_next_state(&disputed, &EscrowAction::Release { by: User::Alice }),
+		Err(EscrowError::WrongStatus)
+	);
+	assert_eq!(
+		EscrowMachine::try_next_state(
+			&disputed,
+			&EscrowAction::Resolve { by: User::Bob, ruling: Ruling::Release }
+		),
+		Err(EscrowError::NotAuthorized)
+	);
+
+	let released = EscrowMachine::next_state(
+		&disputed,
+		&EscrowAction::Resolve { by: User::Charlie, ruling: Ruling::Release },
+	);
+	assert_eq!(released.status(), EscrowStatus::Released);
+	assert_eq!(released.wallet(User::Bob), 40);
+
+	let refunded = EscrowMachine::next_state(
+		&disputed,
+		&EscrowAction::Resolve { by: User::Charlie, ruling: Ruling::Refund },
+	);
+	assert_eq!(refunded.status(), EscrowStatus::Refunded);
+	assert_eq!(refunded.wallet(User::Alice), 100);
+}
+
+#[test]
+fn sm_12_arbiter_only_rules_on_disputes() {
+	let funded = EscrowMachine::next_state(&trade(), &EscrowAction::Fund { by: User::Alice });
+
+	assert_eq!(
+		EscrowMachine::try_next_state(
+			&funded,
+			&EscrowAction::Resolve { by: User::Charlie, ruling: Ruling::Release }
+		),
+		Err(EscrowError::WrongStatus)
+	);
+}
+
+#[test]
+fn sm_12_invariant_catches_lost_money() {
+	let funded = EscrowMachine::next_state(&trade(), &EscrowAction::Fund { by: User::Alice });
+	let vanished = Escrow { held: 0, ..funded.clone() };
+	let unfunded = Escrow { status: EscrowStatus::Created, ..funded.clone() };
+
+	assert_eq!(EscrowMachine::check(&vanished), Err(EscrowViolation::FundsNotConserved));
+	assert_eq!(EscrowMachine::check(&unfunded), Err(EscrowViolation::WrongAmountHeld));
+	assert_eq!(EscrowMachine::check(&funded), Ok(()));
+}
+
+#[test]
+fn sm_12_model_check_funds_are_conserved() {
+	use super::model_checker::check_invariants;
+
+	assert!(check_invariants::<EscrowMachine>(&trade(), 4).is_ok());
+}
@@ -0,0 +1,441 @@
+//! An exchange brings together people who want to buy something with people who want to sell it.
+//! Each of them places an order saying how much they want to trade and at what price. Orders
+//! that can't be filled right away wait in the order book until somebody takes the other side.
+//!
+//! Matching happens entirely inside the state transition, so every node that applies the same
+//! orders in the same order ends up with the same book and the same trades. That is exactly what
+//! it takes to run an exchange on a blockchain.
+
+use super::{EnumerableTransitions, Invariant, MealyStateMachine, StateMachine, User};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// An order waiting in the book
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Order {
+	/// The id the order was given when it was placed
+	pub id: u64,
+	/// The user who placed the order. Only they may cancel it.
+	pub owner: User,
+	/// How much is still left to trade
+	pub quantity: u64,
+}
+
+/// Something a user can do at the exchange
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum OrderBookTransaction {
+	/// Offer to buy the given quantity, paying at most the given price for each unit.
+	PlaceBid { owner: User, price: u64, quantity: u64 },
+	/// Offer to sell the given quantity, getting at least the given price for each unit.
+	PlaceAsk { owner: User, price: u64, quantity: u64 },
+	/// Withdraw whatever is left of the given order from the book.
+	Cancel { owner: User, order: u64 },
+}
+
+/// A trade between a bid and an ask
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Trade {
+	/// The id of the bid
+	pub bid: u64,
+	/// The id of the ask
+	pub ask: u64,
+	/// The price of each unit. Trades happen at the price of the order that was waiting in the
+	/// book, so whoever placed the newer order may get a better price than they asked for.
+	pub price: u64,
+	/// How many units changed hands
+	pub quantity: u64,
+}
+
+/// The order book. Orders at the same price form a level, and within each level the oldest order
+/// is filled first. An incoming order is matched against the best levels on the other side for as
+/// long as the prices cross. Whatever can't be matched waits in the book.
+#[derive(Hash, Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct OrderBook {
+	/// The bids waiting to be filled, by price
+	bids: BTreeMap<u64, VecDeque<Order>>,
+	/// The asks waiting to be filled, by price
+	asks: BTreeMap<u64, VecDeque<Order>>,
+	/// The id of the next order
+	next_order: u64,
+}
+
+/// Which side of the book an order is on
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Side {
+	Bid,
+	Ask,
+}
+
+impl OrderBook {
+	/// The highest price anybody is willing to pay, if there are any bids
+	pub fn best_bid(&self) -> Option<u64> {
+		self.bids.keys().next_back().copied()
+	}
+
+	/// The lowest price anybody is willing to sell for, if there are any asks
+	pub fn best_ask(&self) -> Option<u64> {
+		self.asks.keys().next().copied()
+	}
+
+	/// The orders waiting at the given price on the bid side, oldest first
+	pub fn bids_at(&self, price: u64) -> impl Iterator<Item = &Order> {
+		self.bids.get(&price).into_iter().flatten()
+	}
+
+	/// The orders waiting at the given price on the ask side, oldest first
+	pub fn asks_at(&self, price: u64) -> impl Iterator<Item = &Order> {
+		self.asks.get(&price).into_iter().flatten()
+	}
+
+	/// Match a new order against the other side of the book, then add whatever is left of it to
+	/// its own side. Returns the trades that were made, best price first.
+	fn place(&mut self, side: Side, owner: User, price: u64, quantity: u64) -> Vec<Trade> {
+		let id = self.next_order;
+		self.next_order += 1;
+		let mut incoming = Order { id, owner, quantity };
+		let mut trades = Vec::new();
+
+		let opposite = match side {
+			Side::Bid => &mut self.asks,
+			Side::Ask => &mut self.bids,
+		};
+		while incoming.quantity > 0 {
+			let best = match side {
+				Side::Bid => opposite.keys().next().copied().filter(|ask| *ask <= price),
+				Side::Ask => opposite.keys().next_back().copied().filter(|bid| *bid >= price),
+			};
+			let Some(level_price) = best else { break };
+			let level = opposite.get_mut(&level_price).expect("best price has a level");
+			while let (true, Some(resting)) = (incoming.quantity > 0, level.front_mut()) {
+				let quantity = incoming.quantity.min(resting.quantity);
+				let (bid, ask) = match side {
+					Side::Bid => (incoming.id, resting.id),
+					Side::Ask => (resting.id, incoming.id),
+				};
+				trades.push(Trade { bid, ask, price: level_price, quantity });
+				incoming.quantity -= quantity;
+				resting.quantity -= quantity;
+				if resting.quantity == 0 {
+					level.pop_front();
+				}
+			}
+			if level.is_empty() {
+				opposite.remove(&level_price);
+			}
+		}
+
+		if incoming.quantity > 0 {
+			let own = match side {
+				Side::Bid => &mut self.bids,
+				Side::Ask => &mut self.asks,
+			};
+			own.entry(price).or_default().push_back(incoming);
+		}
+		trades
+	}
+
+	/// Remove the given order from the book, if it is waiting there and belongs to the owner.
+	fn cancel(&mut self, owner: User, order: u64) {
+		for levels in [&mut self.bids, &mut self.asks] {
+			let found = levels.iter().find_map(|(price, level)| {
+				level
+					.iter()
+					.position(|o| o.id == order && o.owner == owner)
+					.map(|i| (*price, i))
+			});
+			if let Some((price, index)) = found {
+				let level = levels.get_mut(&price).expect("order was found at this price");
+				level.remove(index);
+				if level.is_empty() {
+					levels.remove(&price);
+				}
+			}
+		}
+	}
+}
+
+/// A limit order book exchange
+pub struct Exchange;
+
+impl MealyStateMachine for Exchange {
+	type Output = Trade;
+
+	fn next_state_with_output(
+		starting_state: &OrderBook,
+		t: &OrderBookTransaction,
+	) -> (OrderBook, Vec<Trade>) {
+		let mut book = starting_state.clone();
+		let trades = match t {
+			// An order for nothing can't be filled, so it isn't even given an id.
+			OrderBookTransaction::PlaceBid { quantity: 0, .. } |
+			OrderBookTransaction::PlaceAsk { quantity: 0, .. } => vec![],
+			OrderBookTransaction::PlaceBid { owner, price, quantity } =>
+				book.place(Side::Bid, *owner, *price, *quantity),
+			OrderBookTransaction::PlaceAsk { owner, price, quantity } =>
+				book.place(Side::Ask, *owner, *price, *quantity),
+			OrderBookTransaction::Cancel { owner, order } => {
+				book.cancel(*owner, *order);
+				vec![]
+			},
+		};
+		(book, trades)
+	}
+}
+
+impl StateMachine for Exchange {
+	type State = OrderBook;
+	type Transition = OrderBookTransaction;
+
+	fn next_state(starting_state: &OrderBook, t: &OrderBookTransaction) -> OrderBook {
+		Self::next_state_with_output(starting_state, t).0
+	}
+
+	fn human_name() -> String {
+		"Order book exchange".into()
+	}
+}
+
+/// The ways an order book can be broken
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum OrderBookViolation {
+	/// The best bid is at least the best ask. Those orders should have been matched.
+	Crossed,
+	/// A price level has no orders left in it.
+	EmptyLevel,
+	/// An order has nothing left to trade, but is still waiting in the book.
+	EmptyOrder,
+}
+
+impl Invariant for Exchange {
+	type Violation = OrderBookViolation;
+
+	fn check(book: &OrderBook) -> Result<(), OrderBookViolation> {
+		if let (Some(bid), Some(ask)) = (book.best_bid(), book.best_ask()) {
+			if bid >= ask {
+				return Err(OrderBookViolation::Crossed)
+			}
+		}
+		let levels = book.bids.values().chain(book.asks.values());
+		for level in levels {
+			if level.is_empty() {
+				return Err(OrderBookViolation::EmptyLevel)
+			}
+			if level.iter().any(|order| order.quantity == 0) {
+				return Err(OrderBookViolation::EmptyOrder)
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Orders at a few prices around each other, and cancelling the first few orders. Enough to make
+/// the book cross, partially fill, and empty out levels.
+impl EnumerableTransitions for Exchange {
+	fn transitions() -> Vec<OrderBookTransaction> {
+		let mut transitions = Vec::new();
+		for price in [9, 10, 11] {
+			for quantity in [1, 2] {
+				transitions.push(OrderBookTransaction::PlaceBid {
+					owner: User::Alice,
+					price,
+					quantity,
+				});
+				transitions.push(OrderBookTransaction::PlaceAsk {
+					owner: User::Bob,
+					price,
+					quantity,
+				});
+			}
+		}
+		transitions.push(OrderBookTransaction::Cancel { owner: User::Alice, order: 0 });
+		transitions.push(OrderBookTransaction::Cancel { owner: User::Bob, order: 1 });
+		transitions
+	}
+}
+
+#[test]
+fn sm_13_bid_rests_in_empty_book() {
+	let (end, trades) = Exchange::next_state_with_output(
+		&OrderBook::default(),
+		&OrderBookTransaction::PlaceBid { owner: User::Alice, price: 10, quantity: 5 },
+	);
+	let expected = OrderBook {
+		bids: BTreeMap::from([(
+			10,
+			VecDeque::from([Order { id: 0, owner: User::Alice, quantity: 5 }]),
+		)]),
+		next_order: 1,
+		..OrderBook::default()
+	};
+
+	assert_eq!(end, expected);
+	assert_eq!(trades, vec![]);
+}
+
+#[test]
+fn sm_13_orders_that_do_not_cross_both_rest() {
+	let end = Exchange::apply_all(
+		&OrderBook::default(),
+		&[
+			OrderBookTransaction::PlaceBid { owner: User::Alice, price: 9, quantity: 5 },
+			OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 10, quantity: 5 },
+		],
+	);
+
+	assert_eq!((end.best_bid(), end.best_ask()), (Some(9), Some(10)));
+}
+
+#[test]
+fn sm_13_crossing_ask_trades_at_resting_price() {
+	let start = Exchange::next_state(
+		&OrderBook::default(),
+		&OrderBookTransaction::PlaceBid { owner: User::Alice, price: 10, quantity: 5 },
+	);
+	let (end, trades) = Exchange::next_state_with_output(
+		&start,
+		&OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 8, quantity: 5 },
+	);
+
+	assert_eq!(trades, vec![Trade { bid: 0, ask: 1, price: 10, quantity: 5 }]);
+	assert_eq!(end, OrderBook { next_order: 2, ..OrderBook::default() });
+}
+
+#[test]
+fn sm_13_partial_fill_leaves_remainder_resting() {
+	let start = Exchange::next_state(
+		&OrderBook::default(),
+		&OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 10, quantity: 3 },
+	);
+	let (end, trades) = Exchange::next_state_with_output(
+		&start,
+		&OrderBookTransaction::PlaceBid { owner: User::Alice, price: 11, quantity: 5 },
+	);
+
+	assert_eq!(trades, vec![Trade { bid: 1, ask: 0, price: 10, quantity: 3 }]);
+	assert_eq!(end.best_ask(), None);
+	assert_eq!(
+		end.bids_at(11).collect::<Vec<_>>(),
+		vec![&Order { id: 1, owner: User::Alice, quantity: 2 }]
+	);
+}
+
+#[test]
+fn sm_13_sweeps_levels_best_price_first() {
+	let start = Exchange::apply_all(
+		&OrderBook::default(),
+		&[
+			OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 12, quantity: 1 },
+			OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 10, quantity: 1 },
+			OrderBookTransaction::PlaceAsk { owner: User::Charlie, price: 11, quantity: 1 },
+		],
+	);
+	let (end, trades) = Exchange::next_state_with_output(
+		&start,
+		&OrderBookTransaction::PlaceBid { owner: User::Alice, price: 11, quantity: 5 },
+	);
+
+	assert_eq!(
+		trades,
+		vec![
+			Trade { bid: 3, ask: 1, price: 10, quantity: 1 },
+			Trade { bid: 3, ask: 2, price: 11, quantity: 1 },
+		]
+	);
+	assert_eq!((end.best_bid(), end.best_ask()), (Some(11), Some(12)));
+}
+
+#[test]
+fn sm_13_oldest_order_at_a_price_fills_first() {
+	let start = Exchange::apply_all(
+		&OrderBook::default(),
+		&[
+			OrderBookTransaction::PlaceBid { owner: User::Alice, price: 10, quantity: 2 },
+			OrderBookTransaction::PlaceBid { owner: User::Charlie, price: 10, quantity: 2 },
+		],
+	);
+	let (end, trades) = Exchange::next_state_with_output(
+		&start,
+		&OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 10, quantity: 3 },
+	);
+
+	assert_eq!(
+		trades,
+		vec![
+			Trade { bid: 0, ask: 2, price: 10, quantity: 2 },
+			Trade { bid: 1, ask: 2, price: 10, quantity: 1 },
+		]
+	);
+	assert_eq!(
+		end.bids_at(10).collect::<Vec<_>>(),
+		vec![&Order { id: 1, owner: User::Charlie, quantity: 1 }]
+	);
+}
+
+#[test]
+fn sm_13_cancel_removes_order() {
+	let start = Exchange::apply_all(
+		&OrderBook::default(),
+		&[
+			OrderBookTransaction::PlaceBid { owner: User::Alice, price: 10, quantity: 2 },
+			OrderBookTransaction::PlaceBid { owner: User::Alice, price: 9, quantity: 2 },
+		],
+	);
+	let end = Exchange::next_state(
+		&start,
+		&OrderBookTransaction::Cancel { owner: User::Alice, order: 0 },
+	);
+
+	assert_eq!(end.best_bid(), Some(9));
+	assert_eq!(end.bids_at(10).count(), 0);
+	assert_eq!(Exchange::check(&end), Ok(()));
+}
+
+#[test]
+fn sm_13_only_owner_can_cancel() {
+	let start = Exchange::next_state(
+		&OrderBook::default(),
+		&OrderBookTransaction::PlaceBid { owner: User::Alice, price: 10, quantity: 2 },
+	);
+	let end =
+		Exchange::next_state(&start, &OrderBookTransaction::Cancel { owner: User::Bob, order: 0 });
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_13_zero_quantity_is_ignored() {
+	let start = OrderBook::default();
+	let end = Exchange::next_state(
+		&start,
+		&OrderBookTransaction::PlaceAsk { owner: User::Bob, price: 10, quantity: 0 },
+	);
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_13_invariant_catches_crossed_book() {
+	let crossed = OrderBook {
+		bids: BTreeMap::from([(
+			10,
+			VecDeque::from([Order { id: 0, owner: User::Alice, quantity: 1 }]),
+		)]),
+		asks: BTreeMap::from([(
+			10,
+			VecDeque::from([Order { id: 1, owner: User::Bob, quantity: 1 }]),
+		)]),
+		next_order: 2,
+	};
+	let empty_level =
+		OrderBook { asks: BTreeMap::from([(10, VecDeque::new())]), ..OrderBook::default() };
+
+	assert_eq!(Exchange::check(&crossed), Err(OrderBookViolation::Crossed));
+	assert_eq!(Exchange::check(&empty_level), Err(OrderBookViolation::EmptyLevel));
+}
+
+#[test]
+fn sm_13_model_check_book_never_crosses() {
+	use super::model_checker::check_invariants;
+
+	assert!(check_invariants::<Exchange>(&OrderBook::default(), 3).is_ok());
+}
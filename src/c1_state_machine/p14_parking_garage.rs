@@ -0,0 +1,313 @@
+//! A parking garage lets cars in through an entry gate, which hands out a ticket. Before leaving,
+//! the driver pays for the time they were parked, and the exit gate only opens for a paid ticket.
+//! The garage has a fixed number of spaces, so once it is full the entry gate stays shut.
+//!
+//! This is a gentle warm up for the ATM in part 3. Like the ATM, the garage reacts to the passage
+//! of time, which reaches it as tick transitions rather than by reading a clock.
+
+use super::{EnumerableTransitions, Invariant, StateMachine, TimedStateMachine, TryStateMachine};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+/// How much each started hour of parking costs
+pub const HOURLY_RATE: u64 = 3;
+
+/// Drivers who leave again within this long don't pay anything
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Something that can happen at the garage
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum GarageAction {
+	/// A car pulls up to the entry gate and takes a ticket.
+	Arrive,
+	/// The driver pays for the given ticket at the pay station.
+	Pay { ticket: u64 },
+	/// A car pulls up to the exit gate with the given ticket.
+	Exit { ticket: u64 },
+	/// Some time has passed.
+	Tick(Duration),
+}
+
+/// A ticket handed out at the entry gate
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ticket {
+	/// When the car came in
+	pub entered: Duration,
+	/// Whether the ticket has been paid for
+	pub paid: bool,
+}
+
+/// The garage
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Garage {
+	/// How many cars fit in the garage
+	capacity: usize,
+	/// The tickets of the cars inside, by ticket number
+	tickets: BTreeMap<u64, Ticket>,
+	/// The number of the next ticket
+	next_ticket: u64,
+	/// How long the garage has been open
+	now: Duration,
+	/// How much money the pay station has taken
+	takings: u64,
+}
+
+impl Garage {
+	/// An empty garage with room for the given number of cars
+	pub fn new(capacity: usize) -> Self {
+		Garage {
+			capacity,
+			tickets: BTreeMap::new(),
+			next_ticket: 0,
+			now: Duration::ZERO,
+			takings: 0,
+		}
+	}
+
+	/// How many cars are inside
+	pub fn cars_inside(&self) -> usize {
+		self.tickets.len()
+	}
+
+	/// How much money the pay station has taken
+	pub fn takings(&self) -> u64 {
+		self.takings
+	}
+
+	/// How much the given ticket costs if it is paid for now, or `None` if no car inside has it
+	pub fn fee(&self, ticket: u64) -> Option<u64> {
+		let parked = self.now - self.tickets.get(&ticket)?.entered;
+		Some(fee(parked))
+	}
+}
+
+/// How much it costs to park for the given time.
+pub fn fee(parked: Duration) -> u64 {
+	if parked <= GRACE_PERIOD {
+		return 0
+	}
+	let hour = Duration::from_secs(60 * 60);
+	let started_hours = parked.as_secs().div_ceil(hour.as_secs());
+	started_hours * HOURLY_RATE
+}
+
+/// The reasons the garage may refuse an action
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum GarageError {
+	/// There is no room left, so the entry gate stays shut.
+	Full,
+	/// No car inside has that ticket.
+	UnknownTicket,
+	/// The ticket has already been paid for.
+	AlreadyPaid,
+	/// The ticket hasn't been paid for, so the exit gate stays shut.
+	NotPaid,
+}
+
+/// The state machine running the gates and the pay station
+pub struct ParkingGarage;
+
+impl StateMachine for ParkingGarage {
+	type State = Garage;
+	type Transition = GarageAction;
+
+	fn next_state(starting_state: &Garage, t: &GarageAction) -> Garage {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+
+	fn human_name() -> String {
+		"Parking garage".into()
+	}
+}
+
+impl TryStateMachine for ParkingGarage {
+	type Error = GarageError;
+
+	fn try_next_state(starting_state: &Garage, t: &GarageAction) -> Result<Garage, GarageError> {
+		let mut garage = starting_state.clone();
+		match t {
+			GarageAction::Arrive => {
+				if garage.cars_inside() >= garage.capacity {
+					return Err(GarageError::Full)
+				}
+				let ticket = Ticket { entered: garage.now, paid: false };
+				garage.tickets.insert(garage.next_ticket, ticket);
+				garage.next_ticket += 1;
+			},
+			GarageAction::Pay { ticket } => {
+				let fee = garage.fee(*ticket).ok_or(GarageError::UnknownTicket)?;
+				let paid = &mut garage.tickets.get_mut(ticket).expect("fee was found").paid;
+				if *paid {
+					return Err(GarageError::AlreadyPaid)
+				}
+				*paid = true;
+				garage.takings += fee;
+			},
+			GarageAction::Exit { ticket } => {
+				let found = garage.tickets.get(ticket).ok_or(GarageError::UnknownTicket)?;
+				if !found.paid {
+					return Err(GarageError::NotPaid)
+				}
+				garage.tickets.remove(ticket);
+			},
+			GarageAction::Tick(elapsed) => garage.now = garage.now.saturating_add(*elapsed),
+		}
+		Ok(garage)
+	}
+}
+
+impl TimedStateMachine for ParkingGarage {
+	fn tick(elapsed: Duration) -> GarageAction {
+		GarageAction::Tick(elapsed)
+	}
+}
+
+/// Model checking only looks at the first few tickets, and at an hour passing at a time.
+impl EnumerableTransitions for ParkingGarage {
+	fn transitions() -> Vec<GarageAction> {
+		let mut transitions =
+			vec![GarageAction::Arrive, GarageAction::Tick(Duration::from_secs(60 * 60))];
+		for ticket in 0..3 {
+			transitions.push(GarageAction::Pay { ticket });
+			transitions.push(GarageAction::Exit { ticket });
+		}
+		transitions
+	}
+}
+
+/// There are never more cars inside than the garage has room for. The violation is how many cars
+/// too many there are.
+impl Invariant for ParkingGarage {
+	type Violation = usize;
+
+	fn check(garage: &Garage) -> Result<(), usize> {
+		match garage.cars_inside().checked_sub(garage.capacity) {
+			Some(extra) if extra > 0 => Err(extra),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[test]
+fn sm_14_arrive_takes_ticket() {
+	let end = ParkingGarage::next_state(&Garage::new(2), &GarageAction::Arrive);
+	let expected = Garage {
+		tickets: BTreeMap::from([(0, Ticket { entered: Duration::ZERO, paid: false })]),
+		next_ticket: 1,
+		..Garage::new(2)
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_14_full_garage_keeps_gate_shut() {
+	let full =
+		ParkingGarage::apply_all(&Garage::new(2), &[GarageAction::Arrive, GarageAction::Arrive]);
+
+	assert_eq!(full.cars_inside(), 2);
+	assert_eq!(ParkingGarage::try_next_state(&full, &GarageAction::Arrive), Err(GarageError::Full));
+	assert_eq!(ParkingGarage::next_state(&full, &GarageAction::Arrive), full);
+}
+
+#[test]
+fn sm_14_exit_needs_payment() {
+	let parked = ParkingGarage::next_state(&Garage::new(2), &GarageAction::Arrive);
+
+	assert_eq!(
+		ParkingGarage::try_next_state(&parked, &GarageAction::Exit { ticket: 0 }),
+		Err(GarageError::NotPaid)
+	);
+
+	let paid = ParkingGarage::next_state(&parked, &GarageAction::Pay { ticket: 0 });
+	let left = ParkingGarage::next_state(&paid, &GarageAction::Exit { ticket: 0 });
+	assert_eq!(left, Garage { next_ticket: 1, ..Garage::new(2) });
+}
+
+#[test]
+fn sm_14_unknown_and_double_payments() {
+	let paid = ParkingGarage::apply_all(
+		&Garage::new(2),
+		&[GarageAction::Arrive, GarageAction::Pay { ticket: 0 }],
+	);
+
+	assert_eq!(
+		ParkingGarage::try_next_state(&paid, &GarageAction::Pay { ticket: 0 }),
+		Err(GarageError::AlreadyPaid)
+	);
+	assert_eq!(
+		ParkingGarage::try_next_state(&paid, &GarageAction::Pay { ticket: 1 }),
+		Err(GarageError::UnknownTicket)
+	);
+	assert_eq!(
+		ParkingGarage::try_next_state(&paid, &GarageAction::Exit { ticket: 1 }),
+		Err(GarageError::UnknownTicket)
+	);
+}
+
+#[test]
+fn sm_14_leaving_frees_a_space() {
+	let full = ParkingGarage::apply_all(&Garage::new(1), &[GarageAction::Arrive]);
+	let end = ParkingGarage::apply_all(
+		&full,
+		&[GarageAction::Pay { ticket: 0 }, GarageAction::Exit { ticket: 0 }, GarageAction::Arrive],
+	);
+
+	assert_eq!(end.cars_inside(), 1);
+	assert!(end.tickets.contains_key(&1));
+}
+
+#[test]
+fn sm_14_fee_for_each_started_hour() {
+	assert_eq!(fee(Duration::from_secs(5 * 60)), 0);
+	assert_eq!(fee(GRACE_PERIOD), 0);
+	assert_eq!(fee(GRACE_PERIOD + Duration::from_secs(1)), HOURLY_RATE);
+	assert_eq!(fee(Duration::from_secs(60 * 60)), HOURLY_RATE);
+	assert_eq!(fee(Duration::from_secs(60 * 60 + 1)), 2 * HOURLY_RATE);
+}
+
+#[test]
+fn sm_14_timed_fee_with_mock_clock() {
+	use super::clock::{MockClock, Ticker};
+
+	let mut ticker = Ticker::new(MockClock::new());
+	let mut garage = Garage::new(5);
+
+	ticker.clock().advance(Duration::from_secs(30 * 60));
+	garage = ticker.tick::<ParkingGarage>(&garage);
+	garage = ParkingGarage::next_state(&garage, &GarageAction::Arrive);
+
+	ticker.clock().advance(Duration::from_secs(90 * 60));
+	garage = ticker.tick::<ParkingGarage>(&garage);
+	assert_eq!(garage.fee(0), Some(2 * HOURLY_RATE));
+
+	garage = ParkingGarage::next_state(&garage, &GarageAction::Pay { ticket: 0 });
+	assert_eq!(garage.takings(), 2 * HOURLY_RATE);
+}
+
+#[test]
+fn sm_14_invariant_counts_extra_cars() {
+	let overfull = ParkingGarage::apply_all(&Garage::new(3), &vec![GarageAction::Arrive; 3]);
+	let overfull = Garage { capacity: 1, ..overfull };
+
+	assert_eq!(ParkingGarage::check(&overfull), Err(2));
+	assert_eq!(ParkingGarage::check(&Garage::new(0)), Ok(()));
+}
+
+#[test]
+fn sm_14_never_more_cars_than_spaces() {
+	use super::InvariantChecked;
+
+	let mut garage = Garage::new(2);
+	for _ in 0..5 {
+		garage = InvariantChecked::<ParkingGarage>::next_state(&garage, &GarageAction::Arrive);
+	}
+	assert_eq!(garage.cars_inside(), 2);
+}
+
+#[test]
+fn sm_14_model_check_capacity() {
+	use super::model_checker::check_invariants;
+
+	assert!(check_invariants::<ParkingGarage>(&Garage::new(2), 5).is_ok());
+}
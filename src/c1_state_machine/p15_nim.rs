@@ -0,0 +1,267 @@
+//! Nim is a game for two players, played with a few heaps of stones. The players take turns, and
+//! on each turn a player takes one or more stones from a single heap. Whoever takes the last stone
+//! wins.
+//!
+//! Each move is a transition. Not every move is allowed though: players must wait their turn, and
+//! can't take more stones than a heap has, so the machine rejects illegal moves with an error.
+//! Once the last stone is gone the game is over, and the final state records who won.
+
+use super::{EnumerableTransitions, Invariant, StateMachine, TryStateMachine, User};
+use serde::{Deserialize, Serialize};
+
+/// A move in the game
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Move {
+	/// The player making the move
+	pub by: User,
+	/// The index of the heap to take stones from
+	pub heap: usize,
+	/// How many stones to take
+	pub take: u32,
+}
+
+/// The game, in play or finished
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Game {
+	/// How many stones are left in each heap
+	heaps: Vec<u32>,
+	/// The player whose turn it is
+	to_move: User,
+	/// The other player
+	waiting: User,
+	/// The player who took the last stone, once the game is over
+	winner: Option<User>,
+}
+
+impl Game {
+	/// A new game with the given heaps, where `first` moves first against `second`. Fails if both
+	/// players are the same user.
+	pub fn new(heaps: Vec<u32>, first: User, second: User) -> Result<Self, NimError> {
+		if first == second {
+			return Err(NimError::SamePlayer)
+		}
+		let winner = None;
+		let game = Game { heaps, to_move: first, waiting: second, winner };
+		// With no stones at all, the first player can't move, so the second has already won.
+		Ok(if game.stones() == 0 { Game { winner: Some(second), ..game } } else { game })
+	}
+
+	/// How many stones are left in each heap
+	pub fn heaps(&self) -> &[u32] {
+		&self.heaps
+	}
+
+	/// The player whose turn it is
+	pub fn to_move(&self) -> User {
+		self.to_move
+	}
+
+	/// The winner, or `None` while the game is still being played
+	pub fn winner(&self) -> Option<User> {
+		self.winner
+	}
+
+	/// How many stones are left altogether
+	pub fn stones(&self) -> u32 {
+		self.heaps.iter().sum()
+	}
+}
+
+/// The XOR of every heap size. The player to move can force a win exactly when this is not zero.
+pub fn nim_sum(heaps: &[u32]) -> u32 {
+	heaps.iter().fold(0, |sum, heap| sum ^ heap)
+}
+
+/// A move that leaves the heaps with a nim sum of zero, if there is one. Playing such a move every
+/// turn is a winning strategy.
+pub fn winning_move(game: &Game) -> Option<Move> {
+	let sum = nim_sum(&game.heaps);
+	if game.winner.is_some() || sum == 0 {
+		return None
+	}
+	game.heaps.iter().enumerate().find_map(|(heap, &stones)| {
+		let target = stones ^ sum;
+		(target < stones).then(|| Move { by: game.to_move, heap, take: stones - target })
+	})
+}
+
+/// The reasons a move may be illegal
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NimError {
+	/// A game needs two different players.
+	SamePlayer,
+	/// The game is already over.
+	GameOver,
+	/// It is the other player's turn, or the mover isn't playing at all.
+	NotYourTurn,
+	/// There is no heap with that index.
+	NoSuchHeap,
+	/// A move must take at least one stone.
+	TakeNothing,
+	/// The heap doesn't have that many stones.
+	NotEnoughStones,
+}
+
+/// The state machine refereeing a game of Nim
+pub struct Nim;
+
+impl StateMachine for Nim {
+	type State = Game;
+	type Transition = Move;
+
+	fn next_state(starting_state: &Game, t: &Move) -> Game {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+
+	fn human_name() -> String {
+		"Nim".into()
+	}
+}
+
+impl TryStateMachine for Nim {
+	type Error = NimError;
+
+	fn try_next_state(starting_state: &Game, t: &Move) -> Result<Game, NimError> {
+		if starting_state.winner.is_some() {
+			return Err(NimError::GameOver)
+		}
+		if t.by != starting_state.to_move {
+			return Err(NimError::NotYourTurn)
+		}
+		let stones = *starting_state.heaps.get(t.heap).ok_or(NimError::NoSuchHeap)?;
+		if t.take == 0 {
+			return Err(NimError::TakeNothing)
+		}
+		if t.take > stones {
+			return Err(NimError::NotEnoughStones)
+		}
+
+		let mut game = starting_state.clone();
+		game.heaps[t.heap] -= t.take;
+		if game.stones() == 0 {
+			game.winner = Some(t.by);
+		} else {
+			std::mem::swap(&mut game.to_move, &mut game.waiting);
+		}
+		Ok(game)
+	}
+}
+
+/// Model checking only looks at games between Alice and Bob, with up to three heaps of at most
+/// three stones.
+impl EnumerableTransitions for Nim {
+	fn transitions() -> Vec<Move> {
+		let mut transitions = Vec::new();
+		for by in [User::Alice, User::Bob] {
+			for heap in 0..3 {
+				for take in 1..=3 {
+					transitions.push(Move { by, heap, take });
+				}
+			}
+		}
+		transitions
+	}
+}
+
+/// The ways a game can be inconsistent
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NimViolation {
+	/// The stones are all gone but nobody has won, or somebody has won while stones are left.
+	WinnerMismatch,
+	/// Both players are the same user.
+	SamePlayer,
+}
+
+/// The game is over exactly when the last stone is gone, and it is always between two players.
+impl Invariant for Nim {
+	type Violation = NimViolation;
+
+	fn check(game: &Game) -> Result<(), NimViolation> {
+		if game.to_move == game.waiting {
+			return Err(NimViolation::SamePlayer)
+		}
+		if (game.stones() == 0) != game.winner.is_some() {
+			return Err(NimViolation::WinnerMismatch)
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn sm_15_move_takes_stones_and_passes_turn() {
+	let start = Game::new(vec![3, 4, 5], User::Alice, User::Bob).unwrap();
+	let end = Nim::next_state(&start, &Move { by: User::Alice, heap: 1, take: 2 });
+
+	assert_eq!(end.heaps(), &[3, 2, 5]);
+	assert_eq!(end.to_move(), User::Bob);
+	assert_eq!(end.winner(), None);
+}
+
+#[test]
+fn sm_15_taking_last_stone_wins() {
+	let start = Game::new(vec![1, 2], User::Alice, User::Bob).unwrap();
+	let end = Nim::apply_all(
+		&start,
+		&[Move { by: User::Alice, heap: 1, take: 2 }, Move { by: User::Bob, heap: 0, take: 1 }],
+	);
+
+	assert_eq!(end.heaps(), &[0, 0]);
+	assert_eq!(end.winner(), Some(User::Bob));
+	assert_eq!(
+		Nim::try_next_state(&end, &Move { by: User::Bob, heap: 0, take: 1 }),
+		Err(NimError::GameOver)
+	);
+}
+
+#[test]
+fn sm_15_illegal_moves_rejected() {
+	let start = Game::new(vec![3, 4], User::Alice, User::Bob).unwrap();
+	let illegal = |by, heap, take| Nim::try_next_state(&start, &Move { by, heap, take });
+
+	assert_eq!(illegal(User::Bob, 0, 1), Err(NimError::NotYourTurn));
+	assert_eq!(illegal(User::Charlie, 0, 1), Err(NimError::NotYourTurn));
+	assert_eq!(illegal(User::Alice, 2, 1), Err(NimError::NoSuchHeap));
+	assert_eq!(illegal(User::Alice, 0, 0), Err(NimError::TakeNothing));
+	assert_eq!(illegal(User::Alice, 0, 4), Err(NimError::NotEnoughStones));
+	assert_eq!(Nim::next_state(&start, &Move { by: User::Alice, heap: 0, take: 4 }), start);
+}
+
+#[test]
+fn sm_15_new_game_checks_players() {
+	assert_eq!(Game::new(vec![1], User::Alice, User::Alice), Err(NimError::SamePlayer));
+	assert_eq!(Game::new(vec![0, 0], User::Alice, User::Bob).unwrap().winner(), Some(User::Bob));
+}
+
+#[test]
+fn sm_15_winning_strategy_beats_greedy_opponent() {
+	let mut game = Game::new(vec![3, 4, 5], User::Alice, User::Bob).unwrap();
+
+	while game.winner().is_none() {
+		let next = match game.to_move() {
+			User::Alice => winning_move(&game).expect("alice always has a winning move"),
+			_ => {
+				let heap = game.heaps().iter().position(|&stones| stones > 0).unwrap();
+				Move { by: User::Bob, heap, take: 1 }
+			},
+		};
+		game = Nim::try_next_state(&game, &next).unwrap();
+	}
+
+	assert_eq!(game.winner(), Some(User::Alice));
+}
+
+#[test]
+fn sm_15_no_winning_move_from_zero_nim_sum() {
+	let game = Game::new(vec![1, 2, 3], User::Alice, User::Bob).unwrap();
+
+	assert_eq!(nim_sum(game.heaps()), 0);
+	assert_eq!(winning_move(&game), None);
+}
+
+#[test]
+fn sm_15_model_check_winner() {
+	use super::model_checker::check_invariants;
+
+	let start = Game::new(vec![1, 2, 3], User::Alice, User::Bob).unwrap();
+	assert!(check_invariants::<Nim>(&start, 6).is_ok());
+}
@@ -0,0 +1,530 @@
+//! A bridge moves tokens from one chain to another. Tokens can't really leave the chain they were
+//! created on, so instead the bridge locks them up on the origin chain and mints the same number
+//! of wrapped tokens on the other chain. Going back, wrapped tokens are burned and the originals
+//! are unlocked.
+//!
+//! Each chain is its own state machine, and neither can see the other's state. They are coupled
+//! only through their outputs. Locking tokens on the origin emits a message, and somebody relays
+//! that message to the wrapped chain as a transition. Each message carries a nonce, so relaying
+//! it a second time can't mint the same tokens twice.
+//!
+//! Anybody can relay a message, so a chain can't take the relayer's word that the message was
+//! really sent. Each chain keeps an outbox of every message it has emitted, and each chain keeps
+//! its own copy of the other chain's outbox, which it is told about with a `Sync` transition. A
+//! relayed message is only honoured if it sits in that copy under its nonce, so a fabricated one
+//! is refused. The `Sync` itself is trusted: whoever submits it stands in for a light client that
+//! follows the other chain's consensus, which is how a real bridge would learn the outbox. All
+//! this machine checks is that a later `Sync` extends the earlier one rather than rewriting it.
+
+use super::{Invariant, MealyStateMachine, StateMachine, TryStateMachine, User};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// A message from one side of the bridge to the other, saying that tokens were locked or burned
+/// and who should get them on the other side
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct BridgeMessage {
+	/// Numbers the messages sent from one chain, so each can only be relayed once
+	pub nonce: u64,
+	/// The user who gets the tokens on the other chain
+	pub to: User,
+	/// How many tokens
+	pub amount: u64,
+}
+
+/// The reasons either chain may refuse a transaction
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BridgeError {
+	/// Bridging nothing is not allowed.
+	ZeroAmount,
+	/// The sender doesn't have that many tokens.
+	InsufficientFunds,
+	/// This message has already been relayed.
+	AlreadyRelayed,
+	/// The bridge doesn't hold enough locked tokens to honour the message.
+	NotEnoughLocked,
+	/// The other chain never sent this message, as far as this chain has been told.
+	UnknownMessage,
+	/// The reported outbox doesn't extend the one reported before.
+	OutboxRewritten,
+}
+
+/// The chain where the tokens were created
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct OriginChain {
+	/// How many tokens each user holds
+	balances: HashMap<User, u64>,
+	/// How many tokens are locked in the bridge
+	locked: u64,
+	/// Every lock message this chain has sent, indexed by nonce
+	outbox: Vec<BridgeMessage>,
+	/// The wrapped chain's outbox, as far as this chain has been told
+	burns: Vec<BridgeMessage>,
+	/// The nonces of the burn messages already relayed here
+	unlocked: BTreeSet<u64>,
+	/// How many tokens exist. Every one is either held by a user or locked in the bridge.
+	supply: u64,
+}
+
+impl OriginChain {
+	/// A chain where the given users hold the given tokens, and nothing is locked yet
+	pub fn new(balances: HashMap<User, u64>) -> Self {
+		let supply = balances.values().sum();
+		OriginChain {
+			balances,
+			locked: 0,
+			outbox: Vec::new(),
+			burns: Vec::new(),
+			unlocked: BTreeSet::new(),
+			supply,
+		}
+	}
+
+	/// Every lock message this chain has sent
+	pub fn outbox(&self) -> &[BridgeMessage] {
+		&self.outbox
+	}
+
+	/// How many tokens the given user holds
+	pub fn balance(&self, user: User) -> u64 {
+		self.balances.get(&user).copied().unwrap_or(0)
+	}
+
+	/// How many tokens are locked in the bridge
+	pub fn locked(&self) -> u64 {
+		self.locked
+	}
+}
+
+/// A transaction on the origin chain
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum OriginTransaction {
+	/// Lock some of the sender's tokens so they can be minted on the other chain.
+	Lock { from: User, to: User, amount: u64 },
+	/// Relay a message saying tokens were burned on the other chain.
+	Unlock(BridgeMessage),
+	/// Report the wrapped chain's outbox so far. This is trusted.
+	Sync(Vec<BridgeMessage>),
+}
+
+/// The origin side of the bridge
+pub struct LockChain;
+
+impl StateMachine for LockChain {
+	type State = OriginChain;
+	type Transition = OriginTransaction;
+
+	fn next_state(starting_state: &OriginChain, t: &OriginTransaction) -> OriginChain {
+		Self::next_state_with_output(starting_state, t).0
+	}
+
+	fn human_name() -> String {
+		"Bridge origin chain".into()
+	}
+}
+
+impl TryStateMachine for LockChain {
+	type Error = BridgeError;
+
+	fn try_next_state(
+		starting_state: &OriginChain,
+		t: &OriginTransaction,
+	) -> Result<OriginChain, BridgeError> {
+		try_lock(starting_state, t).map(|(chain, _)| chain)
+	}
+}
+
+impl MealyStateMachine for LockChain {
+	type Output = BridgeMessage;
+
+	fn next_state_with_output(
+		starting_state: &OriginChain,
+		t: &OriginTransaction,
+	) -> (OriginChain, Vec<BridgeMessage>) {
+		try_lock(starting_state, t).unwrap_or_else(|_| (starting_state.clone(), Vec::new()))
+	}
+}
+
+/// Apply a transaction to the origin chain, along with the message to relay if tokens were locked
+fn try_lock(
+	starting_state: &OriginChain,
+	t: &OriginTransaction,
+) -> Result<(OriginChain, Vec<BridgeMessage>), BridgeError> {
+	let mut chain = starting_state.clone();
+	match t {
+		OriginTransaction::Lock { from, to, amount } => {
+			if *amount == 0 {
+				return Err(BridgeError::ZeroAmount)
+			}
+			let balance = chain.balances.entry(*from).or_insert(0);
+			*balance = balance.checked_sub(*amount).ok_or(BridgeError::InsufficientFunds)?;
+			chain.locked += amount;
+			let message = send(&mut chain.outbox, *to, *amount);
+			Ok((chain, vec![message]))
+		},
+		OriginTransaction::Unlock(message) => {
+			if chain.unlocked.contains(&message.nonce) {
+				return Err(BridgeError::AlreadyRelayed)
+			}
+			check_sent(&chain.burns, message)?;
+			chain.locked =
+				chain.locked.checked_sub(message.amount).ok_or(BridgeError::NotEnoughLocked)?;
+			chain.unlocked.insert(message.nonce);
+			*chain.balances.entry(message.to).or_insert(0) += message.amount;
+			Ok((chain, Vec::new()))
+		},
+		OriginTransaction::Sync(outbox) => {
+			sync(&mut chain.burns, outbox)?;
+			Ok((chain, Vec::new()))
+		},
+	}
+}
+
+/// Add a message to an outbox, numbered by its place there
+fn send(outbox: &mut Vec<BridgeMessage>, to: User, amount: u64) -> BridgeMessage {
+	let message = BridgeMessage { nonce: outbox.len() as u64, to, amount };
+	outbox.push(message.clone());
+	message
+}
+
+/// Whether the other chain's outbox, as this chain knows it, holds exactly this message
+fn check_sent(outbox: &[BridgeMessage], message: &BridgeMessage) -> Result<(), BridgeError> {
+	match usize::try_from(message.nonce).ok().and_then(|nonce| outbox.get(nonce)) {
+		Some(sent) if sent == message => Ok(()),
+		_ => Err(BridgeError::UnknownMessage),
+	}
+}
+
+/// Replace this chain's copy of the other chain's outbox, as long as nothing already known changes
+fn sync(known: &mut Vec<BridgeMessage>, reported: &[BridgeMessage]) -> Result<(), BridgeError> {
+	if !reported.starts_with(known) {
+		return Err(BridgeError::OutboxRewritten)
+	}
+	*known = reported.to_vec();
+	Ok(())
+}
+
+/// The chain where wrapped tokens live
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct WrappedChain {
+	/// How many wrapped tokens each user holds
+	balances: HashMap<User, u64>,
+	/// Every burn message this chain has sent, indexed by nonce
+	outbox: Vec<BridgeMessage>,
+	/// The origin chain's outbox, as far as this chain has been told
+	locks: Vec<BridgeMessage>,
+	/// The nonces of the lock messages already relayed here
+	minted: BTreeSet<u64>,
+	/// How many wrapped tokens exist
+	supply: u64,
+}
+
+impl WrappedChain {
+	/// How many wrapped tokens the given user holds
+	pub fn balance(&self, user: User) -> u64 {
+		self.balances.get(&user).copied().unwrap_or(0)
+	}
+
+	/// How many wrapped tokens exist
+	pub fn supply(&self) -> u64 {
+		self.supply
+	}
+
+	/// Every burn message this chain has sent
+	pub fn outbox(&self) -> &[BridgeMessage] {
+		&self.outbox
+	}
+}
+
+/// A transaction on the wrapped chain
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum WrappedTransaction {
+	/// Relay a message saying tokens were locked on the origin chain.
+	Mint(BridgeMessage),
+	/// Burn some of the sender's wrapped tokens so the originals can be unlocked.
+	Burn { from: User, to: User, amount: u64 },
+	/// Report the origin chain's outbox so far. This is trusted.
+	Sync(Vec<BridgeMessage>),
+}
+
+/// The wrapped side of the bridge
+pub struct MintChain;
+
+impl StateMachine for MintChain {
+	type State = WrappedChain;
+	type Transition = WrappedTransaction;
+
+	fn next_state(starting_state: &WrappedChain, t: &WrappedTransaction) -> WrappedChain {
+		Self::next_state_with_output(starting_state, t).0
+	}
+
+	fn human_name() -> String {
+		"Bridge wrapped chain".into()
+	}
+}
+
+impl TryStateMachine for MintChain {
+	type Error = BridgeError;
+
+	fn try_next_state(
+		starting_state: &WrappedChain,
+		t: &WrappedTransaction,
+	) -> Result<WrappedChain, BridgeError> {
+		try_mint(starting_state, t).map(|(chain, _)| chain)
+	}
+}
+
+impl MealyStateMachine for MintChain {
+	type Output = BridgeMessage;
+
+	fn next_state_with_output(
+		starting_state: &WrappedChain,
+		t: &WrappedTransaction,
+	) -> (WrappedChain, Vec<BridgeMessage>) {
+		try_mint(starting_state, t).unwrap_or_else(|_| (starting_state.clone(), Vec::new()))
+	}
+}
+
+/// Apply a transaction to the wrapped chain, along with the message to relay if tokens were burned
+fn try_mint(
+	starting_state: &WrappedChain,
+	t: &WrappedTransaction,
+) -> Result<(WrappedChain, Vec<BridgeMessage>), BridgeError> {
+	let mut chain = starting_state.clone();
+	match t {
+		WrappedTransaction::Mint(message) => {
+			if chain.minted.contains(&message.nonce) {
+				return Err(BridgeError::AlreadyRelayed)
+			}
+			check_sent(&chain.locks, message)?;
+			chain.minted.insert(message.nonce);
+			*chain.balances.entry(message.to).or_insert(0) += message.amount;
+			chain.supply += message.amount;
+			Ok((chain, Vec::new()))
+		},
+		WrappedTransaction::Burn { from, to, amount } => {
+			if *amount == 0 {
+				return Err(BridgeError::ZeroAmount)
+			}
+			let balance = chain.balances.entry(*from).or_insert(0);
+			*balance = balance.checked_sub(*amount).ok_or(BridgeError::InsufficientFunds)?;
+			chain.supply -= amount;
+			let message = send(&mut chain.outbox, *to, *amount);
+			Ok((chain, vec![message]))
+		},
+		WrappedTransaction::Sync(outbox) => {
+			sync(&mut chain.locks, outbox)?;
+			Ok((chain, Vec::new()))
+		},
+	}
+}
+
+/// Every token on the origin chain is either held by a user or locked in the bridge.
+impl Invariant for LockChain {
+	type Violation = u64;
+
+	fn check(chain: &OriginChain) -> Result<(), u64> {
+		let total = chain.balances.values().sum::<u64>() + chain.locked;
+		if total == chain.supply {
+			Ok(())
+		} else {
+			Err(total)
+		}
+	}
+}
+
+/// The wrapped supply is exactly what the users hold between them.
+impl Invariant for MintChain {
+	type Violation = u64;
+
+	fn check(chain: &WrappedChain) -> Result<(), u64> {
+		let total = chain.balances.values().sum::<u64>();
+		if total == chain.supply {
+			Ok(())
+		} else {
+			Err(total)
+		}
+	}
+}
+
+/// Whether every wrapped token is backed by a token locked on the origin chain. While messages are
+/// in flight, more may be locked than minted, but never the other way around.
+pub fn fully_backed(origin: &OriginChain, wrapped: &WrappedChain) -> bool {
+	wrapped.supply <= origin.locked
+}
+
+/// An origin chain where Alice has 100 tokens and Bob has 20.
+#[cfg(test)]
+fn alice_and_bob() -> OriginChain {
+	OriginChain::new(HashMap::from([(User::Alice, 100), (User::Bob, 20)]))
+}
+
+#[test]
+fn sm_16_lock_emits_message() {
+	let (origin, messages) = LockChain::next_state_with_output(
+		&alice_and_bob(),
+		&OriginTransaction::Lock { from: User::Alice, to: User::Bob, amount: 30 },
+	);
+
+	assert_eq!(origin.balance(User::Alice), 70);
+	assert_eq!(origin.locked(), 30);
+	assert_eq!(messages, vec![BridgeMessage { nonce: 0, to: User::Bob, amount: 30 }]);
+}
+
+#[test]
+fn sm_16_relayed_lock_mints_wrapped_tokens() {
+	let (origin, messages) = LockChain::next_state_with_output(
+		&alice_and_bob(),
+		&OriginTransaction::Lock { from: User::Alice, to: User::Bob, amount: 30 },
+	);
+	let relayed: Vec<_> = std::iter::once(WrappedTransaction::Sync(origin.outbox().to_vec()))
+		.chain(messages.into_iter().map(WrappedTransaction::Mint))
+		.collect();
+	let wrapped = MintChain::apply_all(&WrappedChain::default(), &relayed);
+
+	assert_eq!(wrapped.balance(User::Bob), 30);
+	assert_eq!(wrapped.supply(), 30);
+	assert!(fully_backed(&origin, &wrapped));
+}
+
+#[test]
+fn sm_16_relaying_twice_mints_once() {
+	let message = BridgeMessage { nonce: 0, to: User::Bob, amount: 30 };
+	let wrapped = MintChain::apply_all(
+		&WrappedChain::default(),
+		&[
+			WrappedTransaction::Sync(vec![message.clone()]),
+			WrappedTransaction::Mint(message.clone()),
+		],
+	);
+
+	assert_eq!(
+		MintChain::try_next_state(&wrapped, &WrappedTransaction::Mint(message)),
+		Err(BridgeError::AlreadyRelayed)
+	);
+}
+
+#[test]
+fn sm_16_burn_and_unlock_round_trip() {
+	let (origin, locks) = LockChain::next_state_with_output(
+		&alice_and_bob(),
+		&OriginTransaction::Lock { from: User::Alice, to: User::Alice, amount: 40 },
+	);
+	let wrapped = MintChain::apply_all(
+		&WrappedChain::default(),
+		&[
+			WrappedTransaction::Sync(origin.outbox().to_vec()),
+			WrappedTransaction::Mint(locks[0].clone()),
+		],
+	);
+
+	let (wrapped, burns) = MintChain::next_state_with_output(
+		&wrapped,
+		&WrappedTransaction::Burn { from: User::Alice, to: User::Bob, amount: 15 },
+	);
+	assert_eq!(wrapped.supply(), 25);
+	assert_eq!(burns, vec![BridgeMessage { nonce: 0, to: User::Bob, amount: 15 }]);
+
+	let origin = LockChain::apply_all(
+		&origin,
+		&[
+			OriginTransaction::Sync(wrapped.outbox().to_vec()),
+			OriginTransaction::Unlock(burns[0].clone()),
+		],
+	);
+	assert_eq!(origin.balance(User::Alice), 60);
+	assert_eq!(origin.balance(User::Bob), 35);
+	assert_eq!(origin.locked(), 25);
+	assert!(fully_backed(&origin, &wrapped));
+	assert_eq!(
+		LockChain::try_next_state(&origin, &OriginTransaction::Unlock(burns[0].clone())),
+		Err(BridgeError::AlreadyRelayed)
+	);
+}
+
+#[test]
+fn sm_16_rejected_transactions() {
+	let lock = |from, amount| {
+		LockChain::try_next_state(
+			&alice_and_bob(),
+			&OriginTransaction::Lock { from, to: User::Alice, amount },
+		)
+	};
+	assert_eq!(lock(User::Bob, 21), Err(BridgeError::InsufficientFunds));
+	assert_eq!(lock(User::Charlie, 1), Err(BridgeError::InsufficientFunds));
+	assert_eq!(lock(User::Alice, 0), Err(BridgeError::ZeroAmount));
+
+	let burned = BridgeMessage { nonce: 0, to: User::Charlie, amount: 5 };
+	let synced =
+		LockChain::next_state(&alice_and_bob(), &OriginTransaction::Sync(vec![burned.clone()]));
+	assert_eq!(
+		LockChain::try_next_state(&synced, &OriginTransaction::Unlock(burned)),
+		Err(BridgeError::NotEnoughLocked)
+	);
+
+	let burn = WrappedTransaction::Burn { from: User::Alice, to: User::Alice, amount: 1 };
+	assert_eq!(
+		MintChain::try_next_state(&WrappedChain::default(), &burn),
+		Err(BridgeError::InsufficientFunds)
+	);
+	assert_eq!(MintChain::next_state_with_output(&WrappedChain::default(), &burn).1, vec![]);
+}
+
+#[test]
+fn sm_16_supplies_conserved() {
+	let (origin, locks) = LockChain::apply_all_with_output(
+		&alice_and_bob(),
+		&[
+			OriginTransaction::Lock { from: User::Alice, to: User::Bob, amount: 50 },
+			OriginTransaction::Lock { from: User::Bob, to: User::Charlie, amount: 20 },
+		],
+	);
+	let relayed: Vec<_> = std::iter::once(WrappedTransaction::Sync(origin.outbox().to_vec()))
+		.chain(locks.into_iter().map(WrappedTransaction::Mint))
+		.collect();
+	let wrapped = MintChain::apply_all(&WrappedChain::default(), &relayed);
+
+	assert_eq!(LockChain::check(&origin), Ok(()));
+	assert_eq!(MintChain::check(&wrapped), Ok(()));
+	assert_eq!(wrapped.supply(), origin.locked());
+}
+
+#[test]
+fn sm_16_fabricated_messages_are_refused() {
+	let (origin, locks) = LockChain::next_state_with_output(
+		&alice_and_bob(),
+		&OriginTransaction::Lock { from: User::Alice, to: User::Bob, amount: 30 },
+	);
+	let mint = |wrapped: &WrappedChain, nonce, to, amount| {
+		MintChain::try_next_state(
+			wrapped,
+			&WrappedTransaction::Mint(BridgeMessage { nonce, to, amount }),
+		)
+	};
+
+	// Until the wrapped chain is told about the origin's outbox, nothing can be minted.
+	assert_eq!(mint(&WrappedChain::default(), 0, User::Bob, 30), Err(BridgeError::UnknownMessage));
+
+	let wrapped = MintChain::next_state(
+		&WrappedChain::default(),
+		&WrappedTransaction::Sync(origin.outbox().to_vec()),
+	);
+	assert_eq!(mint(&wrapped, 0, User::Bob, 3000), Err(BridgeError::UnknownMessage));
+	assert_eq!(mint(&wrapped, 0, User::Charlie, 30), Err(BridgeError::UnknownMessage));
+	assert_eq!(mint(&wrapped, 1, User::Bob, 30), Err(BridgeError::UnknownMessage));
+	assert_eq!(
+		MintChain::try_next_state(
+			&wrapped,
+			&WrappedTransaction::Sync(vec![BridgeMessage {
+				nonce: 0,
+				to: User::Charlie,
+				amount: 30
+			}])
+		),
+		Err(BridgeError::OutboxRewritten)
+	);
+
+	let wrapped = MintChain::next_state(&wrapped, &WrappedTransaction::Mint(locks[0].clone()));
+	assert_eq!(wrapped.balance(User::Bob), 30);
+	assert!(fully_backed(&origin, &wrapped));
+}
@@ -0,0 +1,213 @@
+//! A rate limiter protects a service from being flooded with requests. The token bucket is one of
+//! the simplest ways to build one. The bucket holds tokens, and every request has to take tokens
+//! out of it. Tokens drip back in as time passes, up to the size of the bucket. A burst of requests
+//! can drain the bucket quickly, but after that requests only get through as fast as the bucket
+//! refills.
+//!
+//! Like every timed machine, the limiter never reads a clock. Time passing reaches it as ticks.
+
+use super::{Invariant, StateMachine, TimedStateMachine, TryStateMachine};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Something that can happen to the limiter
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum LimiterAction {
+	/// Some time has passed.
+	Tick(Duration),
+	/// A request that costs the given number of tokens wants to get through.
+	Request { cost: u64 },
+}
+
+/// How quickly the bucket refills. Every `every`, `amount` tokens are added.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct RefillPolicy {
+	/// How many tokens are added at once
+	pub amount: u64,
+	/// How often tokens are added
+	pub every: Duration,
+}
+
+/// The bucket of tokens
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Bucket {
+	/// The most tokens the bucket can hold
+	capacity: u64,
+	/// How many tokens are in the bucket right now
+	tokens: u64,
+	/// How the bucket refills
+	refill: RefillPolicy,
+	/// How long it has been since tokens were last added. This carries leftover time from one
+	/// tick to the next, so that many short ticks refill as much as one long one.
+	since_refill: Duration,
+}
+
+impl Bucket {
+	/// A full bucket of the given size with the given refill policy
+	pub fn new(capacity: u64, refill: RefillPolicy) -> Self {
+		Bucket { capacity, tokens: capacity, refill, since_refill: Duration::ZERO }
+	}
+
+	/// How many tokens are in the bucket right now
+	pub fn tokens(&self) -> u64 {
+		self.tokens
+	}
+}
+
+/// The reasons a request may be turned away
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LimiterError {
+	/// There aren't enough tokens yet. The request may get through later.
+	Throttled,
+	/// The request costs more than the bucket can ever hold, so it will never get through.
+	TooExpensive,
+}
+
+/// The state machine that admits or throttles requests
+pub struct RateLimiter;
+
+impl StateMachine for RateLimiter {
+	type State = Bucket;
+	type Transition = LimiterAction;
+
+	fn next_state(starting_state: &Bucket, t: &LimiterAction) -> Bucket {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+
+	fn human_name() -> String {
+		"Token bucket rate limiter".into()
+	}
+}
+
+impl TryStateMachine for RateLimiter {
+	type Error = LimiterError;
+
+	fn try_next_state(starting_state: &Bucket, t: &LimiterAction) -> Result<Bucket, LimiterError> {
+		let mut bucket = starting_state.clone();
+		match t {
+			LimiterAction::Tick(elapsed) => {
+				let waited = bucket.since_refill.saturating_add(*elapsed);
+				if bucket.refill.every.is_zero() {
+					bucket.tokens = bucket.capacity;
+					return Ok(bucket)
+				}
+				let refills = waited.as_nanos() / bucket.refill.every.as_nanos();
+				let added = refills.saturating_mul(bucket.refill.amount as u128);
+				bucket.tokens = (bucket.tokens as u128 + added).min(bucket.capacity as u128) as u64;
+				// Time doesn't pile up while the bucket is full, or a long quiet spell would be
+				// followed by an early refill.
+				bucket.since_refill = if bucket.tokens == bucket.capacity {
+					Duration::ZERO
+				} else {
+					let leftover = waited.as_nanos() % bucket.refill.every.as_nanos();
+					Duration::from_nanos(leftover as u64)
+				};
+			},
+			LimiterAction::Request { cost } => {
+				if *cost > bucket.capacity {
+					return Err(LimiterError::TooExpensive)
+				}
+				bucket.tokens = bucket.tokens.checked_sub(*cost).ok_or(LimiterError::Throttled)?;
+			},
+		}
+		Ok(bucket)
+	}
+}
+
+impl TimedStateMachine for RateLimiter {
+	fn tick(elapsed: Duration) -> LimiterAction {
+		LimiterAction::Tick(elapsed)
+	}
+}
+
+/// The bucket never holds more tokens than it has room for. The violation is how many tokens too
+/// many it holds.
+impl Invariant for RateLimiter {
+	type Violation = u64;
+
+	fn check(bucket: &Bucket) -> Result<(), u64> {
+		match bucket.tokens.checked_sub(bucket.capacity) {
+			Some(extra) if extra > 0 => Err(extra),
+			_ => Ok(()),
+		}
+	}
+}
+
+/// Two tokens every second, in a bucket that holds five
+#[cfg(test)]
+fn two_per_second() -> Bucket {
+	Bucket::new(5, RefillPolicy { amount: 2, every: Duration::from_secs(1) })
+}
+
+#[test]
+fn sm_17_requests_drain_bucket() {
+	let end = RateLimiter::apply_all(
+		&two_per_second(),
+		&[LimiterAction::Request { cost: 2 }, LimiterAction::Request { cost: 3 }],
+	);
+
+	assert_eq!(end.tokens(), 0);
+	assert_eq!(
+		RateLimiter::try_next_state(&end, &LimiterAction::Request { cost: 1 }),
+		Err(LimiterError::Throttled)
+	);
+	assert_eq!(RateLimiter::next_state(&end, &LimiterAction::Request { cost: 1 }), end);
+}
+
+#[test]
+fn sm_17_tick_refills() {
+	let empty = RateLimiter::next_state(&two_per_second(), &LimiterAction::Request { cost: 5 });
+	let end = RateLimiter::next_state(&empty, &LimiterAction::Tick(Duration::from_millis(1500)));
+
+	assert_eq!(end.tokens(), 2);
+	assert_eq!(end.since_refill, Duration::from_millis(500));
+}
+
+#[test]
+fn sm_17_short_ticks_add_up() {
+	let empty = RateLimiter::next_state(&two_per_second(), &LimiterAction::Request { cost: 5 });
+	let end =
+		RateLimiter::apply_all(&empty, &vec![LimiterAction::Tick(Duration::from_millis(250)); 8]);
+
+	assert_eq!(end.tokens(), 4);
+}
+
+#[test]
+fn sm_17_refill_stops_at_capacity() {
+	let drained = RateLimiter::next_state(&two_per_second(), &LimiterAction::Request { cost: 1 });
+	let end = RateLimiter::next_state(&drained, &LimiterAction::Tick(Duration::from_secs(60)));
+
+	assert_eq!(end, two_per_second());
+}
+
+#[test]
+fn sm_17_too_expensive_never_admitted() {
+	assert_eq!(
+		RateLimiter::try_next_state(&two_per_second(), &LimiterAction::Request { cost: 6 }),
+		Err(LimiterError::TooExpensive)
+	);
+}
+
+#[test]
+fn sm_17_throttled_with_mock_clock() {
+	use super::clock::{MockClock, Ticker};
+
+	let mut ticker = Ticker::new(MockClock::new());
+	let mut bucket = two_per_second();
+	let mut admitted = 0;
+
+	// Ten requests, one every 200ms. The first five use up the bucket, and after that only the
+	// two tokens refilled in the second that passes get through.
+	for _ in 0..10 {
+		ticker.clock().advance(Duration::from_millis(200));
+		bucket = ticker.tick::<RateLimiter>(&bucket);
+		if let Ok(next) = RateLimiter::try_next_state(&bucket, &LimiterAction::Request { cost: 1 })
+		{
+			bucket = next;
+			admitted += 1;
+		}
+	}
+
+	assert_eq!(admitted, 7);
+	assert_eq!(RateLimiter::check(&bucket), Ok(()));
+}
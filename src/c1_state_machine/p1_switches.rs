@@ -2,7 +2,12 @@
 //! In these examples, we use actually switch boards as the state machine. The state is,
 //! well, just the state of the switches.
 
-use super::{StateMachine};
+use super::{
+	repl::ParseTransition, EnumerableTransitions, ReversibleStateMachine, StateMachine,
+	TryStateMachine,
+};
+use core::convert::Infallible;
+use serde::{Deserialize, Serialize};
 
 /// This state machine models a single light switch.
 /// The internal state, a bool, represents whether the switch is on or not.
@@ -17,6 +22,45 @@ impl StateMachine for LightSwitch {
 	fn next_state(starting_state: &bool, t: &()) -> bool {
 		!starting_state
 	}
+
+	fn human_name() -> String {
+		"Light switch".into()
+	}
+}
+
+/// A light switch can always be toggled, so no transition is ever rejected.
+impl TryStateMachine for LightSwitch {
+	type Error = Infallible;
+
+	fn try_next_state(starting_state: &bool, t: &()) -> Result<bool, Infallible> {
+		Ok(Self::next_state(starting_state, t))
+	}
+}
+
+impl EnumerableTransitions for LightSwitch {
+	fn transitions() -> Vec<()> {
+		vec![()]
+	}
+}
+
+/// Toggling a switch is its own inverse, so every transition can be undone.
+impl ReversibleStateMachine for LightSwitch {
+	fn prev_state(ending_state: &bool, _t: &()) -> Option<bool> {
+		Some(!ending_state)
+	}
+}
+
+impl ParseTransition for LightSwitch {
+	fn transition_help() -> String {
+		"`toggle`".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<(), String> {
+		match text {
+			"toggle" => Ok(()),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
 }
 
 /// This second  state machine models two light switches with one weird property.
@@ -24,13 +68,14 @@ impl StateMachine for LightSwitch {
 pub struct WeirdSwitchMachine;
 
 /// The state is now two switches instead of one so we use a struct.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TwoSwitches {
 	first_switch: bool,
 	second_switch: bool,
 }
 
 /// Now there are two switches so we need a proper type for the transition.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Toggle {
 	FirstSwitch,
 	SecondSwitch,
@@ -57,6 +102,55 @@ impl StateMachine for WeirdSwitchMachine {
 			},
 		}
 	}
+
+	fn human_name() -> String {
+		"Weird switches".into()
+	}
+}
+
+/// Weird as it is, either switch can always be toggled.
+impl TryStateMachine for WeirdSwitchMachine {
+	type Error = Infallible;
+
+	fn try_next_state(starting_state: &TwoSwitches, t: &Toggle) -> Result<TwoSwitches, Infallible> {
+		Ok(Self::next_state(starting_state, t))
+	}
+}
+
+impl EnumerableTransitions for WeirdSwitchMachine {
+	fn transitions() -> Vec<Toggle> {
+		vec![Toggle::FirstSwitch, Toggle::SecondSwitch]
+	}
+}
+
+/// Toggling the second switch can always be undone. Turning the first switch off can not, because
+/// afterwards there is no telling whether the second switch was on before it was forced off.
+impl ReversibleStateMachine for WeirdSwitchMachine {
+	fn prev_state(ending_state: &TwoSwitches, t: &Toggle) -> Option<TwoSwitches> {
+		match t {
+			Toggle::FirstSwitch if ending_state.first_switch =>
+				Some(TwoSwitches { first_switch: false, second_switch: ending_state.second_switch }),
+			Toggle::FirstSwitch => None,
+			Toggle::SecondSwitch => Some(TwoSwitches {
+				first_switch: ending_state.first_switch,
+				second_switch: !ending_state.second_switch,
+			}),
+		}
+	}
+}
+
+impl ParseTransition for WeirdSwitchMachine {
+	fn transition_help() -> String {
+		"`first` or `second`".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<Toggle, String> {
+		match text {
+			"first" => Ok(Toggle::FirstSwitch),
+			"second" => Ok(Toggle::SecondSwitch),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
 }
 
 #[test]
@@ -120,3 +214,65 @@ fn sm_1_two_switches_second_goes_off() {
 		TwoSwitches { first_switch: true, second_switch: false }
 	);
 }
+
+#[test]
+fn sm_1_switches_never_reject() {
+	assert_eq!(LightSwitch::try_next_state(&false, &()), Ok(true));
+
+	let state = TwoSwitches { first_switch: true, second_switch: true };
+	assert_eq!(
+		WeirdSwitchMachine::try_next_state(&state, &Toggle::FirstSwitch),
+		Ok(TwoSwitches { first_switch: false, second_switch: false })
+	);
+}
+
+#[test]
+fn sm_1_apply_all_toggles() {
+	assert!(!LightSwitch::apply_all(&false, &[(), (), (), ()]));
+	assert!(LightSwitch::apply_all(&false, &[(), (), ()]));
+	assert!(LightSwitch::apply_all(&true, &[]));
+}
+
+#[test]
+fn sm_1_trace_two_switches() {
+	let state = TwoSwitches { first_switch: false, second_switch: false };
+	let trace = WeirdSwitchMachine::trace(
+		&state,
+		&[Toggle::FirstSwitch, Toggle::SecondSwitch, Toggle::FirstSwitch],
+	);
+
+	assert_eq!(
+		trace,
+		vec![
+			TwoSwitches { first_switch: false, second_switch: false },
+			TwoSwitches { first_switch: true, second_switch: false },
+			TwoSwitches { first_switch: true, second_switch: true },
+			TwoSwitches { first_switch: false, second_switch: false },
+		]
+	);
+}
+
+#[test]
+fn sm_1_light_switch_undo() {
+	assert_eq!(LightSwitch::prev_state(&LightSwitch::next_state(&true, &()), &()), Some(true));
+	assert_eq!(LightSwitch::prev_state(&LightSwitch::next_state(&false, &()), &()), Some(false));
+}
+
+#[test]
+fn sm_1_two_switches_undo_where_possible() {
+	for first_switch in [false, true] {
+		for second_switch in [false, true] {
+			let state = TwoSwitches { first_switch, second_switch };
+			for t in WeirdSwitchMachine::transitions() {
+				let end = WeirdSwitchMachine::next_state(&state, &t);
+				if let Some(prev) = WeirdSwitchMachine::prev_state(&end, &t) {
+					assert_eq!(prev, state);
+				}
+			}
+		}
+	}
+
+	// Turning the first switch off forgets what the second switch was.
+	let end = TwoSwitches { first_switch: false, second_switch: false };
+	assert_eq!(WeirdSwitchMachine::prev_state(&end, &Toggle::FirstSwitch), None);
+}
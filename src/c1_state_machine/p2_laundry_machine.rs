@@ -2,14 +2,19 @@
 //! they're ready to be worn again. Or course washing and wearing clothes takes its toll on the
 //! clothes, and eventually they get tattered.
 
-use super::StateMachine;
+use super::{repl::ParseTransition, EnumerableTransitions, StateMachine, TryStateMachine};
+use core::convert::Infallible;
+use serde::{Deserialize, Serialize};
 
 /// This state machine models the typical life cycle of clothes as they make their way through the
 /// laundry cycle several times before ultimately becoming tattered.
+///
+/// Unlike the light switch, this machine is not reversible. Wearing clean, dirty, and wet clothes
+/// all leaves them dirty, so once they are dirty there is no telling what they were before.
 pub struct ClothesMachine;
 
 /// Models a piece of clothing throughout its lifecycle.
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Hash, PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum ClothesState {
 	/// Clean clothes ready to be worn. With some given life left.
 	Clean(u64),
@@ -24,6 +29,7 @@ pub enum ClothesState {
 }
 
 /// Something you can do with clothes
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum ClothesAction {
 	/// Wearing clothes decreases their life by 1 and makes them dirty.
 	Wear,
@@ -115,6 +121,44 @@ impl StateMachine for ClothesMachine {
 			},
 		}
 	}
+
+	fn human_name() -> String {
+		"Clothes".into()
+	}
+}
+
+/// Anything can be done with clothes at any time. Doing something to tattered clothes is
+/// pointless, but it is a no-op rather than a rejected transition.
+impl TryStateMachine for ClothesMachine {
+	type Error = Infallible;
+
+	fn try_next_state(
+		starting_state: &ClothesState,
+		t: &ClothesAction,
+	) -> Result<ClothesState, Infallible> {
+		Ok(Self::next_state(starting_state, t))
+	}
+}
+
+impl EnumerableTransitions for ClothesMachine {
+	fn transitions() -> Vec<ClothesAction> {
+		vec![ClothesAction::Wear, ClothesAction::Wash, ClothesAction::Dry]
+	}
+}
+
+impl ParseTransition for ClothesMachine {
+	fn transition_help() -> String {
+		"`wear`, `wash`, or `dry`".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<ClothesAction, String> {
+		match text {
+			"wear" => Ok(ClothesAction::Wear),
+			"wash" => Ok(ClothesAction::Wash),
+			"dry" => Ok(ClothesAction::Dry),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
 }
 
 #[test]
@@ -284,3 +328,10 @@ fn sm_2_dry_dirty_until_tattered() {
 	let expected = ClothesState::Tattered;
 	assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_2_washing_tattered_clothes_is_not_rejected() {
+	let start = ClothesState::Tattered;
+	let end = ClothesMachine::try_next_state(&start, &ClothesAction::Wash);
+	assert_eq!(end, Ok(ClothesState::Tattered));
+}
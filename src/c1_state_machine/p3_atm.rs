@@ -2,29 +2,77 @@
 //! The atm may fail to give you cash if it is empty or you haven't swiped your card, or you have
 //! entered the wrong pin.
 
-use super::StateMachine;
+use super::{
+	repl::ParseTransition, EnumerableTransitions, Invariant, MealyStateMachine, StateMachine,
+	TimedStateMachine, TryStateMachine,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeMap, VecDeque},
+	time::Duration,
+};
 
 /// The keys on the ATM keypad
-#[derive(Hash, Debug, PartialEq, Eq, Clone)]
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Key {
+	Zero,
 	One,
 	Two,
 	Three,
 	Four,
+	Five,
+	Six,
+	Seven,
+	Eight,
+	Nine,
 	Enter,
+	/// Abandon the session. Whatever was keyed in so far is forgotten and the card is returned.
+	Cancel,
+	/// Forget the most recently keyed in digit, to correct a typo.
+	Backspace,
+	/// Forget everything keyed in so far, but carry on with the session.
+	Clear,
 }
 
 /// Something you can do to the ATM
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum Action {
 	/// Swipe your card at the ATM. The attached value is the hash of the pin
-	/// that should be keyed in on the keypad next.
+	/// that should be keyed in on the keypad next. The pin is hashed in the
+	/// `ATM_PIN` domain.
 	SwipeCard(u64),
 	/// Press a key on the keypad
 	PressKey(Key),
+	/// Deposit the given amount of cash. Only an authenticated user can make a deposit, and
+	/// doing so ends the session.
+	InsertCash(u64),
+	/// Print the most recent withdrawals and deposits. Only an authenticated user can print a
+	/// statement, and the session carries on afterwards.
+	PrintStatement,
+	/// An operator opens up the machine, takes out any retained card, and resets it.
+	OperatorReset,
+	/// An operator keys in the master pin on the service panel, hoping to put the machine into
+	/// maintenance. The attached value is the hash of the keyed in pin, in the `ATM_PIN` domain.
+	/// This is only possible while no customer session is in progress.
+	OperatorLogin(u64),
+	/// An operator in maintenance refills the machine with the given amount of cash.
+	AddCash(u64),
+	/// An operator in maintenance asks the machine how much cash it believes it holds, so that
+	/// it can be compared with a count of the cash that is really there.
+	AuditCash,
+	/// An operator in maintenance takes out the retained card, so the machine can serve
+	/// customers again after maintenance.
+	ReleaseCard,
+	/// The operator is done, and the machine goes back to whatever it was doing before
+	/// maintenance.
+	OperatorLogout,
+	/// Some time has passed without the user doing anything. A session left idle for longer
+	/// than the ATM's timeout is abandoned and the card is returned.
+	Tick(Duration),
 }
 
 /// The various states of authentication possible with the ATM
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 enum Auth {
 	/// No session has begun yet. Waiting for the user to swipe their card
 	Waiting,
@@ -34,6 +82,13 @@ enum Auth {
 	/// The user has authenticated. Waiting for them to key in the amount
 	/// of cash to withdraw
 	Authenticated,
+	/// Too many wrong pins were entered in a row, so the machine kept the card. It ignores
+	/// everything until an operator resets it.
+	CardRetained,
+	/// An operator entered the master pin and is servicing the machine. Customers are ignored
+	/// until the operator logs out. Remembers whether a card was retained when maintenance began
+	/// and has not been released yet.
+	Maintenance { card_retained: bool },
 }
 
 /// The ATM. When a card is swiped, the ATM learns the correct pin's hash.
@@ -41,8 +96,21 @@ enum Auth {
 /// you like followed by enter. If the pin is incorrect, your card is returned
 /// and the ATM automatically goes back to the main menu. If your pin is correct,
 /// the ATM waits for you to key in an amount of money to withdraw. Withdraws
-/// are bounded only by the cash in the machine (there is no account balance).
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// are bounded by the cash in the machine. An ATM created `with_accounts` also knows the
+/// balance behind every card. It refuses cards it doesn't know, and withdrawals are bounded by
+/// the account balance as well.
+/// An ATM created `with_bills` only hands out whole bills, so it refuses amounts that its
+/// bills can't add up to.
+/// ATMs can also be configured with a limit on how much may be withdrawn in a single session.
+/// An ATM configured with a master pin can be put into maintenance by an operator, who may then
+/// refill it, audit its cash, and release a retained card.
+/// Instead of withdrawing, an authenticated user may deposit cash, which also ends the session.
+/// After `MAX_PIN_ATTEMPTS` wrong pins in a row, the ATM keeps the card and refuses to do
+/// anything more until an operator resets it. Otherwise anyone could guess pins forever.
+/// A session left without any action for longer than the session timeout is abandoned, and the
+/// card is returned. The ATM keeps a short journal of completed withdrawals and deposits, which
+/// an authenticated user can print.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct Atm {
 	/// How much money is in the ATM
 	cash_inside: u64,
@@ -50,6 +118,336 @@ pub struct Atm {
 	expected_pin_hash: Auth,
 	/// All the keys that have been pressed since the last `Enter`
 	keystroke_register: Vec<Key>,
+	/// How long a session may sit without any action before the ATM gives up on it.
+	session_timeout: Duration,
+	/// How long the current session has gone without any action.
+	idle_time: Duration,
+	/// How many wrong pins have been entered since the last correct one.
+	failed_pin_attempts: u32,
+	/// The bank accounts behind the cards, for ATMs that keep track of balances. Without them,
+	/// withdrawals are bounded only by the cash in the machine.
+	accounts: Option<Accounts>,
+	/// The bills in the dispenser, counted by denomination, for ATMs that keep track of them.
+	/// Deposits go into a separate bin and are never dispensed, so these may add up to less than
+	/// `cash_inside`.
+	bills: Option<BTreeMap<u64, u64>>,
+	/// The most recent withdrawals and deposits, oldest first. Only the last `JOURNAL_CAPACITY`
+	/// are kept, so the state can't grow forever.
+	journal: VecDeque<JournalEntry>,
+	/// The most that may be withdrawn in a single session, if there is a limit at all. Asking
+	/// for more ends the session without dispensing anything.
+	withdrawal_limit: Option<u64>,
+	/// The hash of the master pin that lets an operator service the machine, if it can be
+	/// serviced at all. It is hashed in the same domain as card pins.
+	master_pin_hash: Option<u64>,
+}
+
+/// A completed withdrawal or deposit, as recorded in the ATM's journal.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum JournalEntry {
+	Withdrawal(u64),
+	Deposit(u64),
+}
+
+/// The bank accounts an ATM can reach, and which one belongs to the card in the machine.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+struct Accounts {
+	/// The balance of every account, keyed by the pin hash of its card.
+	balances: BTreeMap<u64, u64>,
+	/// The account of the card in the machine, if there is one.
+	current: Option<u64>,
+}
+
+/// How many wrong pins in a row the ATM accepts before it retains the card.
+pub const MAX_PIN_ATTEMPTS: u32 = 3;
+
+/// How many withdrawals and deposits the ATM's journal remembers.
+pub const JOURNAL_CAPACITY: usize = 16;
+
+/// How many of the most recent journal entries a printed statement shows.
+pub const STATEMENT_LENGTH: usize = 5;
+
+/// How long an ATM waits for the next action before abandoning a session, unless told otherwise.
+pub const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Configures a fresh ATM before it is put into service. Start with `Atm::builder()`.
+#[derive(Debug, Clone)]
+pub struct AtmBuilder {
+	atm: Atm,
+}
+
+impl AtmBuilder {
+	/// How much money is in the ATM.
+	pub fn cash_inside(mut self, cash_inside: u64) -> Self {
+		self.atm.cash_inside = cash_inside;
+		self
+	}
+
+	/// How long a session may sit without any action before the ATM gives up on it.
+	pub fn session_timeout(mut self, session_timeout: Duration) -> Self {
+		self.atm.session_timeout = session_timeout;
+		self
+	}
+
+	/// Keep track of the balance of every account, keyed by the pin hash of its card. Unknown
+	/// cards are refused, and withdrawals are bounded by the balance.
+	pub fn accounts(mut self, balances: BTreeMap<u64, u64>) -> Self {
+		self.atm.accounts = Some(Accounts { balances, current: None });
+		self
+	}
+
+	/// Load the dispenser with the given bills, counted by denomination. This replaces the cash
+	/// inside the ATM with the total value of the bills.
+	pub fn bills(mut self, bills: BTreeMap<u64, u64>) -> Self {
+		self.atm.cash_inside = bills.iter().map(|(denomination, count)| denomination * count).sum();
+		self.atm.bills = Some(bills);
+		self
+	}
+
+	/// The most that may be withdrawn in a single session.
+	pub fn withdrawal_limit(mut self, limit: u64) -> Self {
+		self.atm.withdrawal_limit = Some(limit);
+		self
+	}
+
+	/// Let operators put the ATM into maintenance with the master pin of the given hash.
+	pub fn master_pin(mut self, master_pin_hash: u64) -> Self {
+		self.atm.master_pin_hash = Some(master_pin_hash);
+		self
+	}
+
+	/// Put the configured ATM into service, waiting for a card to be swiped.
+	pub fn build(self) -> Atm {
+		self.atm
+	}
+}
+
+impl Default for Atm {
+	/// An empty ATM, waiting for a card to be swiped.
+	fn default() -> Self {
+		Self::new(0)
+	}
+}
+
+impl Atm {
+	/// A fresh ATM holding the given amount of cash, waiting for a card to be swiped.
+	pub fn new(cash_inside: u64) -> Self {
+		Self::builder().cash_inside(cash_inside).build()
+	}
+
+	/// A fresh ATM holding the given amount of cash, which abandons any session left idle for
+	/// longer than the given timeout.
+	pub fn with_session_timeout(cash_inside: u64, session_timeout: Duration) -> Self {
+		Self::builder()
+			.cash_inside(cash_inside)
+			.session_timeout(session_timeout)
+			.build()
+	}
+
+	/// A fresh ATM holding the given amount of cash, which knows the balance of every account.
+	/// Accounts are keyed by the pin hash of their card.
+	pub fn with_accounts(cash_inside: u64, balances: BTreeMap<u64, u64>) -> Self {
+		Self::builder().cash_inside(cash_inside).accounts(balances).build()
+	}
+
+	/// Start configuring a fresh ATM. Anything not configured is the same as for `Atm::new`.
+	pub fn builder() -> AtmBuilder {
+		AtmBuilder {
+			atm: Atm {
+				cash_inside: 0,
+				expected_pin_hash: Auth::Waiting,
+				keystroke_register: Vec::new(),
+				session_timeout: DEFAULT_SESSION_TIMEOUT,
+				idle_time: Duration::ZERO,
+				failed_pin_attempts: 0,
+				accounts: None,
+				bills: None,
+				journal: VecDeque::new(),
+				withdrawal_limit: None,
+				master_pin_hash: None,
+			},
+		}
+	}
+
+	/// The balance of the account belonging to the given card, if this ATM keeps track of
+	/// accounts and knows the card.
+	pub fn balance(&self, card: u64) -> Option<u64> {
+		self.accounts.as_ref()?.balances.get(&card).copied()
+	}
+
+	/// A fresh ATM whose dispenser is loaded with the given bills, counted by denomination.
+	pub fn with_bills(bills: BTreeMap<u64, u64>) -> Self {
+		Self::builder().bills(bills).build()
+	}
+
+	/// Whether the given amount could be withdrawn by the user in the current session.
+	///
+	/// The cheap checks come first, so that working out which bills to hand out is only ever
+	/// attempted for amounts the ATM holds.
+	fn can_withdraw(&self, amount: u64) -> bool {
+		let balance = self.current_balance().unwrap_or(u64::MAX);
+		if amount > balance || self.exceeds_limit(amount) || amount > self.dispensable_cash() {
+			return false
+		}
+		match &self.bills {
+			Some(bills) => pick_bills(bills, amount).is_some(),
+			None => true,
+		}
+	}
+
+	/// Whether the given amount is more than a single session may withdraw.
+	fn exceeds_limit(&self, amount: u64) -> bool {
+		self.withdrawal_limit.is_some_and(|limit| amount > limit)
+	}
+
+	/// Add an entry to the journal, forgetting the oldest one if the journal is full.
+	fn record(&mut self, entry: JournalEntry) {
+		if self.journal.len() == JOURNAL_CAPACITY {
+			self.journal.pop_front();
+		}
+		self.journal.push_back(entry);
+	}
+
+	/// The most recent journal entries, as shown on a printed statement. Oldest first.
+	fn statement(&self) -> Vec<JournalEntry> {
+		let skip = self.journal.len().saturating_sub(STATEMENT_LENGTH);
+		self.journal.iter().skip(skip).cloned().collect()
+	}
+
+	/// How much cash the ATM could possibly hand out.
+	fn dispensable_cash(&self) -> u64 {
+		match &self.bills {
+			Some(bills) => bills.iter().map(|(denomination, count)| denomination * count).sum(),
+			None => self.cash_inside,
+		}
+	}
+
+	/// Take the bills making up the given amount out of the dispenser, if the ATM tracks bills.
+	fn dispense_bills(&mut self, amount: u64) {
+		if let Some(bills) = &mut self.bills {
+			let picked = pick_bills(bills, amount).expect("amount was checked to be dispensable");
+			for (denomination, count) in picked {
+				*bills.get_mut(&denomination).expect("picked bills are in the dispenser") -= count;
+			}
+		}
+	}
+
+	/// Whether the given amount could be deposited by the user in the current session without
+	/// overflowing the machine or the account.
+	fn can_deposit(&self, amount: u64) -> bool {
+		let balance = self.current_balance().unwrap_or(0);
+		self.cash_inside.checked_add(amount).is_some() && balance.checked_add(amount).is_some()
+	}
+
+	/// The balance of the account belonging to the card in the machine.
+	fn current_balance(&self) -> Option<u64> {
+		let accounts = self.accounts.as_ref()?;
+		accounts.balances.get(&accounts.current?).copied()
+	}
+
+	fn current_balance_mut(&mut self) -> Option<&mut u64> {
+		let accounts = self.accounts.as_mut()?;
+		accounts.balances.get_mut(&accounts.current?)
+	}
+
+	/// How much money is in the ATM
+	pub fn cash_inside(&self) -> u64 {
+		self.cash_inside
+	}
+}
+
+/// The hash of a pin, as stored on a card and checked by the ATM. Pins are hashed in the
+/// `ATM_PIN` domain.
+pub fn pin_hash(pin: &[Key]) -> u64 {
+	crate::hash_with_domain(crate::domain::ATM_PIN, &pin)
+}
+
+/// The most units an amount may be made of before the ATM refuses to work out which bills make it
+/// up, see `pick_bills`. That way no amount keyed in can keep the ATM busy for long.
+const MAX_BILL_UNITS: u64 = 1 << 20;
+
+/// Choose bills from the dispenser that add up to exactly the given amount, preferring large
+/// denominations. Returns how many of each denomination to hand out, or `None` if no combination of
+/// the available bills adds up to the amount.
+///
+/// Simply taking as many of the largest bill as possible doesn't always work. With a single 50
+/// and three 20s, 60 can only be made from the 20s. Trying every combination does work, but takes
+/// time exponential in the number of denominations. Instead, every amount is counted in units of
+/// the greatest common divisor of the denominations, since no other amount can be made at all, and
+/// for every denomination we work out which amounts up to the requested one the smaller bills can
+/// make. Then, from the largest denomination down, we use as many bills as still leave an amount
+/// the smaller ones can make. That takes time proportional to the number of units times the number
+/// of denominations, and amounts over `MAX_BILL_UNITS` units are refused.
+fn pick_bills(bills: &BTreeMap<u64, u64>, amount: u64) -> Option<BTreeMap<u64, u64>> {
+	let available: Vec<(u64, u64)> = bills
+		.iter()
+		.filter(|(d, c)| **d > 0 && **c > 0)
+		.map(|(d, c)| (*d, *c))
+		.collect();
+	let unit = available.iter().fold(0, |unit, (denomination, _)| gcd(unit, *denomination));
+	if amount == 0 {
+		return Some(BTreeMap::new())
+	}
+	if unit == 0 || !amount.is_multiple_of(unit) || amount / unit > MAX_BILL_UNITS {
+		return None
+	}
+	let units = (amount / unit) as usize;
+
+	// `makeable[i][v]` says whether `v` units can be made from the `i` smallest denominations.
+	let mut makeable = vec![vec![false; units + 1]];
+	makeable[0][0] = true;
+	for (denomination, count) in &available {
+		let step = (denomination / unit) as usize;
+		let smaller = makeable.last().expect("there is always a row for no bills at all");
+		// The fewest bills of this denomination needed to make each amount, if it can be made.
+		let mut used: Vec<Option<u64>> = vec![None; units + 1];
+		for v in 0..=units {
+			used[v] = if smaller[v] {
+				Some(0)
+			} else if v >= step {
+				used[v - step].filter(|used| used < count).map(|used| used + 1)
+			} else {
+				None
+			};
+		}
+		makeable.push(used.iter().map(Option::is_some).collect());
+	}
+
+	if !makeable[available.len()][units] {
+		return None
+	}
+	let mut picked = BTreeMap::new();
+	let mut rest = units;
+	for (i, (denomination, count)) in available.iter().enumerate().rev() {
+		let step = (denomination / unit) as usize;
+		let most = (*count).min((rest / step) as u64);
+		let take = (0..=most)
+			.rev()
+			.find(|take| makeable[i][rest - *take as usize * step])
+			.expect("the amount left over was checked to be makeable");
+		if take > 0 {
+			picked.insert(*denomination, take);
+		}
+		rest -= take as usize * step;
+	}
+	Some(picked)
+}
+
+/// The greatest common divisor of two numbers. That of zero and any number is the number itself.
+fn gcd(a: u64, b: u64) -> u64 {
+	if b == 0 {
+		a
+	} else {
+		gcd(b, a % b)
+	}
+}
+
+/// Read the keys pressed so far as a decimal amount of cash.
+fn keyed_amount(keys: &[Key]) -> u64 {
+	// Amounts too large to fit are more than any ATM holds anyway, so saturating is harmless.
+	keys.iter()
+		.filter_map(Key::digit)
+		.fold(0, |acc, digit| acc.saturating_mul(10).saturating_add(digit))
 }
 
 impl StateMachine for Atm {
@@ -59,46 +457,162 @@ impl StateMachine for Atm {
 	type Transition = Action;
 
 	fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
-		match t {
+		let mut end = match t {
+			Action::OperatorReset => Atm {
+				cash_inside: starting_state.cash_inside,
+				expected_pin_hash: Auth::Waiting,
+				keystroke_register: vec![],
+				session_timeout: starting_state.session_timeout,
+				idle_time: Duration::ZERO,
+				failed_pin_attempts: 0,
+				accounts: starting_state.accounts.clone(),
+				bills: starting_state.bills.clone(),
+				journal: starting_state.journal.clone(),
+				withdrawal_limit: starting_state.withdrawal_limit,
+				master_pin_hash: starting_state.master_pin_hash,
+			},
+			Action::OperatorLogin(master_pin) => match starting_state.expected_pin_hash {
+				Auth::Waiting | Auth::CardRetained
+					if starting_state.master_pin_hash == Some(*master_pin) =>
+					Atm {
+						expected_pin_hash: Auth::Maintenance {
+							card_retained: starting_state.expected_pin_hash == Auth::CardRetained,
+						},
+						idle_time: Duration::ZERO,
+						..starting_state.clone()
+					},
+				_ => starting_state.clone(),
+			},
+			Action::AddCash(amount) => match (
+				&starting_state.expected_pin_hash,
+				starting_state.cash_inside.checked_add(*amount),
+			) {
+				(Auth::Maintenance { .. }, Some(cash_inside)) =>
+					Atm { cash_inside, ..starting_state.clone() },
+				_ => starting_state.clone(),
+			},
+			Action::AuditCash => starting_state.clone(),
+			Action::ReleaseCard => match starting_state.expected_pin_hash {
+				Auth::Maintenance { card_retained: true } => Atm {
+					expected_pin_hash: Auth::Maintenance { card_retained: false },
+					failed_pin_attempts: 0,
+					..starting_state.clone()
+				},
+				_ => starting_state.clone(),
+			},
+			Action::OperatorLogout => match starting_state.expected_pin_hash {
+				Auth::Maintenance { card_retained } => Atm {
+					expected_pin_hash: if card_retained {
+						Auth::CardRetained
+					} else {
+						Auth::Waiting
+					},
+					..starting_state.clone()
+				},
+				_ => starting_state.clone(),
+			},
+			Action::Tick(elapsed) => match starting_state.expected_pin_hash {
+				Auth::Waiting | Auth::CardRetained | Auth::Maintenance { .. } =>
+					starting_state.clone(),
+				Auth::Authenticating(_) | Auth::Authenticated => {
+					let idle_time = starting_state.idle_time.saturating_add(*elapsed);
+					if idle_time > starting_state.session_timeout {
+						Atm {
+							cash_inside: starting_state.cash_inside,
+							expected_pin_hash: Auth::Waiting,
+							keystroke_register: vec![],
+							session_timeout: starting_state.session_timeout,
+							idle_time: Duration::ZERO,
+							failed_pin_attempts: starting_state.failed_pin_attempts,
+							accounts: starting_state.accounts.clone(),
+							bills: starting_state.bills.clone(),
+							journal: starting_state.journal.clone(),
+							withdrawal_limit: starting_state.withdrawal_limit,
+							master_pin_hash: starting_state.master_pin_hash,
+						}
+					} else {
+						Atm { idle_time, ..starting_state.clone() }
+					}
+				},
+			},
+			// Cancelling does not give back a retained card. Only an operator can do that.
+			Action::PressKey(Key::Cancel)
+				if !matches!(
+					starting_state.expected_pin_hash,
+					Auth::CardRetained | Auth::Maintenance { .. }
+				) =>
+				Atm {
+					cash_inside: starting_state.cash_inside,
+					expected_pin_hash: Auth::Waiting,
+					keystroke_register: vec![],
+					session_timeout: starting_state.session_timeout,
+					idle_time: Duration::ZERO,
+					failed_pin_attempts: starting_state.failed_pin_attempts,
+					accounts: starting_state.accounts.clone(),
+					bills: starting_state.bills.clone(),
+					journal: starting_state.journal.clone(),
+					withdrawal_limit: starting_state.withdrawal_limit,
+					master_pin_hash: starting_state.master_pin_hash,
+				},
 			Action::PressKey(key) => match starting_state.expected_pin_hash {
+				Auth::CardRetained | Auth::Maintenance { .. } => starting_state.clone(),
 				Auth::Waiting => Atm {
 					cash_inside: starting_state.cash_inside,
 					expected_pin_hash: Auth::Waiting,
 					keystroke_register: vec![],
+					session_timeout: starting_state.session_timeout,
+					idle_time: Duration::ZERO,
+					failed_pin_attempts: starting_state.failed_pin_attempts,
+					accounts: starting_state.accounts.clone(),
+					bills: starting_state.bills.clone(),
+					journal: starting_state.journal.clone(),
+					withdrawal_limit: starting_state.withdrawal_limit,
+					master_pin_hash: starting_state.master_pin_hash,
 				},
 				Auth::Authenticating(pin) => {
 					let mut atm = Atm {
 						cash_inside: starting_state.cash_inside,
 						expected_pin_hash: starting_state.expected_pin_hash.clone(),
 						keystroke_register: starting_state.keystroke_register.clone(),
+						session_timeout: starting_state.session_timeout,
+						idle_time: Duration::ZERO,
+						failed_pin_attempts: starting_state.failed_pin_attempts,
+						accounts: starting_state.accounts.clone(),
+						bills: starting_state.bills.clone(),
+						journal: starting_state.journal.clone(),
+						withdrawal_limit: starting_state.withdrawal_limit,
+						master_pin_hash: starting_state.master_pin_hash,
 					};
 					match key {
-						Key::One => {
-							atm.keystroke_register.push(Key::One);
-							atm
-						},
-						Key::Two => {
-							atm.keystroke_register.push(Key::Two);
-							atm
-						},
-						Key::Three => {
-							atm.keystroke_register.push(Key::Three);
-							atm
-						},
-						Key::Four => {
-							atm.keystroke_register.push(Key::Four);
-							atm
-						},
 						Key::Enter => {
-							let entered_pin = crate::hash(&atm.keystroke_register);
+							let entered_pin = pin_hash(&atm.keystroke_register);
 							if pin == entered_pin {
 								atm.expected_pin_hash = Auth::Authenticated;
+								atm.failed_pin_attempts = 0;
 							} else {
-								atm.expected_pin_hash = Auth::Waiting;
+								atm.failed_pin_attempts += 1;
+								atm.expected_pin_hash =
+									if atm.failed_pin_attempts >= MAX_PIN_ATTEMPTS {
+										Auth::CardRetained
+									} else {
+										Auth::Waiting
+									};
 							}
 							atm.keystroke_register = vec![];
 							atm
 						},
+						Key::Backspace => {
+							atm.keystroke_register.pop();
+							atm
+						},
+						Key::Clear => {
+							atm.keystroke_register.clear();
+							atm
+						},
+						digit => {
+							atm.keystroke_register.push(digit.clone());
+							atm
+						},
 					}
 				},
 				Auth::Authenticated => {
@@ -106,75 +620,483 @@ impl StateMachine for Atm {
 						cash_inside: starting_state.cash_inside,
 						expected_pin_hash: starting_state.expected_pin_hash.clone(),
 						keystroke_register: starting_state.keystroke_register.clone(),
+						session_timeout: starting_state.session_timeout,
+						idle_time: Duration::ZERO,
+						failed_pin_attempts: starting_state.failed_pin_attempts,
+						accounts: starting_state.accounts.clone(),
+						bills: starting_state.bills.clone(),
+						journal: starting_state.journal.clone(),
+						withdrawal_limit: starting_state.withdrawal_limit,
+						master_pin_hash: starting_state.master_pin_hash,
 					};
 					match key {
-						Key::One => {
-							atm.keystroke_register.push(Key::One);
-							atm
-						},
-						Key::Two => {
-							atm.keystroke_register.push(Key::Two);
+						Key::Enter => {
+							let amount = keyed_amount(&atm.keystroke_register);
+							if atm.can_withdraw(amount) {
+								atm.cash_inside -= amount;
+								atm.dispense_bills(amount);
+								atm.record(JournalEntry::Withdrawal(amount));
+								if let Some(balance) = atm.current_balance_mut() {
+									*balance -= amount;
+								}
+							}
+							atm.keystroke_register = vec![];
+							atm.expected_pin_hash = Auth::Waiting;
 							atm
 						},
-						Key::Three => {
-							atm.keystroke_register.push(Key::Three);
+						Key::Backspace => {
+							atm.keystroke_register.pop();
 							atm
 						},
-						Key::Four => {
-							atm.keystroke_register.push(Key::Four);
+						Key::Clear => {
+							atm.keystroke_register.clear();
 							atm
 						},
-						Key::Enter => {
-							let amount: u64 =
-								atm.keystroke_register.iter().fold(0, |acc, key| match key {
-									Key::One => format!("{}1", acc).parse::<u64>().unwrap(),
-									Key::Two => format!("{}2", acc).parse::<u64>().unwrap(),
-									Key::Three => format!("{}3", acc).parse::<u64>().unwrap(),
-									Key::Four => format!("{}4", acc).parse::<u64>().unwrap(),
-									_ => acc,
-								});
-							atm.cash_inside = if atm.cash_inside >= amount {
-								atm.cash_inside - amount
-							} else {
-								atm.cash_inside
-							};
-							atm.keystroke_register = vec![];
-							atm.expected_pin_hash = Auth::Waiting;
+						digit => {
+							atm.keystroke_register.push(digit.clone());
 							atm
 						},
 					}
 				},
 			},
 			Action::SwipeCard(pin) => match starting_state.expected_pin_hash {
-				Auth::Waiting => Atm {
-					cash_inside: starting_state.cash_inside,
-					expected_pin_hash: Auth::Authenticating(*pin),
-					keystroke_register: vec![],
+				Auth::CardRetained | Auth::Maintenance { .. } => starting_state.clone(),
+				Auth::Waiting => match &starting_state.accounts {
+					// The bank has never heard of this card, so it is handed straight back.
+					Some(accounts) if !accounts.balances.contains_key(pin) =>
+						starting_state.clone(),
+					_ => Atm {
+						cash_inside: starting_state.cash_inside,
+						expected_pin_hash: Auth::Authenticating(*pin),
+						keystroke_register: vec![],
+						session_timeout: starting_state.session_timeout,
+						idle_time: Duration::ZERO,
+						failed_pin_attempts: starting_state.failed_pin_attempts,
+						accounts: starting_state
+							.accounts
+							.clone()
+							.map(|accounts| Accounts { current: Some(*pin), ..accounts }),
+						bills: starting_state.bills.clone(),
+						journal: starting_state.journal.clone(),
+						withdrawal_limit: starting_state.withdrawal_limit,
+						master_pin_hash: starting_state.master_pin_hash,
+					},
 				},
 				Auth::Authenticating(_pin) => Atm {
 					cash_inside: starting_state.cash_inside,
 					expected_pin_hash: starting_state.expected_pin_hash.clone(),
 					keystroke_register: starting_state.keystroke_register.clone(),
+					session_timeout: starting_state.session_timeout,
+					idle_time: Duration::ZERO,
+					failed_pin_attempts: starting_state.failed_pin_attempts,
+					accounts: starting_state.accounts.clone(),
+					bills: starting_state.bills.clone(),
+					journal: starting_state.journal.clone(),
+					withdrawal_limit: starting_state.withdrawal_limit,
+					master_pin_hash: starting_state.master_pin_hash,
 				},
 				Auth::Authenticated => Atm {
 					cash_inside: starting_state.cash_inside,
 					expected_pin_hash: Auth::Authenticated,
 					keystroke_register: vec![],
+					session_timeout: starting_state.session_timeout,
+					idle_time: Duration::ZERO,
+					failed_pin_attempts: starting_state.failed_pin_attempts,
+					accounts: starting_state.accounts.clone(),
+					bills: starting_state.bills.clone(),
+					journal: starting_state.journal.clone(),
+					withdrawal_limit: starting_state.withdrawal_limit,
+					master_pin_hash: starting_state.master_pin_hash,
+				},
+			},
+			Action::PrintStatement => match starting_state.expected_pin_hash {
+				Auth::Authenticated => Atm { idle_time: Duration::ZERO, ..starting_state.clone() },
+				_ => starting_state.clone(),
+			},
+			Action::InsertCash(amount) =>
+				match (&starting_state.expected_pin_hash, starting_state.can_deposit(*amount)) {
+					(Auth::Authenticated, true) => {
+						let mut atm = Atm {
+							cash_inside: starting_state.cash_inside + amount,
+							expected_pin_hash: Auth::Waiting,
+							keystroke_register: vec![],
+							idle_time: Duration::ZERO,
+							..starting_state.clone()
+						};
+						if let Some(balance) = atm.current_balance_mut() {
+							*balance += amount;
+						}
+						atm.record(JournalEntry::Deposit(*amount));
+						atm
+					},
+					_ => starting_state.clone(),
+				},
+		};
+
+		// Once the card is out of the machine, the ATM no longer has access to its account.
+		if matches!(
+			end.expected_pin_hash,
+			Auth::Waiting | Auth::CardRetained | Auth::Maintenance { .. }
+		) {
+			if let Some(accounts) = &mut end.accounts {
+				accounts.current = None;
+			}
+		}
+		end
+	}
+
+	fn human_name() -> String {
+		"Automated teller machine".into()
+	}
+}
+
+/// The pin of the single card used when enumerating the ATM's transitions.
+const DEMO_PIN: [Key; 2] = [Key::One, Key::Two];
+
+/// The master pin an operator uses when enumerating the ATM's transitions.
+const DEMO_MASTER_PIN: [Key; 2] = [Key::Nine, Key::Nine];
+
+/// Any card could be swiped at an ATM, so its transitions can't truly be enumerated. We assume a
+/// single card, whose pin is `DEMO_PIN`, which is enough to visit every step of a session. Only a
+/// few of the digit keys are listed too, because every extra key multiplies the number of states
+/// that tools like the model checker have to explore.
+impl EnumerableTransitions for Atm {
+	fn transitions() -> Vec<Action> {
+		vec![
+			Action::SwipeCard(pin_hash(&DEMO_PIN)),
+			Action::PressKey(Key::One),
+			Action::PressKey(Key::Two),
+			Action::PressKey(Key::Three),
+			Action::PressKey(Key::Four),
+			Action::PressKey(Key::Enter),
+			Action::PressKey(Key::Cancel),
+			Action::PressKey(Key::Backspace),
+			// Long enough to time out any session.
+			Action::Tick(Duration::MAX),
+			Action::OperatorReset,
+			Action::InsertCash(1),
+			Action::OperatorLogin(pin_hash(&DEMO_MASTER_PIN)),
+			Action::AddCash(1),
+			Action::ReleaseCard,
+			Action::OperatorLogout,
+		]
+	}
+}
+
+impl TimedStateMachine for Atm {
+	fn tick(elapsed: Duration) -> Action {
+		Action::Tick(elapsed)
+	}
+}
+
+impl Key {
+	/// The numeric keys, in order from zero to nine.
+	const DIGITS: [Key; 10] = [
+		Key::Zero,
+		Key::One,
+		Key::Two,
+		Key::Three,
+		Key::Four,
+		Key::Five,
+		Key::Six,
+		Key::Seven,
+		Key::Eight,
+		Key::Nine,
+	];
+
+	/// The key with the given label, if the keypad has one.
+	pub fn from_label(label: char) -> Option<Key> {
+		let digit = label.to_digit(10)?;
+		Some(Self::DIGITS[digit as usize].clone())
+	}
+
+	/// The digit printed on this key, or `None` if it is not a numeric key.
+	pub fn digit(&self) -> Option<u64> {
+		Self::DIGITS.iter().position(|key| key == self).map(|digit| digit as u64)
+	}
+}
+
+impl ParseTransition for Atm {
+	fn transition_help() -> String {
+		"`swipe <pin>` to swipe a card with the given pin, a key from `0` to `9`, `enter`, `cancel`, `backspace`, `clear`, `deposit <amount>`, `statement`, `tick <seconds>` to let time pass, `reset` for an operator to reset the machine, or `operator <pin>` for an operator to log in with the master pin, followed by `add <amount>`, `audit`, `release`, and `logout`".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<Action, String> {
+		if let Some(pin) = text.strip_prefix("swipe ") {
+			let pin = pin
+				.trim()
+				.chars()
+				.map(Key::from_label)
+				.collect::<Option<Vec<_>>>()
+				.ok_or_else(|| format!("Pins may only use the keys 0 to 9, not {:?}", pin))?;
+			return Ok(Action::SwipeCard(pin_hash(&pin)))
+		}
+		if let Some(pin) = text.strip_prefix("operator ") {
+			let pin = pin
+				.trim()
+				.chars()
+				.map(Key::from_label)
+				.collect::<Option<Vec<_>>>()
+				.ok_or_else(|| format!("Pins may only use the keys 0 to 9, not {:?}", pin))?;
+			return Ok(Action::OperatorLogin(pin_hash(&pin)))
+		}
+		if let Some(amount) = text.strip_prefix("add ") {
+			let amount = amount
+				.trim()
+				.parse::<u64>()
+				.map_err(|_| format!("Refills need a whole amount of cash, not {:?}", amount))?;
+			return Ok(Action::AddCash(amount))
+		}
+		if let Some(amount) = text.strip_prefix("deposit ") {
+			let amount = amount
+				.trim()
+				.parse::<u64>()
+				.map_err(|_| format!("Deposits need a whole amount of cash, not {:?}", amount))?;
+			return Ok(Action::InsertCash(amount))
+		}
+		if let Some(seconds) = text.strip_prefix("tick ") {
+			let seconds = seconds
+				.trim()
+				.parse::<u64>()
+				.map_err(|_| format!("Ticks need a whole number of seconds, not {:?}", seconds))?;
+			return Ok(Action::Tick(Duration::from_secs(seconds)))
+		}
+		let mut chars = text.chars();
+		match (text, chars.next().and_then(Key::from_label), chars.next()) {
+			("enter", _, _) => Ok(Action::PressKey(Key::Enter)),
+			("cancel", _, _) => Ok(Action::PressKey(Key::Cancel)),
+			("backspace", _, _) => Ok(Action::PressKey(Key::Backspace)),
+			("clear", _, _) => Ok(Action::PressKey(Key::Clear)),
+			("reset", _, _) => Ok(Action::OperatorReset),
+			("statement", _, _) => Ok(Action::PrintStatement),
+			("audit", _, _) => Ok(Action::AuditCash),
+			("release", _, _) => Ok(Action::ReleaseCard),
+			("logout", _, _) => Ok(Action::OperatorLogout),
+			(_, Some(key), None) => Ok(Action::PressKey(key)),
+			(other, _, _) => Err(format!("Unknown action {:?}", other)),
+		}
+	}
+}
+
+/// The ways an ATM can be in an impossible state.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AtmViolation {
+	/// Keys are registered even though nobody has swiped a card.
+	KeystrokesWithoutCard,
+	/// More wrong pins were entered in a row than the ATM allows, but it did not keep the card.
+	TooManyPinAttempts,
+	/// The bills in the dispenser add up to more than the cash inside the ATM.
+	BillsExceedCash,
+}
+
+impl Invariant for Atm {
+	type Violation = AtmViolation;
+
+	fn check(atm: &Atm) -> Result<(), AtmViolation> {
+		if matches!(
+			atm.expected_pin_hash,
+			Auth::Waiting | Auth::CardRetained | Auth::Maintenance { .. }
+		) && !atm.keystroke_register.is_empty()
+		{
+			return Err(AtmViolation::KeystrokesWithoutCard)
+		}
+		if atm.failed_pin_attempts >= MAX_PIN_ATTEMPTS &&
+			!matches!(
+				atm.expected_pin_hash,
+				Auth::CardRetained | Auth::Maintenance { card_retained: true }
+			) {
+			return Err(AtmViolation::TooManyPinAttempts)
+		}
+		if atm.dispensable_cash() > atm.cash_inside {
+			return Err(AtmViolation::BillsExceedCash)
+		}
+		Ok(())
+	}
+}
+
+/// Things the ATM does that the user can see, besides changing its screen.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AtmEvent {
+	/// The keyed in pin was correct.
+	PinAccepted,
+	/// The keyed in pin was incorrect.
+	PinRejected,
+	/// The given amount of cash came out of the machine.
+	CashDispensed(u64),
+	/// The given amount of cash went into the machine.
+	CashDeposited(u64),
+	/// The requested amount was more than a single session may withdraw, so nothing came out.
+	LimitExceeded,
+	/// The requested amount was more than the account holds, so nothing came out.
+	InsufficientFunds,
+	/// The requested amount was more than the machine holds, so nothing came out.
+	InsufficientCash,
+	/// The machine holds enough cash, but no combination of its bills adds up to the requested
+	/// amount, so nothing came out.
+	AmountNotDispensable,
+	/// Too many wrong pins were entered, so the card stays in the machine.
+	CardRetained,
+	/// A statement listing the most recent withdrawals and deposits was printed.
+	StatementPrinted(Vec<JournalEntry>),
+	/// The session sat idle for too long and was abandoned.
+	SessionTimedOut,
+	/// The session is over and the user's card was handed back.
+	CardReturned,
+	/// The given amount of cash is what the machine believes it holds, as reported to an
+	/// operator auditing it.
+	CashCounted(u64),
+	/// An operator took the retained card out of the machine.
+	CardReleased,
+}
+
+impl MealyStateMachine for Atm {
+	type Output = AtmEvent;
+
+	fn next_state_with_output(starting_state: &Atm, t: &Action) -> (Atm, Vec<AtmEvent>) {
+		let end = Self::next_state(starting_state, t);
+		let events = match (t, &starting_state.expected_pin_hash) {
+			(Action::PressKey(Key::Enter), Auth::Authenticating(_)) =>
+				match end.expected_pin_hash {
+					Auth::Authenticated => vec![AtmEvent::PinAccepted],
+					Auth::CardRetained => vec![AtmEvent::PinRejected, AtmEvent::CardRetained],
+					_ => vec![AtmEvent::PinRejected, AtmEvent::CardReturned],
 				},
+			(Action::PressKey(Key::Enter), Auth::Authenticated) => {
+				let amount = keyed_amount(&starting_state.keystroke_register);
+				let balance = starting_state.current_balance().unwrap_or(u64::MAX);
+				if starting_state.can_withdraw(amount) {
+					vec![AtmEvent::CashDispensed(amount), AtmEvent::CardReturned]
+				} else if starting_state.exceeds_limit(amount) {
+					vec![AtmEvent::LimitExceeded, AtmEvent::CardReturned]
+				} else if amount > balance {
+					vec![AtmEvent::InsufficientFunds, AtmEvent::CardReturned]
+				} else if amount > starting_state.dispensable_cash() {
+					vec![AtmEvent::InsufficientCash, AtmEvent::CardReturned]
+				} else {
+					vec![AtmEvent::AmountNotDispensable, AtmEvent::CardReturned]
+				}
 			},
+			(Action::PressKey(Key::Cancel), Auth::Authenticating(_) | Auth::Authenticated) =>
+				vec![AtmEvent::CardReturned],
+			(Action::PrintStatement, Auth::Authenticated) =>
+				vec![AtmEvent::StatementPrinted(starting_state.statement())],
+			(Action::InsertCash(amount), Auth::Authenticated)
+				if end.expected_pin_hash == Auth::Waiting =>
+				vec![AtmEvent::CashDeposited(*amount), AtmEvent::CardReturned],
+			(Action::AuditCash, Auth::Maintenance { .. }) =>
+				vec![AtmEvent::CashCounted(starting_state.cash_inside)],
+			(Action::ReleaseCard, Auth::Maintenance { card_retained: true }) =>
+				vec![AtmEvent::CardReleased],
+			(Action::Tick(_), Auth::Authenticating(_) | Auth::Authenticated)
+				if end.expected_pin_hash == Auth::Waiting =>
+				vec![AtmEvent::SessionTimedOut, AtmEvent::CardReturned],
+			_ => vec![],
+		};
+		(end, events)
+	}
+}
+
+/// The reasons an ATM refuses an action outright.
+///
+/// Entering a wrong pin or asking for more cash than the machine holds are not errors in this
+/// sense. Those actions are accepted and end the session, just not the way the user hoped.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AtmError {
+	/// A key was pressed before any card was swiped.
+	NoCardSwiped,
+	/// A card was swiped while a session was already in progress.
+	SessionInProgress,
+	/// The machine retained a card and will do nothing until an operator resets it.
+	CardRetained,
+	/// A card was swiped that none of the ATM's accounts belong to.
+	UnknownCard,
+	/// Cash was inserted or a statement requested before the user authenticated.
+	NotAuthenticated,
+	/// The machine cannot count that much cash.
+	TooMuchCash,
+	/// An operator reset was requested, or an operator tried to release a card, but there is no
+	/// retained card to take out.
+	NoCardRetained,
+	/// The machine is being serviced, so it serves no customers until the operator logs out.
+	InMaintenance,
+	/// An operator action was requested without logging in with the master pin first.
+	NotInMaintenance,
+	/// The master pin was wrong, or this machine has no master pin at all.
+	WrongMasterPin,
+}
+
+impl TryStateMachine for Atm {
+	type Error = AtmError;
+
+	fn try_next_state(starting_state: &Atm, t: &Action) -> Result<Atm, AtmError> {
+		match (t, &starting_state.expected_pin_hash) {
+			(Action::OperatorLogin(master_pin), Auth::Waiting | Auth::CardRetained)
+				if starting_state.master_pin_hash != Some(*master_pin) =>
+				Err(AtmError::WrongMasterPin),
+			(Action::OperatorLogin(_), Auth::Authenticating(_) | Auth::Authenticated) =>
+				Err(AtmError::SessionInProgress),
+			(Action::AddCash(amount), Auth::Maintenance { .. })
+				if starting_state.cash_inside.checked_add(*amount).is_none() =>
+				Err(AtmError::TooMuchCash),
+			(Action::ReleaseCard, Auth::Maintenance { card_retained: false }) =>
+				Err(AtmError::NoCardRetained),
+			(
+				Action::AddCash(_) |
+				Action::AuditCash |
+				Action::ReleaseCard |
+				Action::OperatorLogout,
+				Auth::Maintenance { .. },
+			) => Ok(Self::next_state(starting_state, t)),
+			(_, Auth::Maintenance { .. }) => Err(AtmError::InMaintenance),
+			(
+				Action::AddCash(_) |
+				Action::AuditCash |
+				Action::ReleaseCard |
+				Action::OperatorLogout,
+				_,
+			) => Err(AtmError::NotInMaintenance),
+			(Action::OperatorReset, Auth::CardRetained) => Ok(Self::next_state(starting_state, t)),
+			(Action::OperatorReset, _) => Err(AtmError::NoCardRetained),
+			(
+				Action::PressKey(_) |
+				Action::SwipeCard(_) |
+				Action::InsertCash(_) |
+				Action::PrintStatement,
+				Auth::CardRetained,
+			) => Err(AtmError::CardRetained),
+			(
+				Action::PressKey(_) | Action::InsertCash(_) | Action::PrintStatement,
+				Auth::Waiting,
+			) => Err(AtmError::NoCardSwiped),
+			(Action::InsertCash(_) | Action::PrintStatement, Auth::Authenticating(_)) =>
+				Err(AtmError::NotAuthenticated),
+			(Action::InsertCash(amount), Auth::Authenticated)
+				if !starting_state.can_deposit(*amount) =>
+				Err(AtmError::TooMuchCash),
+			(Action::SwipeCard(card), Auth::Waiting)
+				if starting_state.accounts.is_some() && starting_state.balance(*card).is_none() =>
+				Err(AtmError::UnknownCard),
+			(Action::SwipeCard(_), Auth::Authenticating(_) | Auth::Authenticated) =>
+				Err(AtmError::SessionInProgress),
+			_ => Ok(Self::next_state(starting_state, t)),
 		}
 	}
 }
 
 #[test]
 fn sm_3_simple_swipe_card() {
-	let start =
-		Atm { cash_inside: 10, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
 	let end = Atm::next_state(&start, &Action::SwipeCard(1234));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -186,12 +1108,14 @@ fn sm_3_swipe_card_again_part_way_through() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::SwipeCard(1234));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -200,12 +1124,14 @@ fn sm_3_swipe_card_again_part_way_through() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: vec![Key::One, Key::Three],
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::SwipeCard(1234));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: vec![Key::One, Key::Three],
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -213,11 +1139,19 @@ fn sm_3_swipe_card_again_part_way_through() {
 
 #[test]
 fn sm_3_press_key_before_card_swipe() {
-	let start =
-		Atm { cash_inside: 10, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::One));
-	let expected =
-		Atm { cash_inside: 10, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let expected = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
 
 	assert_eq!(end, expected);
 }
@@ -228,12 +1162,14 @@ fn sm_3_enter_single_digit_of_pin() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::One));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: vec![Key::One],
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -242,12 +1178,14 @@ fn sm_3_enter_single_digit_of_pin() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: vec![Key::One],
+		..Default::default()
 	};
 	let end1 = Atm::next_state(&start, &Action::PressKey(Key::Two));
 	let expected1 = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(1234),
 		keystroke_register: vec![Key::One, Key::Two],
+		..Default::default()
 	};
 
 	assert_eq!(end1, expected1);
@@ -257,16 +1195,22 @@ fn sm_3_enter_single_digit_of_pin() {
 fn sm_3_enter_wrong_pin() {
 	// Create hash of pin
 	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
-	let pin_hash = crate::hash(&pin);
+	let pin_hash = crate::hash_with_domain(crate::domain::ATM_PIN, &pin);
 
 	let start = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(pin_hash),
 		keystroke_register: vec![Key::Three, Key::Three, Key::Three, Key::Three],
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
-	let expected =
-		Atm { cash_inside: 10, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let expected = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		failed_pin_attempts: 1,
+		..Default::default()
+	};
 
 	assert_eq!(end, expected);
 }
@@ -275,18 +1219,20 @@ fn sm_3_enter_wrong_pin() {
 fn sm_3_enter_correct_pin() {
 	// Create hash of pin
 	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
-	let pin_hash = crate::hash(&pin);
+	let pin_hash = crate::hash_with_domain(crate::domain::ATM_PIN, &pin);
 
 	let start = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticating(pin_hash),
 		keystroke_register: vec![Key::One, Key::Two, Key::Three, Key::Four],
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -298,12 +1244,14 @@ fn sm_3_enter_single_digit_of_withdraw_amount() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: Vec::new(),
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::One));
 	let expected = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: vec![Key::One],
+		..Default::default()
 	};
 
 	assert_eq!(end, expected);
@@ -312,12 +1260,14 @@ fn sm_3_enter_single_digit_of_withdraw_amount() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: vec![Key::One],
+		..Default::default()
 	};
 	let end1 = Atm::next_state(&start, &Action::PressKey(Key::Four));
 	let expected1 = Atm {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: vec![Key::One, Key::Four],
+		..Default::default()
 	};
 
 	assert_eq!(end1, expected1);
@@ -329,10 +1279,15 @@ fn sm_3_try_to_withdraw_too_much() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: vec![Key::One, Key::Four],
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
-	let expected =
-		Atm { cash_inside: 10, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let expected = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
 
 	assert_eq!(end, expected);
 }
@@ -343,10 +1298,1029 @@ fn sm_3_withdraw_acceptable_amount() {
 		cash_inside: 10,
 		expected_pin_hash: Auth::Authenticated,
 		keystroke_register: vec![Key::One],
+		..Default::default()
+	};
+	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+	let expected = Atm {
+		cash_inside: 9,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		journal: VecDeque::from([JournalEntry::Withdrawal(1)]),
+		..Default::default()
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_3_pin_hashed_outside_its_domain_is_rejected() {
+	// A hash of the right keys, but made for some other purpose, must not unlock the ATM.
+	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+	let pin_hash = crate::hash(&pin);
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(pin_hash),
+		keystroke_register: vec![Key::One, Key::Two, Key::Three, Key::Four],
+		..Default::default()
 	};
 	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
-	let expected =
-		Atm { cash_inside: 9, expected_pin_hash: Auth::Waiting, keystroke_register: Vec::new() };
+	let expected = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		failed_pin_attempts: 1,
+		..Default::default()
+	};
 
 	assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_3_try_press_key_before_card_swipe_is_rejected() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	let end = Atm::try_next_state(&start, &Action::PressKey(Key::One));
+
+	assert_eq!(end, Err(AtmError::NoCardSwiped));
+}
+
+#[test]
+fn sm_3_try_swipe_card_during_session_is_rejected() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(1234),
+		keystroke_register: vec![Key::One],
+		..Default::default()
+	};
+	assert_eq!(
+		Atm::try_next_state(&start, &Action::SwipeCard(1234)),
+		Err(AtmError::SessionInProgress)
+	);
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	assert_eq!(
+		Atm::try_next_state(&start, &Action::SwipeCard(1234)),
+		Err(AtmError::SessionInProgress)
+	);
+}
+
+#[test]
+fn sm_3_try_wrong_pin_is_accepted() {
+	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+	let pin_hash = crate::hash_with_domain(crate::domain::ATM_PIN, &pin);
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(pin_hash),
+		keystroke_register: vec![Key::Three],
+		..Default::default()
+	};
+	let end = Atm::try_next_state(&start, &Action::PressKey(Key::Enter));
+	let expected = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		failed_pin_attempts: 1,
+		..Default::default()
+	};
+
+	assert_eq!(end, Ok(expected));
+}
+
+#[test]
+fn sm_3_withdrawal_emits_cash_dispensed() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Four],
+		..Default::default()
+	};
+	let (end, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+
+	assert_eq!(end, Atm::next_state(&start, &Action::PressKey(Key::Enter)));
+	assert_eq!(events, vec![AtmEvent::CashDispensed(4), AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_withdrawing_too_much_emits_insufficient_cash() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::One, Key::Four],
+		..Default::default()
+	};
+	let (_, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+
+	assert_eq!(events, vec![AtmEvent::InsufficientCash, AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_pin_entry_emits_outcome() {
+	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+	let pin_hash = crate::hash_with_domain(crate::domain::ATM_PIN, &pin);
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(pin_hash),
+		keystroke_register: pin,
+		..Default::default()
+	};
+	let (_, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::PinAccepted]);
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(pin_hash),
+		keystroke_register: vec![Key::Two],
+		..Default::default()
+	};
+	let (_, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::PinRejected, AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_typing_keys_emits_nothing() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	let (_, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Three));
+
+	assert!(events.is_empty());
+}
+
+#[test]
+fn sm_3_keystrokes_while_waiting_violate_invariant() {
+	let atm = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: vec![Key::One],
+		..Default::default()
+	};
+
+	assert_eq!(Atm::check(&atm), Err(AtmViolation::KeystrokesWithoutCard));
+}
+
+#[test]
+fn sm_3_full_session_upholds_invariants() {
+	use super::InvariantChecked;
+
+	let pin = vec![Key::One, Key::Two];
+	let pin_hash = crate::hash_with_domain(crate::domain::ATM_PIN, &pin);
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	let end = InvariantChecked::<Atm>::apply_all(
+		&start,
+		&[
+			Action::PressKey(Key::Four),
+			Action::SwipeCard(pin_hash),
+			Action::PressKey(Key::One),
+			Action::PressKey(Key::Two),
+			Action::PressKey(Key::Enter),
+			Action::SwipeCard(pin_hash),
+			Action::PressKey(Key::Three),
+			Action::PressKey(Key::Enter),
+		],
+	);
+	let expected = Atm {
+		cash_inside: 7,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		journal: VecDeque::from([JournalEntry::Withdrawal(3)]),
+		..Default::default()
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_3_atm_json_round_trip() {
+	let atm = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(1234),
+		keystroke_register: vec![Key::One, Key::Four],
+		..Default::default()
+	};
+	let json = serde_json::to_string(&atm).unwrap();
+
+	assert_eq!(
+		json,
+		r#"{"cash_inside":10,"expected_pin_hash":{"Authenticating":1234},"keystroke_register":["One","Four"],"session_timeout":{"secs":30,"nanos":0},"idle_time":{"secs":0,"nanos":0},"failed_pin_attempts":0,"accounts":null,"bills":null,"journal":[],"withdrawal_limit":null,"master_pin_hash":null}"#
+	);
+	assert_eq!(serde_json::from_str::<Atm>(&json).unwrap(), atm);
+}
+
+#[test]
+fn sm_3_actions_json_round_trip() {
+	let actions = vec![Action::SwipeCard(1234), Action::PressKey(Key::Enter)];
+	let json = serde_json::to_string(&actions).unwrap();
+
+	assert_eq!(json, r#"[{"SwipeCard":1234},{"PressKey":"Enter"}]"#);
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	let decoded: Vec<Action> = serde_json::from_str(&json).unwrap();
+	assert_eq!(Atm::apply_all(&start, &decoded), Atm::apply_all(&start, &actions));
+}
+
+#[test]
+fn sm_3_session_flow_graph() {
+	use super::state_graph::explore;
+
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Waiting,
+		keystroke_register: Vec::new(),
+		..Default::default()
+	};
+	let graph = explore::<Atm>(&start, 200);
+	let dot = graph.to_dot_by(|atm| {
+		match atm.expected_pin_hash {
+			Auth::Waiting => "Waiting",
+			Auth::Authenticating(_) => "Authenticating",
+			Auth::Authenticated => "Authenticated",
+			Auth::CardRetained => "CardRetained",
+			Auth::Maintenance { .. } => "Maintenance",
+		}
+		.to_string()
+	});
+
+	assert!(dot.contains("\"Waiting\" -> \"Authenticating\" [label=\"SwipeCard("));
+	assert!(dot.contains("\"Authenticating\" -> \"Authenticated\" [label=\"PressKey(Enter)\"]"));
+	assert!(dot.contains("\"Authenticating\" -> \"Waiting\" [label=\"PressKey(Enter)\"]"));
+	assert!(dot.contains("\"Authenticated\" -> \"Waiting\" [label=\"PressKey(Enter)\"]"));
+	assert!(!dot.contains("\"Waiting\" -> \"Authenticated\""));
+}
+
+#[test]
+fn sm_3_withdraw_amount_with_every_digit() {
+	let start = Atm {
+		cash_inside: 10_000,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Nine, Key::Zero, Key::Eight, Key::Five],
+		..Default::default()
+	};
+	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+
+	assert_eq!(
+		end,
+		Atm { journal: VecDeque::from([JournalEntry::Withdrawal(9085)]), ..Atm::new(915) }
+	);
+	assert_eq!(keyed_amount(&[Key::Six, Key::Seven, Key::Zero]), 670);
+	assert_eq!(keyed_amount(&vec![Key::Nine; 30]), u64::MAX);
+}
+
+#[test]
+fn sm_3_cancel_half_entered_pin() {
+	let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticating(pin_hash(&pin)),
+		keystroke_register: vec![Key::One, Key::Two],
+		..Default::default()
+	};
+	let (end, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Cancel));
+
+	assert_eq!(end, Atm::new(10));
+	assert_eq!(events, vec![AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_cancel_withdrawal() {
+	let start = Atm {
+		cash_inside: 10,
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Five],
+		..Default::default()
+	};
+	let (end, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Cancel));
+
+	assert_eq!(end, Atm::new(10));
+	assert_eq!(events, vec![AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_cancel_without_card() {
+	let end = Atm::next_state(&Atm::new(10), &Action::PressKey(Key::Cancel));
+
+	assert_eq!(end, Atm::new(10));
+	assert_eq!(
+		Atm::try_next_state(&Atm::new(10), &Action::PressKey(Key::Cancel)),
+		Err(AtmError::NoCardSwiped)
+	);
+}
+
+#[test]
+fn sm_3_idle_session_times_out() {
+	let start = Atm::apply_all(
+		&Atm::with_session_timeout(10, Duration::from_secs(30)),
+		&[Action::SwipeCard(1234), Action::PressKey(Key::One)],
+	);
+
+	let waiting = Atm::next_state(&start, &Action::Tick(Duration::from_secs(30)));
+	assert_eq!(waiting.expected_pin_hash, Auth::Authenticating(1234));
+	assert_eq!(waiting.keystroke_register, vec![Key::One]);
+
+	let (end, events) =
+		Atm::next_state_with_output(&waiting, &Action::Tick(Duration::from_secs(1)));
+	assert_eq!(end, Atm::with_session_timeout(10, Duration::from_secs(30)));
+	assert_eq!(events, vec![AtmEvent::SessionTimedOut, AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_activity_resets_idle_time() {
+	let pin = vec![Key::One, Key::Two];
+	let timeout = Duration::from_secs(30);
+	let end = Atm::apply_all(
+		&Atm::with_session_timeout(10, timeout),
+		&[
+			Action::SwipeCard(pin_hash(&pin)),
+			Action::Tick(Duration::from_secs(20)),
+			Action::PressKey(Key::One),
+			Action::Tick(Duration::from_secs(20)),
+			Action::PressKey(Key::Two),
+			Action::Tick(Duration::from_secs(20)),
+			Action::PressKey(Key::Enter),
+			Action::Tick(Duration::from_secs(20)),
+		],
+	);
+
+	assert_eq!(end.expected_pin_hash, Auth::Authenticated);
+	assert_eq!(end.idle_time, Duration::from_secs(20));
+}
+
+#[test]
+fn sm_3_ticks_without_card_change_nothing() {
+	let start = Atm::new(10);
+	let (end, events) = Atm::next_state_with_output(&start, &Action::Tick(Duration::MAX));
+
+	assert_eq!(end, start);
+	assert!(events.is_empty());
+	assert_eq!(Atm::try_next_state(&start, &Action::Tick(Duration::MAX)), Ok(start));
+}
+
+#[test]
+fn sm_3_timeout_driven_by_clock() {
+	use super::clock::{MockClock, Ticker};
+
+	let mut ticker = Ticker::new(MockClock::new());
+	let mut atm = Atm::next_state(&Atm::new(10), &Action::SwipeCard(1234));
+
+	ticker.clock().advance(Duration::from_secs(29));
+	atm = ticker.tick::<Atm>(&atm);
+	assert_eq!(atm.expected_pin_hash, Auth::Authenticating(1234));
+
+	// Idle time adds up across ticks.
+	ticker.clock().advance(Duration::from_secs(2));
+	atm = ticker.tick::<Atm>(&atm);
+	assert_eq!(atm, Atm::new(10));
+}
+
+#[test]
+fn sm_3_card_retained_after_too_many_wrong_pins() {
+	let pin = vec![Key::One, Key::Two];
+	let wrong_guess = [
+		Action::SwipeCard(pin_hash(&pin)),
+		Action::PressKey(Key::Three),
+		Action::PressKey(Key::Enter),
+	];
+	let mut atm = Atm::new(10);
+	for _ in 1..MAX_PIN_ATTEMPTS {
+		atm = Atm::apply_all(&atm, &wrong_guess);
+		assert_eq!(atm.expected_pin_hash, Auth::Waiting);
+	}
+	atm = Atm::apply_all(&atm, &wrong_guess[..2]);
+
+	let (end, events) = Atm::next_state_with_output(&atm, &Action::PressKey(Key::Enter));
+	assert_eq!(end.expected_pin_hash, Auth::CardRetained);
+	assert_eq!(events, vec![AtmEvent::PinRejected, AtmEvent::CardRetained]);
+}
+
+#[test]
+fn sm_3_correct_pin_resets_failed_attempts() {
+	let pin = vec![Key::One, Key::Two];
+	let start = Atm { failed_pin_attempts: MAX_PIN_ATTEMPTS - 1, ..Atm::new(10) };
+	let end = Atm::apply_all(
+		&start,
+		&[
+			Action::SwipeCard(pin_hash(&pin)),
+			Action::PressKey(Key::One),
+			Action::PressKey(Key::Two),
+			Action::PressKey(Key::Enter),
+		],
+	);
+
+	assert_eq!(end.expected_pin_hash, Auth::Authenticated);
+	assert_eq!(end.failed_pin_attempts, 0);
+}
+
+#[test]
+fn sm_3_retained_card_ignores_everything_but_operator() {
+	let retained = Atm {
+		expected_pin_hash: Auth::CardRetained,
+		failed_pin_attempts: MAX_PIN_ATTEMPTS,
+		..Atm::new(10)
+	};
+
+	for action in [
+		Action::SwipeCard(1234),
+		Action::PressKey(Key::One),
+		Action::PressKey(Key::Enter),
+		Action::PressKey(Key::Cancel),
+		Action::Tick(Duration::MAX),
+	] {
+		assert_eq!(Atm::next_state(&retained, &action), retained);
+	}
+	assert_eq!(
+		Atm::try_next_state(&retained, &Action::PressKey(Key::Cancel)),
+		Err(AtmError::CardRetained)
+	);
+	assert_eq!(Atm::try_next_state(&retained, &Action::OperatorReset), Ok(Atm::new(10)));
+	assert_eq!(
+		Atm::try_next_state(&Atm::new(10), &Action::OperatorReset),
+		Err(AtmError::NoCardRetained)
+	);
+}
+
+#[test]
+fn sm_3_too_many_attempts_violate_invariant() {
+	let atm = Atm { failed_pin_attempts: MAX_PIN_ATTEMPTS, ..Atm::new(10) };
+
+	assert_eq!(Atm::check(&atm), Err(AtmViolation::TooManyPinAttempts));
+}
+
+#[test]
+fn sm_3_deposit_ends_session() {
+	let start = Atm { expected_pin_hash: Auth::Authenticated, ..Atm::new(10) };
+	let (end, events) = Atm::next_state_with_output(&start, &Action::InsertCash(25));
+
+	assert_eq!(end, Atm { journal: VecDeque::from([JournalEntry::Deposit(25)]), ..Atm::new(35) });
+	assert_eq!(events, vec![AtmEvent::CashDeposited(25), AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_deposit_requires_authentication() {
+	let authenticating = Atm::next_state(&Atm::new(10), &Action::SwipeCard(1234));
+
+	assert_eq!(Atm::next_state(&Atm::new(10), &Action::InsertCash(5)), Atm::new(10));
+	assert_eq!(Atm::next_state(&authenticating, &Action::InsertCash(5)), authenticating);
+	assert_eq!(
+		Atm::try_next_state(&Atm::new(10), &Action::InsertCash(5)),
+		Err(AtmError::NoCardSwiped)
+	);
+	assert_eq!(
+		Atm::try_next_state(&authenticating, &Action::InsertCash(5)),
+		Err(AtmError::NotAuthenticated)
+	);
+}
+
+#[test]
+fn sm_3_deposit_too_large_is_refused() {
+	let start = Atm { expected_pin_hash: Auth::Authenticated, ..Atm::new(u64::MAX - 1) };
+	let (end, events) = Atm::next_state_with_output(&start, &Action::InsertCash(2));
+
+	assert_eq!(end, start);
+	assert!(events.is_empty());
+	assert_eq!(Atm::try_next_state(&start, &Action::InsertCash(2)), Err(AtmError::TooMuchCash));
+}
+
+/// Swipe the given card, key in its pin, and then key in the given amount.
+#[cfg(test)]
+fn session(pin: &[Key], amount: &[Key]) -> Vec<Action> {
+	let mut actions = vec![Action::SwipeCard(pin_hash(pin))];
+	actions.extend(pin.iter().cloned().map(Action::PressKey));
+	actions.push(Action::PressKey(Key::Enter));
+	actions.extend(amount.iter().cloned().map(Action::PressKey));
+	actions
+}
+
+#[test]
+fn sm_3_withdrawal_limited_by_balance() {
+	let alice = [Key::One, Key::Two];
+	let bob = [Key::Three, Key::Four];
+	let atm =
+		Atm::with_accounts(100, BTreeMap::from([(pin_hash(&alice), 30), (pin_hash(&bob), 500)]));
+
+	let atm = Atm::apply_all(&atm, &session(&alice, &[Key::Two, Key::Zero]));
+	let (atm, events) = Atm::next_state_with_output(&atm, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::CashDispensed(20), AtmEvent::CardReturned]);
+	assert_eq!(atm.cash_inside(), 80);
+	assert_eq!(atm.balance(pin_hash(&alice)), Some(10));
+
+	let atm = Atm::apply_all(&atm, &session(&alice, &[Key::Two, Key::Zero]));
+	let (atm, events) = Atm::next_state_with_output(&atm, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::InsufficientFunds, AtmEvent::CardReturned]);
+	assert_eq!(atm.balance(pin_hash(&alice)), Some(10));
+
+	// Bob has plenty in the bank, but the machine doesn't hold that much.
+	let atm = Atm::apply_all(&atm, &session(&bob, &[Key::Two, Key::Zero, Key::Zero]));
+	let (atm, events) = Atm::next_state_with_output(&atm, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::InsufficientCash, AtmEvent::CardReturned]);
+	assert_eq!(atm.balance(pin_hash(&bob)), Some(500));
+	assert_eq!(atm.cash_inside(), 80);
+}
+
+#[test]
+fn sm_3_deposit_credits_account() {
+	let alice = [Key::One, Key::Two];
+	let start = Atm::with_accounts(100, BTreeMap::from([(pin_hash(&alice), 30)]));
+
+	let atm = Atm::apply_all(&start, &session(&alice, &[]));
+	let atm = Atm::next_state(&atm, &Action::InsertCash(15));
+
+	assert_eq!(
+		atm,
+		Atm {
+			journal: VecDeque::from([JournalEntry::Deposit(15)]),
+			..Atm::with_accounts(115, BTreeMap::from([(pin_hash(&alice), 45)]))
+		}
+	);
+}
+
+#[test]
+fn sm_3_unknown_card_is_rejected() {
+	let start = Atm::with_accounts(100, BTreeMap::from([(pin_hash(&[Key::One]), 30)]));
+	let stranger = Action::SwipeCard(pin_hash(&[Key::Two]));
+
+	assert_eq!(Atm::next_state(&start, &stranger), start);
+	assert_eq!(Atm::try_next_state(&start, &stranger), Err(AtmError::UnknownCard));
+	assert_eq!(start.balance(pin_hash(&[Key::Two])), None);
+	assert_eq!(Atm::new(100).balance(pin_hash(&[Key::One])), None);
+}
+
+#[test]
+fn sm_3_pick_bills_backtracks() {
+	let bills = BTreeMap::from([(10, 0), (20, 3), (50, 1)]);
+
+	assert_eq!(pick_bills(&bills, 60), Some(BTreeMap::from([(20, 3)])));
+	assert_eq!(pick_bills(&bills, 70), Some(BTreeMap::from([(20, 1), (50, 1)])));
+	assert_eq!(pick_bills(&bills, 0), Some(BTreeMap::new()));
+	assert_eq!(pick_bills(&bills, 30), None);
+	assert_eq!(pick_bills(&bills, 200), None);
+}
+
+#[test]
+fn sm_3_pick_bills_is_fast_for_full_dispensers() {
+	let bills = BTreeMap::from([(10, 100_000), (20, 100_000), (50, 100_000)]);
+
+	// Nothing adds up to an amount that isn't a multiple of ten, so no search is needed at all.
+	assert_eq!(pick_bills(&bills, 99_995), None);
+	assert_eq!(
+		pick_bills(&bills, 7_999_990),
+		Some(BTreeMap::from([(10, 99_999), (20, 100_000), (50, 100_000)]))
+	);
+	assert_eq!(pick_bills(&bills, 8_000_010), None);
+
+	// The same, keyed in at the ATM.
+	let keyed_in = |keys: Vec<Key>| {
+		let start = Atm {
+			expected_pin_hash: Auth::Authenticated,
+			keystroke_register: keys,
+			..Atm::with_bills(BTreeMap::from([(10, 2000), (20, 2000), (50, 2000)]))
+		};
+		Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter)).1
+	};
+	let nines = |last| vec![Key::Nine, Key::Nine, Key::Nine, Key::Nine, last];
+	assert_eq!(
+		keyed_in(nines(Key::Five)),
+		vec![AtmEvent::AmountNotDispensable, AtmEvent::CardReturned]
+	);
+	assert_eq!(
+		keyed_in(nines(Key::Zero)),
+		vec![AtmEvent::CashDispensed(99_990), AtmEvent::CardReturned]
+	);
+}
+
+#[test]
+fn sm_3_withdrawal_paid_in_bills() {
+	let start = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Six, Key::Zero],
+		..Atm::with_bills(BTreeMap::from([(10, 0), (20, 3), (50, 1)]))
+	};
+	let (end, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+
+	assert_eq!(events, vec![AtmEvent::CashDispensed(60), AtmEvent::CardReturned]);
+	assert_eq!(
+		end,
+		Atm {
+			journal: VecDeque::from([JournalEntry::Withdrawal(60)]),
+			..Atm::with_bills(BTreeMap::from([(10, 0), (20, 0), (50, 1)]))
+		}
+	);
+}
+
+#[test]
+fn sm_3_withdrawal_not_made_of_bills_fails() {
+	let start = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Three, Key::Five],
+		..Atm::with_bills(BTreeMap::from([(10, 5), (20, 5)]))
+	};
+	let (end, events) = Atm::next_state_with_output(&start, &Action::PressKey(Key::Enter));
+
+	assert_eq!(events, vec![AtmEvent::AmountNotDispensable, AtmEvent::CardReturned]);
+	assert_eq!(end, Atm::with_bills(BTreeMap::from([(10, 5), (20, 5)])));
+}
+
+#[test]
+fn sm_3_deposits_are_not_dispensed() {
+	let start = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		..Atm::with_bills(BTreeMap::from([(20, 1)]))
+	};
+	let deposited = Atm::next_state(&start, &Action::InsertCash(100));
+	assert_eq!(deposited.cash_inside(), 120);
+	assert_eq!(Atm::check(&deposited), Ok(()));
+
+	let withdrawing = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Five, Key::Zero],
+		..deposited
+	};
+	let (_, events) = Atm::next_state_with_output(&withdrawing, &Action::PressKey(Key::Enter));
+	assert_eq!(events, vec![AtmEvent::InsufficientCash, AtmEvent::CardReturned]);
+}
+
+#[test]
+fn sm_3_session_event_sequences() {
+	use AtmEvent::*;
+
+	let pin = [Key::One, Key::Two];
+	let enter = Action::PressKey(Key::Enter);
+	let wrong_guess = vec![
+		Action::SwipeCard(pin_hash(&pin)),
+		Action::PressKey(Key::Two),
+		Action::PressKey(Key::One),
+		enter.clone(),
+	];
+	let scenarios = [
+		(session(&pin, &[Key::Four]), vec![PinAccepted]),
+		(
+			[session(&pin, &[Key::Four]), vec![enter.clone()]].concat(),
+			vec![PinAccepted, CashDispensed(4), CardReturned],
+		),
+		(
+			[session(&pin, &[Key::Five, Key::Zero]), vec![enter.clone()]].concat(),
+			vec![PinAccepted, InsufficientCash, CardReturned],
+		),
+		(wrong_guess.clone(), vec![PinRejected, CardReturned]),
+		(
+			vec![wrong_guess.clone(); MAX_PIN_ATTEMPTS as usize].concat(),
+			vec![PinRejected, CardReturned, PinRejected, CardReturned, PinRejected, CardRetained],
+		),
+		(
+			[session(&pin, &[]), vec![Action::InsertCash(5)]].concat(),
+			vec![PinAccepted, CashDeposited(5), CardReturned],
+		),
+		(
+			[session(&pin, &[Key::Three]), vec![Action::PressKey(Key::Cancel)]].concat(),
+			vec![PinAccepted, CardReturned],
+		),
+		(
+			vec![Action::SwipeCard(pin_hash(&pin)), Action::Tick(Duration::MAX)],
+			vec![SessionTimedOut, CardReturned],
+		),
+	];
+
+	for (actions, expected) in scenarios {
+		let (_, events) = Atm::apply_all_with_output(&Atm::new(10), &actions);
+		assert_eq!(events, expected, "for {:?}", actions);
+	}
+}
+
+#[test]
+fn sm_3_account_event_sequences() {
+	let pin = [Key::One, Key::Two];
+	let atm = Atm::with_accounts(100, BTreeMap::from([(pin_hash(&pin), 5)]));
+	let actions = [session(&pin, &[Key::Nine]), vec![Action::PressKey(Key::Enter)]].concat();
+	let (_, events) = Atm::apply_all_with_output(&atm, &actions);
+
+	assert_eq!(
+		events,
+		vec![AtmEvent::PinAccepted, AtmEvent::InsufficientFunds, AtmEvent::CardReturned]
+	);
+}
+
+#[test]
+fn sm_3_statement_shows_recent_transactions() {
+	let pin = [Key::One, Key::Two];
+	let actions = [
+		session(&pin, &[Key::Three]),
+		vec![Action::PressKey(Key::Enter)],
+		session(&pin, &[]),
+		vec![Action::InsertCash(20)],
+		session(&pin, &[]),
+		vec![Action::PrintStatement],
+	]
+	.concat();
+	let (end, events) = Atm::apply_all_with_output(&Atm::new(10), &actions);
+
+	assert_eq!(end.expected_pin_hash, Auth::Authenticated);
+	assert_eq!(
+		events.last(),
+		Some(&AtmEvent::StatementPrinted(vec![
+			JournalEntry::Withdrawal(3),
+			JournalEntry::Deposit(20)
+		]))
+	);
+}
+
+#[test]
+fn sm_3_journal_is_bounded() {
+	let mut atm = Atm { expected_pin_hash: Auth::Authenticated, ..Atm::new(0) };
+	for amount in 1..=(JOURNAL_CAPACITY as u64 + 3) {
+		atm = Atm::next_state(&atm, &Action::InsertCash(amount));
+		atm.expected_pin_hash = Auth::Authenticated;
+	}
+
+	assert_eq!(atm.journal.len(), JOURNAL_CAPACITY);
+	assert_eq!(atm.journal.front(), Some(&JournalEntry::Deposit(4)));
+	let (_, events) = Atm::next_state_with_output(&atm, &Action::PrintStatement);
+	let last = JOURNAL_CAPACITY as u64 + 3;
+	assert_eq!(
+		events,
+		vec![AtmEvent::StatementPrinted(
+			(last - STATEMENT_LENGTH as u64 + 1..=last).map(JournalEntry::Deposit).collect()
+		)]
+	);
+}
+
+#[test]
+fn sm_3_failed_withdrawals_are_not_journaled() {
+	let start = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Five, Key::Zero],
+		..Atm::new(10)
+	};
+	let end = Atm::next_state(&start, &Action::PressKey(Key::Enter));
+
+	assert!(end.journal.is_empty());
+	assert_eq!(
+		Atm::try_next_state(&Atm::new(10), &Action::PrintStatement),
+		Err(AtmError::NoCardSwiped)
+	);
+}
+
+#[test]
+fn sm_3_backspace_corrects_typo_in_pin() {
+	let pin = [Key::One, Key::Two];
+	let start = Atm::new(10);
+	let keys = [Key::One, Key::Three, Key::Backspace, Key::Two, Key::Enter];
+	let actions = std::iter::once(Action::SwipeCard(pin_hash(&pin)))
+		.chain(keys.into_iter().map(Action::PressKey))
+		.collect::<Vec<_>>();
+
+	let end = Atm::apply_all(&start, &actions);
+	assert_eq!(end.expected_pin_hash, Auth::Authenticated);
+	assert_eq!(end.keystroke_register, vec![]);
+}
+
+#[test]
+fn sm_3_backspace_on_empty_register_does_nothing() {
+	let start = Atm { expected_pin_hash: Auth::Authenticating(1234), ..Atm::new(10) };
+	let end = Atm::next_state(&start, &Action::PressKey(Key::Backspace));
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_3_clear_empties_amount_but_keeps_session() {
+	let start = Atm {
+		expected_pin_hash: Auth::Authenticated,
+		keystroke_register: vec![Key::Nine, Key::Nine],
+		..Atm::new(10)
+	};
+	let end = Atm::apply_all(
+		&start,
+		&[Action::PressKey(Key::Clear), Action::PressKey(Key::Five), Action::PressKey(Key::Enter)],
+	);
+
+	assert_eq!(end, Atm { journal: VecDeque::from([JournalEntry::Withdrawal(5)]), ..Atm::new(5) });
+}
+
+#[test]
+fn sm_3_clear_without_card_does_nothing() {
+	let start = Atm::new(10);
+	let end = Atm::next_state(&start, &Action::PressKey(Key::Clear));
+
+	assert_eq!(end, start);
+	assert_eq!(
+		Atm::try_next_state(&start, &Action::PressKey(Key::Clear)),
+		Err(AtmError::NoCardSwiped)
+	);
+}
+
+#[test]
+fn sm_3_operator_refills_and_audits() {
+	let master_pin = pin_hash(&[Key::Nine, Key::Nine]);
+	let start = Atm::builder().cash_inside(10).master_pin(master_pin).build();
+
+	let (end, events) = Atm::apply_all_with_output(
+		&start,
+		&[
+			Action::OperatorLogin(master_pin),
+			Action::AddCash(90),
+			Action::AuditCash,
+			Action::OperatorLogout,
+		],
+	);
+	assert_eq!(events, vec![AtmEvent::CashCounted(100)]);
+	assert_eq!(end, Atm { cash_inside: 100, ..start });
+}
+
+#[test]
+fn sm_3_operator_needs_master_pin() {
+	let master_pin = pin_hash(&[Key::Nine, Key::Nine]);
+	let card_pin = pin_hash(&[Key::One, Key::Two]);
+	let start = Atm::builder().cash_inside(10).master_pin(master_pin).build();
+
+	assert_eq!(Atm::next_state(&start, &Action::OperatorLogin(card_pin)), start);
+	assert_eq!(
+		Atm::try_next_state(&start, &Action::OperatorLogin(card_pin)),
+		Err(AtmError::WrongMasterPin)
+	);
+	assert_eq!(Atm::try_next_state(&start, &Action::AddCash(90)), Err(AtmError::NotInMaintenance));
+	assert_eq!(Atm::next_state(&start, &Action::AddCash(90)), start);
+
+	// Without a master pin, no operator can ever get in.
+	let unserviceable = Atm::new(10);
+	assert_eq!(
+		Atm::try_next_state(&unserviceable, &Action::OperatorLogin(master_pin)),
+		Err(AtmError::WrongMasterPin)
+	);
+
+	// Nor can they take over in the middle of a customer's session.
+	let in_session = Atm::next_state(&start, &Action::SwipeCard(card_pin));
+	assert_eq!(Atm::next_state(&in_session, &Action::OperatorLogin(master_pin)), in_session);
+	assert_eq!(
+		Atm::try_next_state(&in_session, &Action::OperatorLogin(master_pin)),
+		Err(AtmError::SessionInProgress)
+	);
+}
+
+#[test]
+fn sm_3_maintenance_ignores_customers() {
+	let master_pin = pin_hash(&[Key::Nine, Key::Nine]);
+	let start = Atm::builder().cash_inside(10).master_pin(master_pin).build();
+	let maintenance = Atm::next_state(&start, &Action::OperatorLogin(master_pin));
+
+	for action in [
+		Action::SwipeCard(pin_hash(&[Key::One, Key::Two])),
+		Action::PressKey(Key::One),
+		Action::PressKey(Key::Cancel),
+		Action::InsertCash(5),
+		Action::Tick(Duration::MAX),
+	] {
+		assert_eq!(Atm::next_state(&maintenance, &action), maintenance);
+		assert_eq!(Atm::try_next_state(&maintenance, &action), Err(AtmError::InMaintenance));
+	}
+}
+
+#[test]
+fn sm_3_operator_releases_retained_card() {
+	let master_pin = pin_hash(&[Key::Nine, Key::Nine]);
+	let retained = Atm {
+		expected_pin_hash: Auth::CardRetained,
+		failed_pin_attempts: MAX_PIN_ATTEMPTS,
+		..Atm::builder().cash_inside(10).master_pin(master_pin).build()
+	};
+
+	// Logging out without releasing the card leaves it retained.
+	let maintenance = Atm::next_state(&retained, &Action::OperatorLogin(master_pin));
+	assert_eq!(maintenance.expected_pin_hash, Auth::Maintenance { card_retained: true });
+	assert_eq!(Atm::next_state(&maintenance, &Action::OperatorLogout), retained);
+
+	let (released, events) = Atm::next_state_with_output(&maintenance, &Action::ReleaseCard);
+	assert_eq!(events, vec![AtmEvent::CardReleased]);
+	assert_eq!(Atm::try_next_state(&released, &Action::ReleaseCard), Err(AtmError::NoCardRetained));
+	assert_eq!(
+		Atm::next_state(&released, &Action::OperatorLogout),
+		Atm::builder().cash_inside(10).master_pin(master_pin).build()
+	);
+}
+
+#[test]
+fn sm_3_maintenance_upholds_invariants() {
+	use super::model_checker::check_invariants;
+
+	let start = Atm::builder().cash_inside(10).master_pin(pin_hash(&DEMO_MASTER_PIN)).build();
+	// Deep enough to retain a card with three wrong pins and then log in to release it.
+	assert!(check_invariants::<Atm>(&start, 7).is_ok());
+}
+
+#[test]
+fn sm_3_builder_configures_atm() {
+	let atm = Atm::builder()
+		.cash_inside(500)
+		.session_timeout(Duration::from_secs(60))
+		.withdrawal_limit(100)
+		.build();
+
+	assert_eq!(atm.cash_inside(), 500);
+	assert_eq!(atm.session_timeout, Duration::from_secs(60));
+	assert_eq!(atm.withdrawal_limit, Some(100));
+	assert_eq!(Atm::builder().cash_inside(10).build(), Atm::new(10));
+}
+
+#[test]
+fn sm_3_withdrawal_over_limit_ends_session() {
+	let pin = [Key::One, Key::Two];
+	let atm = Atm::builder().cash_inside(500).withdrawal_limit(100).build();
+
+	let over =
+		[session(&pin, &[Key::One, Key::Zero, Key::One]), vec![Action::PressKey(Key::Enter)]]
+			.concat();
+	let (end, events) = Atm::apply_all_with_output(&atm, &over);
+	assert_eq!(
+		events,
+		vec![AtmEvent::PinAccepted, AtmEvent::LimitExceeded, AtmEvent::CardReturned]
+	);
+	assert_eq!(end, atm);
+
+	let at_limit =
+		[session(&pin, &[Key::One, Key::Zero, Key::Zero]), vec![Action::PressKey(Key::Enter)]]
+			.concat();
+	let (end, events) = Atm::apply_all_with_output(&atm, &at_limit);
+	assert_eq!(
+		events,
+		vec![AtmEvent::PinAccepted, AtmEvent::CashDispensed(100), AtmEvent::CardReturned]
+	);
+	assert_eq!(end.cash_inside(), 400);
+}
+
+#[test]
+fn sm_3_parse_transitions() {
+	assert!(matches!(Atm::parse_transition("3"), Ok(Action::PressKey(Key::Three))));
+	assert!(matches!(Atm::parse_transition("enter"), Ok(Action::PressKey(Key::Enter))));
+	assert!(matches!(Atm::parse_transition("cancel"), Ok(Action::PressKey(Key::Cancel))));
+	assert!(matches!(
+		Atm::parse_transition("swipe 1234"),
+		Ok(Action::SwipeCard(hash)) if hash == pin_hash(&[Key::One, Key::Two, Key::Three, Key::Four])
+	));
+	assert!(matches!(Atm::parse_transition("0"), Ok(Action::PressKey(Key::Zero))));
+	assert!(matches!(Atm::parse_transition("9"), Ok(Action::PressKey(Key::Nine))));
+	assert!(matches!(
+		Atm::parse_transition("tick 5"),
+		Ok(Action::Tick(elapsed)) if elapsed == Duration::from_secs(5)
+	));
+	assert!(Atm::parse_transition("tick soon").is_err());
+	assert!(matches!(Atm::parse_transition("reset"), Ok(Action::OperatorReset)));
+	assert!(matches!(Atm::parse_transition("deposit 20"), Ok(Action::InsertCash(20))));
+	assert!(matches!(Atm::parse_transition("statement"), Ok(Action::PrintStatement)));
+	assert!(Atm::parse_transition("swipe 12a5").is_err());
+	assert!(Atm::parse_transition("34").is_err());
+	assert!(Atm::parse_transition("withdraw").is_err());
+}
+
+#[test]
+fn sm_3_repl_session() {
+	let mut output = Vec::new();
+	let end = super::repl::run::<Atm>(
+		Atm::new(10),
+		"swipe 12\n1\n2\nenter\n3\nenter\n".as_bytes(),
+		&mut output,
+	)
+	.unwrap();
+
+	assert_eq!(end, Atm { journal: VecDeque::from([JournalEntry::Withdrawal(3)]), ..Atm::new(7) });
+}
+
+#[test]
+fn sm_3_model_check_invariants() {
+	use super::model_checker::check_invariants;
+
+	assert!(check_invariants::<Atm>(&Atm::new(10), 5).is_ok());
+}
+
+#[test]
+fn sm_3_model_check_finds_shortest_withdrawal() {
+	use super::model_checker::check;
+
+	// With the demo card, the quickest way to get cash is to swipe, key in the two digit pin,
+	// then withdraw a single digit amount.
+	let counterexample = check::<Atm>(&Atm::new(10), 6, |atm| atm.cash_inside >= 10).unwrap_err();
+
+	assert_eq!(counterexample.transitions.len(), 6);
+	assert!(matches!(counterexample.transitions[0], Action::SwipeCard(_)));
+	assert_eq!(counterexample.states.last().unwrap().cash_inside, 9);
+}
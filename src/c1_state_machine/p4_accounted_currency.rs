@@ -7,9 +7,24 @@
 //!
 //! In this module we design a state machine that tracks the currency balances of several users.
 //! Each user is associated with an account balance and users are able to send money to other users.
-
-use super::{StateMachine, User};
-use std::collections::HashMap;
+//!
+//! Sending money isn't free. Every transfer carries a fee on top of the amount sent, which either
+//! goes to a collector account, much like a miner or validator collecting fees, or is burned.
+//! The ledger also keeps track of the total issuance, that is how much money exists. Only minting
+//! and burning, including burning fees, may change it.
+//!
+//! Finally, only the owner of an account may send money out of it. Each transfer is signed with
+//! the sender's key, and carries the sender's nonce, which counts the transfers they have sent so
+//! far. A forged transfer has a signature that doesn't check out, and a transfer that is replayed
+//! has a nonce that has already been used, so both are rejected.
+
+use super::{Invariant, StateMachine, TryStateMachine, User};
+use crate::crypto::Pair;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	hash::{Hash, Hasher},
+};
 
 /// This state machine models a multi-user currency system. It tracks the balance of each
 /// user and allows users to send funds to one another.
@@ -23,7 +38,89 @@ pub struct AccountedCurrency;
 /// when its balance falls back to 0.
 type Balances = HashMap<User, u64>;
 
+/// Where transfer fees end up
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum FeeDestination {
+	/// Fees are destroyed, which reduces the total issuance.
+	#[default]
+	Burn,
+	/// Fees are credited to the given account.
+	Collector(User),
+}
+
+/// The state of an accounted currency system. The balances, along with how much money exists and
+/// where fees go.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Ledger {
+	/// The balance of every account
+	balances: Balances,
+	/// How much money exists. This always equals the sum of all balances.
+	total_issuance: u64,
+	/// Where transfer fees end up
+	fee_destination: FeeDestination,
+	/// How many transfers each user has sent. Nonces are kept even after an account is removed,
+	/// otherwise old transfers could be replayed once the account is refilled.
+	nonces: HashMap<User, u64>,
+}
+
+impl Ledger {
+	/// A ledger with the given balances where fees are burned
+	pub fn new(balances: Balances) -> Self {
+		let total_issuance = balances.values().sum();
+		Ledger {
+			balances,
+			total_issuance,
+			fee_destination: FeeDestination::Burn,
+			nonces: HashMap::new(),
+		}
+	}
+
+	/// The same ledger, but with fees going to the given destination instead
+	pub fn with_fee_destination(self, fee_destination: FeeDestination) -> Self {
+		Ledger { fee_destination, ..self }
+	}
+
+	/// The balance of every account
+	pub fn balances(&self) -> &Balances {
+		&self.balances
+	}
+
+	/// How much money exists
+	pub fn total_issuance(&self) -> u64 {
+		self.total_issuance
+	}
+
+	/// The nonce the given user's next transfer must carry
+	pub fn nonce(&self, user: User) -> u64 {
+		self.nonces.get(&user).copied().unwrap_or(0)
+	}
+}
+
+/// Hash maps can't be hashed directly, because the order of their entries isn't fixed. Hashing
+/// each entry on its own and sorting those hashes gives equal ledgers equal hashes, so a block
+/// header can commit to a ledger.
+impl Hash for Ledger {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		let sorted = |map: &HashMap<User, u64>| {
+			let mut entries: Vec<u64> = map.iter().map(|entry| crate::hash(&entry)).collect();
+			entries.sort_unstable();
+			entries
+		};
+		sorted(&self.balances).hash(state);
+		self.total_issuance.hash(state);
+		self.fee_destination.hash(state);
+		sorted(&self.nonces).hash(state);
+	}
+}
+
+impl From<Balances> for Ledger {
+	fn from(balances: Balances) -> Self {
+		Ledger::new(balances)
+	}
+}
+
 /// The state transitions that users can make in an accounted currency system
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub enum AccountingTransaction {
 	/// Create some new money for the given minter in the given amount
 	Mint { minter: User, amount: u64 },
@@ -31,241 +128,555 @@ pub enum AccountingTransaction {
 	/// If the burn amount exceeds the account balance, burn the entire
 	/// amount and remove the account from storage
 	Burn { burner: User, amount: u64 },
-	/// Send some tokens from one account to another
-	Transfer { sender: User, receiver: User, amount: u64 },
+	/// Send some tokens from one account to another. The sender pays the fee on top of the
+	/// amount, and the fee goes wherever the ledger sends fees. The signature is made by the
+	/// sender over everything else in the transfer, and is packed into a `u64`.
+	Transfer { sender: User, receiver: User, amount: u64, fee: u64, nonce: u64, signature: u64 },
+}
+
+impl AccountingTransaction {
+	/// A transfer signed with the sender's key
+	pub fn signed_transfer(
+		sender: User,
+		receiver: User,
+		amount: u64,
+		fee: u64,
+		nonce: u64,
+	) -> AccountingTransaction {
+		let signature = sender.pair().sign(&(sender, receiver, amount, fee, nonce)).into();
+		AccountingTransaction::Transfer { sender, receiver, amount, fee, nonce, signature }
+	}
+}
+
+/// The reasons an accounting transaction may be rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccountingError {
+	/// The transaction would not move any money at all
+	ZeroAmount,
+	/// The account that is supposed to give up money does not exist
+	UnknownAccount,
+	/// Money can only be sent to somebody else
+	SelfTransfer,
+	/// The sender does not have enough money to cover the transfer
+	InsufficientBalance,
+	/// The resulting balance is too large to be represented
+	Overflow,
+	/// The transfer wasn't signed by the sender
+	BadSignature,
+	/// The transfer's nonce isn't the sender's next one, so it is either replayed or out of order
+	BadNonce,
 }
 
 /// We model this system as a state machine with three possible transitions
 impl StateMachine for AccountedCurrency {
-	type State = Balances;
+	type State = Ledger;
 	type Transition = AccountingTransaction;
 
-	fn next_state(starting_state: &Balances, t: &AccountingTransaction) -> Balances {
-		match t {
-			AccountingTransaction::Mint { minter, amount } => starting_state.clone(),
-			AccountingTransaction::Burn { burner, amount } => starting_state.clone(),
-			AccountingTransaction::Transfer { sender, receiver, amount } => starting_state.clone(),
+	fn next_state(starting_state: &Ledger, t: &AccountingTransaction) -> Ledger {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+}
+
+/// Every invalid transaction is rejected, leaving the balances exactly as they were.
+impl TryStateMachine for AccountedCurrency {
+	type Error = AccountingError;
+
+	fn try_next_state(
+		starting_state: &Ledger,
+		t: &AccountingTransaction,
+	) -> Result<Ledger, AccountingError> {
+		todo!("Exercise 1")
+	}
+}
+
+/// The ways a ledger can be inconsistent
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LedgerViolation {
+	/// The given account has a balance of zero but wasn't removed.
+	DeadAccount(User),
+	/// The total issuance doesn't match the sum of all balances.
+	IssuanceMismatch,
+}
+
+/// The existential deposit must be respected. That is to say, accounts with a balance of zero are
+/// removed from the map rather than left lying around. Also, the total issuance accounts for
+/// exactly the money in the balances.
+impl Invariant for AccountedCurrency {
+	type Violation = LedgerViolation;
+
+	fn check(ledger: &Ledger) -> Result<(), LedgerViolation> {
+		if let Some((user, _)) = ledger.balances.iter().find(|(_, balance)| **balance == 0) {
+			return Err(LedgerViolation::DeadAccount(*user))
+		}
+		let sum = ledger
+			.balances
+			.values()
+			.try_fold(0u64, |sum, balance| sum.checked_add(*balance));
+		if sum != Some(ledger.total_issuance) {
+			return Err(LedgerViolation::IssuanceMismatch)
 		}
+		Ok(())
 	}
 }
 
 #[test]
 fn sm_4_mint_creates_account() {
-	let start = HashMap::new();
+	let start = Ledger::default();
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Mint { minter: User::Alice, amount: 100 },
 	);
-	let expected = HashMap::from([(User::Alice, 100)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_mint_creates_second_account() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Mint { minter: User::Bob, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_mint_increases_balance() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Mint { minter: User::Alice, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 150)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 150)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_empty_mint() {
-	let start = HashMap::new();
+	let start = Ledger::default();
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Mint { minter: User::Alice, amount: 0 },
 	);
-	let expected = HashMap::new();
+	let expected = Ledger::default();
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_simple_burn() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Alice, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 50)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 50)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_burn_no_existential_deposit_left() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Bob, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 100)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_non_registered_burner() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Bob, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 100)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_burn_more_than_balance() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end2 = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Bob, amount: 100 },
 	);
-	let expected2 = HashMap::from([(User::Alice, 100)]);
+	let expected2 = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end2, expected2);
 }
 
 #[test]
 fn sm_4_empty_burn() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Alice, amount: 0 },
 	);
-	let expected = HashMap::from([(User::Alice, 100)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_burner_does_not_exist() {
-	let start = HashMap::from([(User::Alice, 100)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
 	let end = AccountedCurrency::next_state(
 		&start,
 		&AccountingTransaction::Burn { burner: User::Bob, amount: 50 },
 	);
-	let expected = HashMap::from([(User::Alice, 100)]);
+	let expected = Ledger::from(HashMap::from([(User::Alice, 100)]));
 
 	assert_eq!(end, expected);
 }
 
 #[test]
 fn sm_4_simple_transfer() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Alice, receiver: User::Bob, amount: 10 },
+		&AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 90), (User::Bob, 60)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 
-	let start = HashMap::from([(User::Alice, 90), (User::Bob, 60)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 90), (User::Bob, 60)]));
 	let end1 = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Bob, receiver: User::Alice, amount: 50 },
+		&AccountingTransaction::signed_transfer(User::Bob, User::Alice, 50, 0, 0),
 	);
 	let expected1 = HashMap::from([(User::Alice, 140), (User::Bob, 10)]);
 
-	assert_eq!(end1, expected1);
+	assert_eq!(end1.balances(), &expected1);
 }
 
 #[test]
 fn sm_4_send_to_same_user() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Bob, receiver: User::Bob, amount: 10 },
+		&AccountingTransaction::signed_transfer(User::Bob, User::Bob, 10, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 }
 
 #[test]
 fn sm_4_insufficient_balance_transfer() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Bob, receiver: User::Alice, amount: 60 },
+		&AccountingTransaction::signed_transfer(User::Bob, User::Alice, 60, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 }
 
 #[test]
 fn sm_4_sender_not_registered() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer {
-			sender: User::Charlie,
-			receiver: User::Alice,
-			amount: 50,
-		},
+		&AccountingTransaction::signed_transfer(User::Charlie, User::Alice, 50, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 }
 
 #[test]
 fn sm_4_receiver_not_registered() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer {
-			sender: User::Alice,
-			receiver: User::Charlie,
-			amount: 50,
-		},
+		&AccountingTransaction::signed_transfer(User::Alice, User::Charlie, 50, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 50), (User::Bob, 50), (User::Charlie, 50)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 }
 
 #[test]
 fn sm_4_sender_to_empty_balance() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Bob, receiver: User::Alice, amount: 50 },
+		&AccountingTransaction::signed_transfer(User::Bob, User::Alice, 50, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 150)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
 }
 
 #[test]
 fn sm_4_transfer() {
-	let start = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
 	let end = AccountedCurrency::next_state(
 		&start,
-		&AccountingTransaction::Transfer { sender: User::Bob, receiver: User::Charlie, amount: 50 },
+		&AccountingTransaction::signed_transfer(User::Bob, User::Charlie, 50, 0, 0),
 	);
 	let expected = HashMap::from([(User::Alice, 100), (User::Charlie, 50)]);
 
-	assert_eq!(end, expected);
+	assert_eq!(end.balances(), &expected);
+}
+
+#[test]
+fn sm_4_try_rejections_are_distinguishable() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
+	let try_next = |t| AccountedCurrency::try_next_state(&start, &t);
+
+	assert_eq!(
+		try_next(AccountingTransaction::Mint { minter: User::Alice, amount: 0 }),
+		Err(AccountingError::ZeroAmount)
+	);
+	assert_eq!(
+		try_next(AccountingTransaction::Burn { burner: User::Charlie, amount: 1 }),
+		Err(AccountingError::UnknownAccount)
+	);
+	assert_eq!(
+		try_next(AccountingTransaction::signed_transfer(User::Bob, User::Bob, 10, 0, 0)),
+		Err(AccountingError::SelfTransfer)
+	);
+	assert_eq!(
+		try_next(AccountingTransaction::signed_transfer(User::Bob, User::Alice, 60, 0, 0)),
+		Err(AccountingError::InsufficientBalance)
+	);
+	assert_eq!(
+		try_next(AccountingTransaction::Mint { minter: User::Alice, amount: u64::MAX }),
+		Err(AccountingError::Overflow)
+	);
+}
+
+#[test]
+fn sm_4_apply_all_transactions() {
+	let end = AccountedCurrency::apply_all(
+		&Ledger::default(),
+		&[
+			AccountingTransaction::Mint { minter: User::Alice, amount: 100 },
+			AccountingTransaction::signed_transfer(User::Alice, User::Bob, 30, 0, 0),
+			AccountingTransaction::Burn { burner: User::Bob, amount: 10 },
+			AccountingTransaction::signed_transfer(User::Bob, User::Charlie, 20, 0, 0),
+		],
+	);
+	let expected = HashMap::from([(User::Alice, 70), (User::Charlie, 20)]);
+
+	assert_eq!(end.balances(), &expected);
+}
+
+#[test]
+#[should_panic]
+#[cfg(debug_assertions)]
+fn sm_4_checked_machine_panics_on_violation() {
+	use super::InvariantChecked;
+
+	// The starting state is already broken. Transitions don't fix it, so the check catches it.
+	let start = Ledger::from(HashMap::from([(User::Alice, 0)]));
+	InvariantChecked::<AccountedCurrency>::next_state(
+		&start,
+		&AccountingTransaction::Mint { minter: User::Bob, amount: 5 },
+	);
+}
+
+#[test]
+fn sm_4_dead_accounts_violate_invariant() {
+	assert_eq!(AccountedCurrency::check(&Ledger::from(HashMap::from([(User::Alice, 1)]))), Ok(()));
+	assert_eq!(
+		AccountedCurrency::check(&Ledger::from(HashMap::from([(User::Alice, 1), (User::Bob, 0)]))),
+		Err(LedgerViolation::DeadAccount(User::Bob))
+	);
+}
+
+#[test]
+fn sm_4_ledger_json_round_trip() {
+	let ledger = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]))
+		.with_fee_destination(FeeDestination::Collector(User::Charlie));
+	let json = serde_json::to_string(&ledger).unwrap();
+
+	assert_eq!(serde_json::from_str::<Ledger>(&json).unwrap(), ledger);
+}
+
+#[test]
+fn sm_4_transfer_fee_burned() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]));
+	let end = AccountedCurrency::next_state(
+		&start,
+		&AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 2, 0),
+	);
+
+	assert_eq!(end.balances(), &HashMap::from([(User::Alice, 88), (User::Bob, 60)]));
+	assert_eq!(end.total_issuance(), 148);
+	assert_eq!(AccountedCurrency::check(&end), Ok(()));
+}
+
+#[test]
+fn sm_4_transfer_fee_collected() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100), (User::Bob, 50)]))
+		.with_fee_destination(FeeDestination::Collector(User::Charlie));
+	let end = AccountedCurrency::next_state(
+		&start,
+		&AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 2, 0),
+	);
+
+	assert_eq!(
+		end.balances(),
+		&HashMap::from([(User::Alice, 88), (User::Bob, 60), (User::Charlie, 2)])
+	);
+	assert_eq!(end.total_issuance(), 150);
+}
+
+#[test]
+fn sm_4_fee_must_be_affordable() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
+	let transfer = |amount, fee| {
+		AccountedCurrency::try_next_state(
+			&start,
+			&AccountingTransaction::signed_transfer(User::Alice, User::Bob, amount, fee, 0),
+		)
+	};
+
+	assert_eq!(transfer(99, 2), Err(AccountingError::InsufficientBalance));
+	assert_eq!(transfer(1, u64::MAX), Err(AccountingError::InsufficientBalance));
+	let end = transfer(98, 2).unwrap();
+	assert_eq!(end.balances(), &HashMap::from([(User::Bob, 98)]));
+	assert_eq!(end.total_issuance(), 98);
+}
+
+#[test]
+fn sm_4_issuance_only_changes_by_mint_and_burn() {
+	let start = Ledger::default().with_fee_destination(FeeDestination::Collector(User::Charlie));
+	let states = AccountedCurrency::trace(
+		&start,
+		&[
+			AccountingTransaction::Mint { minter: User::Alice, amount: 100 },
+			AccountingTransaction::signed_transfer(User::Alice, User::Bob, 30, 5, 0),
+			AccountingTransaction::signed_transfer(User::Bob, User::Charlie, 30, 0, 0),
+			AccountingTransaction::Burn { burner: User::Charlie, amount: 20 },
+			AccountingTransaction::Burn { burner: User::Alice, amount: 1000 },
+		],
+	);
+	let issuance: Vec<_> = states.iter().map(Ledger::total_issuance).collect();
+
+	assert_eq!(issuance, vec![0, 100, 100, 100, 80, 15]);
+	assert!(states.iter().all(|state| AccountedCurrency::check(state).is_ok()));
+}
+
+#[test]
+fn sm_4_issuance_mismatch_violates_invariant() {
+	let ledger = Ledger { total_issuance: 7, ..Ledger::from(HashMap::from([(User::Alice, 5)])) };
+
+	assert_eq!(AccountedCurrency::check(&ledger), Err(LedgerViolation::IssuanceMismatch));
+}
+
+#[test]
+fn sm_4_transfer_bumps_nonce() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
+	let end = AccountedCurrency::apply_all(
+		&start,
+		&[
+			AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 0),
+			AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 1),
+		],
+	);
+
+	assert_eq!(end.balances(), &HashMap::from([(User::Alice, 80), (User::Bob, 20)]));
+	assert_eq!(end.nonce(User::Alice), 2);
+	assert_eq!(end.nonce(User::Bob), 0);
+}
+
+#[test]
+fn sm_4_replayed_transfer_rejected() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
+	let transfer = AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 0);
+	let once = AccountedCurrency::next_state(&start, &transfer);
+
+	assert_eq!(AccountedCurrency::try_next_state(&once, &transfer), Err(AccountingError::BadNonce));
+	assert_eq!(
+		AccountedCurrency::try_next_state(
+			&start,
+			&AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 1),
+		),
+		Err(AccountingError::BadNonce)
+	);
+}
+
+#[test]
+fn sm_4_forged_transfer_rejected() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 100)]));
+
+	// Bob signs a transfer out of Alice's account with his own key.
+	let message = (User::Alice, User::Bob, 50u64, 0u64, 0u64);
+	let forged = AccountingTransaction::Transfer {
+		sender: User::Alice,
+		receiver: User::Bob,
+		amount: 50,
+		fee: 0,
+		nonce: 0,
+		signature: User::Bob.pair().sign(&message).into(),
+	};
+	assert_eq!(
+		AccountedCurrency::try_next_state(&start, &forged),
+		Err(AccountingError::BadSignature)
+	);
+
+	// Bob raises the amount of a transfer Alice really did sign.
+	let AccountingTransaction::Transfer { signature, .. } =
+		AccountingTransaction::signed_transfer(User::Alice, User::Bob, 5, 0, 0)
+	else {
+		unreachable!()
+	};
+	let tampered = AccountingTransaction::Transfer {
+		sender: User::Alice,
+		receiver: User::Bob,
+		amount: 50,
+		fee: 0,
+		nonce: 0,
+		signature,
+	};
+	assert_eq!(
+		AccountedCurrency::try_next_state(&start, &tampered),
+		Err(AccountingError::BadSignature)
+	);
+}
+
+#[test]
+fn sm_4_nonce_survives_removed_account() {
+	let start = Ledger::from(HashMap::from([(User::Alice, 10)]));
+	let transfer = AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 0);
+	let refilled = AccountedCurrency::apply_all(
+		&start,
+		&[transfer, AccountingTransaction::Mint { minter: User::Alice, amount: 10 }],
+	);
+
+	assert_eq!(refilled.nonce(User::Alice), 1);
+	let replayed = AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 0, 0);
+	assert_eq!(
+		AccountedCurrency::try_next_state(&refilled, &replayed),
+		Err(AccountingError::BadNonce)
+	);
+}
+
+#[test]
+fn sm_4_equal_ledgers_hash_equally() {
+	let mut one = HashMap::new();
+	let mut other = HashMap::new();
+	for (user, balance) in [(User::Alice, 1), (User::Bob, 2), (User::Charlie, 3)] {
+		one.insert(user, balance);
+	}
+	for (user, balance) in [(User::Charlie, 3), (User::Alice, 1), (User::Bob, 2)] {
+		other.insert(user, balance);
+	}
+
+	assert_eq!(crate::hash(&Ledger::from(one)), crate::hash(&Ledger::from(other)));
 }
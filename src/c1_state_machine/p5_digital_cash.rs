@@ -1,10 +1,19 @@
 //! In this module we design another multi-user currency system. This one is not based on
 //! accounts, but rather, is modelled after a paper cash system. The system tracks individual
-//! cash bills. Each bill has an amount and an owner, and can be spent in its entirety.
+//! cash bills. Each bill has an amount and an owner, and can only be spent in its entirety.
 //! When a state transition spends bills, new bills are created in lesser or equal amount.
+//!
+//! Paying somebody an amount that doesn't exactly match your bills works just like it does with
+//! paper cash. You hand over a bill that is worth more, and get change back. Here, the change is
+//! simply another new bill, owned by the sender. Whatever is spent but not received back by
+//! anybody is the fee for the transfer.
 
-use super::{StateMachine, User};
-use std::collections::HashSet;
+use super::{StateMachine, TryStateMachine, User};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashSet,
+	hash::{Hash, Hasher},
+};
 
 /// This state machine models a multi-user currency system. It tracks a set of bills in
 /// circulation, and updates that set when money is transferred.
@@ -13,26 +22,45 @@ pub struct DigitalCashSystem;
 /// A single bill in the digital cash system. Each bill has an owner who is allowed to spent
 /// it and an amount that it is worth. It also has serial number to ensure that each bill
 /// is unique.
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Bill {
 	owner: User,
 	amount: u64,
 	serial: u64,
 }
 
+impl Bill {
+	/// The user who may spend this bill
+	pub fn owner(&self) -> User {
+		self.owner
+	}
+
+	/// How much this bill is worth
+	pub fn amount(&self) -> u64 {
+		self.amount
+	}
+
+	/// The serial number that makes this bill unique
+	pub fn serial(&self) -> u64 {
+		self.serial
+	}
+}
+
 /// The State of a digital cash system. Primarily just the set of currently circulating bills.,
 /// but also a counter for the next serial number.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct State {
 	/// The set of currently circulating bills
 	bills: HashSet<Bill>,
 	/// The next serial number to use when a bill is created.
 	next_serial: u64,
+	/// The total fees paid by all transfers so far.
+	fees: u64,
 }
 
 impl State {
 	pub fn new() -> Self {
-		State { bills: HashSet::<Bill>::new(), next_serial: 0 }
+		State { bills: HashSet::<Bill>::new(), next_serial: 0, fees: 0 }
 	}
 
 	pub fn set_serial(&mut self, serial: u64) {
@@ -43,6 +71,34 @@ impl State {
 		self.next_serial
 	}
 
+	/// The total fees paid by all transfers so far
+	pub fn fees(&self) -> u64 {
+		self.fees
+	}
+
+	/// Every bill in circulation, in no particular order
+	pub fn bills(&self) -> impl Iterator<Item = &Bill> {
+		self.bills.iter()
+	}
+
+	/// The bills the given user may spend, oldest first
+	pub fn utxos_of(&self, owner: User) -> Vec<&Bill> {
+		let mut owned: Vec<_> = self.bills().filter(|bill| bill.owner == owner).collect();
+		owned.sort_by_key(|bill| bill.serial);
+		owned
+	}
+
+	/// How much the given user's bills are worth altogether
+	pub fn balance_of(&self, owner: User) -> u64 {
+		self.bills().filter(|bill| bill.owner == owner).map(|bill| bill.amount).sum()
+	}
+
+	/// How much all the bills in circulation are worth. Fees are not included, since they are
+	/// out of circulation.
+	pub fn total_issuance(&self) -> u64 {
+		self.bills().map(|bill| bill.amount).sum()
+	}
+
 	fn increment_serial(&mut self) {
 		self.next_serial += 1
 	}
@@ -53,6 +109,19 @@ impl State {
 	}
 }
 
+/// Hash sets can't be hashed directly, because the order of their bills isn't fixed. Hashing each
+/// bill on its own and sorting those hashes gives equal states equal hashes, so a block header can
+/// commit to a state.
+impl Hash for State {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		let mut bills: Vec<u64> = self.bills.iter().map(crate::hash).collect();
+		bills.sort_unstable();
+		bills.hash(state);
+		self.next_serial.hash(state);
+		self.fees.hash(state);
+	}
+}
+
 impl FromIterator<Bill> for State {
 	fn from_iter<I: IntoIterator<Item = Bill>>(iter: I) -> Self {
 		let mut state = State::new();
@@ -71,23 +140,82 @@ impl<const N: usize> From<[Bill; N]> for State {
 }
 
 /// The state transitions that users can make in a digital cash system
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Serialize, Deserialize)]
 pub enum CashTransaction {
 	/// Mint a single new bill owned by the minter
 	Mint { minter: User, amount: u64 },
 	/// Send some money from some users to other users. The money does not all need
 	/// to come from the same user, and it does not all need to go to the same user.
 	/// The total amount received must be less than or equal to the amount spent.
-	/// The discrepancy between the amount sent and received is destroyed. Therefore,
-	/// no dedicated burn transaction is required.
+	/// The discrepancy between the amount sent and received is the fee. It is taken out of
+	/// circulation and added to the fees collected so far.
 	Transfer { spends: Vec<Bill>, receives: Vec<Bill> },
 }
 
+impl CashTransaction {
+	/// A transfer that spends the given bills to pay `amount` to `to`, leaving `fee` for the fee
+	/// and returning whatever is left as change to the owner of the first spent bill. The new bills
+	/// are numbered starting from the state's next serial number. Fails if the bills aren't worth
+	/// enough to cover the amount and the fee.
+	pub fn pay(
+		state: &State,
+		spends: Vec<Bill>,
+		to: User,
+		amount: u64,
+		fee: u64,
+	) -> Result<CashTransaction, CashError> {
+		let sender = spends.first().ok_or(CashError::NoSpends)?.owner;
+		let spent = spends
+			.iter()
+			.try_fold(0u64, |total, bill| total.checked_add(bill.amount))
+			.ok_or(CashError::Overflow)?;
+		let change = amount
+			.checked_add(fee)
+			.and_then(|cost| spent.checked_sub(cost))
+			.ok_or(CashError::OutputsExceedInputs)?;
+
+		let mut receives = vec![Bill { owner: to, amount, serial: state.next_serial() }];
+		if change > 0 {
+			receives.push(Bill { owner: sender, amount: change, serial: state.next_serial() + 1 });
+		}
+		Ok(CashTransaction::Transfer { spends, receives })
+	}
+}
+
+/// The reasons a cash transaction may be rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CashError {
+	/// Bills must be worth something
+	ZeroValue,
+	/// A transfer must spend at least one bill
+	NoSpends,
+	/// A spent bill is not in circulation
+	UnknownBill,
+	/// The same bill was spent more than once in a single transaction
+	DoubleSpend,
+	/// New bills must be numbered consecutively, starting from the next serial number
+	InvalidSerial,
+	/// The bills received are worth more than the bills spent
+	OutputsExceedInputs,
+	/// The amounts involved are too large to be represented
+	Overflow,
+}
+
 /// We model this system as a state machine with two possible transitions
 impl StateMachine for DigitalCashSystem {
 	type State = State;
 	type Transition = CashTransaction;
 
 	fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+}
+
+/// Every invalid transaction is rejected, leaving the bills in circulation exactly as they were.
+impl TryStateMachine for DigitalCashSystem {
+	type Error = CashError;
+
+	fn try_next_state(starting_state: &State, t: &CashTransaction) -> Result<State, CashError> {
 		todo!("Exercise 1")
 	}
 }
@@ -147,6 +275,7 @@ fn sm_5_empty_receive_fails() {
 	);
 	let mut expected = State::from([]);
 	expected.set_serial(1);
+	expected.fees = 20;
 	assert_eq!(end, expected);
 }
 
@@ -305,6 +434,7 @@ fn sm_5_spending_from_alice_to_all() {
 		Bill { owner: User::Charlie, amount: 10, serial: 3 },
 	]);
 	expected.set_serial(4);
+	expected.fees = 12;
 	assert_eq!(end, expected);
 }
 
@@ -356,5 +486,166 @@ fn sm_5_spending_from_charlie_to_all() {
 		Bill { owner: User::Charlie, amount: 5, serial: 61 },
 	]);
 	expected.set_serial(62);
+	expected.fees = 16;
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_try_rejections_are_distinguishable() {
+	let start = State::from([Bill { owner: User::Alice, amount: 20, serial: 0 }]);
+	let try_next = |spends, receives| {
+		DigitalCashSystem::try_next_state(&start, &CashTransaction::Transfer { spends, receives })
+	};
+	let alices = Bill { owner: User::Alice, amount: 20, serial: 0 };
+
+	assert_eq!(try_next(vec![alices.clone(), alices.clone()], vec![]), Err(CashError::DoubleSpend));
+	assert_eq!(
+		try_next(vec![Bill { owner: User::Bob, amount: 20, serial: 0 }], vec![]),
+		Err(CashError::UnknownBill)
+	);
+	assert_eq!(
+		try_next(vec![alices.clone()], vec![Bill { owner: User::Bob, amount: 20, serial: 7 }]),
+		Err(CashError::InvalidSerial)
+	);
+	assert_eq!(
+		try_next(vec![alices], vec![Bill { owner: User::Bob, amount: 21, serial: 1 }]),
+		Err(CashError::OutputsExceedInputs)
+	);
+	assert_eq!(
+		DigitalCashSystem::try_next_state(
+			&start,
+			&CashTransaction::Mint { minter: User::Bob, amount: 0 }
+		),
+		Err(CashError::ZeroValue)
+	);
+}
+
+#[test]
+fn sm_5_state_json_round_trip() {
+	let state = State::from([
+		Bill { owner: User::Alice, amount: 42, serial: 0 },
+		Bill { owner: User::Bob, amount: 7, serial: 1 },
+	]);
+	let json = serde_json::to_string(&state).unwrap();
+
+	assert_eq!(serde_json::from_str::<State>(&json).unwrap(), state);
+}
+
+#[test]
+fn sm_5_pay_with_change() {
+	let start = State::from([Bill { owner: User::Alice, amount: 50, serial: 0 }]);
+	let payment = CashTransaction::pay(
+		&start,
+		vec![Bill { owner: User::Alice, amount: 50, serial: 0 }],
+		User::Bob,
+		30,
+		2,
+	)
+	.unwrap();
+	let end = DigitalCashSystem::next_state(&start, &payment);
+
+	let mut expected = State::from([
+		Bill { owner: User::Bob, amount: 30, serial: 1 },
+		Bill { owner: User::Alice, amount: 18, serial: 2 },
+	]);
+	expected.set_serial(3);
+	expected.fees = 2;
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_5_pay_exact_amount_needs_no_change() {
+	let start = State::from([
+		Bill { owner: User::Alice, amount: 20, serial: 0 },
+		Bill { owner: User::Alice, amount: 15, serial: 1 },
+	]);
+	let payment = CashTransaction::pay(
+		&start,
+		vec![
+			Bill { owner: User::Alice, amount: 20, serial: 0 },
+			Bill { owner: User::Alice, amount: 15, serial: 1 },
+		],
+		User::Charlie,
+		34,
+		1,
+	)
+	.unwrap();
+	let end = DigitalCashSystem::next_state(&start, &payment);
+
+	let mut expected = State::from([Bill { owner: User::Charlie, amount: 34, serial: 2 }]);
+	expected.set_serial(3);
+	expected.fees = 1;
 	assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_5_pay_more_than_bills_fails() {
+	let start = State::from([Bill { owner: User::Alice, amount: 20, serial: 0 }]);
+	let alices = vec![Bill { owner: User::Alice, amount: 20, serial: 0 }];
+
+	assert_eq!(
+		CashTransaction::pay(&start, alices.clone(), User::Bob, 20, 1).err(),
+		Some(CashError::OutputsExceedInputs)
+	);
+	assert_eq!(
+		CashTransaction::pay(&start, vec![], User::Bob, 20, 0).err(),
+		Some(CashError::NoSpends)
+	);
+}
+
+#[test]
+fn sm_5_fees_accumulate() {
+	let start = State::from([
+		Bill { owner: User::Alice, amount: 20, serial: 0 },
+		Bill { owner: User::Bob, amount: 10, serial: 1 },
+	]);
+	let end = DigitalCashSystem::apply_all(
+		&start,
+		&[
+			CashTransaction::Transfer {
+				spends: vec![Bill { owner: User::Alice, amount: 20, serial: 0 }],
+				receives: vec![Bill { owner: User::Bob, amount: 17, serial: 2 }],
+			},
+			CashTransaction::Transfer {
+				spends: vec![Bill { owner: User::Bob, amount: 10, serial: 1 }],
+				receives: vec![Bill { owner: User::Alice, amount: 5, serial: 3 }],
+			},
+		],
+	);
+
+	assert_eq!(end.fees(), 8);
+}
+
+#[test]
+fn sm_5_query_bills_by_owner() {
+	let state = State::from([
+		Bill { owner: User::Alice, amount: 20, serial: 0 },
+		Bill { owner: User::Bob, amount: 7, serial: 1 },
+		Bill { owner: User::Alice, amount: 5, serial: 2 },
+	]);
+
+	assert_eq!(state.balance_of(User::Alice), 25);
+	assert_eq!(state.balance_of(User::Charlie), 0);
+	assert_eq!(
+		state.utxos_of(User::Alice),
+		vec![
+			&Bill { owner: User::Alice, amount: 20, serial: 0 },
+			&Bill { owner: User::Alice, amount: 5, serial: 2 },
+		]
+	);
+	assert_eq!(state.utxos_of(User::Charlie), Vec::<&Bill>::new());
+	assert_eq!(state.total_issuance(), 32);
+	assert_eq!(state.bills().count(), 3);
+}
+
+#[test]
+fn sm_5_issuance_drops_by_fee() {
+	let start = State::from([Bill { owner: User::Alice, amount: 50, serial: 0 }]);
+	let spend = start.utxos_of(User::Alice).into_iter().cloned().collect();
+	let payment = CashTransaction::pay(&start, spend, User::Bob, 30, 4).unwrap();
+	let end = DigitalCashSystem::next_state(&start, &payment);
+
+	assert_eq!(end.balance_of(User::Bob), 30);
+	assert_eq!(end.balance_of(User::Alice), 16);
+	assert_eq!(end.total_issuance() + end.fees(), start.total_issuance());
+}
@@ -0,0 +1,429 @@
+//! The vending machine sells snacks for coins. You insert coins until you have enough credit,
+//! then pick a product. The machine hands out the product along with your change. If it can't
+//! make change, or the product is sold out, you can always get your coins back instead.
+
+use super::{repl::ParseTransition, EnumerableTransitions, MealyStateMachine, StateMachine};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The coins the machine accepts. Anything else is handed straight back.
+pub const ACCEPTED_COINS: [u64; 4] = [5, 10, 25, 100];
+
+/// The products a vending machine may sell
+#[derive(Hash, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum Product {
+	Cola,
+	Chips,
+	Candy,
+}
+
+/// The price of a product, and how many of it are left in the machine
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Slot {
+	pub price: u64,
+	pub stock: u64,
+}
+
+/// Something you can do to the vending machine
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum VendingAction {
+	/// Insert a coin of the given value. Coins that the machine doesn't accept are returned.
+	InsertCoin(u64),
+	/// Pick a product. If you have enough credit, it comes out along with your change.
+	Select(Product),
+	/// Press the coin return lever to get back every coin inserted since the last purchase.
+	Refund,
+}
+
+/// The vending machine. Inserted coins are held aside until a purchase, so a refund gives back
+/// exactly the coins that went in. When a product is bought, those coins drop into the coin box,
+/// and the change is paid out of the coin box. A purchase only goes through if the product is in
+/// stock, the credit covers its price, and the coin box can make exact change.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct VendingMachine {
+	/// The products for sale, along with their prices and stock
+	slots: BTreeMap<Product, Slot>,
+	/// The coins available to make change, counted by denomination
+	coin_box: BTreeMap<u64, u64>,
+	/// The coins inserted since the last purchase or refund, in the order they went in
+	inserted: Vec<u64>,
+}
+
+impl VendingMachine {
+	/// A fresh vending machine with the given products and coins for change, waiting for a
+	/// customer.
+	pub fn new(slots: BTreeMap<Product, Slot>, coin_box: BTreeMap<u64, u64>) -> Self {
+		VendingMachine { slots, coin_box, inserted: Vec::new() }
+	}
+
+	/// How much the current customer has inserted
+	pub fn credit(&self) -> u64 {
+		self.inserted.iter().sum()
+	}
+
+	/// How many of the given product are left. Products the machine doesn't sell have none left.
+	pub fn stock(&self, product: Product) -> u64 {
+		self.slots.get(&product).map_or(0, |slot| slot.stock)
+	}
+
+	/// The total value of the coins available to make change
+	pub fn coin_box_total(&self) -> u64 {
+		self.coin_box.iter().map(|(denomination, count)| denomination * count).sum()
+	}
+
+	/// The coin box as it would be once the inserted coins have dropped into it.
+	fn coin_box_with_inserted(&self) -> BTreeMap<u64, u64> {
+		let mut coin_box = self.coin_box.clone();
+		for coin in &self.inserted {
+			*coin_box.entry(*coin).or_default() += 1;
+		}
+		coin_box
+	}
+}
+
+/// Choose coins from the coin box that add up to exactly the given amount, preferring large
+/// coins. Returns the coins largest first, or `None` if no combination of the available coins
+/// adds up to the amount.
+///
+/// Handing out as many large coins as possible doesn't always work. With one 25 and three 10s,
+/// 30 can only be made from the 10s.
+fn make_change(coin_box: &BTreeMap<u64, u64>, amount: u64) -> Option<Vec<u64>> {
+	fn pick(available: &[(u64, u64)], amount: u64) -> Option<Vec<u64>> {
+		let ((denomination, count), smaller) = match available.split_first() {
+			Some(first) => first,
+			None => return if amount == 0 { Some(Vec::new()) } else { None },
+		};
+		let most = if *denomination == 0 { 0 } else { (*count).min(amount / denomination) };
+		(0..=most).rev().find_map(|used| {
+			let rest = pick(smaller, amount - used * denomination)?;
+			let mut coins = vec![*denomination; used as usize];
+			coins.extend(rest);
+			Some(coins)
+		})
+	}
+
+	let largest_first = coin_box.iter().rev().map(|(d, c)| (*d, *c)).collect::<Vec<_>>();
+	pick(&largest_first, amount)
+}
+
+impl StateMachine for VendingMachine {
+	type State = Self;
+	type Transition = VendingAction;
+
+	fn next_state(starting_state: &Self, t: &VendingAction) -> Self {
+		match t {
+			VendingAction::InsertCoin(coin) if ACCEPTED_COINS.contains(coin) => {
+				let mut inserted = starting_state.inserted.clone();
+				inserted.push(*coin);
+				VendingMachine { inserted, ..starting_state.clone() }
+			},
+			VendingAction::InsertCoin(_) => starting_state.clone(),
+			VendingAction::Select(product) => {
+				let slot = match starting_state.slots.get(product) {
+					Some(slot) if slot.stock > 0 => slot,
+					_ => return starting_state.clone(),
+				};
+				let change = match starting_state.credit().checked_sub(slot.price) {
+					Some(change) => change,
+					None => return starting_state.clone(),
+				};
+				let mut coin_box = starting_state.coin_box_with_inserted();
+				let coins = match make_change(&coin_box, change) {
+					Some(coins) => coins,
+					None => return starting_state.clone(),
+				};
+				for coin in coins {
+					*coin_box.get_mut(&coin).expect("change comes from the coin box") -= 1;
+				}
+				let mut slots = starting_state.slots.clone();
+				slots.get_mut(product).expect("product was found above").stock -= 1;
+				VendingMachine { slots, coin_box, inserted: Vec::new() }
+			},
+			VendingAction::Refund =>
+				VendingMachine { inserted: Vec::new(), ..starting_state.clone() },
+		}
+	}
+
+	fn human_name() -> String {
+		"Vending machine".into()
+	}
+}
+
+/// Things the vending machine does that the customer can see
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VendingEvent {
+	/// The coin is not one the machine accepts, so it was handed straight back.
+	CoinRejected(u64),
+	/// The product came out of the machine.
+	Vended(Product),
+	/// Coins came out of the coin return, either as change or as a refund. Largest first.
+	CoinsReturned(Vec<u64>),
+	/// The product is not in the machine, so nothing came out.
+	SoldOut(Product),
+	/// The credit doesn't cover the price of the product, which is attached.
+	InsufficientCredit(u64),
+	/// The coin box can't make change for this purchase, so nothing came out.
+	ExactChangeOnly,
+}
+
+impl MealyStateMachine for VendingMachine {
+	type Output = VendingEvent;
+
+	fn next_state_with_output(
+		starting_state: &Self,
+		t: &VendingAction,
+	) -> (Self, Vec<VendingEvent>) {
+		let end = Self::next_state(starting_state, t);
+		let events = match t {
+			VendingAction::InsertCoin(coin) if !ACCEPTED_COINS.contains(coin) =>
+				vec![VendingEvent::CoinRejected(*coin)],
+			VendingAction::InsertCoin(_) => vec![],
+			VendingAction::Select(product) => match starting_state.slots.get(product) {
+				Some(slot) if slot.stock > 0 && starting_state.credit() < slot.price =>
+					vec![VendingEvent::InsufficientCredit(slot.price)],
+				Some(slot) if slot.stock > 0 => {
+					let change = starting_state.credit() - slot.price;
+					match make_change(&starting_state.coin_box_with_inserted(), change) {
+						Some(coins) if coins.is_empty() => vec![VendingEvent::Vended(*product)],
+						Some(coins) =>
+							vec![VendingEvent::Vended(*product), VendingEvent::CoinsReturned(coins)],
+						None => vec![VendingEvent::ExactChangeOnly],
+					}
+				},
+				_ => vec![VendingEvent::SoldOut(*product)],
+			},
+			VendingAction::Refund if starting_state.inserted.is_empty() => vec![],
+			VendingAction::Refund => {
+				let mut coins = starting_state.inserted.clone();
+				coins.sort_unstable_by(|a, b| b.cmp(a));
+				vec![VendingEvent::CoinsReturned(coins)]
+			},
+		};
+		(end, events)
+	}
+}
+
+/// Every accepted coin, every product, and the coin return. A rejected coin is included too, to
+/// show that it changes nothing.
+impl EnumerableTransitions for VendingMachine {
+	fn transitions() -> Vec<VendingAction> {
+		let coins = ACCEPTED_COINS.into_iter().chain([1]).map(VendingAction::InsertCoin);
+		let products = [Product::Cola, Product::Chips, Product::Candy]
+			.into_iter()
+			.map(VendingAction::Select);
+		coins.chain(products).chain([VendingAction::Refund]).collect()
+	}
+}
+
+impl ParseTransition for VendingMachine {
+	fn transition_help() -> String {
+		"`coin <value>` to insert a coin, `cola`, `chips`, or `candy` to pick a product, or `refund` to get your coins back".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<VendingAction, String> {
+		if let Some(coin) = text.strip_prefix("coin ") {
+			let coin = coin
+				.trim()
+				.parse::<u64>()
+				.map_err(|_| format!("Coins have a whole value, not {:?}", coin))?;
+			return Ok(VendingAction::InsertCoin(coin))
+		}
+		match text {
+			"cola" => Ok(VendingAction::Select(Product::Cola)),
+			"chips" => Ok(VendingAction::Select(Product::Chips)),
+			"candy" => Ok(VendingAction::Select(Product::Candy)),
+			"refund" => Ok(VendingAction::Refund),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
+}
+
+/// A machine selling cola for 75 and chips for 50, with one cola and five bags of chips left. It
+/// sells no candy. Its coin box holds two 25s and two 10s.
+#[cfg(test)]
+fn stocked_machine() -> VendingMachine {
+	VendingMachine::new(
+		BTreeMap::from([
+			(Product::Cola, Slot { price: 75, stock: 1 }),
+			(Product::Chips, Slot { price: 50, stock: 5 }),
+		]),
+		BTreeMap::from([(25, 2), (10, 2)]),
+	)
+}
+
+#[test]
+fn sm_7_insert_coin() {
+	let start = stocked_machine();
+	let end = VendingMachine::next_state(&start, &VendingAction::InsertCoin(25));
+	let expected = VendingMachine { inserted: vec![25], ..stocked_machine() };
+
+	assert_eq!(end, expected);
+	assert_eq!(end.credit(), 25);
+}
+
+#[test]
+fn sm_7_insert_unknown_coin() {
+	let start = stocked_machine();
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::InsertCoin(3));
+
+	assert_eq!(end, start);
+	assert_eq!(events, vec![VendingEvent::CoinRejected(3)]);
+}
+
+#[test]
+fn sm_7_buy_with_exact_credit() {
+	let start = VendingMachine { inserted: vec![25, 25, 25], ..stocked_machine() };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Cola));
+	let expected = VendingMachine::new(
+		BTreeMap::from([
+			(Product::Cola, Slot { price: 75, stock: 0 }),
+			(Product::Chips, Slot { price: 50, stock: 5 }),
+		]),
+		BTreeMap::from([(25, 5), (10, 2)]),
+	);
+
+	assert_eq!(end, expected);
+	assert_eq!(events, vec![VendingEvent::Vended(Product::Cola)]);
+}
+
+#[test]
+fn sm_7_buy_with_change() {
+	let start = VendingMachine { inserted: vec![100], ..stocked_machine() };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Chips));
+
+	assert_eq!(
+		events,
+		vec![VendingEvent::Vended(Product::Chips), VendingEvent::CoinsReturned(vec![25, 25])]
+	);
+	assert_eq!(end.stock(Product::Chips), 4);
+	assert_eq!(end.credit(), 0);
+	assert_eq!(end.coin_box, BTreeMap::from([(10, 2), (25, 0), (100, 1)]));
+}
+
+#[test]
+fn sm_7_change_is_not_always_greedy() {
+	// 30 can't start with a 25, because there are no 5s to finish it off.
+	let start = VendingMachine::new(
+		BTreeMap::from([(Product::Candy, Slot { price: 70, stock: 1 })]),
+		BTreeMap::from([(25, 1), (10, 3)]),
+	);
+	let start = VendingMachine { inserted: vec![100], ..start };
+	let (_, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Candy));
+
+	assert_eq!(
+		events,
+		vec![VendingEvent::Vended(Product::Candy), VendingEvent::CoinsReturned(vec![10, 10, 10])]
+	);
+}
+
+#[test]
+fn sm_7_inserted_coins_can_be_change() {
+	// The coin box is empty, but the customer's own 25 pays back their change.
+	let start = VendingMachine::new(
+		BTreeMap::from([(Product::Chips, Slot { price: 50, stock: 1 })]),
+		BTreeMap::new(),
+	);
+	let start = VendingMachine { inserted: vec![25, 10, 10, 10, 10, 10], ..start };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Chips));
+
+	assert_eq!(events[0], VendingEvent::Vended(Product::Chips));
+	assert_eq!(end.coin_box_total(), 50);
+}
+
+#[test]
+fn sm_7_exact_change_only() {
+	let start = VendingMachine::new(
+		BTreeMap::from([(Product::Chips, Slot { price: 50, stock: 1 })]),
+		BTreeMap::new(),
+	);
+	let start = VendingMachine { inserted: vec![100], ..start };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Chips));
+
+	assert_eq!(end, start);
+	assert_eq!(events, vec![VendingEvent::ExactChangeOnly]);
+}
+
+#[test]
+fn sm_7_insufficient_credit() {
+	let start = VendingMachine { inserted: vec![25, 25], ..stocked_machine() };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Cola));
+
+	assert_eq!(end, start);
+	assert_eq!(events, vec![VendingEvent::InsufficientCredit(75)]);
+}
+
+#[test]
+fn sm_7_sold_out() {
+	let start = VendingMachine { inserted: vec![100, 100], ..stocked_machine() };
+	let after_first = VendingMachine::next_state(&start, &VendingAction::Select(Product::Cola));
+	let (end, events) =
+		VendingMachine::next_state_with_output(&after_first, &VendingAction::Select(Product::Cola));
+
+	assert_eq!(after_first.stock(Product::Cola), 0);
+	assert_eq!(end, after_first);
+	assert_eq!(events, vec![VendingEvent::SoldOut(Product::Cola)]);
+}
+
+#[test]
+fn sm_7_product_not_sold() {
+	let start = VendingMachine { inserted: vec![100], ..stocked_machine() };
+	let (end, events) =
+		VendingMachine::next_state_with_output(&start, &VendingAction::Select(Product::Candy));
+
+	assert_eq!(end, start);
+	assert_eq!(events, vec![VendingEvent::SoldOut(Product::Candy)]);
+}
+
+#[test]
+fn sm_7_refund_returns_inserted_coins() {
+	let start = VendingMachine { inserted: vec![10, 100, 25], ..stocked_machine() };
+	let (end, events) = VendingMachine::next_state_with_output(&start, &VendingAction::Refund);
+
+	assert_eq!(end, stocked_machine());
+	assert_eq!(events, vec![VendingEvent::CoinsReturned(vec![100, 25, 10])]);
+}
+
+#[test]
+fn sm_7_refund_with_nothing_inserted() {
+	let start = stocked_machine();
+	let (end, events) = VendingMachine::next_state_with_output(&start, &VendingAction::Refund);
+
+	assert_eq!(end, start);
+	assert_eq!(events, vec![]);
+}
+
+#[test]
+fn sm_7_coin_box_keeps_price_of_everything_sold() {
+	use super::model_checker::check;
+
+	// Whatever the customers do, the coin box grows by exactly the price of what was sold. The
+	// rest of their coins either stay aside as credit or come back out.
+	let start = stocked_machine();
+	let sold = |vm: &VendingMachine| {
+		75 * (1 - vm.stock(Product::Cola)) + 50 * (5 - vm.stock(Product::Chips))
+	};
+
+	assert!(check::<VendingMachine>(&start, 4, |vm| {
+		vm.coin_box_total() == start.coin_box_total() + sold(vm)
+	})
+	.is_ok());
+}
+
+#[test]
+fn sm_7_parse_transitions() {
+	assert_eq!(VendingMachine::parse_transition("coin 25"), Ok(VendingAction::InsertCoin(25)));
+	assert_eq!(
+		VendingMachine::parse_transition("chips"),
+		Ok(VendingAction::Select(Product::Chips))
+	);
+	assert_eq!(VendingMachine::parse_transition("refund"), Ok(VendingAction::Refund));
+	assert!(VendingMachine::parse_transition("coin quarter").is_err());
+	assert!(VendingMachine::parse_transition("kick").is_err());
+}
@@ -0,0 +1,302 @@
+//! A traffic controller runs the lights where two roads cross. Traffic on one road gets a green
+//! light while the other waits at red, then they swap. Pedestrians can press a button to get a
+//! short phase where all traffic stops and they may cross.
+//!
+//! The one thing this controller must never do is let both roads go at once. Rather than hoping
+//! the tests cover every sequence of button presses, that rule is written down as an invariant so
+//! the model checker can prove it holds in every reachable state.
+
+use super::{repl::ParseTransition, EnumerableTransitions, Invariant, StateMachine};
+use serde::{Deserialize, Serialize};
+
+/// The colour shown by a traffic light
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Light {
+	Green,
+	Yellow,
+	Red,
+}
+
+/// The two roads crossing at the intersection
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Road {
+	NorthSouth,
+	EastWest,
+}
+
+/// Something that can happen at the intersection
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum IntersectionAction {
+	/// The controller's timer fires and the lights move on to their next phase.
+	Tick,
+	/// A pedestrian presses the button asking to cross.
+	PressButton,
+}
+
+/// The state of every light at the intersection.
+///
+/// The lights go through a fixed cycle. A green light turns yellow, then red. Once both are red,
+/// the other road turns green. If a pedestrian pressed the button, a walk phase is slipped in
+/// while both roads are red, before either of them turns green again.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Intersection {
+	/// The light facing traffic on the north-south road
+	pub north_south: Light,
+	/// The light facing traffic on the east-west road
+	pub east_west: Light,
+	/// Whether the pedestrian signal says walk
+	pub walk: bool,
+	/// Whether a pedestrian is waiting for the walk signal
+	pub button_pressed: bool,
+	/// The road that turns green the next time both roads are red. This is always the road that
+	/// was not green most recently.
+	pub next_green: Road,
+}
+
+impl Default for Intersection {
+	/// North-south traffic is moving, and east-west traffic is next.
+	fn default() -> Self {
+		Intersection {
+			north_south: Light::Green,
+			east_west: Light::Red,
+			walk: false,
+			button_pressed: false,
+			next_green: Road::EastWest,
+		}
+	}
+}
+
+impl Intersection {
+	/// The light facing the given road
+	pub fn light(&self, road: Road) -> Light {
+		match road {
+			Road::NorthSouth => self.north_south,
+			Road::EastWest => self.east_west,
+		}
+	}
+
+	fn light_mut(&mut self, road: Road) -> &mut Light {
+		match road {
+			Road::NorthSouth => &mut self.north_south,
+			Road::EastWest => &mut self.east_west,
+		}
+	}
+}
+
+impl Road {
+	/// The road crossing this one
+	pub fn other(&self) -> Road {
+		match self {
+			Road::NorthSouth => Road::EastWest,
+			Road::EastWest => Road::NorthSouth,
+		}
+	}
+}
+
+/// The state machine controlling an intersection
+pub struct TrafficController;
+
+impl StateMachine for TrafficController {
+	type State = Intersection;
+	type Transition = IntersectionAction;
+
+	fn next_state(starting_state: &Intersection, t: &IntersectionAction) -> Intersection {
+		let mut end = starting_state.clone();
+		match t {
+			IntersectionAction::PressButton => {
+				// Someone who can already walk doesn't need to wait for another walk phase.
+				if !starting_state.walk {
+					end.button_pressed = true;
+				}
+			},
+			IntersectionAction::Tick => {
+				let moving = [Road::NorthSouth, Road::EastWest]
+					.into_iter()
+					.find(|road| starting_state.light(*road) != Light::Red);
+				match moving {
+					Some(road) if starting_state.light(road) == Light::Green =>
+						*end.light_mut(road) = Light::Yellow,
+					Some(road) => *end.light_mut(road) = Light::Red,
+					None if starting_state.walk => end.walk = false,
+					None if starting_state.button_pressed => {
+						end.walk = true;
+						end.button_pressed = false;
+					},
+					None => {
+						*end.light_mut(starting_state.next_green) = Light::Green;
+						end.next_green = starting_state.next_green.other();
+					},
+				}
+			},
+		}
+		end
+	}
+
+	fn human_name() -> String {
+		"Traffic intersection".into()
+	}
+}
+
+/// The ways an intersection can be dangerous
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum IntersectionViolation {
+	/// Neither road's light is red, so traffic on both may be moving. Most dramatically, both are
+	/// green.
+	CrossTrafficMoving,
+	/// Pedestrians were told to walk while traffic may be moving.
+	WalkWithTraffic,
+}
+
+impl Invariant for TrafficController {
+	type Violation = IntersectionViolation;
+
+	fn check(state: &Intersection) -> Result<(), IntersectionViolation> {
+		let north_south_stopped = state.north_south == Light::Red;
+		let east_west_stopped = state.east_west == Light::Red;
+		if !north_south_stopped && !east_west_stopped {
+			return Err(IntersectionViolation::CrossTrafficMoving)
+		}
+		if state.walk && !(north_south_stopped && east_west_stopped) {
+			return Err(IntersectionViolation::WalkWithTraffic)
+		}
+		Ok(())
+	}
+}
+
+impl EnumerableTransitions for TrafficController {
+	fn transitions() -> Vec<IntersectionAction> {
+		vec![IntersectionAction::Tick, IntersectionAction::PressButton]
+	}
+}
+
+impl ParseTransition for TrafficController {
+	fn transition_help() -> String {
+		"`tick` to move the lights on, or `press` to press the pedestrian button".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<IntersectionAction, String> {
+		match text {
+			"tick" => Ok(IntersectionAction::Tick),
+			"press" => Ok(IntersectionAction::PressButton),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
+}
+
+#[test]
+fn sm_8_green_turns_yellow() {
+	let start = Intersection::default();
+	let end = TrafficController::next_state(&start, &IntersectionAction::Tick);
+	let expected = Intersection { north_south: Light::Yellow, ..Intersection::default() };
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_8_yellow_turns_red() {
+	let start = Intersection {
+		north_south: Light::Red,
+		east_west: Light::Yellow,
+		next_green: Road::NorthSouth,
+		..Intersection::default()
+	};
+	let end = TrafficController::next_state(&start, &IntersectionAction::Tick);
+	let expected = Intersection {
+		north_south: Light::Red,
+		east_west: Light::Red,
+		next_green: Road::NorthSouth,
+		..Intersection::default()
+	};
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_8_full_cycle_without_pedestrians() {
+	let start = Intersection::default();
+	let states = TrafficController::trace(&start, &vec![IntersectionAction::Tick; 6]);
+	let lights = states.iter().map(|s| (s.north_south, s.east_west)).collect::<Vec<_>>();
+
+	assert_eq!(
+		lights,
+		vec![
+			(Light::Green, Light::Red),
+			(Light::Yellow, Light::Red),
+			(Light::Red, Light::Red),
+			(Light::Red, Light::Green),
+			(Light::Red, Light::Yellow),
+			(Light::Red, Light::Red),
+			(Light::Green, Light::Red),
+		]
+	);
+	assert_eq!(states[6], start);
+}
+
+#[test]
+fn sm_8_button_adds_walk_phase() {
+	let start = Intersection::default();
+	let end = TrafficController::apply_all(
+		&start,
+		&[IntersectionAction::PressButton, IntersectionAction::Tick, IntersectionAction::Tick],
+	);
+	assert_eq!(
+		end,
+		Intersection {
+			north_south: Light::Red,
+			east_west: Light::Red,
+			button_pressed: true,
+			..Intersection::default()
+		}
+	);
+
+	let walking = TrafficController::next_state(&end, &IntersectionAction::Tick);
+	assert!(walking.walk);
+	assert!(!walking.button_pressed);
+
+	// Once the pedestrians have crossed, traffic carries on where it left off.
+	let after = TrafficController::next_state(&walking, &IntersectionAction::Tick);
+	assert!(!after.walk);
+	assert_eq!(after.light(Road::NorthSouth), Light::Red);
+	let after = TrafficController::next_state(&after, &IntersectionAction::Tick);
+	assert_eq!(after.light(Road::EastWest), Light::Green);
+}
+
+#[test]
+fn sm_8_button_while_walking_does_nothing() {
+	let start = Intersection { north_south: Light::Red, walk: true, ..Intersection::default() };
+	let end = TrafficController::next_state(&start, &IntersectionAction::PressButton);
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_8_invariant_rejects_both_green() {
+	let both_green = Intersection { east_west: Light::Green, ..Intersection::default() };
+	let walk_with_traffic = Intersection { walk: true, ..Intersection::default() };
+
+	assert_eq!(
+		TrafficController::check(&both_green),
+		Err(IntersectionViolation::CrossTrafficMoving)
+	);
+	assert_eq!(
+		TrafficController::check(&walk_with_traffic),
+		Err(IntersectionViolation::WalkWithTraffic)
+	);
+	assert_eq!(TrafficController::check(&Intersection::default()), Ok(()));
+}
+
+#[test]
+fn sm_8_model_check_intersection_is_safe() {
+	use super::model_checker::check_invariants;
+
+	// Every state is reachable well within this many transitions, so this covers them all.
+	let reached = check_invariants::<TrafficController>(&Intersection::default(), 20);
+	assert!(reached.is_ok());
+}
+
+#[test]
+fn sm_8_parse_transitions() {
+	assert_eq!(TrafficController::parse_transition("tick"), Ok(IntersectionAction::Tick));
+	assert_eq!(TrafficController::parse_transition("press"), Ok(IntersectionAction::PressButton));
+	assert!(TrafficController::parse_transition("honk").is_err());
+}
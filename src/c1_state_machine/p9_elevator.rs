@@ -0,0 +1,318 @@
+//! An elevator carries people between the floors of a building. People call it to a floor, and
+//! it moves there one floor at a time, opening its doors when it arrives.
+//!
+//! Unlike the earlier machines, the elevator has to remember a whole queue of requests, and the
+//! order in which it serves them matters. A careless controller could keep bouncing between two
+//! busy floors while someone on the top floor waits forever. This one always heads for the oldest
+//! request, so every request is eventually served.
+
+use super::{repl::ParseTransition, EnumerableTransitions, Invariant, StateMachine};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Something that can happen to the elevator
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum ElevatorAction {
+	/// Someone calls the elevator to the given floor, either from the hallway or from inside the
+	/// car. Calls to floors the building doesn't have are ignored.
+	Call(u32),
+	/// Someone presses the door open button. The doors only open while the car is stopped, which
+	/// it always is between moves.
+	OpenDoors,
+	/// The doors close, either because someone pressed the button or because they timed out.
+	CloseDoors,
+	/// The motor moves the car one floor towards the oldest pending request. The car never moves
+	/// with its doors open. When it reaches a floor somebody asked for, it stops there and opens
+	/// its doors.
+	Move,
+}
+
+/// The elevator.
+///
+/// Pending requests are kept in the order they were made. The car always moves towards the oldest
+/// one, although it also stops at any other requested floor it passes on the way. So the oldest
+/// request keeps getting closer, and it is reached after at most as many moves as there are
+/// floors, no matter how many new calls come in meanwhile.
+#[derive(Hash, Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Elevator {
+	/// How many floors the building has. They are numbered from zero.
+	floors: u32,
+	/// The floor the car is at
+	floor: u32,
+	/// Whether the doors are open
+	doors_open: bool,
+	/// The floors the car has been called to but not yet reached, oldest first. Each floor appears
+	/// at most once.
+	pending: VecDeque<u32>,
+}
+
+impl Elevator {
+	/// An elevator in a building with the given number of floors, waiting on the ground floor
+	/// with its doors closed.
+	pub fn new(floors: u32) -> Self {
+		Elevator { floors, floor: 0, doors_open: false, pending: VecDeque::new() }
+	}
+
+	/// The floor the car is at
+	pub fn floor(&self) -> u32 {
+		self.floor
+	}
+
+	/// Whether the doors are open
+	pub fn doors_open(&self) -> bool {
+		self.doors_open
+	}
+
+	/// The floors the car has been called to but not yet reached, oldest first
+	pub fn pending(&self) -> impl Iterator<Item = u32> + '_ {
+		self.pending.iter().copied()
+	}
+
+	/// Open the doors at the current floor, which serves any request for it.
+	fn arrive(&mut self) {
+		self.doors_open = true;
+		let floor = self.floor;
+		self.pending.retain(|pending| *pending != floor);
+	}
+}
+
+impl StateMachine for Elevator {
+	type State = Self;
+	type Transition = ElevatorAction;
+
+	fn next_state(starting_state: &Self, t: &ElevatorAction) -> Self {
+		let mut end = starting_state.clone();
+		match t {
+			ElevatorAction::Call(floor) => {
+				let already_there = *floor == end.floor && end.doors_open;
+				if *floor < end.floors && !already_there && !end.pending.contains(floor) {
+					end.pending.push_back(*floor);
+				}
+			},
+			ElevatorAction::OpenDoors => end.arrive(),
+			ElevatorAction::CloseDoors => end.doors_open = false,
+			ElevatorAction::Move => {
+				let target = match end.pending.front() {
+					Some(target) if !end.doors_open => *target,
+					_ => return end,
+				};
+				if target > end.floor {
+					end.floor += 1;
+				} else if target < end.floor {
+					end.floor -= 1;
+				}
+				if end.pending.contains(&end.floor) {
+					end.arrive();
+				}
+			},
+		}
+		end
+	}
+
+	fn human_name() -> String {
+		"Elevator".into()
+	}
+}
+
+/// The ways an elevator can be in an impossible state
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ElevatorViolation {
+	/// The car is at a floor the building doesn't have.
+	NoSuchFloor,
+	/// A floor the building doesn't have is waiting to be served.
+	UnreachableRequest,
+	/// The same floor is waiting to be served twice.
+	DuplicateRequest,
+	/// The doors are open at a floor that is still waiting to be served.
+	UnservedOpenFloor,
+}
+
+impl Invariant for Elevator {
+	type Violation = ElevatorViolation;
+
+	fn check(state: &Elevator) -> Result<(), ElevatorViolation> {
+		if state.floor >= state.floors {
+			return Err(ElevatorViolation::NoSuchFloor)
+		}
+		if state.pending.iter().any(|floor| *floor >= state.floors) {
+			return Err(ElevatorViolation::UnreachableRequest)
+		}
+		let mut seen = Vec::new();
+		for floor in &state.pending {
+			if seen.contains(floor) {
+				return Err(ElevatorViolation::DuplicateRequest)
+			}
+			seen.push(*floor);
+		}
+		if state.doors_open && state.pending.contains(&state.floor) {
+			return Err(ElevatorViolation::UnservedOpenFloor)
+		}
+		Ok(())
+	}
+}
+
+/// Calls to a handful of floors, along with every other action. A call to a floor above the
+/// third is included too, to show that calls to missing floors change nothing in a small building.
+impl EnumerableTransitions for Elevator {
+	fn transitions() -> Vec<ElevatorAction> {
+		vec![
+			ElevatorAction::Call(0),
+			ElevatorAction::Call(1),
+			ElevatorAction::Call(2),
+			ElevatorAction::Call(7),
+			ElevatorAction::OpenDoors,
+			ElevatorAction::CloseDoors,
+			ElevatorAction::Move,
+		]
+	}
+}
+
+impl ParseTransition for Elevator {
+	fn transition_help() -> String {
+		"`call <floor>` to call the elevator, `open` or `close` for the doors, or `move` to move the car one floor".into()
+	}
+
+	fn parse_transition(text: &str) -> Result<ElevatorAction, String> {
+		if let Some(floor) = text.strip_prefix("call ") {
+			let floor = floor
+				.trim()
+				.parse::<u32>()
+				.map_err(|_| format!("Floors are numbered from 0, not {:?}", floor))?;
+			return Ok(ElevatorAction::Call(floor))
+		}
+		match text {
+			"open" => Ok(ElevatorAction::OpenDoors),
+			"close" => Ok(ElevatorAction::CloseDoors),
+			"move" => Ok(ElevatorAction::Move),
+			other => Err(format!("Unknown action {:?}", other)),
+		}
+	}
+}
+
+#[test]
+fn sm_9_call_elevator() {
+	let start = Elevator::new(5);
+	let end = Elevator::next_state(&start, &ElevatorAction::Call(3));
+	let expected = Elevator { pending: VecDeque::from([3]), ..Elevator::new(5) };
+
+	assert_eq!(end, expected);
+}
+
+#[test]
+fn sm_9_call_is_remembered_once() {
+	let start = Elevator { pending: VecDeque::from([3, 1]), ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Call(3));
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_call_missing_floor() {
+	let start = Elevator::new(5);
+	let end = Elevator::next_state(&start, &ElevatorAction::Call(5));
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_call_to_open_car_floor_is_already_served() {
+	let start = Elevator { floor: 2, doors_open: true, ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Call(2));
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_move_towards_request_and_open_on_arrival() {
+	let start = Elevator { pending: VecDeque::from([2]), ..Elevator::new(5) };
+	let first = Elevator::next_state(&start, &ElevatorAction::Move);
+	let second = Elevator::next_state(&first, &ElevatorAction::Move);
+
+	assert_eq!(first, Elevator { floor: 1, pending: VecDeque::from([2]), ..Elevator::new(5) });
+	assert_eq!(second, Elevator { floor: 2, doors_open: true, ..Elevator::new(5) });
+}
+
+#[test]
+fn sm_9_move_down() {
+	let start = Elevator { floor: 4, pending: VecDeque::from([1]), ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Move);
+
+	assert_eq!(end.floor(), 3);
+}
+
+#[test]
+fn sm_9_never_moves_with_doors_open() {
+	let start = Elevator { doors_open: true, pending: VecDeque::from([3]), ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Move);
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_move_without_requests() {
+	let start = Elevator { floor: 2, ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Move);
+
+	assert_eq!(end, start);
+}
+
+#[test]
+fn sm_9_stops_at_requested_floors_on_the_way() {
+	let start = Elevator { pending: VecDeque::from([3, 1]), ..Elevator::new(5) };
+	let end = Elevator::next_state(&start, &ElevatorAction::Move);
+
+	assert_eq!(end, Elevator { floor: 1, doors_open: true, pending: VecDeque::from([3]), ..start });
+}
+
+#[test]
+fn sm_9_open_doors_serves_current_floor() {
+	let start = Elevator { floor: 2, pending: VecDeque::from([4, 2]), ..Elevator::new(5) };
+	let opened = Elevator::next_state(&start, &ElevatorAction::OpenDoors);
+	let closed = Elevator::next_state(&opened, &ElevatorAction::CloseDoors);
+
+	assert_eq!(
+		opened,
+		Elevator { doors_open: true, pending: VecDeque::from([4]), ..start.clone() }
+	);
+	assert_eq!(closed, Elevator { pending: VecDeque::from([4]), ..start });
+}
+
+#[test]
+fn sm_9_oldest_request_is_never_starved() {
+	// Somebody on the top floor calls first. Then people on the two lowest floors keep calling
+	// over and over, boarding and leaving as soon as the doors open. Even so, the car reaches the
+	// top floor within as many moves as the building has floors.
+	let mut elevator = Elevator::next_state(&Elevator::new(10), &ElevatorAction::Call(9));
+	for moves in 1..=10 {
+		for action in [
+			ElevatorAction::Call(0),
+			ElevatorAction::Call(1),
+			ElevatorAction::CloseDoors,
+			ElevatorAction::Move,
+		] {
+			elevator = Elevator::next_state(&elevator, &action);
+		}
+		if elevator.floor() == 9 {
+			assert!(elevator.doors_open());
+			assert!(moves <= 10);
+			return
+		}
+	}
+	panic!("the call to the top floor was never served");
+}
+
+#[test]
+fn sm_9_model_check_invariants() {
+	use super::model_checker::check_invariants;
+
+	assert!(check_invariants::<Elevator>(&Elevator::new(3), 6).is_ok());
+}
+
+#[test]
+fn sm_9_parse_transitions() {
+	assert_eq!(Elevator::parse_transition("call 4"), Ok(ElevatorAction::Call(4)));
+	assert_eq!(Elevator::parse_transition("open"), Ok(ElevatorAction::OpenDoors));
+	assert_eq!(Elevator::parse_transition("move"), Ok(ElevatorAction::Move));
+	assert!(Elevator::parse_transition("call roof").is_err());
+	assert!(Elevator::parse_transition("jump").is_err());
+}
@@ -0,0 +1,93 @@
+//! An interactive driver for state machines. It shows the current state, reads transitions typed
+//! in as text, and shows the resulting state. Try it with `cargo run --bin repl -- atm`.
+
+use super::StateMachine;
+use std::{
+	fmt::Debug,
+	io::{self, BufRead, Write},
+};
+
+/// State machines whose transitions can be typed in as text.
+pub trait ParseTransition: StateMachine {
+	/// A short description of the text that `parse_transition` understands.
+	fn transition_help() -> String;
+
+	/// Parse a single transition from a line of user input, or explain what is wrong with it.
+	fn parse_transition(text: &str) -> Result<Self::Transition, String>;
+}
+
+/// Drive the given state machine from the input, one transition per line, writing the state after
+/// every transition to the output. Stops at the end of the input or when the user types `quit`,
+/// and returns the final state.
+pub fn run<SM>(
+	initial: SM::State,
+	input: impl BufRead,
+	mut output: impl Write,
+) -> io::Result<SM::State>
+where
+	SM: ParseTransition,
+	SM::State: Debug,
+{
+	writeln!(output, "{}", SM::human_name())?;
+	writeln!(output, "Transitions: {}. Type `quit` to leave.", SM::transition_help())?;
+	writeln!(output, "{:?}", initial)?;
+
+	let mut state = initial;
+	let mut lines = input.lines();
+	loop {
+		write!(output, "> ")?;
+		output.flush()?;
+
+		let line = match lines.next() {
+			Some(line) => line?,
+			None => break,
+		};
+		let line = line.trim();
+		if line.is_empty() {
+			continue
+		}
+		if line == "quit" {
+			break
+		}
+
+		match SM::parse_transition(line) {
+			Ok(t) => {
+				state = SM::next_state(&state, &t);
+				writeln!(output, "{:?}", state)?;
+			},
+			Err(e) => writeln!(output, "{}", e)?,
+		}
+	}
+
+	writeln!(output)?;
+	Ok(state)
+}
+
+#[test]
+fn sm_repl_drives_light_switch() {
+	use super::p1_switches::LightSwitch;
+
+	let mut output = Vec::new();
+	let end =
+		run::<LightSwitch>(false, "toggle\n\ntoggle\ntoggle\n".as_bytes(), &mut output).unwrap();
+
+	assert!(end);
+	let output = String::from_utf8(output).unwrap();
+	assert!(output.ends_with("false\n> true\n> > false\n> true\n> \n"));
+}
+
+#[test]
+fn sm_repl_reports_bad_input_and_stops_on_quit() {
+	use super::p2_laundry_machine::{ClothesMachine, ClothesState};
+
+	let mut output = Vec::new();
+	let end = run::<ClothesMachine>(
+		ClothesState::Clean(5),
+		"wear\niron\nquit\nwash\n".as_bytes(),
+		&mut output,
+	)
+	.unwrap();
+
+	assert_eq!(end, ClothesState::Dirty(4));
+	assert!(String::from_utf8(output).unwrap().contains("Unknown action \"iron\""));
+}
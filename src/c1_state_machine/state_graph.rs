@@ -0,0 +1,143 @@
+//! Tooling to explore every state a machine can reach and draw the result as a graph.
+//!
+//! The graphs are emitted in the DOT language understood by Graphviz. For example, save the output
+//! to `atm.dot` and run `dot -Tsvg atm.dot > atm.svg` to get a picture for your slides.
+
+use super::{EnumerableTransitions, StateMachine};
+use std::fmt::Debug;
+
+/// The portion of a state machine's state graph that is reachable from some starting state.
+pub struct StateGraph<SM: StateMachine> {
+	/// Every state discovered, in the order they were discovered. The first is the starting state.
+	states: Vec<SM::State>,
+	/// The transitions between states as indices into `states`, along with a transition label.
+	edges: Vec<(usize, usize, String)>,
+}
+
+/// Explore the states reachable from the given starting state, breadth first.
+///
+/// Many machines have infinitely many reachable states, so exploration stops discovering new states
+/// once `max_states` have been found. Transitions leading to undiscovered states are left out.
+pub fn explore<SM>(starting_state: &SM::State, max_states: usize) -> StateGraph<SM>
+where
+	SM: EnumerableTransitions,
+	SM::State: Clone + PartialEq,
+	SM::Transition: Debug,
+{
+	let mut states = vec![starting_state.clone()];
+	let mut edges = Vec::new();
+
+	let mut current = 0;
+	while current < states.len() {
+		for t in SM::transitions() {
+			let next = SM::next_state(&states[current], &t);
+			let index = match states.iter().position(|s| *s == next) {
+				Some(index) => index,
+				None if states.len() < max_states => {
+					states.push(next);
+					states.len() - 1
+				},
+				None => continue,
+			};
+			edges.push((current, index, format!("{:?}", t)));
+		}
+		current += 1;
+	}
+
+	StateGraph { states, edges }
+}
+
+impl<SM: StateMachine> StateGraph<SM> {
+	/// The states that were discovered. The first one is the starting state.
+	pub fn states(&self) -> &[SM::State] {
+		&self.states
+	}
+
+	/// The transitions that were discovered as pairs of indices into `states`, along with a
+	/// label describing the transition.
+	pub fn edges(&self) -> &[(usize, usize, String)] {
+		&self.edges
+	}
+
+	/// Render the graph in DOT format, labelling each state with its debug representation.
+	pub fn to_dot(&self) -> String
+	where
+		SM::State: Debug,
+	{
+		self.to_dot_by(|state| format!("{:?}", state))
+	}
+
+	/// Render the graph in DOT format, labelling each state with the given function.
+	///
+	/// States that get the same label are drawn as a single node. This is a handy way to summarize
+	/// a big graph. For example, labelling ATM states by their authentication status alone shows
+	/// the overall flow of a session without drawing a node for every possible keystroke.
+	pub fn to_dot_by(&self, label: impl Fn(&SM::State) -> String) -> String {
+		let labels: Vec<String> = self.states.iter().map(label).collect();
+
+		let mut nodes: Vec<&String> = Vec::new();
+		for l in &labels {
+			if !nodes.contains(&l) {
+				nodes.push(l);
+			}
+		}
+
+		let mut edges: Vec<(&String, &String, &String)> = Vec::new();
+		for (from, to, t) in &self.edges {
+			let edge = (&labels[*from], &labels[*to], t);
+			if !edges.contains(&edge) {
+				edges.push(edge);
+			}
+		}
+
+		let mut dot = format!("digraph \"{}\" {{\n", escape(&SM::human_name()));
+		for node in nodes {
+			dot.push_str(&format!("\t\"{}\";\n", escape(node)));
+		}
+		for (from, to, t) in edges {
+			dot.push_str(&format!(
+				"\t\"{}\" -> \"{}\" [label=\"{}\"];\n",
+				escape(from),
+				escape(to),
+				escape(t)
+			));
+		}
+		dot.push_str("}\n");
+		dot
+	}
+}
+
+/// Escape a string so it can be used as a quoted DOT identifier.
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[test]
+fn sm_graph_light_switch() {
+	use super::p1_switches::LightSwitch;
+
+	let graph = explore::<LightSwitch>(&false, 10);
+
+	assert_eq!(graph.states(), &[false, true]);
+	assert_eq!(graph.edges(), &[(0, 1, "()".to_string()), (1, 0, "()".to_string())]);
+	assert_eq!(
+		graph.to_dot(),
+		"digraph \"Light switch\" {\n\t\"false\";\n\t\"true\";\n\t\"false\" -> \"true\" \
+		 [label=\"()\"];\n\t\"true\" -> \"false\" [label=\"()\"];\n}\n"
+	);
+}
+
+#[test]
+fn sm_graph_exploration_is_bounded() {
+	use super::p2_laundry_machine::{ClothesMachine, ClothesState};
+
+	let graph = explore::<ClothesMachine>(&ClothesState::Clean(100), 5);
+
+	assert_eq!(graph.states().len(), 5);
+	assert!(graph.edges().iter().all(|(from, to, _)| *from < 5 && *to < 5));
+}
+
+#[test]
+fn sm_graph_escapes_labels() {
+	assert_eq!(escape(r#"say "hi" \o/"#), r#"say \"hi\" \\o/"#);
+}
@@ -0,0 +1,736 @@
+//! Once forks appear, the blockchain is really a tree. A node that only keeps chains as lists has
+//! to store the common prefix of every fork over and over, and can't easily ask which blocks build
+//! on a given one. The block tree indexes every known header by its hash, and every header's
+//! children by their parent.
+//!
+//! With the whole tree at hand, fork choice can look at blocks that are _not_ in a chain. GHOST
+//! (Greedy Heaviest Observed SubTree) does exactly that. Starting from the root, it keeps stepping
+//! into the child whose subtree holds the most blocks. Blocks on losing forks still count towards
+//! the fork they build on, so the work that went into them isn't wasted.
+//!
+//! The GHOST rule was first published in 2013 by Yonatan Sompolinsky and Aviv Zohar.
+//! Learn more at https://eprint.iacr.org/2013/881.pdf
+
+use super::p4_batched_extrinsics::Header;
+use std::{
+	cmp::Reverse,
+	collections::{HashMap, HashSet},
+};
+type Hash = u64;
+
+/// A fork choice rule that looks at the whole block tree. The rules of part 5 only ever compare
+/// chains, so they can't see the blocks on other forks, which GHOST needs.
+pub trait TreeForkChoice {
+	/// The hash of the tip of the best chain in the given tree
+	fn best_tip(tree: &BlockTree) -> Hash;
+
+	/// A human-readable name for this rule, for reporting which rule picked what.
+	fn human_name() -> String {
+		"Unnamed fork choice rule".into()
+	}
+}
+
+/// The best chain is the one with the highest tip.
+pub struct LongestChain;
+
+/// The best chain is the one with the most accumulated work. See `work`.
+pub struct HeaviestChain;
+
+/// The best chain is the one GHOST picks. See `BlockTree::ghost`.
+pub struct Ghost;
+
+impl TreeForkChoice for LongestChain {
+	fn best_tip(tree: &BlockTree) -> Hash {
+		tree.best_leaf_by(|tip| tree.headers[&tip].height())
+	}
+
+	fn human_name() -> String {
+		"Longest chain".into()
+	}
+}
+
+impl TreeForkChoice for HeaviestChain {
+	fn best_tip(tree: &BlockTree) -> Hash {
+		tree.best_leaf_by(|tip| tree.chain_to(tip).map_or(0, |chain| chain.iter().map(work).sum()))
+	}
+
+	fn human_name() -> String {
+		"Heaviest chain".into()
+	}
+}
+
+impl TreeForkChoice for Ghost {
+	fn best_tip(tree: &BlockTree) -> Hash {
+		tree.ghost()
+	}
+
+	fn human_name() -> String {
+		"GHOST".into()
+	}
+}
+
+/// Roughly how much work went into the given header. A hash below `h` takes about `2^64 / h`
+/// attempts to find, so the lower the hash, the more work the header shows.
+pub fn work(header: &Header) -> u128 {
+	(1 << 64) / (header.hash() as u128 + 1)
+}
+
+/// The reasons a header may be refused by the tree
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ImportError {
+	/// The header's parent isn't in the tree.
+	UnknownParent,
+	/// The header's height doesn't follow its parent's.
+	BadHeight,
+	/// The header's parent was on a branch that was pruned when a competing block was finalized.
+	PrunedParent,
+	/// The header is no higher than the finalized block, so it competes with a finalized block.
+	BelowFinality,
+	/// The header forks off the best chain at a height that already holds as many forks as the
+	/// tree's limits allow.
+	TooManyForks,
+	/// The header is on a fork deep below the best tip, and the fork shows too little work.
+	InsufficientWork,
+}
+
+/// Bounds on the forks a tree keeps. Forks cost nothing to store, but an attacker can produce
+/// them by the million, especially cheap ones far below the tip where nobody else is building.
+/// Without bounds, such a fork bomb eventually exhausts the node's memory.
+///
+/// Headers extending the tip of the best chain are never limited, so the bounds can't keep a
+/// node from following the chain.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ForkLimits {
+	/// The most headers kept at any one height, not counting the one on the best chain
+	pub max_forks_per_height: usize,
+	/// How far below the best tip a header has to be for its fork to count as deep
+	pub deep_fork_depth: u64,
+	/// The least work a deep fork must show, counted from where it leaves the best chain. See
+	/// `work`.
+	pub min_deep_fork_work: u128,
+}
+
+/// No bounds at all
+impl Default for ForkLimits {
+	fn default() -> Self {
+		ForkLimits {
+			max_forks_per_height: usize::MAX,
+			deep_fork_depth: u64::MAX,
+			min_deep_fork_work: 0,
+		}
+	}
+}
+
+/// Every known header, indexed by hash, along with the children of each one.
+#[derive(Clone, Debug)]
+pub struct BlockTree {
+	/// The hash of the header everything else builds on, usually genesis
+	root: Hash,
+	/// Every header in the tree, by hash
+	headers: HashMap<Hash, Header>,
+	/// The hashes of the children of every header in the tree, in the order they were imported
+	children: HashMap<Hash, Vec<Hash>>,
+	/// The hash of the latest finalized header. The root counts as finalized from the start.
+	finalized: Hash,
+	/// The hashes of the headers that were pruned because they compete with a finalized one
+	pruned: HashSet<Hash>,
+	/// Which forks the tree is willing to keep
+	limits: ForkLimits,
+}
+
+impl BlockTree {
+	/// A tree holding only the given root header, keeping every fork it is given.
+	pub fn new(root: Header) -> Self {
+		Self::with_limits(root, ForkLimits::default())
+	}
+
+	/// A tree holding only the given root header, keeping only the forks the given limits allow.
+	pub fn with_limits(root: Header, limits: ForkLimits) -> Self {
+		let hash = root.hash();
+		BlockTree {
+			root: hash,
+			headers: HashMap::from([(hash, root)]),
+			children: HashMap::from([(hash, Vec::new())]),
+			finalized: hash,
+			pruned: HashSet::new(),
+			limits,
+		}
+	}
+
+	/// Add the given header to the tree, and return its hash. Its parent must already be in the
+	/// tree. Importing a header that is already there changes nothing.
+	pub fn import(&mut self, header: Header) -> Result<Hash, ImportError> {
+		let hash = header.hash();
+		if self.headers.contains_key(&hash) {
+			return Ok(hash)
+		}
+		if self.pruned.contains(&header.parent()) {
+			return Err(ImportError::PrunedParent)
+		}
+		let parent = self.headers.get(&header.parent()).ok_or(ImportError::UnknownParent)?;
+		if header.height() <= self.headers[&self.finalized].height() {
+			return Err(ImportError::BelowFinality)
+		}
+		if !parent.verify_child(&header) {
+			return Err(ImportError::BadHeight)
+		}
+		self.check_fork_limits(&header)?;
+		self.children.entry(header.parent()).or_default().push(hash);
+		self.children.insert(hash, Vec::new());
+		self.headers.insert(hash, header);
+		Ok(hash)
+	}
+
+	/// Whether the given header, whose parent is in the tree, may be kept under the tree's limits
+	fn check_fork_limits(&self, header: &Header) -> Result<(), ImportError> {
+		let best = LongestChain::best_tip(self);
+		if header.parent() == best {
+			return Ok(())
+		}
+		let mut best_chain: HashSet<Hash> =
+			self.path_down_to(best, self.root).into_iter().collect();
+		best_chain.insert(self.root);
+		let forks = self
+			.headers
+			.iter()
+			.filter(|(hash, other)| other.height() == header.height() && !best_chain.contains(hash))
+			.count();
+		if forks >= self.limits.max_forks_per_height {
+			return Err(ImportError::TooManyForks)
+		}
+
+		let best_height = self.headers[&best].height();
+		if header.height().saturating_add(self.limits.deep_fork_depth) < best_height {
+			let mut fork_work = work(header);
+			let mut ancestor = header.parent();
+			while !best_chain.contains(&ancestor) {
+				let fork_header = &self.headers[&ancestor];
+				fork_work += work(fork_header);
+				ancestor = fork_header.parent();
+			}
+			if fork_work < self.limits.min_deep_fork_work {
+				return Err(ImportError::InsufficientWork)
+			}
+		}
+		Ok(())
+	}
+
+	/// The hash of the root header
+	pub fn root(&self) -> Hash {
+		self.root
+	}
+
+	/// The hash of the latest finalized header
+	pub fn finalized(&self) -> Hash {
+		self.finalized
+	}
+
+	/// Mark the header with the given hash as finalized, and prune every header that competes with
+	/// it, along with everything building on those. Only the finalized header's ancestors and
+	/// descendants are kept, so no fork choice can ever revert it. Returns the hashes of the
+	/// pruned headers, or `None` if the header isn't in the tree or doesn't build on the header
+	/// that is already finalized.
+	///
+	/// The hashes of pruned headers are remembered, so that late blocks building on them can be
+	/// told apart from blocks whose parent simply hasn't arrived yet.
+	pub fn finalize(&mut self, hash: Hash) -> Option<Vec<Hash>> {
+		if self.common_ancestor(hash, self.finalized)? != self.finalized {
+			return None
+		}
+		let mut keep: HashSet<Hash> =
+			self.chain_to(hash)?.iter().map(|header| header.hash()).collect();
+		let mut pending = vec![hash];
+		while let Some(hash) = pending.pop() {
+			keep.insert(hash);
+			pending.extend_from_slice(self.children(hash));
+		}
+
+		let pruned: Vec<Hash> =
+			self.headers.keys().filter(|h| !keep.contains(h)).copied().collect();
+		for hash in &pruned {
+			self.headers.remove(hash);
+			self.children.remove(hash);
+		}
+		for children in self.children.values_mut() {
+			children.retain(|child| keep.contains(child));
+		}
+		self.pruned.extend(&pruned);
+		self.finalized = hash;
+		Some(pruned)
+	}
+
+	/// The header with the given hash, if it is in the tree
+	pub fn get(&self, hash: Hash) -> Option<&Header> {
+		self.headers.get(&hash)
+	}
+
+	/// The hashes of the children of the header with the given hash
+	pub fn children(&self, hash: Hash) -> &[Hash] {
+		self.children.get(&hash).map_or(&[], Vec::as_slice)
+	}
+
+	/// The hashes of every header without children, in no particular order
+	pub fn leaves(&self) -> Vec<Hash> {
+		self.children
+			.iter()
+			.filter(|(_, children)| children.is_empty())
+			.map(|(h, _)| *h)
+			.collect()
+	}
+
+	/// The chain from the root to the header with the given hash, or `None` if it isn't in the
+	/// tree.
+	pub fn chain_to(&self, tip: Hash) -> Option<Vec<Header>> {
+		let mut chain = vec![self.get(tip)?.clone()];
+		while chain.last()?.hash() != self.root {
+			let parent = self.get(chain.last()?.parent())?;
+			chain.push(parent.clone());
+		}
+		chain.reverse();
+		Some(chain)
+	}
+
+	/// How many headers are in the subtree under the header with the given hash, counting itself.
+	pub fn subtree_size(&self, hash: Hash) -> usize {
+		let mut size = 0;
+		let mut pending = vec![hash];
+		while let Some(hash) = pending.pop() {
+			size += 1;
+			pending.extend_from_slice(self.children(hash));
+		}
+		size
+	}
+
+	/// The hash of the latest header that both given headers build on, counting each header as
+	/// building on itself. Returns `None` if either header isn't in the tree.
+	pub fn common_ancestor(&self, a: Hash, b: Hash) -> Option<Hash> {
+		let (mut a, mut b) = (self.get(a)?, self.get(b)?);
+		while a.height() > b.height() {
+			a = self.get(a.parent())?;
+		}
+		while b.height() > a.height() {
+			b = self.get(b.parent())?;
+		}
+		while a != b {
+			a = self.get(a.parent())?;
+			b = self.get(b.parent())?;
+		}
+		Some(a.hash())
+	}
+
+	/// What a node has to do to switch its best chain from the old tip to the new one. Returns the
+	/// hashes of the headers to retract, starting from the old tip, and the hashes of the headers
+	/// to enact, ending with the new tip. The common ancestor is in neither. Returns `None` if
+	/// either tip isn't in the tree.
+	pub fn reorg_path(&self, old_tip: Hash, new_tip: Hash) -> Option<(Vec<Hash>, Vec<Hash>)> {
+		let ancestor = self.common_ancestor(old_tip, new_tip)?;
+		let mut retracted = self.path_down_to(old_tip, ancestor);
+		let enacted = self.path_down_to(new_tip, ancestor);
+		retracted.reverse();
+		Some((retracted, enacted))
+	}
+
+	/// The hashes of the headers from just after the given ancestor up to the given header, which
+	/// must build on it
+	fn path_down_to(&self, mut hash: Hash, ancestor: Hash) -> Vec<Hash> {
+		let mut path = Vec::new();
+		while hash != ancestor {
+			path.push(hash);
+			hash = self.headers[&hash].parent();
+		}
+		path.reverse();
+		path
+	}
+
+	/// The leaf with the greatest given key. Ties go to the leaf with the lowest hash, so every
+	/// node with the same tree picks the same leaf.
+	fn best_leaf_by<K: Ord>(&self, key: impl Fn(Hash) -> K) -> Hash {
+		self.leaves()
+			.into_iter()
+			.max_by_key(|leaf| (key(*leaf), Reverse(*leaf)))
+			.unwrap_or(self.root)
+	}
+
+	/// The tip of the best chain by the GHOST rule. Starting from the root, keep stepping into the
+	/// child with the biggest subtree. Ties go to the child with the lowest hash, so every node
+	/// with the same tree picks the same tip, whatever order the headers arrived in.
+	pub fn ghost(&self) -> Hash {
+		let mut best = self.root;
+		while let Some(child) = self
+			.children(best)
+			.iter()
+			.max_by_key(|child| (self.subtree_size(**child), Reverse(**child)))
+		{
+			best = *child;
+		}
+		best
+	}
+}
+
+/// Draw the given tree the way the doc comments draw forks by hand. Each block is labelled with its
+/// height, or `G` for a genesis root, and blocks on forks get a prime for each fork they are on.
+/// The first child of each block carries on along the same line, and every other child starts a
+/// line of its own, below the rest of its elder siblings' subtrees.
+///
+/// ```text
+/// G -- 1 -- 2 -- 3 -- 4
+///            \-- 3' -- 4'
+/// ```
+pub fn render_fork_tree(tree: &BlockTree) -> String {
+	let mut lines = vec![String::new()];
+	draw(tree, tree.root, 0, &mut lines);
+	lines.join("\n")
+}
+
+/// Draw the subtree under the given block onto the end of the last line, and new lines below it.
+fn draw(tree: &BlockTree, hash: Hash, primes: usize, lines: &mut Vec<String>) {
+	let line = lines.last_mut().expect("there is always a line to draw on");
+	line.push_str(&label(tree, hash, primes));
+	let end = line.len();
+	for (fork, child) in tree.children(hash).iter().enumerate() {
+		match fork {
+			0 => lines.last_mut().expect("there is always a line").push_str(" -- "),
+			_ => lines.push(format!("{}\\-- ", " ".repeat(end))),
+		}
+		draw(tree, *child, primes + fork, lines);
+	}
+}
+
+/// How the given block is labelled in drawings of the given tree
+fn label(tree: &BlockTree, hash: Hash, primes: usize) -> String {
+	match tree.headers[&hash].height() {
+		0 => "G".into(),
+		height => format!("{}{}", height, "'".repeat(primes)),
+	}
+}
+
+/// Describe the given tree in the DOT language, for drawing with Graphviz. Blocks are labelled
+/// just like `render_fork_tree` labels them.
+pub fn render_fork_tree_dot(tree: &BlockTree) -> String {
+	let mut dot = String::from("digraph {\n");
+	let mut pending = vec![(tree.root, 0)];
+	while let Some((hash, primes)) = pending.pop() {
+		dot += &format!("    \"{:x}\" [label=\"{}\"];\n", hash, label(tree, hash, primes));
+		for (fork, child) in tree.children(hash).iter().enumerate() {
+			dot += &format!("    \"{:x}\" -> \"{:x}\";\n", hash, child);
+			pending.push((*child, primes + fork));
+		}
+	}
+	dot + "}\n"
+}
+
+/// A tree like this one, where the fork from a is longer, but the fork from 1 has more blocks.
+/// Returns the tree, and the hashes of e and 4.
+///
+/// ```text
+///        /-- 2 -- 3 -- 4
+///   /-- 1 -- 2'
+/// G      \-- 2''
+///   \-- a -- b -- c -- d -- e
+/// ```
+#[cfg(test)]
+fn bushy_tree() -> (BlockTree, Hash, Hash) {
+	let g = Header::genesis();
+	let mut tree = BlockTree::new(g.clone());
+	let mut import_chain = |parent: &Header, length: u64, root: Hash| {
+		let mut tip = parent.clone();
+		for state in 0..length {
+			tip = tip.child(root, state);
+			tree.import(tip.clone()).unwrap();
+		}
+		tip
+	};
+	let b1 = import_chain(&g, 1, 1);
+	let b4 = import_chain(&b1, 3, 1);
+	import_chain(&b1, 1, 2);
+	import_chain(&b1, 1, 3);
+	let long = import_chain(&g, 5, 4);
+	(tree, long.hash(), b4.hash())
+}
+
+#[test]
+fn bc_tree_indexes_children() {
+	let g = Header::genesis();
+	let b1 = g.child(1, 1);
+	let b2 = b1.child(2, 3);
+	let b2_fork = b1.child(3, 4);
+	let mut tree = BlockTree::new(g.clone());
+
+	for header in [&b1, &b2, &b2_fork] {
+		assert_eq!(tree.import(header.clone()), Ok(header.hash()));
+	}
+	assert_eq!(tree.import(b2.clone()), Ok(b2.hash()));
+
+	assert_eq!(tree.children(g.hash()), &[b1.hash()]);
+	assert_eq!(tree.children(b1.hash()), &[b2.hash(), b2_fork.hash()]);
+	let mut leaves = tree.leaves();
+	leaves.sort();
+	let mut expected = vec![b2.hash(), b2_fork.hash()];
+	expected.sort();
+	assert_eq!(leaves, expected);
+	assert_eq!(tree.chain_to(b2.hash()), Some(vec![g, b1, b2]));
+	assert_eq!(tree.subtree_size(tree.root()), 4);
+}
+
+#[test]
+fn bc_tree_refuses_orphans() {
+	let g = Header::genesis();
+	let mut tree = BlockTree::new(g.clone());
+	let b2 = g.child(1, 1).child(2, 3);
+
+	assert_eq!(tree.import(b2.clone()), Err(ImportError::UnknownParent));
+	assert_eq!(tree.get(b2.hash()), None);
+	assert_eq!(tree.chain_to(b2.hash()), None);
+}
+
+#[test]
+fn bc_tree_ghost_prefers_the_bushiest_subtree() {
+	let (tree, long, bushy) = bushy_tree();
+
+	// The longest chain ends at e, but there are more blocks under 1 than under a.
+	assert_eq!(tree.chain_to(long).unwrap().len(), 6);
+	assert_eq!(tree.ghost(), bushy);
+}
+
+#[test]
+fn bc_tree_ghost_breaks_ties_by_hash() {
+	let g = Header::genesis();
+	let a = g.child(1, 1);
+	let b = g.child(2, 2);
+	let mut forward = BlockTree::new(g.clone());
+	let mut backward = BlockTree::new(g);
+	forward.import(a.clone()).unwrap();
+	forward.import(b.clone()).unwrap();
+	backward.import(b.clone()).unwrap();
+	backward.import(a.clone()).unwrap();
+
+	assert_eq!(forward.ghost(), a.hash().min(b.hash()));
+	assert_eq!(backward.ghost(), forward.ghost());
+}
+
+#[test]
+fn bc_tree_common_ancestor() {
+	let g = Header::genesis();
+	let b1 = g.child(1, 1);
+	let b2 = b1.child(2, 3);
+	let b3 = b2.child(3, 6);
+	let b2_fork = b1.child(4, 5);
+	let mut tree = BlockTree::new(g.clone());
+	for header in [&b1, &b2, &b3, &b2_fork] {
+		tree.import(header.clone()).unwrap();
+	}
+
+	assert_eq!(tree.common_ancestor(b3.hash(), b2_fork.hash()), Some(b1.hash()));
+	assert_eq!(tree.common_ancestor(b2_fork.hash(), b3.hash()), Some(b1.hash()));
+	assert_eq!(tree.common_ancestor(b3.hash(), b1.hash()), Some(b1.hash()));
+	assert_eq!(tree.common_ancestor(b3.hash(), b3.hash()), Some(b3.hash()));
+	assert_eq!(tree.common_ancestor(b3.hash(), 7), None);
+}
+
+#[test]
+fn bc_tree_reorg_path() {
+	let g = Header::genesis();
+	let b1 = g.child(1, 1);
+	let b2 = b1.child(2, 3);
+	let b3 = b2.child(3, 6);
+	let b2_fork = b1.child(4, 5);
+	let b3_fork = b2_fork.child(5, 10);
+	let b4_fork = b3_fork.child(6, 16);
+	let mut tree = BlockTree::new(g.clone());
+	for header in [&b1, &b2, &b3, &b2_fork, &b3_fork, &b4_fork] {
+		tree.import(header.clone()).unwrap();
+	}
+
+	let (retracted, enacted) = tree.reorg_path(b3.hash(), b4_fork.hash()).unwrap();
+	assert_eq!(retracted, vec![b3.hash(), b2.hash()]);
+	assert_eq!(enacted, vec![b2_fork.hash(), b3_fork.hash(), b4_fork.hash()]);
+
+	// Moving along the same chain retracts nothing.
+	assert_eq!(tree.reorg_path(b1.hash(), b3.hash()), Some((vec![], vec![b2.hash(), b3.hash()])));
+	assert_eq!(tree.reorg_path(b3.hash(), b3.hash()), Some((vec![], vec![])));
+}
+
+#[test]
+fn bc_tree_fork_choice_rules_differ() {
+	let (tree, long, bushy) = bushy_tree();
+
+	assert_eq!(LongestChain::best_tip(&tree), long);
+	assert_eq!(Ghost::best_tip(&tree), bushy);
+	assert!(tree.leaves().contains(&HeaviestChain::best_tip(&tree)));
+}
+
+#[test]
+fn bc_tree_heaviest_chain_counts_work() {
+	let g = Header::genesis();
+	let short = g.child(1, 1);
+	let b1 = g.child(2, 2);
+	let b2 = b1.child(3, 5);
+	let mut tree = BlockTree::new(g);
+	for header in [&short, &b1, &b2] {
+		tree.import(header.clone()).unwrap();
+	}
+
+	let heaviest = if work(&short) > work(&b1) + work(&b2) { short.hash() } else { b2.hash() };
+	assert_eq!(HeaviestChain::best_tip(&tree), heaviest);
+}
+
+/// Run every fork choice rule over the same random trees, and report how often they disagree.
+/// They always agree on a tree without forks, and always pick a leaf.
+#[test]
+fn bc_tree_fork_choice_differential() {
+	use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+	fn best_tips(tree: &BlockTree) -> [(String, Hash); 3] {
+		[
+			(LongestChain::human_name(), LongestChain::best_tip(tree)),
+			(HeaviestChain::human_name(), HeaviestChain::best_tip(tree)),
+			(Ghost::human_name(), Ghost::best_tip(tree)),
+		]
+	}
+
+	// A fixed seed, so that a failure can be reproduced
+	let mut rng = StdRng::seed_from_u64(1087);
+	let mut disagreements = Vec::new();
+	for _ in 0..50 {
+		let g = Header::genesis();
+		let mut tree = BlockTree::new(g.clone());
+		let mut known = vec![g];
+		for _ in 0..20 {
+			let parent = known.choose(&mut rng).unwrap();
+			let child = parent.child(rng.gen(), rng.gen());
+			tree.import(child.clone()).unwrap();
+			known.push(child);
+		}
+
+		let tips = best_tips(&tree);
+		let leaves = tree.leaves();
+		assert!(tips.iter().all(|(_, tip)| leaves.contains(tip)));
+		if tips.iter().any(|(_, tip)| *tip != tips[0].1) {
+			disagreements.push((tree, tips));
+		}
+	}
+
+	// Random trees are bushy enough for the rules to disagree now and then, but not always.
+	assert!(!disagreements.is_empty());
+	assert!(disagreements.len() < 50);
+	for (tree, tips) in &disagreements {
+		let height = |tip: Hash| tree.get(tip).unwrap().height();
+		let [(_, longest), (_, heaviest), (_, ghost)] = tips;
+		// The longest chain rule never picks a shorter chain than the others.
+		assert!(height(*longest) >= height(*heaviest));
+		assert!(height(*longest) >= height(*ghost));
+	}
+
+	let g = Header::genesis();
+	let mut line = BlockTree::new(g.clone());
+	let mut tip = g;
+	for _ in 0..5 {
+		tip = tip.child(rng.gen(), rng.gen());
+		line.import(tip.clone()).unwrap();
+	}
+	assert!(best_tips(&line).iter().all(|(_, best)| *best == tip.hash()));
+}
+
+#[test]
+fn bc_tree_finality_prunes_competing_branches() {
+	let (mut tree, long, bushy) = bushy_tree();
+	let b1 = tree.chain_to(bushy).unwrap()[1].hash();
+
+	let pruned = tree.finalize(b1).unwrap();
+	// The five blocks from a to e go.
+	assert_eq!(pruned.len(), 5);
+	assert!(pruned.contains(&long));
+	assert_eq!(tree.get(long), None);
+	assert_eq!(tree.subtree_size(tree.root()), 7);
+	assert_eq!(tree.finalized(), b1);
+	assert_eq!(LongestChain::best_tip(&tree), bushy);
+
+	// Finality never moves back onto a pruned branch, or backwards.
+	assert_eq!(tree.finalize(long), None);
+	assert_eq!(tree.finalize(tree.root()), None);
+}
+
+#[test]
+fn bc_tree_rejects_blocks_past_finality() {
+	use super::finality::{Finality, Vote};
+	use crate::crypto::{Pair, SchnorrPair};
+
+	let g = Header::genesis();
+	let b1 = g.child(1, 1);
+	let b2 = b1.child(2, 3);
+	let fork_b1 = g.child(3, 3);
+	let mut tree = BlockTree::new(g.clone());
+	for header in [&b1, &b2, &fork_b1] {
+		tree.import(header.clone()).unwrap();
+	}
+
+	// The voters finalize b2.
+	let voter = SchnorrPair::from_seed(0);
+	let mut finality = Finality::with_equal_stakes(&[voter.public()]);
+	finality.import_vote(Vote::new(&voter, &b2)).unwrap();
+	let (_, finalized) = finality.finalize(&tree.chain_to(b2.hash()).unwrap()).unwrap().unwrap();
+	tree.finalize(finalized).unwrap();
+
+	assert_eq!(tree.import(fork_b1.child(4, 7)), Err(ImportError::PrunedParent));
+	assert_eq!(tree.import(b1.child(5, 6)), Err(ImportError::BelowFinality));
+	assert_eq!(tree.import(g.child(1, 1).child(2, 3)), Err(ImportError::UnknownParent));
+	assert!(tree.import(b2.child(6, 9)).is_ok());
+}
+
+#[test]
+fn bc_tree_survives_a_fork_bomb() {
+	let limits =
+		ForkLimits { max_forks_per_height: 2, deep_fork_depth: 3, min_deep_fork_work: 1000 };
+	let g = Header::genesis();
+	let mut tree = BlockTree::with_limits(g.clone(), limits);
+	let mut chain = vec![g.clone()];
+	for state in 1..=10 {
+		let tip = chain.last().unwrap().child(0, state);
+		tree.import(tip.clone()).unwrap();
+		chain.push(tip);
+	}
+
+	// The attacker floods the tree with cheap forks near the tip. Only two are kept.
+	let refused = (0..1000)
+		.map(|state| tree.import(chain[7].child(1, state)))
+		.filter(|result| *result == Err(ImportError::TooManyForks))
+		.count();
+	assert_eq!(refused, 998);
+	assert_eq!(tree.subtree_size(tree.root()), 13);
+
+	// Forks deep below the tip are only kept if somebody worked hard on them.
+	let cheap = (0..).map(|state| g.child(1, state)).find(|h| work(h) < 1000).unwrap();
+	assert_eq!(tree.import(cheap), Err(ImportError::InsufficientWork));
+	let heavy = (0..).map(|state| g.child(1, state)).find(|h| work(h) >= 1000).unwrap();
+	assert!(tree.import(heavy).is_ok());
+
+	// The best chain can always be extended.
+	assert!(tree.import(chain[10].child(0, 11)).is_ok());
+}
+
+#[test]
+fn bc_tree_renders_forks() {
+	let (tree, _, _) = bushy_tree();
+
+	let expected = [
+		"G -- 1 -- 2 -- 3 -- 4",
+		"      \\-- 2'",
+		"      \\-- 2''",
+		" \\-- 1' -- 2' -- 3' -- 4' -- 5'",
+	];
+	assert_eq!(render_fork_tree(&tree), expected.join("\n"));
+	assert_eq!(render_fork_tree(&BlockTree::new(Header::genesis())), "G");
+}
+
+#[test]
+fn bc_tree_renders_dot() {
+	let g = Header::genesis();
+	let b1 = g.child(1, 1);
+	let mut tree = BlockTree::new(g.clone());
+	tree.import(b1.clone()).unwrap();
+
+	let dot = render_fork_tree_dot(&tree);
+	assert!(dot.starts_with("digraph {"));
+	assert!(dot.contains(&format!("\"{:x}\" [label=\"1\"];", b1.hash())));
+	assert!(dot.contains(&format!("\"{:x}\" -> \"{:x}\";", g.hash(), b1.hash())));
+}
@@ -0,0 +1,155 @@
+//! Developing a runtime or a wallet against a real chain is slow. Every extrinsic has to wait for
+//! somebody to mine or author a block before its effects show up. Development chains skip all
+//! that. There is a single node, no consensus, and blocks are authored whenever the developer
+//! wants them.
+//!
+//! There are two ways to decide when. With instant seal, the node authors a block as soon as an
+//! extrinsic arrives in its pool, so every extrinsic is executed right away. With manual seal, the
+//! node only authors when it is asked to, which is handy for tests that need to control exactly
+//! what goes into each block. Real nodes take that request over RPC as `engine_createBlock`, and
+//! may also be asked to finalize a block with `engine_finalizeBlock`. Here those are just methods.
+
+use super::{
+	p4_batched_extrinsics::{Block, Stf},
+	transaction_pool::{PoolError, PoolExtrinsic, TransactionPool},
+};
+use std::hash::Hash as StdHash;
+type Hash = u64;
+
+/// When a development chain authors blocks
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SealMode {
+	/// A block is authored as soon as an extrinsic is submitted.
+	Instant,
+	/// Blocks are only authored when `create_block` is called.
+	Manual,
+}
+
+/// A single node development chain, executed with the STF `F`
+pub struct DevChain<F: Stf> {
+	/// Extrinsics waiting for the next block
+	pool: TransactionPool<F>,
+	/// Every block from genesis to the best block. There is nobody to fork with, so this is the
+	/// whole chain.
+	blocks: Vec<Block<F::Extrinsic, F::State>>,
+	/// The height of the last finalized block
+	finalized: usize,
+	/// When blocks are authored
+	mode: SealMode,
+}
+
+impl<F: Stf> DevChain<F>
+where
+	F::Extrinsic: PoolExtrinsic + StdHash + Clone + PartialEq,
+	F::State: StdHash + Clone + Eq,
+{
+	/// A chain with just the given genesis block, which is final from the start
+	pub fn new(stf: F, genesis: Block<F::Extrinsic, F::State>, mode: SealMode) -> Self {
+		DevChain { pool: TransactionPool::new(stf), blocks: vec![genesis], finalized: 0, mode }
+	}
+
+	/// Every block from genesis to the best block
+	pub fn blocks(&self) -> &[Block<F::Extrinsic, F::State>] {
+		&self.blocks
+	}
+
+	/// The most recently authored block
+	pub fn best(&self) -> &Block<F::Extrinsic, F::State> {
+		self.blocks.last().expect("the chain always contains genesis")
+	}
+
+	/// The last finalized block
+	pub fn finalized(&self) -> &Block<F::Extrinsic, F::State> {
+		&self.blocks[self.finalized]
+	}
+
+	/// The extrinsics waiting for the next block
+	pub fn pending(&self) -> &[F::Extrinsic] {
+		self.pool.pending()
+	}
+
+	/// Submit the given extrinsic to the pool, checking it against the best state. With instant
+	/// seal, a block including it is authored right away.
+	pub fn submit(&mut self, extrinsic: F::Extrinsic) -> Result<(), PoolError<F::Error>> {
+		self.pool.submit(extrinsic, &self.best().header().state())?;
+		if self.mode == SealMode::Instant {
+			self.create_block(false);
+		}
+		Ok(())
+	}
+
+	/// Author a block on top of the best block from the ready extrinsics, and return its hash.
+	/// Nothing is authored if no extrinsic is ready, unless an empty block is asked for.
+	///
+	/// This is `engine_createBlock`.
+	pub fn create_block(&mut self, create_empty: bool) -> Option<Hash> {
+		let best = self.blocks.last().expect("the chain always contains genesis");
+		if !create_empty && self.pool.ready(&best.header().state()).is_empty() {
+			return None
+		}
+		let block = self.pool.author(best);
+		self.pool.revalidate(&block.header().state());
+		let hash = block.header().hash();
+		self.blocks.push(block);
+		Some(hash)
+	}
+
+	/// Finalize the block with the given hash, along with all of its ancestors. Returns whether
+	/// the block is in the chain and not already behind the last finalized block.
+	///
+	/// This is `engine_finalizeBlock`.
+	pub fn finalize_block(&mut self, hash: Hash) -> bool {
+		match self.blocks.iter().position(|block| block.header().hash() == hash) {
+			Some(height) if height >= self.finalized => {
+				self.finalized = height;
+				true
+			},
+			_ => false,
+		}
+	}
+}
+
+#[test]
+fn bc_dev_instant_seal_authors_on_every_submit() {
+	use super::transaction_pool::{pay, Wallet};
+
+	let mut chain = DevChain::new(Wallet, Block::genesis_with_state(100), SealMode::Instant);
+	chain.submit(pay(3, 0)).unwrap();
+	chain.submit(pay(2, 1)).unwrap();
+
+	assert_eq!(chain.blocks().len(), 3);
+	assert!(chain.pending().is_empty());
+	assert_eq!(chain.best().header().state(), 94);
+	assert!(chain.blocks()[0].verify_sub_chain_with(&Wallet, &chain.blocks()[1..]));
+
+	// Extrinsics that can't be executed are still turned away, and nothing is authored.
+	assert!(chain.submit(pay(5, 200)).is_err());
+	assert_eq!(chain.blocks().len(), 3);
+}
+
+#[test]
+fn bc_dev_manual_seal_waits_to_be_asked() {
+	use super::transaction_pool::{pay, Wallet};
+
+	let mut chain = DevChain::new(Wallet, Block::genesis_with_state(100), SealMode::Manual);
+	assert_eq!(chain.create_block(false), None);
+
+	chain.submit(pay(3, 0)).unwrap();
+	chain.submit(pay(2, 1)).unwrap();
+	assert_eq!(chain.blocks().len(), 1);
+	assert_eq!(chain.pending().len(), 2);
+
+	let b1 = chain.create_block(false).unwrap();
+	assert_eq!(chain.best().body().len(), 2);
+	assert!(chain.pending().is_empty());
+	let b2 = chain.create_block(true).unwrap();
+	assert!(chain.best().body().is_empty());
+
+	// Finality only ever moves forward, and only to blocks in the chain.
+	assert_eq!(chain.finalized().header().height(), 0);
+	assert!(chain.finalize_block(b2));
+	assert_eq!(chain.finalized().header().hash(), b2);
+	assert!(!chain.finalize_block(b1));
+	assert!(!chain.finalize_block(12345));
+	assert_eq!(chain.finalized().header().hash(), b2);
+}
@@ -0,0 +1,378 @@
+//! Fork choice only ever says which chain is best _for now_. A longer or heavier chain may turn up
+//! at any time, and then blocks that seemed settled are reverted. Finality puts a stop to that.
+//!
+//! A fixed set of voters, each with some stake, sign votes on the blocks they consider best. A vote
+//! for a block is also a vote for all of its ancestors. Once voters holding more than two thirds
+//! of the stake have voted for a block or one of its descendants, the block is finalized, and no
+//! fork choice may ever revert it.
+//!
+//! Two thirds is what makes this safe. Two conflicting blocks could only both be finalized if more
+//! than a third of the stake voted for both, and voting for two blocks on different forks is
+//! equivocation. Two votes at the same height always give it away. Votes at different heights
+//! only do given the block tree, to tell whether one block builds on the other. Either way, the
+//! equivocators' votes are thrown out of the tally.
+
+use super::{block_tree::BlockTree, p4_batched_extrinsics::Header, p5_fork_choice::ForkChoice};
+use crate::crypto::{Pair, Public, SchnorrPair, Signature};
+use std::collections::HashMap;
+type Hash = u64;
+
+/// A voter's signed vote for a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Vote {
+	/// Who voted
+	pub voter: Public,
+	/// The hash of the block voted for
+	pub target: Hash,
+	/// The height of the block voted for
+	pub height: u64,
+	/// The voter's signature over the target and height
+	pub signature: Signature,
+}
+
+impl Vote {
+	/// Vote for the given block with the given key.
+	pub fn new(pair: &SchnorrPair, block: &Header) -> Self {
+		let (target, height) = (block.hash(), block.height());
+		Vote { voter: pair.public(), target, height, signature: pair.sign(&(target, height)) }
+	}
+
+	/// Whether the vote was really signed by its voter.
+	pub fn verify(&self) -> bool {
+		SchnorrPair::verify(&self.signature, &(self.target, self.height), &self.voter)
+	}
+}
+
+/// The reasons a vote may be refused
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VoteError {
+	/// The voter isn't one of the voters.
+	UnknownVoter,
+	/// The signature wasn't made by the voter.
+	BadSignature,
+}
+
+/// The reasons the finalized block can't move to the given chain
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FinalityError {
+	/// The chain doesn't include the block that is already finalized, so finalizing anything on
+	/// it would revert that block.
+	RevertsFinalized,
+}
+
+/// Collects votes and keeps track of which block is finalized.
+#[derive(Clone, Debug)]
+pub struct Finality {
+	/// The stake of each voter
+	stakes: HashMap<Public, u64>,
+	/// Every valid vote of each voter, by height
+	votes: HashMap<Public, HashMap<u64, Vote>>,
+	/// Pairs of votes in which a voter voted for two blocks on different forks
+	equivocations: Vec<(Vote, Vote)>,
+	/// The height and hash of the latest finalized block, if any
+	finalized: Option<(u64, Hash)>,
+}
+
+impl Finality {
+	/// Start collecting votes from the given voters, each with the given stake. Nothing is
+	/// finalized yet.
+	pub fn new(stakes: impl IntoIterator<Item = (Public, u64)>) -> Self {
+		Finality {
+			stakes: stakes.into_iter().collect(),
+			votes: HashMap::new(),
+			equivocations: Vec::new(),
+			finalized: None,
+		}
+	}
+
+	/// Start collecting votes from the given voters, each with the same stake.
+	pub fn with_equal_stakes(voters: &[Public]) -> Self {
+		Self::new(voters.iter().map(|voter| (*voter, 1)))
+	}
+
+	/// The height and hash of the latest finalized block, if any
+	pub fn finalized(&self) -> Option<(u64, Hash)> {
+		self.finalized
+	}
+
+	/// Every pair of votes found so far in which a voter voted for two blocks on different forks
+	pub fn equivocations(&self) -> &[(Vote, Vote)] {
+		&self.equivocations
+	}
+
+	/// Add the given vote to the tally. A vote that conflicts with one the voter already cast is
+	/// kept as proof of equivocation, and the voter's stake stops counting for anything.
+	pub fn import_vote(&mut self, vote: Vote) -> Result<(), VoteError> {
+		if !self.stakes.contains_key(&vote.voter) {
+			return Err(VoteError::UnknownVoter)
+		}
+		if !vote.verify() {
+			return Err(VoteError::BadSignature)
+		}
+		let cast = self.votes.entry(vote.voter).or_default();
+		let first = *cast.entry(vote.height).or_insert(vote);
+		if first.target != vote.target {
+			self.equivocations.push((first, vote));
+		}
+		Ok(())
+	}
+
+	/// Look for voters who voted for blocks on different forks of the given tree, at any heights,
+	/// and keep their votes as proof of equivocation. Votes for blocks the tree doesn't have are
+	/// left alone. Returns how many new equivocations were found.
+	pub fn detect_equivocations(&mut self, tree: &BlockTree) -> usize {
+		let mut found = Vec::new();
+		for (voter, cast) in &self.votes {
+			if self.equivocated(voter) {
+				continue
+			}
+			let mut cast: Vec<Vote> = cast.values().copied().collect();
+			cast.sort_unstable_by_key(|vote| vote.height);
+			// Every vote must be for a descendant of each earlier vote's block, or the two are on
+			// different forks.
+			let builds_on = |earlier: &Vote, later: &Vote| {
+				tree.common_ancestor(earlier.target, later.target)
+					.is_none_or(|common| common == earlier.target)
+			};
+			let conflict = cast.iter().enumerate().find_map(|(i, later)| {
+				let earlier = cast[..i].iter().find(|earlier| !builds_on(earlier, later))?;
+				Some((*earlier, *later))
+			});
+			found.extend(conflict);
+		}
+		let count = found.len();
+		self.equivocations.extend(found);
+		count
+	}
+
+	/// Whether the given voter has equivocated
+	fn equivocated(&self, voter: &Public) -> bool {
+		self.equivocations.iter().any(|(first, _)| first.voter == *voter)
+	}
+
+	/// Finalize the latest block of the given chain that more than two thirds of the stake has
+	/// voted for, counting votes for its descendants in the chain too. Returns the newly
+	/// finalized block, if any.
+	///
+	/// Equivocators' stake still counts towards the total, but not in support of any block, so
+	/// equivocating can only ever stall finality, never finalize conflicting blocks.
+	pub fn finalize(&mut self, chain: &[Header]) -> Result<Option<(u64, Hash)>, FinalityError> {
+		if !self.includes_finalized(chain) {
+			return Err(FinalityError::RevertsFinalized)
+		}
+		let total: u128 = self.stakes.values().map(|stake| *stake as u128).sum();
+		let position: HashMap<Hash, usize> =
+			chain.iter().enumerate().map(|(index, header)| (header.hash(), index)).collect();
+
+		// The deepest block of the chain each honest voter has voted for
+		let mut deepest = Vec::new();
+		for (voter, cast) in &self.votes {
+			if self.equivocated(voter) {
+				continue
+			}
+			let index = cast.values().filter_map(|vote| position.get(&vote.target)).max();
+			if let Some(index) = index {
+				deepest.push((*index, self.stakes[voter] as u128));
+			}
+		}
+		deepest.sort_unstable_by(|a, b| b.cmp(a));
+
+		let mut support = 0;
+		for (index, stake) in deepest {
+			support += stake;
+			if 3 * support > 2 * total {
+				let header = &chain[index];
+				let newly = (header.height(), header.hash());
+				if self.finalized.is_some_and(|(height, _)| height >= newly.0) {
+					return Ok(None)
+				}
+				self.finalized = Some(newly);
+				return Ok(Some(newly))
+			}
+		}
+		Ok(None)
+	}
+
+	/// Whether the given chain includes the finalized block. Every chain does while nothing is
+	/// finalized.
+	pub fn includes_finalized(&self, chain: &[Header]) -> bool {
+		match self.finalized {
+			Some((_, hash)) => chain.iter().any(|header| header.hash() == hash),
+			None => true,
+		}
+	}
+
+	/// Decide whether a node that currently considers `current` best should switch to
+	/// `candidate`. The candidate must be better according to the fork choice rule `F`, and
+	/// must not revert the finalized block.
+	pub fn should_switch<F: ForkChoice>(&self, current: &[Header], candidate: &[Header]) -> bool {
+		self.includes_finalized(candidate) && F::first_chain_is_better(candidate, current)
+	}
+}
+
+/// The longest chain wins. The fork choice rules of part 5 are left as exercises, so the tests
+/// below bring their own.
+#[cfg(test)]
+struct Longest;
+
+#[cfg(test)]
+impl ForkChoice for Longest {
+	fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+		chain_1.len() > chain_2.len()
+	}
+}
+
+/// The given number of key pairs, and the finality gadget they vote in with equal stakes
+#[cfg(test)]
+fn voters(count: u64) -> (Vec<SchnorrPair>, Finality) {
+	let pairs: Vec<SchnorrPair> = (0..count).map(SchnorrPair::from_seed).collect();
+	let publics: Vec<Public> = pairs.iter().map(|pair| pair.public()).collect();
+	(pairs, Finality::with_equal_stakes(&publics))
+}
+
+/// A chain from genesis with the given number of blocks after genesis
+#[cfg(test)]
+fn chain(length: usize) -> Vec<Header> {
+	let mut chain = vec![Header::genesis()];
+	for i in 0..length {
+		let child = chain[i].child(0, 0);
+		chain.push(child);
+	}
+	chain
+}
+
+#[test]
+fn bc_finality_needs_more_than_two_thirds() {
+	let (pairs, mut finality) = voters(4);
+	let chain = chain(3);
+
+	for pair in &pairs[..2] {
+		finality.import_vote(Vote::new(pair, &chain[3])).unwrap();
+	}
+	assert_eq!(finality.finalize(&chain), Ok(None));
+
+	finality.import_vote(Vote::new(&pairs[2], &chain[2])).unwrap();
+	assert_eq!(finality.finalize(&chain), Ok(Some((2, chain[2].hash()))));
+	assert_eq!(finality.finalized(), Some((2, chain[2].hash())));
+}
+
+#[test]
+fn bc_finality_weighs_stake() {
+	let pairs: Vec<SchnorrPair> = (0..3).map(SchnorrPair::from_seed).collect();
+	let mut finality =
+		Finality::new([(pairs[0].public(), 5), (pairs[1].public(), 1), (pairs[2].public(), 1)]);
+	let chain = chain(2);
+
+	finality.import_vote(Vote::new(&pairs[1], &chain[2])).unwrap();
+	finality.import_vote(Vote::new(&pairs[2], &chain[2])).unwrap();
+	assert_eq!(finality.finalize(&chain), Ok(None));
+
+	finality.import_vote(Vote::new(&pairs[0], &chain[1])).unwrap();
+	assert_eq!(finality.finalize(&chain), Ok(Some((1, chain[1].hash()))));
+}
+
+#[test]
+fn bc_finality_refuses_bad_votes() {
+	let (pairs, mut finality) = voters(2);
+	let genesis = Header::genesis();
+	let outsider = SchnorrPair::from_seed(99);
+
+	assert_eq!(finality.import_vote(Vote::new(&outsider, &genesis)), Err(VoteError::UnknownVoter));
+	let forged = Vote { voter: pairs[1].public(), ..Vote::new(&pairs[0], &genesis) };
+	assert_eq!(finality.import_vote(forged), Err(VoteError::BadSignature));
+}
+
+#[test]
+fn bc_finality_ignores_equivocators() {
+	let (pairs, mut finality) = voters(4);
+	let chain = chain(1);
+	let fork = chain[0].child(1, 1);
+
+	finality.import_vote(Vote::new(&pairs[0], &chain[1])).unwrap();
+	finality.import_vote(Vote::new(&pairs[0], &fork)).unwrap();
+	for pair in &pairs[1..3] {
+		finality.import_vote(Vote::new(pair, &chain[1])).unwrap();
+	}
+
+	assert_eq!(finality.equivocations().len(), 1);
+	assert_eq!(finality.equivocations()[0].0.voter, pairs[0].public());
+	assert_eq!(finality.finalize(&chain), Ok(None));
+
+	finality.import_vote(Vote::new(&pairs[3], &chain[1])).unwrap();
+	assert_eq!(finality.finalize(&chain), Ok(Some((1, chain[1].hash()))));
+}
+
+#[test]
+fn bc_finality_is_never_reverted() {
+	let (pairs, mut finality) = voters(3);
+	let current = chain(2);
+	for pair in &pairs {
+		finality.import_vote(Vote::new(pair, &current[1])).unwrap();
+	}
+	finality.finalize(&current).unwrap();
+
+	// A longer fork from genesis would win on length alone, but it reverts the finalized block.
+	let mut fork = vec![current[0].clone()];
+	for i in 0..5 {
+		let child = fork[i].child(1, 1);
+		fork.push(child);
+	}
+	assert!(!finality.should_switch::<Longest>(&current, &fork));
+	assert_eq!(finality.finalize(&fork), Err(FinalityError::RevertsFinalized));
+
+	// A longer chain building on the finalized block is fine.
+	let mut longer = current.clone();
+	let child = longer[2].child(1, 1);
+	longer.push(child);
+	assert!(finality.should_switch::<Longest>(&current, &longer));
+
+	// Finality never moves backwards.
+	assert_eq!(finality.finalize(&current[..2]), Ok(None));
+	assert_eq!(finality.finalized(), Some((1, current[1].hash())));
+}
+
+#[test]
+fn bc_finality_catches_equivocation_across_heights() {
+	let (pairs, mut finality) = voters(4);
+	let chain = chain(2);
+	let mut tree = BlockTree::new(chain[0].clone());
+	for header in &chain[1..] {
+		tree.import(header.clone()).unwrap();
+	}
+	let fork_1 = chain[0].child(1, 1);
+	let fork_2 = fork_1.child(1, 2);
+	tree.import(fork_1).unwrap();
+	tree.import(fork_2.clone()).unwrap();
+
+	// Voting for a block and later for its descendant is fine.
+	finality.import_vote(Vote::new(&pairs[1], &chain[1])).unwrap();
+	finality.import_vote(Vote::new(&pairs[1], &chain[2])).unwrap();
+	// Voting for blocks on different forks is not, whatever their heights.
+	finality.import_vote(Vote::new(&pairs[0], &chain[1])).unwrap();
+	finality.import_vote(Vote::new(&pairs[0], &fork_2)).unwrap();
+	for pair in &pairs[2..] {
+		finality.import_vote(Vote::new(pair, &chain[2])).unwrap();
+	}
+	assert!(finality.equivocations().is_empty());
+
+	assert_eq!(finality.detect_equivocations(&tree), 1);
+	assert_eq!(finality.equivocations()[0].0.voter, pairs[0].public());
+	assert_eq!(finality.detect_equivocations(&tree), 0);
+	// Three of the four voters are left, which is still more than two thirds.
+	assert_eq!(finality.finalize(&chain), Ok(Some((2, chain[2].hash()))));
+}
+
+#[test]
+fn bc_finality_handles_huge_stakes() {
+	let pairs: Vec<SchnorrPair> = (0..3).map(SchnorrPair::from_seed).collect();
+	let mut finality = Finality::new(pairs.iter().map(|pair| (pair.public(), u64::MAX)));
+	let chain = chain(1);
+
+	for pair in &pairs[..2] {
+		finality.import_vote(Vote::new(pair, &chain[1])).unwrap();
+	}
+	assert_eq!(finality.finalize(&chain), Ok(None));
+
+	finality.import_vote(Vote::new(&pairs[2], &chain[1])).unwrap();
+	assert_eq!(finality.finalize(&chain), Ok(Some((1, chain[1].hash()))));
+}
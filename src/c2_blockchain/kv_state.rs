@@ -0,0 +1,319 @@
+//! So far the state of our chains has been a number or two. Real chains store account balances,
+//! contract code, and much more, all in one big key-value map. This module replaces the single
+//! `u64` state of part 4 with such a map.
+//!
+//! Like in part 6, the state isn't stored in the blocks. The header's `state` field holds the
+//! Merkle root of the map instead. The root commits to every key and value, so a node that
+//! executes a block on top of its own copy of the parent state can check that it got exactly
+//! the state the author did by comparing roots.
+//!
+//! How the map is stored is up to the state backend. The flat map here is the simplest one, and
+//! the trie next door is the one real chains use. There is also a flat map that can be snapshotted
+//! and restored for free, for tests that want to try several scenarios from the same point.
+
+use super::p4_batched_extrinsics::{Execute, Header};
+use crate::{domain, hash, hash_with_domain};
+use std::{collections::BTreeMap, convert::Infallible, rc::Rc};
+type Hash = u64;
+
+/// A way of storing the state of the chain, which is a map from keys to values.
+///
+/// Different backends may come up with different roots for the same map. The only requirement is
+/// that a backend's root commits to every key and value, so a chain must stick to one backend.
+pub trait StateBackend: Clone {
+	/// The value stored under the given key, if any
+	fn get(&self, key: u64) -> Option<u64>;
+
+	/// Store the given value under the given key, replacing whatever was there.
+	fn insert(&mut self, key: u64, value: u64);
+
+	/// Remove whatever is stored under the given key.
+	fn remove(&mut self, key: u64);
+
+	/// The commitment to the complete state that is stored in headers
+	fn root(&self) -> Hash;
+}
+
+/// The simplest state backend. A flat map from keys to values.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct State {
+	entries: BTreeMap<u64, u64>,
+}
+
+impl State {
+	/// An empty state
+	pub fn new() -> Self {
+		State::default()
+	}
+}
+
+impl StateBackend for State {
+	fn get(&self, key: u64) -> Option<u64> {
+		self.entries.get(&key).copied()
+	}
+
+	fn insert(&mut self, key: u64, value: u64) {
+		self.entries.insert(key, value);
+	}
+
+	fn remove(&mut self, key: u64) {
+		self.entries.remove(&key);
+	}
+
+	/// The Merkle root of the state.
+	///
+	/// The leaves are the key-value pairs in order of their keys. Each layer of the tree hashes
+	/// neighbouring pairs of nodes from the layer below, and a node without a neighbour moves up
+	/// unchanged, until only the root is left. The empty state has a root of zero.
+	///
+	/// Changing a single entry means hashing the whole tree again.
+	fn root(&self) -> Hash {
+		merkle_root(&self.entries)
+	}
+}
+
+/// The Merkle root of the given entries, as described on `State::root`.
+fn merkle_root(entries: &BTreeMap<u64, u64>) -> Hash {
+	let mut layer: Vec<Hash> = entries
+		.iter()
+		.map(|entry| hash_with_domain(domain::MERKLE_LEAF, &entry))
+		.collect();
+	while layer.len() > 1 {
+		layer = layer
+			.chunks(2)
+			.map(|pair| match pair {
+				[left, right] => hash_with_domain(domain::MERKLE_NODE, &(left, right)),
+				[single] => *single,
+				_ => unreachable!("chunks are never empty or longer than two"),
+			})
+			.collect();
+	}
+	layer.first().copied().unwrap_or(0)
+}
+
+impl<const N: usize> From<[(u64, u64); N]> for State {
+	fn from(entries: [(u64, u64); N]) -> Self {
+		State { entries: BTreeMap::from(entries) }
+	}
+}
+
+/// A flat map like `State`, with the same roots, that is copied on write.
+///
+/// The map is shared between every copy of the state, so copying one, or taking a snapshot of it,
+/// costs nothing. Only when a shared map is written to is it copied, and then only once. This lets
+/// a test or simulation checkpoint the state part way through a run and branch off several
+/// scenarios from there, without serializing anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SnapshotState {
+	entries: Rc<BTreeMap<u64, u64>>,
+}
+
+/// A checkpoint of a `SnapshotState`, which it can be restored to later
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snapshot(Rc<BTreeMap<u64, u64>>);
+
+impl SnapshotState {
+	/// An empty state
+	pub fn new() -> Self {
+		SnapshotState::default()
+	}
+
+	/// A checkpoint of the state as it is now. Later changes to the state don't affect it.
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot(self.entries.clone())
+	}
+
+	/// Throw away every change since the given checkpoint was taken.
+	pub fn restore(&mut self, snapshot: &Snapshot) {
+		self.entries = snapshot.0.clone();
+	}
+}
+
+impl StateBackend for SnapshotState {
+	fn get(&self, key: u64) -> Option<u64> {
+		self.entries.get(&key).copied()
+	}
+
+	fn insert(&mut self, key: u64, value: u64) {
+		Rc::make_mut(&mut self.entries).insert(key, value);
+	}
+
+	fn remove(&mut self, key: u64) {
+		if self.entries.contains_key(&key) {
+			Rc::make_mut(&mut self.entries).remove(&key);
+		}
+	}
+
+	/// The same Merkle root `State` has for the same entries
+	fn root(&self) -> Hash {
+		merkle_root(&self.entries)
+	}
+}
+
+impl<const N: usize> From<[(u64, u64); N]> for SnapshotState {
+	fn from(entries: [(u64, u64); N]) -> Self {
+		SnapshotState { entries: Rc::new(BTreeMap::from(entries)) }
+	}
+}
+
+/// The extrinsics of a key-value chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Extrinsic {
+	/// Store the given value under the given key.
+	Set { key: u64, value: u64 },
+	/// Remove whatever is stored under the given key.
+	Remove { key: u64 },
+}
+
+impl<B: StateBackend> Execute<Extrinsic> for B {
+	type Error = Infallible;
+
+	fn execute(&self, extrinsic: &Extrinsic) -> Result<B, Infallible> {
+		let mut state = self.clone();
+		match *extrinsic {
+			Extrinsic::Set { key, value } => state.insert(key, value),
+			Extrinsic::Remove { key } => state.remove(key),
+		}
+		Ok(state)
+	}
+}
+
+/// A complete block. The header's state is the state root.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Block {
+	pub header: Header<Extrinsic, Hash>,
+	pub body: Vec<Extrinsic>,
+}
+
+/// Methods for creating and verifying blocks.
+///
+/// As in part 6, the state is not stored in the blocks, so a pre-state is passed explicitly. It
+/// may be kept in any backend.
+impl Block {
+	/// Returns a new valid genesis block committing to the given genesis state. By convention this
+	/// block has no extrinsics.
+	pub fn genesis<B: StateBackend>(genesis_state: &B) -> Self {
+		Block { header: Header::genesis_with_state(genesis_state.root()), body: vec![] }
+	}
+
+	/// Execute the given extrinsics in order on top of the given state.
+	pub fn execute<B: StateBackend>(pre_state: &B, extrinsics: &[Extrinsic]) -> B {
+		extrinsics.iter().fold(pre_state.clone(), |state, extrinsic| {
+			let Ok(state) = state.execute(extrinsic);
+			state
+		})
+	}
+
+	/// Create and return a valid child block, building on the given state of this block.
+	pub fn child<B: StateBackend>(&self, pre_state: &B, extrinsics: Vec<Extrinsic>) -> Self {
+		let post_state = Self::execute(pre_state, &extrinsics);
+		Block { header: self.header.child(hash(&extrinsics), post_state.root()), body: extrinsics }
+	}
+
+	/// Verify this block as a child of the given parent header, whose state is given. Returns the
+	/// post-state if the block is valid.
+	///
+	/// The pre-state must match the parent's state root, and executing the body on top of it must
+	/// give the state root in this block's header.
+	pub fn verify<B: StateBackend>(
+		&self,
+		parent: &Header<Extrinsic, Hash>,
+		pre_state: &B,
+	) -> Option<B> {
+		if pre_state.root() != parent.state() ||
+			!parent.verify_child(&self.header) ||
+			self.header.extrinsics_root() != hash(&self.body)
+		{
+			return None
+		}
+		let post_state = Self::execute(pre_state, &self.body);
+		(post_state.root() == self.header.state()).then_some(post_state)
+	}
+
+	/// Verify that all the given blocks form a valid chain from this block, whose state is given,
+	/// to the tip.
+	pub fn verify_sub_chain<B: StateBackend>(&self, pre_state: &B, chain: &[Block]) -> bool {
+		let mut parent = self;
+		let mut state = pre_state.clone();
+		for child in chain {
+			match child.verify(&parent.header, &state) {
+				Some(post_state) => state = post_state,
+				None => return false,
+			}
+			parent = child;
+		}
+		true
+	}
+}
+
+#[test]
+fn bc_kv_root_commits_to_every_entry() {
+	let state = State::from([(1, 10), (2, 20), (3, 30)]);
+
+	assert_eq!(State::new().root(), 0);
+	assert_eq!(state.root(), State::from([(3, 30), (1, 10), (2, 20)]).root());
+	assert_ne!(state.root(), State::from([(1, 10), (2, 20), (3, 31)]).root());
+	assert_ne!(state.root(), State::from([(1, 10), (2, 20)]).root());
+	assert_ne!(state.root(), State::from([(1, 10), (2, 20), (4, 30)]).root());
+}
+
+#[test]
+fn bc_kv_root_of_a_single_entry_is_its_leaf() {
+	let state = State::from([(7, 70)]);
+
+	assert_eq!(state.root(), hash_with_domain(domain::MERKLE_LEAF, &(&7u64, &70u64)));
+}
+
+#[test]
+fn bc_kv_builds_valid_chain() {
+	let genesis_state = State::from([(1, 10)]);
+	let g = Block::genesis(&genesis_state);
+	let b1 = g.child(&genesis_state, vec![Extrinsic::Set { key: 2, value: 20 }]);
+	let state_1 = State::from([(1, 10), (2, 20)]);
+	let b2 = b1.child(&state_1, vec![Extrinsic::Remove { key: 1 }]);
+
+	assert_eq!(b2.header.state(), State::from([(2, 20)]).root());
+	assert_eq!(b1.verify(&g.header, &genesis_state), Some(state_1));
+	assert!(g.verify_sub_chain(&genesis_state, &[b1, b2]));
+}
+
+#[test]
+fn bc_kv_rejects_wrong_state_root() {
+	let genesis_state = State::new();
+	let g = Block::genesis(&genesis_state);
+	let set = vec![Extrinsic::Set { key: 1, value: 1 }];
+	let wrong = Block {
+		header: g.header.child(hash(&set), State::from([(1, 2)]).root()),
+		body: set.clone(),
+	};
+
+	assert_eq!(wrong.verify(&g.header, &genesis_state), None);
+	assert!(!g.verify_sub_chain(&genesis_state, &[wrong]));
+
+	// Nor does a valid block check out on top of the wrong pre-state.
+	let b1 = g.child(&genesis_state, set);
+	assert_eq!(b1.verify(&g.header, &State::from([(5, 5)])), None);
+}
+
+#[test]
+fn bc_kv_snapshots_branch_scenarios() {
+	let genesis_state = SnapshotState::from([(1, 10)]);
+	let g = Block::genesis(&genesis_state);
+	assert_eq!(g.header.state(), Block::genesis(&State::from([(1, 10)])).header.state());
+
+	let b1 = g.child(&genesis_state, vec![Extrinsic::Set { key: 2, value: 20 }]);
+	let mut state = b1.verify(&g.header, &genesis_state).unwrap();
+	let checkpoint = state.snapshot();
+
+	// One scenario removes a key, the other overwrites it, both starting from the checkpoint.
+	state.remove(1);
+	assert_eq!(state.root(), State::from([(2, 20)]).root());
+	state.restore(&checkpoint);
+	assert_eq!(state.get(1), Some(10));
+	state.insert(1, 11);
+	assert_eq!(state.root(), State::from([(1, 11), (2, 20)]).root());
+
+	// Writing to the state never touched the checkpoint.
+	state.restore(&checkpoint);
+	assert_eq!(state.root(), b1.header.state());
+}
@@ -3,11 +3,23 @@
 //! consensus algorithm to help users decide which history is the canonical one.
 
 // We make the complete Block and Header types publicly visible so that we can continue developing
-// against them in future chapters. The prior iterations are not available outside this chapter.
+// against them in future chapters, along with the fork choice rules that operate on them. The
+// consensus lesson is public too, since its engines and chain spec are worth building on. The other
+// prior iterations are not available outside this chapter.
 
 mod p1_header_chain;
 mod p2_extrinsic_state;
-mod p3_consensus;
+pub mod p3_consensus;
 pub mod p4_batched_extrinsics;
-mod p5_fork_choice;
+pub mod p5_fork_choice;
 mod p6_rich_state;
+pub mod block_tree;
+pub mod dev_seal;
+pub mod finality;
+pub mod kv_state;
+pub mod replay;
+pub mod state_machine_runtime;
+pub mod transaction_pool;
+pub mod trie;
+
+pub use p3_consensus::fork_choice;
@@ -2,7 +2,7 @@
 //! structure. We learned from the lecture that it is actually the headers that are hash linked, so
 //! let's start with that.
 
-use crate::hash;
+use crate::{hash, hash_header};
 
 // We will use Rust's built-in hashing where the output type is u64. I'll make an alias
 // so the code is slightly more readable.
@@ -30,7 +30,7 @@ impl Header {
 	/// Create and return a valid child header.
 	fn child(&self) -> Self {
 		Header {
-			parent: hash(&self),
+			parent: hash_header(&self),
 			height: self.height + 1,
 			extrinsics_root: (),
 			state_root: (),
@@ -46,7 +46,7 @@ impl Header {
 		let chain_iter = chain.iter();
 		let mut prev = self.clone();
 		for block in chain_iter {
-			if block.height != prev.height + 1 || block.parent != hash(&prev) {
+			if block.height != prev.height + 1 || block.parent != hash_header(&prev) {
 				return false;
 			}
 			prev = block.clone();
@@ -105,7 +105,7 @@ fn bc_1_child_block_height() {
 fn bc_1_child_block_parent() {
 	let g = Header::genesis();
 	let b1 = g.child();
-	assert!(b1.parent == hash(&g));
+	assert!(b1.parent == hash_header(&g));
 }
 
 #[test]
@@ -161,3 +161,13 @@ fn bc_1_invalid_chain_is_really_invalid() {
 	let invalid_chain = build_an_invalid_chain();
 	assert!(!invalid_chain[0].verify_sub_chain(&invalid_chain[1..]))
 }
+
+#[test]
+fn bc_1_parent_hash_is_domain_separated() {
+	// Headers are hashed in their own domain, so a plain hash of the parent is not a valid link.
+	let g = Header::genesis();
+	let mut b1 = g.child();
+	b1.parent = hash(&g);
+
+	assert!(!g.verify_sub_chain(&[b1]))
+}
@@ -7,7 +7,7 @@
 //! In the coming parts of this tutorial, we will expand this to be more real-world like and
 //! use some real batching.
 #[allow(unused, unused_variables)]
-use crate::hash;
+use crate::hash_header;
 
 // We will use Rust's built-in hashing where the output type is u64. I'll make an alias
 // so the code is slightly more readable.
@@ -41,7 +41,7 @@ impl Header {
 	/// and at each block we add the extrinsic to the state.
 	fn child(&self, extrinsic: u64) -> Self {
 		Header {
-			parent: hash(&self),
+			parent: hash_header(&self),
 			height: self.height + 1,
 			extrinsic,
 			state: self.state + extrinsic,
@@ -71,7 +71,7 @@ impl Header {
 fn is_block_valid(block: &Header, prev: &Header) -> bool {
 	block.height == prev.height + 1
 		&& block.state == prev.state + block.extrinsic
-		&& block.parent == hash(&prev)
+		&& block.parent == hash_header(&prev)
 }
 
 /// Build and return a valid chain with the given number of blocks.
@@ -162,7 +162,7 @@ fn bc_2_child_block_height() {
 fn bc_2_child_block_parent() {
 	let g = Header::genesis();
 	let b1 = g.child(0);
-	assert!(b1.parent == hash(&g));
+	assert!(b1.parent == hash_header(&g));
 }
 
 #[test]
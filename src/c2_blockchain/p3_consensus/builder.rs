@@ -0,0 +1,112 @@
+//! A builder for headers that break the rules on purpose.
+
+use super::{ConsensusDigest, Engine, Epoch, Hash, Header};
+
+/// Builds a header with any of its fields overridden, valid or not. Start with
+/// `ChainSpec::child_builder`.
+///
+/// Each override only changes its own field. Nothing else is worked out again, so a builder can
+/// make a header that is wrong in exactly one way. Sealing comes last, so the seal covers the
+/// overrides, and the header is only wrong where it was meant to be.
+#[derive(Debug, Clone)]
+pub struct HeaderBuilder {
+	pub(super) header: Header,
+}
+
+impl HeaderBuilder {
+	/// The hash of the parent the header claims to have.
+	pub fn parent(mut self, parent: Hash) -> Self {
+		self.header.parent = parent;
+		self
+	}
+
+	/// The height the header claims to be at.
+	pub fn height(mut self, height: u64) -> Self {
+		self.header.height = height;
+		self
+	}
+
+	/// The extrinsic in the header. The state is left alone.
+	pub fn extrinsic(mut self, extrinsic: u64) -> Self {
+		self.header.extrinsic = extrinsic;
+		self
+	}
+
+	/// The state the header claims to end in.
+	pub fn state(mut self, state: u64) -> Self {
+		self.header.state = state;
+		self
+	}
+
+	/// When the header claims to be authored. The threshold is left alone.
+	pub fn timestamp(mut self, timestamp: u64) -> Self {
+		self.header.timestamp = timestamp;
+		self
+	}
+
+	/// The threshold the header is mined to.
+	pub fn threshold(mut self, threshold: u64) -> Self {
+		self.header.threshold = threshold;
+		self
+	}
+
+	/// The protocol version the author runs.
+	pub fn version(mut self, version: u32) -> Self {
+		self.header.version = version;
+		self
+	}
+
+	/// Make the header the last of its epoch, committing to the given next epoch.
+	pub fn next_epoch(mut self, next: &Epoch) -> Self {
+		self.header.next_epoch = next.commitment();
+		self
+	}
+
+	/// The hashes of the uncles the header includes. The state is left alone.
+	pub fn uncles(mut self, uncles: Vec<Hash>) -> Self {
+		self.header.uncles = uncles;
+		self
+	}
+
+	/// Seal the header with the given nonce, whether or not it gets the hash below the threshold.
+	pub fn nonce(mut self, nonce: u64) -> Self {
+		self.header.consensus_digest = ConsensusDigest::Pow { nonce };
+		self
+	}
+
+	/// The header as built so far. Unless a nonce was given, it is not sealed.
+	pub fn build(self) -> Header {
+		self.header
+	}
+
+	/// Seal the header with the given engine, replacing any nonce it was given.
+	pub fn seal<C: Engine>(self, engine: &C) -> Header {
+		self.header.seal(engine)
+	}
+}
+
+#[test]
+fn bc_3_builder_without_overrides() {
+	use super::{AlwaysValid, ProofOfWork};
+	let g = Header::genesis();
+
+	assert_eq!(g.child_builder(1).build(), g.child_unsealed(1));
+	assert_eq!(g.child_builder(1).seal(&AlwaysValid), g.child_with(1, &AlwaysValid));
+	assert!(g.verify_sub_chain(&[g.child_builder(1).seal(&ProofOfWork)]));
+}
+
+#[test]
+fn bc_3_builder_seals_after_overriding() {
+	use super::{AlwaysValid, ProofOfWork, VerifyError};
+	let g = Header::genesis();
+	let b1 = g.child_builder(1).extrinsic(2).version(3).seal(&ProofOfWork);
+
+	// The seal covers the overrides, so only the state is wrong, not the work.
+	assert_eq!((b1.extrinsic, b1.state, b1.version), (2, 1, 3));
+	assert!(ProofOfWork.validate(&b1));
+	assert_eq!(g.try_verify_sub_chain(&[b1]), Err(VerifyError::BadState { height: 1 }));
+
+	// A nonce given up front is replaced by the seal.
+	let sealed = g.child_builder(1).nonce(7).seal(&AlwaysValid);
+	assert_eq!(sealed.consensus_digest, ConsensusDigest::Empty);
+}
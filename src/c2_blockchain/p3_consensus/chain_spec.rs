@@ -0,0 +1,410 @@
+//! The parameters of a chain, and authoring headers that follow them.
+
+use super::{
+	ConsensusDigest, Deployment, Engine, Fork, Hash, Header, HeaderBuilder, ProofOfWork, RuleSet,
+	ADJUSTMENT_QUOTIENT, GENESIS_TIME, MAX_FUTURE_DRIFT, MAX_UNCLES, MAX_UNCLE_DEPTH,
+	TARGET_BLOCK_TIME, THRESHOLD, UNCLE_REWARD,
+};
+use crate::{
+	c1_state_machine::clock::{Clock, SystemClock},
+	hash_header,
+};
+use std::{
+	cmp::Ordering,
+	collections::BTreeMap,
+	time::{Duration, UNIX_EPOCH},
+};
+
+/// The parameters of a chain. The constants above are only the defaults, so experimenting with
+/// other settings doesn't take a recompile, and tests can try several of them side by side.
+///
+/// Every node on a chain must use the same spec. Nodes with different specs disagree about which
+/// blocks are valid, so they end up on different chains.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpec {
+	/// The threshold of the genesis block. Later blocks retarget from here.
+	pub threshold: u64,
+	/// The rule changes this chain undergoes, in height order. See `with_fork`.
+	pub forks: Vec<Fork>,
+	/// The rule changes that happen once enough authors signal for them
+	pub deployments: Vec<Deployment>,
+	/// The state the chain starts in
+	pub genesis_state: u64,
+	/// When the chain launched, in seconds since the Unix epoch. Header timestamps count from
+	/// here.
+	pub genesis_time: u64,
+	/// How long we would like each block to take, in seconds
+	pub target_block_time: u64,
+	/// Each block moves the threshold by this fraction of its parent's threshold. Anything below 2
+	/// is treated as 2, because 1 would drop the threshold to zero in a single fast block and 0
+	/// doesn't make sense at all.
+	pub adjustment_quotient: u64,
+	/// How far into the future a block's timestamp may be, in seconds
+	pub max_future_drift: u64,
+	/// How many blocks each epoch lasts, on chains verified with `verify_epochs`. Zero means
+	/// the first epoch never ends.
+	pub epoch_length: u64,
+	/// Whether the genesis header must be mined like any other. See `Header::genesis_mined`.
+	pub mined_genesis: bool,
+	/// How much a block adds to the state for each uncle it includes
+	pub uncle_reward: u64,
+	/// How many blocks back an uncle may be
+	pub max_uncle_depth: u64,
+	/// How many uncles a single block may include
+	pub max_uncles: usize,
+	/// The hashes of the blocks at some heights, which are trusted without verifying the blocks
+	/// below them. See `with_checkpoint`.
+	pub checkpoints: BTreeMap<u64, Hash>,
+}
+
+impl Default for ChainSpec {
+	fn default() -> Self {
+		ChainSpec {
+			threshold: THRESHOLD,
+			forks: Vec::new(),
+			deployments: Vec::new(),
+			genesis_state: 0,
+			genesis_time: GENESIS_TIME,
+			target_block_time: TARGET_BLOCK_TIME,
+			adjustment_quotient: ADJUSTMENT_QUOTIENT,
+			max_future_drift: MAX_FUTURE_DRIFT,
+			epoch_length: 0,
+			mined_genesis: false,
+			uncle_reward: UNCLE_REWARD,
+			max_uncle_depth: MAX_UNCLE_DEPTH,
+			max_uncles: MAX_UNCLES,
+			checkpoints: BTreeMap::new(),
+		}
+	}
+}
+
+impl ChainSpec {
+	/// Add a fork to the schedule, keeping it in height order. Blocks above the given height follow
+	/// the given rules until the next fork.
+	pub fn with_fork(mut self, height: u64, rules: RuleSet) -> Self {
+		let index = self.forks.partition_point(|fork| fork.height <= height);
+		self.forks.insert(index, Fork { height, rules });
+		self
+	}
+
+	/// Add a deployment, which activates once enough authors signal for it.
+	pub fn with_deployment(mut self, deployment: Deployment) -> Self {
+		self.deployments.push(deployment);
+		self
+	}
+
+	/// Trust that the block at the given height has the given hash, replacing any checkpoint
+	/// already at that height.
+	///
+	/// The hash of a block commits to its parent, and so to the whole chain below it. A chain that
+	/// links up to a trusted hash must be the trusted chain, so verifying it again would be a
+	/// waste. Only the hash links of the blocks up to the latest checkpoint in a chain are checked,
+	/// and everything after it is verified as usual.
+	pub fn with_checkpoint(mut self, height: u64, hash: Hash) -> Self {
+		self.checkpoints.insert(height, hash);
+		self
+	}
+
+	/// The rules a block at the given height must follow. Blocks before the first fork only have
+	/// to follow the consensus engine.
+	pub fn rules_at(&self, height: u64) -> RuleSet {
+		self.forks
+			.iter()
+			.rev()
+			.find(|fork| fork.height < height)
+			.map_or_else(RuleSet::default, |f| f.rules)
+	}
+
+	/// The real clock, counting from the launch of this chain like header timestamps do. Blocks
+	/// checked against it can't claim to be from further in the future than the spec allows.
+	pub fn clock(&self) -> SystemClock {
+		SystemClock::since(UNIX_EPOCH + Duration::from_secs(self.genesis_time))
+	}
+
+	/// Returns a new valid genesis header for this chain.
+	pub fn genesis(&self) -> Header {
+		Header {
+			parent: 0,
+			height: 0,
+			extrinsic: 0,
+			state: self.genesis_state,
+			timestamp: 0,
+			threshold: self.threshold,
+			version: 0,
+			next_epoch: 0,
+			uncles: Vec::new(),
+			consensus_digest: ConsensusDigest::Empty,
+		}
+	}
+
+	/// The threshold a child of the given parent must use if it is authored at the given time.
+	///
+	/// Blocks that come quicker than the target make the next block more difficult, and blocks
+	/// that come slower make it easier, so the average block time settles on the target.
+	pub(super) fn next_threshold(&self, parent: &Header, timestamp: u64) -> u64 {
+		let step = parent.threshold / self.adjustment_quotient.max(2);
+		let block_time = timestamp.saturating_sub(parent.timestamp);
+		match block_time.cmp(&self.target_block_time) {
+			Ordering::Less => parent.threshold - step,
+			Ordering::Equal => parent.threshold,
+			Ordering::Greater => parent.threshold.saturating_add(step),
+		}
+	}
+
+	/// Create and return a child of the given parent, authored at the given time, but not sealed
+	/// yet. Its digest is zero, so it is the same every time, whatever engine seals it later.
+	pub fn child_unsealed(&self, parent: &Header, extrinsic: u64, timestamp: u64) -> Header {
+		Header {
+			parent: hash_header(parent),
+			height: parent.height + 1,
+			extrinsic,
+			state: parent.state + extrinsic,
+			timestamp,
+			threshold: self.next_threshold(parent, timestamp),
+			version: parent.version,
+			next_epoch: 0,
+			uncles: Vec::new(),
+			consensus_digest: ConsensusDigest::Empty,
+		}
+	}
+
+	/// Start building a child of the given parent, authored at the given time. Anything not
+	/// overridden is the same as for `child_unsealed`.
+	pub fn child_builder(&self, parent: &Header, extrinsic: u64, timestamp: u64) -> HeaderBuilder {
+		HeaderBuilder { header: self.child_unsealed(parent, extrinsic, timestamp) }
+	}
+
+	/// Create and return a child of the given parent including the given uncles, authored at the
+	/// given time, but not sealed yet. The state includes the reward for the uncles.
+	pub fn child_unsealed_with_uncles(
+		&self,
+		parent: &Header,
+		extrinsic: u64,
+		timestamp: u64,
+		uncles: &[Header],
+	) -> Header {
+		let child = self.child_unsealed(parent, extrinsic, timestamp);
+		let uncles: Vec<Hash> = uncles.iter().map(hash_header).collect();
+		Header { state: child.state + self.uncle_reward * uncles.len() as u64, uncles, ..child }
+	}
+
+	/// The state a child of the given parent must end in
+	pub(super) fn state_after(&self, parent: &Header, block: &Header) -> u64 {
+		parent.state + block.extrinsic + self.uncle_reward * block.uncles.len() as u64
+	}
+
+	/// Create and return a child of the given parent, authored at the given time and sealed by
+	/// the given engine.
+	pub fn child_at<C: Engine>(
+		&self,
+		parent: &Header,
+		extrinsic: u64,
+		timestamp: u64,
+		engine: &C,
+	) -> Header {
+		self.child_unsealed(parent, extrinsic, timestamp).seal(engine)
+	}
+
+	/// Create and return a child of the given parent, authored at the time the given clock reads,
+	/// and sealed by the given engine. Our clocks count from zero, so we read them as seconds
+	/// since genesis.
+	///
+	/// If the clock is behind the parent, the child is timestamped one second after the parent
+	/// instead, so that it is still valid.
+	pub fn child_with_clock<C: Engine, K: Clock>(
+		&self,
+		parent: &Header,
+		extrinsic: u64,
+		clock: &K,
+		engine: &C,
+	) -> Header {
+		let timestamp = clock.now().as_secs().max(parent.timestamp + 1);
+		self.child_at(parent, extrinsic, timestamp, engine)
+	}
+
+	/// Whether the given header is a genesis header of this chain. Without a mined genesis, there
+	/// is exactly one. With a mined genesis, any nonce that gets it below the threshold will do.
+	pub fn is_genesis(&self, header: &Header) -> bool {
+		let unsealed = Header { consensus_digest: ConsensusDigest::Empty, ..header.clone() };
+		if self.mined_genesis {
+			unsealed == self.genesis() && ProofOfWork.validate(header)
+		} else {
+			*header == self.genesis()
+		}
+	}
+}
+
+#[test]
+fn bc_3_on_time_blocks_keep_difficulty() {
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	let b2 = b1.child(2);
+
+	assert_eq!(g.threshold, THRESHOLD);
+	assert_eq!(b2.threshold, THRESHOLD);
+	assert_eq!(b2.timestamp, 2 * TARGET_BLOCK_TIME);
+}
+
+#[test]
+fn bc_3_fast_blocks_get_harder() {
+	let g = Header::genesis();
+	let b1 = g.child_at(1, 2, &ProofOfWork);
+	let b2 = b1.child_at(1, 4, &ProofOfWork);
+
+	assert_eq!(b1.threshold, THRESHOLD - THRESHOLD / ADJUSTMENT_QUOTIENT);
+	assert!(b2.threshold < b1.threshold);
+	assert!(hash_header(&b2) < b2.threshold);
+	assert!(g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_slow_blocks_get_easier() {
+	let g = Header::genesis();
+	let b1 = g.child_at(1, 60, &ProofOfWork);
+
+	assert_eq!(b1.threshold, THRESHOLD + THRESHOLD / ADJUSTMENT_QUOTIENT);
+	assert!(g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_cant_verify_wrong_difficulty() {
+	use super::AlwaysValid;
+	let g = Header::genesis();
+	// The block uses the easier threshold of a slow block without actually being slow.
+	let b1 = ChainSpec::default().child_builder(&g, 1, 60).timestamp(5).seal(&AlwaysValid);
+
+	assert!(!g.verify_sub_chain_with(&[b1], &AlwaysValid));
+}
+
+#[test]
+fn bc_3_child_timestamp_from_clock() {
+	use super::AlwaysValid;
+	use crate::c1_state_machine::clock::MockClock;
+	use std::time::Duration;
+
+	let clock = MockClock::new();
+	let g = Header::genesis();
+
+	clock.advance(Duration::from_secs(7));
+	let b1 = g.child_with_clock(1, &clock, &AlwaysValid);
+	assert_eq!(b1.timestamp, 7);
+
+	// The clock hasn't moved, but the child must still come after its parent.
+	let b2 = b1.child_with_clock(1, &clock, &AlwaysValid);
+	assert_eq!(b2.timestamp, 8);
+
+	assert!(g.verify_sub_chain_with_clock(&[b1, b2], &AlwaysValid, &clock));
+}
+
+#[test]
+fn bc_3_cant_verify_timestamp_not_after_parent() {
+	use super::AlwaysValid;
+	let g = Header::genesis();
+	let b1 = g.child_at(1, 10, &AlwaysValid);
+	let b2 = b1.child_at(1, 10, &AlwaysValid);
+
+	assert!(!g.verify_sub_chain_with(&[b1, b2], &AlwaysValid));
+}
+
+#[test]
+fn bc_3_cant_verify_timestamp_too_far_in_future() {
+	use super::AlwaysValid;
+	use crate::c1_state_machine::clock::MockClock;
+	use std::time::Duration;
+
+	let clock = MockClock::new();
+	clock.advance(Duration::from_secs(100));
+	let g = Header::genesis();
+	let near = g.child_at(1, 100 + MAX_FUTURE_DRIFT, &AlwaysValid);
+	let far = g.child_at(1, 101 + MAX_FUTURE_DRIFT, &AlwaysValid);
+
+	assert!(g.verify_sub_chain_with_clock(&[near], &AlwaysValid, &clock));
+	assert!(!g.verify_sub_chain_with_clock(&[far], &AlwaysValid, &clock));
+}
+
+#[test]
+fn bc_3_chain_spec_genesis() {
+	let spec = ChainSpec { genesis_state: 5, threshold: u64::MAX / 2, ..ChainSpec::default() };
+	let g = spec.genesis();
+
+	assert_eq!(g.state, 5);
+	assert_eq!(g.threshold, u64::MAX / 2);
+	assert_eq!(spec.child_at(&g, 3, 10, &ProofOfWork).state, 8);
+}
+
+#[test]
+fn bc_3_chain_spec_changes_retargeting() {
+	use super::AlwaysValid;
+	use crate::c1_state_machine::clock::SystemClock;
+	let slow = ChainSpec { target_block_time: 60, adjustment_quotient: 4, ..ChainSpec::default() };
+	let g = slow.genesis();
+	let b1 = slow.child_at(&g, 1, 10, &AlwaysValid);
+
+	assert_eq!(b1.threshold, THRESHOLD - THRESHOLD / 4);
+	let clock = SystemClock::new();
+	assert!(slow.verify_sub_chain(&g, std::slice::from_ref(&b1), &AlwaysValid, &clock));
+	// Nodes following the default spec disagree about the difficulty.
+	assert!(!g.verify_sub_chain_with(&[b1], &AlwaysValid));
+}
+
+#[test]
+fn bc_3_tiny_adjustment_quotients_are_clamped() {
+	let g = Header::genesis();
+	let halving = ChainSpec { adjustment_quotient: 2, ..ChainSpec::default() };
+	for quotient in [0, 1] {
+		let spec = ChainSpec { adjustment_quotient: quotient, ..ChainSpec::default() };
+		assert_eq!(spec.next_threshold(&g, 1), halving.next_threshold(&g, 1));
+		assert_eq!(spec.next_threshold(&g, 1), THRESHOLD - THRESHOLD / 2);
+		assert_eq!(spec.next_threshold(&g, 1000), halving.next_threshold(&g, 1000));
+	}
+}
+
+#[test]
+fn bc_3_unsealed_children_are_deterministic() {
+	let g = Header::genesis();
+	let partial = g.child_unsealed(5);
+
+	assert_eq!(partial, g.child_unsealed(5));
+	assert_eq!(partial.consensus_digest, ConsensusDigest::Empty);
+	assert_eq!(partial.state, 5);
+	assert_eq!(partial.timestamp, TARGET_BLOCK_TIME);
+}
+
+#[test]
+fn bc_3_mined_genesis() {
+	use super::VerifyError;
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec { mined_genesis: true, ..ChainSpec::default() };
+	let g = Header::genesis_mined(&spec);
+	let b1 = g.child(1);
+	let clock = SystemClock::new();
+
+	assert!(ProofOfWork.validate(&g));
+	assert!(spec.is_genesis(&g));
+	assert_eq!(spec.verify_chain(&[g.clone(), b1], &ProofOfWork, &clock), Ok(()));
+
+	// An unmined genesis doesn't do on this chain, and a mined one doesn't do on others.
+	assert!(!spec.is_genesis(&spec.genesis()));
+	assert!(!ChainSpec::default().is_genesis(&g));
+	assert_eq!(
+		ChainSpec::default().verify_chain(&[g], &ProofOfWork, &clock),
+		Err(VerifyError::BadGenesis)
+	);
+}
+
+#[test]
+fn bc_3_verify_chain_from_plain_genesis() {
+	use super::VerifyError;
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default();
+	let g = spec.genesis();
+	let b1 = g.child(1);
+	let clock = SystemClock::new();
+
+	assert_eq!(spec.verify_chain(&[g.clone(), b1.clone()], &ProofOfWork, &clock), Ok(()));
+	assert_eq!(spec.verify_chain(&[b1], &ProofOfWork, &clock), Err(VerifyError::BadGenesis));
+	assert_eq!(spec.verify_chain(&[], &ProofOfWork, &clock), Err(VerifyError::BadGenesis));
+	// Somebody else mining their own genesis ends up on a different chain.
+	let mined = ChainSpec { mined_genesis: true, ..ChainSpec::default() };
+	assert_ne!(Header::genesis_mined(&mined), Header::genesis_mined(&mined));
+}
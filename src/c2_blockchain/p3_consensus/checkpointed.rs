@@ -0,0 +1,135 @@
+//! An engine that adds checkpoints, signed by an authority, on top of another engine.
+
+use super::{ConsensusDigest, Engine, Header};
+
+/// Two engines composed into one. Every block is sealed by the work engine, and every block at a
+/// multiple of the interval is a checkpoint, which must be sealed by the authority engine as well.
+/// A chain missing a valid checkpoint is invalid however much work went into it, which stops an
+/// attacker with more hash power from rewriting history past the last checkpoint.
+///
+/// A checkpoint's digest holds the work engine's nonce next to the authority's signature, and each
+/// engine checks its own half. So the work engine must seal with a nonce and the authority with a
+/// signature. An interval of zero means there are no checkpoints.
+pub struct Checkpointed<W, A> {
+	/// The engine that seals ordinary blocks
+	pub work: W,
+	/// The engine that seals checkpoints
+	pub authority: A,
+	/// How many blocks apart the checkpoints are
+	pub interval: u64,
+}
+
+impl<W, A> Checkpointed<W, A> {
+	/// Whether the block at the given height is a checkpoint
+	pub fn is_checkpoint(&self, height: u64) -> bool {
+		height.checked_rem(self.interval) == Some(0)
+	}
+}
+
+impl<W: Engine, A: Engine> Engine for Checkpointed<W, A> {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		let work = self.work.seal(partial_header);
+		if !self.is_checkpoint(partial_header.height) {
+			return work
+		}
+		match (work, self.authority.seal(partial_header)) {
+			(ConsensusDigest::Pow { nonce }, ConsensusDigest::Poa { signature }) =>
+				ConsensusDigest::Checkpoint { nonce, signature },
+			// Any other seals don't fit in a checkpoint's digest.
+			_ => ConsensusDigest::Empty,
+		}
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		if !self.is_checkpoint(header.height) {
+			return self.work.validate(header)
+		}
+		let ConsensusDigest::Checkpoint { nonce, signature } = header.consensus_digest else {
+			return false
+		};
+		let mined = Header { consensus_digest: ConsensusDigest::Pow { nonce }, ..header.clone() };
+		let signed =
+			Header { consensus_digest: ConsensusDigest::Poa { signature }, ..header.clone() };
+		self.work.validate(&mined) && self.authority.validate(&signed)
+	}
+}
+
+#[test]
+fn bc_3_checkpoints_every_nth_block() {
+	use super::{poa::ProofOfAuthority, ProofOfWork};
+	use crate::crypto::{Pair, SchnorrPair};
+	let hybrid = Checkpointed {
+		work: ProofOfWork,
+		authority: ProofOfAuthority::dictator(SchnorrPair::from_seed(1)),
+		interval: 3,
+	};
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &hybrid);
+	let b2 = b1.child_with(2, &hybrid);
+	let b3 = b2.child_with(3, &hybrid);
+	let b4 = b3.child_with(4, &hybrid);
+
+	assert!(!hybrid.is_checkpoint(2));
+	assert!(hybrid.is_checkpoint(3));
+	assert!(matches!(b3.consensus_digest, ConsensusDigest::Checkpoint { .. }));
+	assert!(hybrid.validate(&b3));
+	assert!(ProofOfWork.validate(&b4));
+	assert!(g.verify_sub_chain_with(&[b1, b2, b3, b4], &hybrid));
+}
+
+#[test]
+fn bc_3_chain_without_checkpoint_rejected() {
+	use super::{poa::ProofOfAuthority, ProofOfWork};
+	use crate::crypto::{Pair, SchnorrPair};
+	let hybrid = Checkpointed {
+		work: ProofOfWork,
+		authority: ProofOfAuthority::dictator(SchnorrPair::from_seed(1)),
+		interval: 2,
+	};
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &hybrid);
+
+	// However well mined, a block at a checkpoint height needs the authority's signature.
+	let mined_b2 = b1.child(2);
+	assert!(ProofOfWork.validate(&mined_b2));
+	assert!(!g.verify_sub_chain_with(&[b1.clone(), mined_b2], &hybrid));
+
+	// Nor is the signature alone.
+	let signed_b2 = b1.child_with(2, &hybrid.authority);
+	assert!(!g.verify_sub_chain_with(&[b1.clone(), signed_b2], &hybrid));
+
+	// A forged checkpoint is no better.
+	let outsider = Checkpointed {
+		work: ProofOfWork,
+		authority: ProofOfAuthority::dictator(SchnorrPair::from_seed(2)),
+		interval: 2,
+	};
+	let forged_b2 = b1.child_with(2, &outsider);
+	assert!(!g.verify_sub_chain_with(&[b1.clone(), forged_b2], &hybrid));
+
+	// And a properly signed checkpoint still needs its work.
+	let b2 = b1.child_with(2, &hybrid);
+	assert!(g.verify_sub_chain_with(&[b1.clone(), b2.clone()], &hybrid));
+	let ConsensusDigest::Checkpoint { signature, .. } = b2.consensus_digest else { unreachable!() };
+	let unmined = |nonce: &u64| {
+		let mined =
+			Header { consensus_digest: ConsensusDigest::Pow { nonce: *nonce }, ..b2.clone() };
+		!ProofOfWork.validate(&mined)
+	};
+	let nonce = (0..).find(unmined).unwrap();
+	let lazy_b2 =
+		Header { consensus_digest: ConsensusDigest::Checkpoint { nonce, signature }, ..b2 };
+	assert!(!g.verify_sub_chain_with(&[b1, lazy_b2], &hybrid));
+}
+
+#[test]
+fn bc_3_no_checkpoints_with_zero_interval() {
+	use super::{AlwaysValid, ProofOfWork};
+	let hybrid = Checkpointed { work: ProofOfWork, authority: AlwaysValid, interval: 0 };
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	let b2 = b1.child(2);
+
+	assert!(!hybrid.is_checkpoint(0));
+	assert!(g.verify_sub_chain_with(&[b1, b2], &hybrid));
+}
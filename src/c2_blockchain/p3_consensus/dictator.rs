@@ -0,0 +1,78 @@
+//! An engine that lets a single key author blocks without any work.
+
+use super::{
+	poa::{sign_header, signed_by},
+	ConsensusDigest, Engine, Header, ProofOfWork,
+};
+use crate::crypto::{Public, SchnorrPair};
+
+/// Proof of work, except that a dictator may seal any block with a signature instead of mining it.
+///
+/// Whether to obey the dictator is up to each node's operator. Nodes that obey and nodes that
+/// don't agree about every mined block, but split into two chains as soon as the dictator seals
+/// one. Which chain wins is no longer a question for the code, but for the people running it.
+pub struct DictatorOverride {
+	/// The public key of the dictator
+	pub dictator: Public,
+	/// The dictator's key, on the dictator's own node. Every other node mines its blocks.
+	pub signer: Option<SchnorrPair>,
+	/// Whether headers sealed by the dictator are valid. Nodes that don't obey still accept mined
+	/// headers, including the dictator's own.
+	pub obey: bool,
+}
+
+impl Engine for DictatorOverride {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		match &self.signer {
+			Some(signer) => sign_header(signer, partial_header),
+			None => ProofOfWork.seal(partial_header),
+		}
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		ProofOfWork.validate(header) || (self.obey && signed_by(header, &self.dictator))
+	}
+}
+
+#[test]
+fn bc_3_dictator_overrides_work() {
+	use super::{ChainSpec, VerifyError};
+	use crate::{c1_state_machine::clock::SystemClock, crypto::Pair};
+	let dictator = SchnorrPair::from_seed(0);
+	let obeying = DictatorOverride { dictator: dictator.public(), signer: None, obey: true };
+	let palace = DictatorOverride { signer: Some(dictator.clone()), ..obeying };
+	let rebel = DictatorOverride { dictator: dictator.public(), signer: None, obey: false };
+
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &obeying);
+	let b2 = b1.child_with(2, &palace);
+	let chain = [b1, b2];
+
+	// Everybody agrees on the mined block, but only obeying nodes accept the decree.
+	assert!(g.verify_sub_chain_with(&chain[..1], &rebel));
+	assert!(g.verify_sub_chain_with(&chain, &obeying));
+	assert_eq!(
+		ChainSpec::default().try_verify_sub_chain(&g, &chain, &rebel, &SystemClock::new()),
+		Err(VerifyError::InsufficientWork { height: 2 })
+	);
+}
+
+#[test]
+fn bc_3_dictator_needs_no_work() {
+	use super::{poa::ProofOfAuthority, ChainSpec};
+	use crate::{c1_state_machine::clock::SystemClock, crypto::Pair};
+	// Nobody can mine on this chain, but the dictator doesn't have to.
+	let hopeless = ChainSpec { threshold: 0, ..ChainSpec::default() };
+	let dictator = SchnorrPair::from_seed(0);
+	let palace =
+		DictatorOverride { dictator: dictator.public(), signer: Some(dictator), obey: true };
+	let g = hopeless.genesis();
+	let b1 = hopeless.child_at(&g, 1, 10, &palace);
+
+	let clock = SystemClock::new();
+	assert!(hopeless.verify_sub_chain(&g, std::slice::from_ref(&b1), &palace, &clock));
+	// A forged decree is no good.
+	let forger = SchnorrPair::from_seed(1);
+	let forged = hopeless.child_unsealed(&g, 1, 10).seal(&ProofOfAuthority::dictator(forger));
+	assert!(!hopeless.verify_sub_chain(&g, &[forged], &palace, &clock));
+}
@@ -0,0 +1,197 @@
+//! Epochs, which change the consensus parameters at fixed heights.
+
+use super::{
+	poa::signed_by_any, AlwaysValid, ChainSpec, Engine, Hash, Header, ProofOfWork, VerifyError,
+};
+use crate::{c1_state_machine::clock::Clock, crypto::Public, hash};
+use std::collections::VecDeque;
+
+/// The consensus parameters that hold for a whole epoch. Ordinary blocks are mined against the
+/// epoch's threshold, which stays put for the epoch instead of retargeting block by block. The last
+/// block of the epoch is signed by one of the epoch's authorities instead, and commits to the
+/// parameters of the next epoch, so only the authorities can change them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Epoch {
+	/// Who may end the epoch and decide on the next one
+	pub authorities: Vec<Public>,
+	/// The threshold of every block in the epoch
+	pub threshold: u64,
+}
+
+impl Epoch {
+	/// The commitment to this epoch that the last header of the previous epoch carries
+	pub fn commitment(&self) -> Hash {
+		hash(self)
+	}
+}
+
+/// What verification needs to know about epochs as it walks along a chain. Headers only carry
+/// commitments, so the parameters of upcoming epochs are handed over alongside the chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EpochContext {
+	/// The epoch the next block belongs to
+	pub current: Epoch,
+	/// The parameters of the epochs after the current one, in order
+	pub upcoming: VecDeque<Epoch>,
+}
+
+impl ChainSpec {
+	/// Create and return a child of the given parent in the given epoch, authored at the given
+	/// time, but not sealed yet.
+	pub fn epoch_child_unsealed(
+		&self,
+		parent: &Header,
+		extrinsic: u64,
+		timestamp: u64,
+		epoch: &Epoch,
+	) -> Header {
+		let child = self.child_unsealed(parent, extrinsic, timestamp);
+		Header { threshold: epoch.threshold, ..child }
+	}
+
+	/// Verify that all the given headers form a valid chain from the given header to the tip, where
+	/// the given context says which epoch the first block is in, and what comes after it. The
+	/// first epoch ends at the first multiple of the epoch length.
+	///
+	/// On success, the context is moved along to the epoch after the tip, ready to verify more
+	/// blocks on top of it. On failure, it is left as it was.
+	pub fn verify_epochs<K: Clock>(
+		&self,
+		from: &Header,
+		context: &mut EpochContext,
+		chain: &[Header],
+		clock: &K,
+	) -> Result<(), VerifyError> {
+		let latest = clock.now().as_secs() + self.max_future_drift;
+		let mut walked = context.clone();
+		let mut prev = from;
+		for block in chain {
+			let height = prev.height + 1;
+			let epoch = &walked.current;
+			self.check_block(block, prev, latest, &AlwaysValid, epoch.threshold)?;
+			if height.checked_rem(self.epoch_length) == Some(0) {
+				if !signed_by_any(block, &epoch.authorities) {
+					return Err(VerifyError::InsufficientWork { height })
+				}
+				match walked.upcoming.pop_front() {
+					Some(next) if next.commitment() == block.next_epoch => walked.current = next,
+					_ => return Err(VerifyError::BadEpochChange { height }),
+				}
+			} else if block.next_epoch != 0 {
+				return Err(VerifyError::BadEpochChange { height })
+			} else if !ProofOfWork.validate(block) {
+				return Err(VerifyError::InsufficientWork { height })
+			}
+			prev = block;
+		}
+		*context = walked;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+/// A chain spec with three blocks to an epoch, and the epochs it goes through. The first two
+/// epochs' authorities are seeds 1 and 2.
+fn epoch_spec() -> (ChainSpec, Vec<Epoch>) {
+	use crate::crypto::{Pair, SchnorrPair};
+	let spec = ChainSpec { epoch_length: 3, ..ChainSpec::default() };
+	let epochs = (1..=3)
+		.map(|seed| Epoch {
+			authorities: vec![SchnorrPair::from_seed(seed).public()],
+			threshold: u64::MAX >> seed,
+		})
+		.collect();
+	(spec, epochs)
+}
+
+#[cfg(test)]
+/// Build the given number of epochs on top of genesis, mining ordinary blocks and having each
+/// epoch's authority end it.
+fn build_epochs(spec: &ChainSpec, epochs: &[Epoch], count: usize) -> Vec<Header> {
+	use super::{poa::ProofOfAuthority, TARGET_BLOCK_TIME};
+	use crate::crypto::{Pair, SchnorrPair};
+	let mut chain = Vec::new();
+	let mut tip = spec.genesis();
+	for (index, epoch) in epochs.iter().enumerate().take(count) {
+		let authority = ProofOfAuthority::dictator(SchnorrPair::from_seed(index as u64 + 1));
+		for position in 1..=spec.epoch_length {
+			let partial =
+				spec.epoch_child_unsealed(&tip, 1, tip.timestamp + TARGET_BLOCK_TIME, epoch);
+			tip = if position == spec.epoch_length {
+				partial.announcing(&epochs[index + 1]).seal(&authority)
+			} else {
+				partial.seal(&ProofOfWork)
+			};
+			chain.push(tip.clone());
+		}
+	}
+	chain
+}
+
+#[test]
+fn bc_3_epochs_change_at_boundaries() {
+	use crate::c1_state_machine::clock::SystemClock;
+	let (spec, epochs) = epoch_spec();
+	let chain = build_epochs(&spec, &epochs, 2);
+	let mut context = EpochContext {
+		current: epochs[0].clone(),
+		upcoming: epochs[1..].iter().cloned().collect(),
+	};
+
+	assert_eq!(chain[2].threshold, u64::MAX >> 1);
+	assert_eq!(chain[3].threshold, u64::MAX >> 2);
+	assert_eq!(chain[2].next_epoch, epochs[1].commitment());
+	assert_eq!(chain[3].next_epoch, 0);
+
+	// Verifying in two goes carries the context over.
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+	assert_eq!(spec.verify_epochs(&g, &mut context, &chain[..4], &clock), Ok(()));
+	assert_eq!(context.current, epochs[1]);
+	assert_eq!(spec.verify_epochs(&chain[3], &mut context, &chain[4..], &clock), Ok(()));
+	assert_eq!(context.current, epochs[2]);
+	assert!(context.upcoming.is_empty());
+}
+
+#[test]
+fn bc_3_epoch_change_needs_authority_and_commitment() {
+	use super::poa::ProofOfAuthority;
+	use crate::{
+		c1_state_machine::clock::SystemClock,
+		crypto::{Pair, SchnorrPair},
+	};
+	let (spec, epochs) = epoch_spec();
+	let chain = build_epochs(&spec, &epochs, 1);
+	let mut context = EpochContext {
+		current: epochs[0].clone(),
+		upcoming: epochs[1..].iter().cloned().collect(),
+	};
+	let before = context.clone();
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+	let ending_with = |last: Header| [chain[0].clone(), chain[1].clone(), last];
+
+	// Signed by somebody outside the epoch's authorities
+	let outsider = ProofOfAuthority::dictator(SchnorrPair::from_seed(9));
+	let forged = chain[2].clone().seal(&outsider);
+	assert_eq!(
+		spec.verify_epochs(&g, &mut context, &ending_with(forged), &clock),
+		Err(VerifyError::InsufficientWork { height: 3 })
+	);
+	assert_eq!(context, before);
+
+	// Committing to some other epoch than the one handed over
+	let authority = ProofOfAuthority::dictator(SchnorrPair::from_seed(1));
+	let wrong = chain[2].clone().announcing(&epochs[2]).seal(&authority);
+	assert_eq!(
+		spec.verify_epochs(&g, &mut context, &ending_with(wrong), &clock),
+		Err(VerifyError::BadEpochChange { height: 3 })
+	);
+
+	// Announcing mid-epoch
+	let early = chain[0].clone().announcing(&epochs[1]).seal(&ProofOfWork);
+	assert_eq!(
+		spec.verify_epochs(&g, &mut context, &[early], &clock),
+		Err(VerifyError::BadEpochChange { height: 1 })
+	);
+}
@@ -0,0 +1,133 @@
+//! Fork choice: picking the best of several chains. Validity rules decide which chains are
+//! allowed at all. When there are still several valid chains, as there are after a contentious
+//! fork like the even and odd chains, every node needs to pick the same one.
+
+use super::Header;
+use crate::hash_header;
+use std::cmp::Reverse;
+
+/// Pick the best of the given chains by the longest chain rule, which is enough to choose a side
+/// of a contentious fork like the even and odd chains. Part 5 explores other fork choice rules.
+///
+/// The longest chain is the one whose tip is highest, so chains may start anywhere. Ties go to the
+/// chain whose tip has the lowest hash, which doesn't depend on the order the chains are given in,
+/// so every node picks the same chain. Empty chains never win, and are only returned when every
+/// chain is empty.
+pub fn best_chain<'a>(leaves: &[&'a [Header]]) -> &'a [Header] {
+	best_by(leaves, |chain| chain.last().map_or(0, |tip| tip.height))
+}
+
+/// Pick the best of the given chains by the heaviest chain rule. The heaviest chain is the one
+/// with the most total work, however many blocks that took, so a miner can't get ahead by rushing
+/// out lots of easy blocks.
+///
+/// The work of the blocks the chains have in common counts for all of them, so it doesn't matter
+/// whether the chains include their common prefix or not, as long as they all do the same. Ties
+/// are broken just like `best_chain` breaks them.
+pub fn heaviest_chain<'a>(leaves: &[&'a [Header]]) -> &'a [Header] {
+	best_by(leaves, total_work)
+}
+
+/// The work that went into the given headers, as the number of hashes they took to mine on
+/// average. A header with threshold `t` takes `2^64 / t` hashes, so the lower the thresholds, the
+/// heavier the chain. This is what a heaviest chain fork choice compares, rather than the length.
+///
+/// The work of the parent a chain builds on is not included, so pass a whole chain from genesis
+/// or compare sub chains that fork from the same parent.
+pub fn total_work(chain: &[Header]) -> u128 {
+	chain.iter().map(Header::work).sum()
+}
+
+/// The non-empty chain with the greatest given key, with ties going to the chain whose tip has
+/// the lowest hash. Returns an empty chain if there is no other.
+fn best_by<'a, K: Ord>(leaves: &[&'a [Header]], key: impl Fn(&[Header]) -> K) -> &'a [Header] {
+	leaves
+		.iter()
+		.filter_map(|chain| Some((key(chain), Reverse(hash_header(chain.last()?)), *chain)))
+		.max_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)))
+		.map_or(&[], |(_, _, chain)| chain)
+}
+
+#[test]
+fn bc_3_best_chain_is_longest() {
+	use super::build_contentious_forked_chain;
+	let (prefix, even, odd) = build_contentious_forked_chain();
+	let even_chain = [&prefix[..], &even].concat();
+	let longer_odd = [&prefix[..], &odd, &[odd[1].child(1)]].concat();
+
+	assert_eq!(best_chain(&[&even_chain, &longer_odd]), &longer_odd[..]);
+	assert_eq!(best_chain(&[&longer_odd, &even_chain]), &longer_odd[..]);
+	// Only the tip counts, not where the chain starts.
+	assert_eq!(best_chain(&[&even_chain, &longer_odd[3..]]), &longer_odd[3..]);
+	assert_eq!(best_chain(&[&[], &even_chain]), &even_chain[..]);
+	assert!(best_chain(&[]).is_empty());
+}
+
+#[test]
+fn bc_3_best_chain_breaks_ties_by_tip_hash() {
+	use super::build_contentious_forked_chain;
+	let (prefix, even, odd) = build_contentious_forked_chain();
+	let even_chain = [&prefix[..], &even].concat();
+	let odd_chain = [&prefix[..], &odd].concat();
+	let lower = if hash_header(&even[1]) < hash_header(&odd[1]) { &even_chain } else { &odd_chain };
+
+	assert_eq!(best_chain(&[&even_chain, &odd_chain]), &lower[..]);
+	assert_eq!(best_chain(&[&odd_chain, &even_chain]), &lower[..]);
+}
+
+#[test]
+fn bc_3_total_work_adds_up_thresholds() {
+	let g = Header::genesis();
+	let easy = Header { threshold: u64::MAX, ..g.clone() };
+	let hard = Header { threshold: u64::MAX / 1000, ..g.clone() };
+
+	assert_eq!(total_work(&[]), 0);
+	assert_eq!(total_work(&[easy]), 1);
+	assert_eq!(total_work(&[g.clone(), hard]), 1100);
+	assert_eq!(total_work(&[Header { threshold: 0, ..g }]), 1 << 64);
+}
+
+#[test]
+fn bc_3_shorter_chain_can_be_heavier() {
+	use super::{AlwaysValid, ChainSpec};
+	use crate::c1_state_machine::clock::SystemClock;
+	// Quick blocks halve the threshold and slow blocks raise it by half, so two quick blocks take
+	// more work than three slow ones.
+	let spec = ChainSpec { adjustment_quotient: 2, ..ChainSpec::default() };
+	let g = spec.genesis();
+	let mut quick = vec![spec.child_at(&g, 1, 1, &AlwaysValid)];
+	quick.push(spec.child_at(&quick[0], 2, 2, &AlwaysValid));
+	let mut slow = vec![spec.child_at(&g, 1, 20, &AlwaysValid)];
+	slow.push(spec.child_at(&slow[0], 2, 40, &AlwaysValid));
+	slow.push(spec.child_at(&slow[1], 3, 60, &AlwaysValid));
+
+	assert!(total_work(&quick) > total_work(&slow));
+	let clock = SystemClock::new();
+	let quick_verified = spec.verify_sub_chain_detailed(&g, &quick, &AlwaysValid, &clock);
+	let slow_verified = spec.verify_sub_chain_detailed(&g, &slow, &AlwaysValid, &clock);
+	assert_eq!(quick_verified.work, total_work(&quick));
+	assert_eq!(slow_verified.work, total_work(&slow));
+}
+
+#[test]
+fn bc_3_heaviest_chain_beats_longer_chain() {
+	use super::{AlwaysValid, ChainSpec};
+	// Quick blocks halve the threshold and slow blocks raise it by half.
+	let spec = ChainSpec { adjustment_quotient: 2, ..ChainSpec::default() };
+	let g = spec.genesis();
+	let mut quick = vec![g.clone()];
+	let mut slow = vec![g];
+	for i in 1..=2 {
+		quick.push(spec.child_at(&quick[i - 1], 1, i as u64, &AlwaysValid));
+	}
+	for i in 1..=4 {
+		slow.push(spec.child_at(&slow[i - 1], 1, 20 * i as u64, &AlwaysValid));
+	}
+
+	assert_eq!(best_chain(&[&quick, &slow]), &slow[..]);
+	assert_eq!(heaviest_chain(&[&quick, &slow]), &quick[..]);
+	assert_eq!(heaviest_chain(&[&slow, &quick]), &quick[..]);
+	// Leaving out the common genesis changes nothing.
+	assert_eq!(heaviest_chain(&[&quick[1..], &slow[1..]]), &quick[1..]);
+	assert!(heaviest_chain(&[&[]]).is_empty());
+}
@@ -0,0 +1,590 @@
+//! We now have a hash-linked header chain that accepts simple extrinsics and tracks simple state.
+//! Now we will explore consensus. We are not looking at finality or fork choice here. Rather,
+//! we are adding validity rules. There are two common types of validity rules and we will explore
+//! both.
+//! 1. Rules to throttle authoring. In this case we will use a simple PoW.
+//! 2. Arbitrary / Political rules. Here we will implement two alternate validity rules
+//!
+//! Both kinds of rules live behind the same `Engine` trait. The header chain checks ancestry
+//! and execution itself, and asks a consensus engine about everything else. Swapping the engine
+//! swaps the rules without touching the chain. The trait is called `Engine` rather than
+//! `Consensus` because it only works with this lesson's header. Chapter 3 has the `Consensus`
+//! trait, which works with any header, and keeping the names apart keeps the two from being
+//! confused when both are in scope.
+//!
+//! The engines live in their own modules, as do the chain spec and the verification built on it.
+use crate::{c1_state_machine::clock::Clock, hash_header};
+
+pub mod builder;
+pub mod chain_spec;
+pub mod checkpointed;
+pub mod dictator;
+pub mod epochs;
+pub mod fork_choice;
+pub mod poa;
+pub mod pos;
+pub mod pow;
+pub mod rules;
+pub mod stats;
+pub mod verify;
+
+// The items the modules build on each other for. Everything else is used from its own module.
+use builder::HeaderBuilder;
+use chain_spec::ChainSpec;
+use epochs::Epoch;
+use pow::ProofOfWork;
+use rules::{Deployment, Fork, Parity, PoliticalFork, PoliticalRule, RuleSet};
+use verify::{Verified, VerifyError};
+
+// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
+// so the code is slightly more readable.
+type Hash = u64;
+
+/// In this lesson we are introducing proof of work onto our blocks. We need a hash threshold.
+/// You may change this as you see fit, and I encourage you to experiment. Probably best to start
+/// high so we aren't wasting time mining. I'll start with 1 in 100 blocks being valid.
+const THRESHOLD: u64 = u64::max_value() / 100;
+
+/// How long we would like each block to take, in seconds. Mining more blocks than this makes
+/// mining harder, and mining fewer makes it easier.
+const TARGET_BLOCK_TIME: u64 = 10;
+
+/// When the default chain launched, in seconds since the Unix epoch. Header timestamps count from
+/// here, so they can be compared with the real time.
+const GENESIS_TIME: u64 = 1_700_000_000;
+
+/// How far into the future a block's timestamp may be, in seconds, according to the clock of the
+/// node verifying it. Clocks never agree perfectly, so some leeway is needed. Without any limit
+/// though, a miner could claim their blocks took ages to make mining easier.
+const MAX_FUTURE_DRIFT: u64 = 120;
+
+/// How quickly the difficulty adjusts. Each block moves the threshold by this fraction of its
+/// parent's threshold.
+const ADJUSTMENT_QUOTIENT: u64 = 16;
+
+/// In this lesson we introduce the concept of a contentious hard fork. The fork will happen at
+/// this block height. The default chain has no forks, so this is only used by the even and odd
+/// chains below.
+const FORK_HEIGHT: u64 = 2;
+
+/// How much a block adds to the state for each uncle it includes. See `Header`.
+const UNCLE_REWARD: u64 = 1;
+
+/// How many blocks back an uncle may be. Older competing blocks are not worth rewarding, because
+/// their miners have long since moved on.
+const MAX_UNCLE_DEPTH: u64 = 6;
+
+/// How many uncles a single block may include
+const MAX_UNCLES: usize = 2;
+
+/// The header is now expanded to contain a consensus digest.
+/// For Proof of Work, the consensus digest is basically just a nonce which gets the block
+/// hash below a certain threshold. Although we could call the field `nonce` we will leave
+/// the more general `digest` term. For PoA we would have a cryptographic signature in this field.
+///
+/// Mining power comes and goes, so a fixed threshold would make blocks arrive faster or slower
+/// over time. Instead, each header records its own threshold, which is retargeted from its parent's
+/// depending on how long the block took to mine. That takes a timestamp in each header too.
+///
+/// Each header records the protocol version its author runs. Authors signal that they are ready
+/// for an upgrade by authoring blocks with the new version.
+///
+/// Finally, a header may include uncles. When two miners find a block at the same height, only one
+/// of them makes it into the chain, and the work that went into the other is wasted. A later block
+/// can include the hash of the other one as an uncle, and is rewarded for it in the state, so
+/// that work still counts for something.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+	parent: Hash,
+	height: u64,
+	extrinsic: u64,
+	state: u64,
+	/// When the block was authored, in seconds since genesis. Each block must be later than its
+	/// parent.
+	timestamp: u64,
+	/// The hash of this header must be below this threshold. The lower it is, the more difficult
+	/// the block is to mine.
+	threshold: u64,
+	/// The protocol version the author runs
+	version: u32,
+	/// The commitment to the next epoch, in the last header of each epoch. Zero everywhere else.
+	next_epoch: Hash,
+	/// The hashes of valid competing blocks at recent heights, which are rewarded in the state
+	uncles: Vec<Hash>,
+	consensus_digest: ConsensusDigest,
+}
+
+/// What an engine puts in a header to seal it. The digest says which kind of engine sealed the
+/// header, so an engine can tell a header sealed some other way apart from one it sealed badly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ConsensusDigest {
+	/// The header is not sealed at all.
+	#[default]
+	Empty,
+	/// The nonce that gets the hash of the header below its threshold
+	Pow { nonce: u64 },
+	/// An authority's signature over the pre-seal hash of the header, packed into a `u64`
+	Poa { signature: u64 },
+	/// Both of the above, for checkpoints that need work and a signature alike
+	Checkpoint { nonce: u64, signature: u64 },
+}
+
+/// A consensus engine decides which headers are valid beyond the basic rules of the chain, and
+/// knows how to author headers that it considers valid.
+///
+/// This is the version for this lesson's fixed header. Chapter 3's `Consensus` trait generalises
+/// it to any header, with the digest type left up to the engine.
+pub trait Engine {
+	/// Calculate a consensus digest that makes the given header valid. The header's own digest is
+	/// ignored.
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest;
+
+	/// Check the header against the consensus rules. Ancestry and execution are not checked here.
+	fn validate(&self, header: &Header) -> bool;
+}
+
+/// Every header is valid, whatever its digest, and headers are left unsealed. Handy for
+/// development and for testing other rules in isolation.
+pub struct AlwaysValid;
+
+impl Engine for AlwaysValid {
+	fn seal(&self, _: &Header) -> ConsensusDigest {
+		ConsensusDigest::Empty
+	}
+
+	fn validate(&self, _: &Header) -> bool {
+		true
+	}
+}
+
+// Here are the methods for creating new header and verifying headers.
+// They all follow the default chain spec.
+impl Header {
+	/// How many hashes it takes to mine this header on average
+	pub fn work(&self) -> u128 {
+		(1 << 64) / self.threshold.max(1) as u128
+	}
+
+	/// Returns a new valid genesis header.
+	pub fn genesis() -> Self {
+		ChainSpec::default().genesis()
+	}
+
+	/// Returns a genesis header for the given chain, mined like any other block.
+	///
+	/// Mining starts from a random nonce, so two nodes mining genesis for themselves end up with
+	/// different genesis headers, and so on different chains. That is why real chains hard code
+	/// their genesis header instead, or at least publish it.
+	pub fn genesis_mined(spec: &ChainSpec) -> Self {
+		spec.genesis().seal(&ProofOfWork)
+	}
+
+	/// Create and return a valid child header.
+	pub fn child(&self, extrinsic: u64) -> Self {
+		self.child_with(extrinsic, &ProofOfWork)
+	}
+
+	/// Create and return a child header sealed by the given engine. The child is authored exactly
+	/// one target block time after its parent.
+	pub fn child_with<C: Engine>(&self, extrinsic: u64, engine: &C) -> Self {
+		self.child_unsealed(extrinsic).seal(engine)
+	}
+
+	/// Create and return a child header that is not sealed yet. The child is authored exactly one
+	/// target block time after its parent.
+	pub fn child_unsealed(&self, extrinsic: u64) -> Self {
+		ChainSpec::default().child_unsealed(self, extrinsic, self.timestamp + TARGET_BLOCK_TIME)
+	}
+
+	/// Make this header the last of its epoch, committing to the given next epoch.
+	pub fn announcing(self, next: &Epoch) -> Self {
+		Header { next_epoch: next.commitment(), ..self }
+	}
+
+	/// Author this header with the given protocol version. Children inherit their parent's
+	/// version, so this only needs doing when an author upgrades.
+	pub fn signalling(self, version: u32) -> Self {
+		Header { version, ..self }
+	}
+
+	/// The hash of this header without its digest, which is what seals are made for
+	pub fn pre_seal_hash(&self) -> Hash {
+		hash_header(&Header { consensus_digest: ConsensusDigest::Empty, ..self.clone() })
+	}
+
+	/// Seal this header with the given engine, replacing any digest it already had.
+	pub fn seal<C: Engine>(self, engine: &C) -> Self {
+		let consensus_digest = engine.seal(&self);
+		Header { consensus_digest, ..self }
+	}
+
+	/// Start building a child header. The child is authored exactly one target block time after
+	/// its parent.
+	pub fn child_builder(&self, extrinsic: u64) -> HeaderBuilder {
+		ChainSpec::default().child_builder(self, extrinsic, self.timestamp + TARGET_BLOCK_TIME)
+	}
+
+	/// Create and return a child header authored at the time the given clock reads, and sealed by
+	/// the given engine.
+	pub fn child_with_clock<C: Engine, K: Clock>(
+		&self,
+		extrinsic: u64,
+		clock: &K,
+		engine: &C,
+	) -> Self {
+		ChainSpec::default().child_with_clock(self, extrinsic, clock, engine)
+	}
+
+	/// Create and return a child header authored at the given time and sealed by the given
+	/// engine.
+	pub fn child_at<C: Engine>(&self, extrinsic: u64, timestamp: u64, engine: &C) -> Self {
+		ChainSpec::default().child_at(self, extrinsic, timestamp, engine)
+	}
+
+	/// Verify that all the given headers form a valid chain from this header to the tip.
+	///
+	/// In addition to all the rules we had before, we now need to check that the block hash
+	/// is below a specific threshold.
+	pub fn verify_sub_chain(&self, chain: &[Header]) -> bool {
+		self.try_verify_sub_chain(chain).is_ok()
+	}
+
+	/// Like `verify_sub_chain`, but says what is wrong with the first invalid block.
+	pub fn try_verify_sub_chain(&self, chain: &[Header]) -> Result<(), VerifyError> {
+		self.verify_sub_chain_detailed(chain).error.map_or(Ok(()), Err)
+	}
+
+	/// Like `verify_sub_chain`, but says how many blocks are valid before the first invalid one,
+	/// and what is wrong with it.
+	pub fn verify_sub_chain_detailed(&self, chain: &[Header]) -> Verified {
+		let spec = ChainSpec::default();
+		spec.verify_sub_chain_detailed(self, chain, &ProofOfWork, &spec.clock())
+	}
+
+	/// Verify that all the given headers form a valid chain from this header to the tip, where
+	/// the given engine decides what is valid at the consensus level. Timestamps are checked
+	/// against the real time since the chain launched.
+	pub fn verify_sub_chain_with<C: Engine>(&self, chain: &[Header], engine: &C) -> bool {
+		self.verify_sub_chain_with_clock(chain, engine, &ChainSpec::default().clock())
+	}
+
+	/// Verify that all the given headers form a valid chain from this header to the tip, where
+	/// the given engine decides what is valid at the consensus level, and no block is too far
+	/// ahead of the given clock.
+	pub fn verify_sub_chain_with_clock<C: Engine, K: Clock>(
+		&self,
+		chain: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> bool {
+		ChainSpec::default().verify_sub_chain(self, chain, engine, clock)
+	}
+
+	// After the blockchain ran for a while, a political rift formed in the community.
+	// (See the constant FORK_HEIGHT) which is set to 2 by default.
+	// Most community members have become obsessed over the state of the blockchain.
+	// On the one side, people believe that only blocks with even states should be valid.
+	// On the other side, people believe in only blocks with odd states.
+
+	/// verify that the given headers form a valid chain.
+	/// In this case "valid" means that the STATE MUST BE EVEN.
+	pub fn verify_sub_chain_even(&self, chain: &[Header]) -> bool {
+		self.verify_sub_chain_with_rule(chain, Parity::Even, FORK_HEIGHT)
+	}
+
+	/// verify that the given headers form a valid chain.
+	/// In this case "valid" means that the STATE MUST BE ODD.
+	pub fn verify_sub_chain_odd(&self, chain: &[Header]) -> bool {
+		self.verify_sub_chain_with_rule(chain, Parity::Odd, FORK_HEIGHT)
+	}
+
+	/// Verify that the given headers form a valid proof of work chain, where every block after
+	/// the given fork height also follows the given rule.
+	pub fn verify_sub_chain_with_rule<R: PoliticalRule>(
+		&self,
+		chain: &[Header],
+		rule: R,
+		fork_height: u64,
+	) -> bool {
+		self.verify_sub_chain_with(chain, &PoliticalFork { engine: ProofOfWork, rule, fork_height })
+	}
+}
+
+/// Build and return two different chains with a common prefix.
+/// They should have the same genesis header.
+///
+/// Both chains should be valid according to the original validity rules.
+/// The first chain should be valid only according to the even rules.
+/// The second chain should be valid only according to the odd rules.
+///
+/// Return your solutions as three vectors:
+/// 1. The common prefix including genesis
+/// 2. The even suffix (non-overlapping with the common prefix)
+/// 3. The odd suffix (non-overlapping with the common prefix)
+///
+/// Here is an example of two such chains:
+///            /-- 3 -- 4
+/// G -- 1 -- 2
+///            \-- 3'-- 4'
+pub fn build_contentious_forked_chain() -> (Vec<Header>, Vec<Header>, Vec<Header>) {
+	let g = Header::genesis();
+	let a1 = g.child(2); // 2
+	let a2 = a1.child(5); // 7
+	let e1 = a2.child(1); // 8
+	let e2 = e1.child(2); // 10
+	let o1 = a2.child(2); // 9
+	let o2 = o1.child(4); // 13
+
+	(vec![g, a1, a2], vec![e1, e2], vec![o1, o2])
+}
+
+// To run these tests: `cargo test bc_3`
+#[test]
+fn bc_3_genesis_block_height() {
+	let g = Header::genesis();
+	assert!(g.height == 0);
+}
+
+#[test]
+fn bc_3_genesis_block_parent() {
+	let g = Header::genesis();
+	assert!(g.parent == 0);
+}
+
+#[test]
+fn bc_3_genesis_block_extrinsic() {
+	// Typically genesis blocks do not have any extrinsics.
+	// In Substrate they never do. So our convention is to have the extrinsic be 0.
+	let g = Header::genesis();
+	assert!(g.extrinsic == 0);
+}
+
+#[test]
+fn bc_3_genesis_block_state() {
+	let g = Header::genesis();
+	assert!(g.state == 0);
+}
+
+#[test]
+fn bc_3_genesis_consensus_digest() {
+	// We could require that the genesis block have a valid proof of work as well.
+	// But instead I've chosen the simpler path of defining the nonce = 0 in genesis.
+	let g = Header::genesis();
+	let b1 = g.child(7); // <- no way of knowing what consensus_digest is yet
+	assert!(hash_header(&b1) < THRESHOLD); // Original
+	                            // assert!(hash(&b1.consensus_digest) < THRESHOLD);
+}
+
+#[test]
+fn bc_3_child_block_height() {
+	let g = Header::genesis();
+	let b1 = g.child(0);
+	assert!(b1.height == 1);
+}
+
+#[test]
+fn bc_3_child_block_parent() {
+	let g = Header::genesis();
+	let b1 = g.child(0);
+	assert!(b1.parent == hash_header(&g));
+}
+
+#[test]
+fn bc_3_child_block_extrinsic() {
+	let g = Header::genesis();
+	let b1 = g.child(7);
+	assert_eq!(b1.extrinsic, 7);
+}
+
+#[test]
+fn bc_3_child_block_state() {
+	let g = Header::genesis();
+	let b1 = g.child(7);
+	assert_eq!(b1.state, 7);
+}
+
+#[test]
+fn bc_3_child_block_consensus_digest() {
+	let g = Header::genesis();
+	let b1 = g.child(7);
+	assert!(hash_header(&b1) < THRESHOLD);
+}
+
+#[test]
+fn bc_3_verify_genesis_only() {
+	let g = Header::genesis();
+
+	assert!(g.verify_sub_chain(&[]));
+}
+
+#[test]
+fn bc_3_verify_three_blocks() {
+	let g = Header::genesis();
+	let b1 = g.child(5);
+	let b2 = b1.child(6);
+
+	assert_eq!(b2.state, 11);
+	assert!(g.verify_sub_chain(&[b1, b2]));
+}
+
+#[test]
+fn bc_3_cant_verify_invalid_parent() {
+	let g = Header::genesis();
+	let b1 = g.child_builder(5).parent(10).seal(&ProofOfWork);
+
+	assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_cant_verify_invalid_number() {
+	let g = Header::genesis();
+	let b1 = g.child_builder(5).height(10).seal(&ProofOfWork);
+
+	assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_cant_verify_invalid_state() {
+	let g = Header::genesis();
+	let b1 = g.child_builder(5).state(10).seal(&ProofOfWork);
+
+	assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_cant_verify_invalid_pow() {
+	let g = Header::genesis();
+	// It is possible that this test will pass with a false positive because
+	// the PoW difficulty is relatively low.
+	let b1 = g.child_builder(5).nonce(10).build();
+
+	assert!(!g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_even_chain_valid() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+					  // It' all about the states, not the extrinsics. So once the state is even
+					  // we need to keep it that way. So add evens
+	let b3 = b2.child(1); // 4
+	let b4 = b3.child(2); // 6
+
+	assert!(g.verify_sub_chain_even(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_even_chain_invalid_first_block_after_fork() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+	let b3 = b2.child(2); // 5 - invalid
+	let b4 = b3.child(1); // 6
+
+	assert!(!g.verify_sub_chain_even(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_even_chain_invalid_second_block_after_fork() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+	let b3 = b2.child(1); // 4
+	let b4 = b3.child(1); // 5 - invalid
+
+	assert!(!g.verify_sub_chain_even(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_odd_chain_valid() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+					  // It' all about the states, not the extrinsics. So once the state is odd
+					  // we need to keep it that way. So add evens
+	let b3 = b2.child(2); // 5
+	let b4 = b3.child(2); // 7
+
+	assert!(g.verify_sub_chain_odd(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_odd_chain_invalid_first_block_after_fork() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+	let b3 = b2.child(1); // 4 - invalid
+	let b4 = b3.child(1); // 5
+
+	assert!(!g.verify_sub_chain_odd(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_odd_chain_invalid_second_block_after_fork() {
+	let g = Header::genesis(); // 0
+	let b1 = g.child(2); // 2
+	let b2 = b1.child(1); // 3
+	let b3 = b2.child(2); // 5
+	let b4 = b3.child(1); // 6 - invalid
+
+	assert!(!g.verify_sub_chain_odd(&[b1, b2, b3, b4]));
+}
+
+#[test]
+fn bc_3_verify_forked_chain() {
+	let (prefix, even, odd) = build_contentious_forked_chain();
+
+	let g = &prefix[0];
+	let full_even_chain = [&prefix[1..], &even].concat();
+	let full_odd_chain = [&prefix[1..], &odd].concat();
+
+	// Both chains are individually valid according to the original rules.
+	assert!(g.verify_sub_chain(&full_even_chain[..]));
+	assert!(g.verify_sub_chain(&full_odd_chain[..]));
+
+	// Only the even chain is valid according to the even rules
+	assert!(g.verify_sub_chain_even(&full_even_chain[..]));
+	assert!(!g.verify_sub_chain_even(&full_odd_chain[..]));
+
+	// Only the odd chain is valid according to the odd rules
+	assert!(!g.verify_sub_chain_odd(&full_even_chain[..]));
+	assert!(g.verify_sub_chain_odd(&full_odd_chain[..]));
+}
+
+#[test]
+fn bc_3_always_valid_still_checks_ancestry() {
+	let g = Header::genesis();
+	let b1 = g.child_with(5, &AlwaysValid);
+	assert_eq!(b1.consensus_digest, ConsensusDigest::Empty);
+	assert!(g.verify_sub_chain_with(std::slice::from_ref(&b1), &AlwaysValid));
+
+	let mut bad = b1;
+	bad.state = 6;
+	assert!(!g.verify_sub_chain_with(&[bad], &AlwaysValid));
+}
+
+#[test]
+fn bc_3_long_default_chains_verify() {
+	let g = Header::genesis();
+	let mut chain = vec![g.child(0)];
+	while chain.len() < 20 {
+		chain.push(chain.last().unwrap().child(1));
+	}
+
+	assert_eq!(g.try_verify_sub_chain(&chain), Ok(()));
+	assert!(g.verify_sub_chain_with(&chain, &ProofOfWork));
+	assert_eq!(g.verify_sub_chain_detailed(&chain).valid, 20);
+}
+
+#[test]
+fn bc_3_verify_rejects_blocks_from_the_future() {
+	let g = Header::genesis();
+	let now = ChainSpec::default().clock().now().as_secs();
+	let current = g.child_at(1, now, &ProofOfWork);
+	let future = g.child_at(1, now + 10 * MAX_FUTURE_DRIFT, &ProofOfWork);
+
+	assert!(g.verify_sub_chain(&[current]));
+	assert!(!g.verify_sub_chain(std::slice::from_ref(&future)));
+	assert_eq!(g.try_verify_sub_chain(&[future]), Err(VerifyError::TooLate { height: 1 }));
+}
@@ -0,0 +1,358 @@
+//! Proof of authority, where a fixed set of keys may author, either any of them at any time or
+//! each in turn. Authorities that sign two competing blocks can be caught doing so.
+
+use super::{ConsensusDigest, Engine, Header};
+use crate::crypto::{Keystore, Pair, Public, SchnorrPair, POA};
+use std::collections::HashMap;
+
+/// Proof of authority. A header is valid when it is signed by one of the authorities. The
+/// signature covers the pre-seal hash of the header, and is packed into the digest.
+pub struct ProofOfAuthority {
+	/// The public keys of everybody allowed to author
+	pub authorities: Vec<Public>,
+	/// The key this node seals with. Headers sealed by a key that is not an authority are invalid.
+	pub signer: SchnorrPair,
+}
+
+impl ProofOfAuthority {
+	/// A dictatorship is proof of authority with exactly one authority.
+	pub fn dictator(dictator: SchnorrPair) -> Self {
+		ProofOfAuthority { authorities: vec![dictator.public()], signer: dictator }
+	}
+
+	/// Proof of authority sealing with the key of the given authority, taken from the keystore.
+	/// Returns `None` if the keystore doesn't hold that key.
+	pub fn from_keystore(
+		authorities: Vec<Public>,
+		keystore: &Keystore,
+		signer: &Public,
+	) -> Option<Self> {
+		let signer = keystore.key_pair(POA, signer)?;
+		Some(ProofOfAuthority { authorities, signer })
+	}
+}
+
+/// Sign the pre-seal hash of the given header with the given key.
+pub(super) fn sign_header(signer: &SchnorrPair, partial_header: &Header) -> ConsensusDigest {
+	ConsensusDigest::Poa { signature: signer.sign(&partial_header.pre_seal_hash()).into() }
+}
+
+/// Whether the given header is sealed with a signature by the given authority
+pub(super) fn signed_by(header: &Header, authority: &Public) -> bool {
+	match header.consensus_digest {
+		ConsensusDigest::Poa { signature } =>
+			SchnorrPair::verify(&signature.into(), &header.pre_seal_hash(), authority),
+		_ => false,
+	}
+}
+
+impl Engine for ProofOfAuthority {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		sign_header(&self.signer, partial_header)
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		signed_by_any(header, &self.authorities)
+	}
+}
+
+/// Whether the given header is sealed with a signature by one of the given authorities
+pub(super) fn signed_by_any(header: &Header, authorities: &[Public]) -> bool {
+	authorities.iter().any(|authority| signed_by(header, authority))
+}
+
+/// Proof that an authority signed two different headers at the same height. An honest authority
+/// never does that, so anybody holding the proof can punish the authority without having to
+/// trust whoever found it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EquivocationProof {
+	/// The authority that equivocated
+	pub authority: Public,
+	/// One of the headers it signed
+	pub first: Header,
+	/// The other header it signed at the same height
+	pub second: Header,
+}
+
+impl EquivocationProof {
+	/// Check the proof independently of whoever made it.
+	pub fn verify(&self) -> bool {
+		self.first != self.second &&
+			self.first.height == self.second.height &&
+			signed_by(&self.first, &self.authority) &&
+			signed_by(&self.second, &self.authority)
+	}
+}
+
+/// Scan the given headers for any of the given authorities signing two different headers at the
+/// same height. Pass two competing chains, or every header of a fork tree. Headers that appear
+/// more than once, like the common prefix of two chains, are only counted once.
+///
+/// Only signed headers can equivocate. A mined header doesn't say who mined it, and mining two
+/// competing blocks is just unlucky rather than dishonest.
+pub fn find_equivocations<'a>(
+	authorities: &[Public],
+	headers: impl IntoIterator<Item = &'a Header>,
+) -> Vec<EquivocationProof> {
+	let mut signed: HashMap<(Public, u64), &Header> = HashMap::new();
+	let mut proofs = Vec::new();
+	for header in headers {
+		for authority in authorities.iter().filter(|authority| signed_by(header, authority)) {
+			let first = *signed.entry((*authority, header.height)).or_insert(header);
+			if first != header {
+				proofs.push(EquivocationProof {
+					authority: *authority,
+					first: first.clone(),
+					second: header.clone(),
+				});
+			}
+		}
+	}
+	proofs
+}
+
+/// Proof of authority where the authorities take turns. The block at each height must be signed by
+/// the authority whose turn it is, counting round the list by height, so a block signed by any
+/// other authority is invalid even though the signature is good.
+pub struct RoundRobinAuthority {
+	/// The public keys of everybody allowed to author, in the order they take turns
+	pub authorities: Vec<Public>,
+	/// The key this node seals with. Headers it seals out of turn are invalid.
+	pub signer: SchnorrPair,
+}
+
+impl RoundRobinAuthority {
+	/// The authority whose turn it is to author at the given height, if there are any authorities
+	pub fn authority_at(&self, height: u64) -> Option<&Public> {
+		let turns = self.authorities.len() as u64;
+		self.authorities.get(height.checked_rem(turns)? as usize)
+	}
+
+	/// Whether it is this node's turn to author at the given height
+	pub fn in_turn(&self, height: u64) -> bool {
+		self.authority_at(height) == Some(&self.signer.public())
+	}
+}
+
+impl Engine for RoundRobinAuthority {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		sign_header(&self.signer, partial_header)
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		self.authority_at(header.height)
+			.is_some_and(|authority| signed_by(header, authority))
+	}
+}
+
+#[test]
+fn bc_3_poa_chain_valid() {
+	let alice = SchnorrPair::from_seed(1);
+	let bob = SchnorrPair::from_seed(2);
+	let authorities = vec![alice.public(), bob.public()];
+	let as_alice = ProofOfAuthority { authorities: authorities.clone(), signer: alice };
+	let as_bob = ProofOfAuthority { authorities, signer: bob };
+
+	let g = Header::genesis();
+	let b1 = g.child_with(3, &as_alice);
+	let b2 = b1.child_with(4, &as_bob);
+
+	assert!(g.verify_sub_chain_with(&[b1, b2], &as_alice));
+}
+
+#[test]
+fn bc_3_poa_signs_with_keystore_keys() {
+	let mut keystore = Keystore::in_memory();
+	let (_, root) = keystore.generate_with_phrase::<SchnorrPair>(POA).unwrap();
+	let authorities: Vec<Public> = (0..3)
+		.map(|i| keystore.derive::<SchnorrPair>(POA, &root, &format!("//authority//{}", i)))
+		.collect::<Result<_, _>>()
+		.unwrap();
+	let engines: Vec<ProofOfAuthority> = authorities
+		.iter()
+		.map(|authority| {
+			ProofOfAuthority::from_keystore(authorities.clone(), &keystore, authority).unwrap()
+		})
+		.collect();
+
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &engines[0]);
+	let b2 = b1.child_with(2, &engines[2]);
+	assert!(g.verify_sub_chain_with(&[b1, b2], &engines[1]));
+
+	// Only keys in the keystore can seal.
+	let stranger = SchnorrPair::from_seed(7).public();
+	assert!(ProofOfAuthority::from_keystore(authorities, &keystore, &stranger).is_none());
+}
+
+#[test]
+fn bc_3_poa_rejects_outsider_and_tampering() {
+	let dictator = ProofOfAuthority::dictator(SchnorrPair::from_seed(1));
+	let outsider = ProofOfAuthority::dictator(SchnorrPair::from_seed(2));
+
+	let g = Header::genesis();
+	let b1 = g.child_with(3, &outsider);
+	assert!(!g.verify_sub_chain_with(&[b1], &dictator));
+
+	// Changing the extrinsic after signing breaks the signature even when the state still adds up.
+	let mut b1 = g.child_with(3, &dictator);
+	assert!(g.verify_sub_chain_with(std::slice::from_ref(&b1), &dictator));
+	b1.extrinsic = 4;
+	b1.state = 4;
+	assert!(!g.verify_sub_chain_with(&[b1], &dictator));
+}
+
+#[test]
+fn bc_3_engines_seal_the_same_partial_header() {
+	use super::ProofOfWork;
+	let dictator = ProofOfAuthority::dictator(SchnorrPair::from_seed(7));
+	let partial = Header::genesis().child_unsealed(1);
+	let mined = partial.clone().seal(&ProofOfWork);
+	let signed = partial.clone().seal(&dictator);
+
+	assert_eq!(mined.pre_seal_hash(), partial.pre_seal_hash());
+	assert_eq!(signed.pre_seal_hash(), partial.pre_seal_hash());
+	assert!(ProofOfWork.validate(&mined));
+	assert!(dictator.validate(&signed));
+	// Resealing replaces the old digest.
+	assert!(dictator.validate(&mined.seal(&dictator)));
+}
+
+#[cfg(test)]
+/// Round robin engines sealing as each of the given number of authorities, in turn order
+fn round_robin_authorities(count: u64) -> Vec<RoundRobinAuthority> {
+	let pairs: Vec<_> = (0..count).map(SchnorrPair::from_seed).collect();
+	let authorities: Vec<_> = pairs.iter().map(|pair| pair.public()).collect();
+	pairs
+		.into_iter()
+		.map(|signer| RoundRobinAuthority { authorities: authorities.clone(), signer })
+		.collect()
+}
+
+#[test]
+fn bc_3_round_robin_authorities_take_turns() {
+	let engines = round_robin_authorities(3);
+	let g = Header::genesis();
+	let mut chain = Vec::new();
+	let mut tip = g.clone();
+	for height in 1..=6 {
+		let engine = engines.iter().find(|engine| engine.in_turn(height)).unwrap();
+		tip = tip.child_with(height, engine);
+		chain.push(tip.clone());
+	}
+
+	assert_eq!(engines[0].authority_at(3), Some(&engines[0].signer.public()));
+	assert!(engines[1].in_turn(4));
+	for engine in &engines {
+		assert!(g.verify_sub_chain_with(&chain, engine));
+	}
+}
+
+#[test]
+fn bc_3_round_robin_rejects_out_of_turn_authority() {
+	let engines = round_robin_authorities(3);
+	let g = Header::genesis();
+
+	// Height 1 is the second authority's turn.
+	let in_turn = g.child_with(1, &engines[1]);
+	let out_of_turn = g.child_with(1, &engines[2]);
+	assert!(g.verify_sub_chain_with(&[in_turn], &engines[0]));
+	assert!(!g.verify_sub_chain_with(&[out_of_turn], &engines[0]));
+
+	// A plain authority set doesn't care whose turn it is.
+	let anyone = ProofOfAuthority {
+		authorities: engines[0].authorities.clone(),
+		signer: SchnorrPair::from_seed(0),
+	};
+	assert!(g.verify_sub_chain_with(&[g.child_with(1, &engines[2])], &anyone));
+}
+
+#[test]
+fn bc_3_round_robin_without_authorities_rejects_everything() {
+	let nobody = RoundRobinAuthority { authorities: Vec::new(), signer: SchnorrPair::from_seed(0) };
+	let g = Header::genesis();
+
+	assert_eq!(nobody.authority_at(1), None);
+	assert!(!g.verify_sub_chain_with(&[g.child_with(1, &nobody)], &nobody));
+}
+
+#[test]
+fn bc_3_digest_says_which_engine_sealed() {
+	use super::AlwaysValid;
+	let dictator = ProofOfAuthority::dictator(SchnorrPair::from_seed(1));
+	let g = Header::genesis();
+	let mined = g.child(1);
+	let signed = g.child_with(1, &dictator);
+	let unsealed = g.child_with(1, &AlwaysValid);
+
+	assert!(matches!(mined.consensus_digest, ConsensusDigest::Pow { .. }));
+	assert!(matches!(signed.consensus_digest, ConsensusDigest::Poa { .. }));
+	assert_eq!(unsealed.consensus_digest, ConsensusDigest::Empty);
+}
+
+#[test]
+fn bc_3_engines_reject_each_others_digests() {
+	use super::ProofOfWork;
+	let dictator = ProofOfAuthority::dictator(SchnorrPair::from_seed(1));
+	let easy = Header::genesis().child_builder(1).threshold(u64::MAX).build();
+	let mined = easy.clone().seal(&ProofOfWork);
+	let signed = easy.clone().seal(&dictator);
+
+	// Any hash would do, but a signature is not a nonce, and an empty digest is not either.
+	assert!(ProofOfWork.validate(&mined));
+	assert!(!ProofOfWork.validate(&signed));
+	assert!(!ProofOfWork.validate(&easy));
+
+	// Reinterpreting the nonce as a signature doesn't fool the authority.
+	let ConsensusDigest::Pow { nonce } = mined.consensus_digest else { unreachable!() };
+	let forged = Header { consensus_digest: ConsensusDigest::Poa { signature: nonce }, ..easy };
+	assert!(dictator.validate(&signed));
+	assert!(!dictator.validate(&mined));
+	assert!(!dictator.validate(&forged));
+}
+
+#[test]
+fn bc_3_equivocation_found_across_forks() {
+	let alice = SchnorrPair::from_seed(1);
+	let bob = SchnorrPair::from_seed(2);
+	let authorities = vec![alice.public(), bob.public()];
+	let as_alice = ProofOfAuthority { authorities: authorities.clone(), signer: alice.clone() };
+	let as_bob = ProofOfAuthority { authorities: authorities.clone(), signer: bob };
+
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &as_bob);
+	let b2 = b1.child_with(2, &as_alice);
+	let b2_fork = b1.child_with(3, &as_alice);
+	let chain = [b1.clone(), b2.clone()];
+	let fork = [b1, b2_fork.clone()];
+
+	let proofs = find_equivocations(&authorities, chain.iter().chain(&fork));
+	assert_eq!(
+		proofs,
+		vec![EquivocationProof { authority: alice.public(), first: b2, second: b2_fork }]
+	);
+	assert!(proofs[0].verify());
+}
+
+#[test]
+fn bc_3_honest_forks_are_not_equivocation() {
+	let alice = SchnorrPair::from_seed(1);
+	let bob = SchnorrPair::from_seed(2);
+	let authorities = vec![alice.public(), bob.public()];
+	let as_alice = ProofOfAuthority { authorities: authorities.clone(), signer: alice.clone() };
+	let as_bob = ProofOfAuthority { authorities: authorities.clone(), signer: bob.clone() };
+
+	// Competing blocks by different authorities, and mined blocks, are fine.
+	let g = Header::genesis();
+	let a1 = g.child_with(1, &as_alice);
+	let b1 = g.child_with(1, &as_bob);
+	let m1 = g.child(1);
+	let m1_fork = g.child(2);
+	assert!(find_equivocations(&authorities, [&a1, &b1, &m1, &m1_fork, &a1]).is_empty());
+
+	// A proof that doesn't hold up is rejected.
+	let framed = EquivocationProof { authority: bob.public(), first: a1.clone(), second: b1 };
+	assert!(!framed.verify());
+	let same = EquivocationProof { authority: alice.public(), first: a1.clone(), second: a1 };
+	assert!(!same.verify());
+}
@@ -0,0 +1,185 @@
+//! Proof of stake, where the author of each block is picked at random, weighted by stake.
+
+use super::{
+	poa::{sign_header, signed_by},
+	ConsensusDigest, Engine, Hash, Header,
+};
+use crate::{
+	crypto::{Keystore, Pair, Public, SchnorrPair, POS},
+	hash,
+};
+use std::collections::HashMap;
+
+/// The stakes recorded in the state of a chain. Our headers only hold a single number of state, so
+/// the stakes are kept alongside, by the hash of the block whose state they are in.
+pub trait StakeLedger {
+	/// Every staker and their stake in the state after the block with the given hash
+	fn stakes_at(&self, block: Hash) -> Vec<(Public, u64)>;
+}
+
+/// Stakes that never change, so they are the same in every state
+impl StakeLedger for Vec<(Public, u64)> {
+	fn stakes_at(&self, _: Hash) -> Vec<(Public, u64)> {
+		self.clone()
+	}
+}
+
+/// Stakes that change from block to block, by block hash. Blocks missing from the map have no
+/// stakers at all.
+impl StakeLedger for HashMap<Hash, Vec<(Public, u64)>> {
+	fn stakes_at(&self, block: Hash) -> Vec<(Public, u64)> {
+		self.get(&block).cloned().unwrap_or_default()
+	}
+}
+
+/// Proof of stake. Like round robin authority, only one staker may author each block, but instead
+/// of taking turns, the author is drawn at random, weighted by stake. A staker holding a quarter
+/// of the stake authors about a quarter of the blocks.
+///
+/// The draw is seeded by the parent's hash and the height, so everybody can recompute it, but
+/// nobody knows who is next until the parent is authored. The stakes are the ones in the parent's
+/// state, since the block's own state isn't settled until it is authored.
+pub struct ProofOfStake<L> {
+	/// Where the stakes come from
+	pub ledger: L,
+	/// The key this node seals with. Headers it seals when it isn't the leader are invalid.
+	pub signer: SchnorrPair,
+}
+
+impl<L: StakeLedger> ProofOfStake<L> {
+	/// Proof of stake sealing with the key of the given staker, taken from the keystore. Returns
+	/// `None` if the keystore doesn't hold that key.
+	pub fn from_keystore(ledger: L, keystore: &Keystore, signer: &Public) -> Option<Self> {
+		let signer = keystore.key_pair(POS, signer)?;
+		Some(ProofOfStake { ledger, signer })
+	}
+
+	/// The staker eligible to author the child of the given parent at the given height, if
+	/// anybody has any stake
+	pub fn leader(&self, parent: Hash, height: u64) -> Option<Public> {
+		let stakes = self.ledger.stakes_at(parent);
+		let total = stakes.iter().map(|(_, stake)| *stake as u128).sum::<u128>();
+		let mut ticket = (hash(&(parent, height)) as u128).checked_rem(total)?;
+		for (staker, stake) in stakes {
+			match ticket.checked_sub(stake as u128) {
+				Some(rest) => ticket = rest,
+				None => return Some(staker),
+			}
+		}
+		None
+	}
+
+	/// Whether this node is eligible to author the child of the given parent at the given height
+	pub fn is_leader(&self, parent: Hash, height: u64) -> bool {
+		self.leader(parent, height) == Some(self.signer.public())
+	}
+}
+
+impl<L: StakeLedger> Engine for ProofOfStake<L> {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		sign_header(&self.signer, partial_header)
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		self.leader(header.parent, header.height)
+			.is_some_and(|leader| signed_by(header, &leader))
+	}
+}
+
+/// Proof of stake engines for stakers with the given stakes, sharing the given ledger
+#[cfg(test)]
+fn stakers<L: StakeLedger + Clone>(count: u64, ledger: L) -> Vec<ProofOfStake<L>> {
+	(0..count)
+		.map(|seed| ProofOfStake { ledger: ledger.clone(), signer: SchnorrPair::from_seed(seed) })
+		.collect()
+}
+
+/// The given stakes for the first few stakers made by `stakers`
+#[cfg(test)]
+fn stakes(stakes: &[u64]) -> Vec<(Public, u64)> {
+	let stakers = (0..).map(|seed| SchnorrPair::from_seed(seed).public());
+	stakers.zip(stakes.iter().copied()).collect()
+}
+
+#[test]
+fn bc_3_stake_leaders_author_a_valid_chain() {
+	use crate::hash_header;
+	let engines = stakers(3, stakes(&[1, 2, 3]));
+	let g = Header::genesis();
+	let mut chain = Vec::new();
+	let mut tip = g.clone();
+	for extrinsic in 1..=6 {
+		let height = tip.height + 1;
+		let engine = engines.iter().find(|e| e.is_leader(hash_header(&tip), height)).unwrap();
+		tip = tip.child_with(extrinsic, engine);
+		chain.push(tip.clone());
+	}
+
+	for engine in &engines {
+		assert!(g.verify_sub_chain_with(&chain, engine));
+	}
+	let b1 = chain[0].clone();
+	let leader = engines[0].leader(hash_header(&g), 1).unwrap();
+	let imposter = engines.iter().find(|e| e.signer.public() != leader).unwrap();
+	let stolen = Header { consensus_digest: imposter.seal(&b1), ..b1 };
+	assert!(!g.verify_sub_chain_with(&[stolen], &engines[0]));
+}
+
+#[test]
+fn bc_3_stakers_sign_with_keystore_keys() {
+	use crate::hash_header;
+	let mut keystore = Keystore::in_memory();
+	let stakers: Vec<Public> =
+		(0..3).map(|seed| keystore.insert::<SchnorrPair>(POS, seed).unwrap()).collect();
+	let ledger = stakes(&[1, 2, 3]);
+	assert_eq!(stakers, ledger.iter().map(|(staker, _)| *staker).collect::<Vec<_>>());
+
+	let g = Header::genesis();
+	let leader = stakers
+		.iter()
+		.map(|staker| ProofOfStake::from_keystore(ledger.clone(), &keystore, staker).unwrap())
+		.find(|engine| engine.is_leader(hash_header(&g), 1))
+		.unwrap();
+	let b1 = g.child_with(1, &leader);
+	assert!(g.verify_sub_chain_with(&[b1], &leader));
+
+	// A key meant for proof of authority is no good for staking.
+	let authority = keystore.generate::<SchnorrPair>(crate::crypto::POA).unwrap();
+	assert!(ProofOfStake::from_keystore(ledger, &keystore, &authority).is_none());
+}
+
+#[test]
+fn bc_3_stake_leaders_are_weighted_by_stake() {
+	let ledger = stakes(&[1, 3, 0]);
+	let engine = &stakers(1, ledger.clone())[0];
+	let mut picks = [0; 3];
+	for parent in 0..4000 {
+		let leader = engine.leader(parent, 1).unwrap();
+		let index = ledger.iter().position(|(staker, _)| *staker == leader).unwrap();
+		picks[index] += 1;
+	}
+
+	// About a quarter and three quarters, and never the staker without stake.
+	assert!((800..1200).contains(&picks[0]), "{:?}", picks);
+	assert!((2800..3200).contains(&picks[1]), "{:?}", picks);
+	assert_eq!(picks[2], 0);
+	assert_eq!(stakers(1, stakes(&[0, 0]))[0].leader(0, 1), None);
+}
+
+#[test]
+fn bc_3_stake_leaders_follow_the_parent_state() {
+	use crate::hash_header;
+	// Only the first staker has stake after genesis, and only the second after the next block.
+	let g = Header::genesis();
+	let first_only = ProofOfStake { ledger: stakes(&[1, 0]), signer: SchnorrPair::from_seed(0) };
+	let b1 = g.child_with(1, &first_only);
+	let ledger =
+		HashMap::from([(hash_header(&g), stakes(&[1, 0])), (hash_header(&b1), stakes(&[0, 1]))]);
+	let engines = stakers(2, ledger);
+
+	let b2 = b1.child_with(2, &engines[1]);
+	assert!(g.verify_sub_chain_with(&[b1.clone(), b2], &engines[0]));
+	assert!(!g.verify_sub_chain_with(&[b1.clone(), b1.child_with(2, &engines[0])], &engines[0]));
+	// Nobody has any stake after an unknown block.
+	assert_eq!(engines[0].leader(0, 1), None);
+}
@@ -0,0 +1,276 @@
+//! Proof of work, mined on one thread or several, and optionally cancelled part way.
+
+use super::{ConsensusDigest, Engine, Header};
+use crate::hash_header;
+use rand::{thread_rng, Rng};
+use std::{
+	sync::atomic::{AtomicBool, Ordering as AtomicOrdering},
+	thread,
+};
+
+/// Proof of work. A header is valid when it is sealed with a nonce, and its hash is below the
+/// threshold recorded in it. Whether that threshold is the right one is a rule of the chain, not
+/// of the engine.
+pub struct ProofOfWork;
+
+/// Mining was abandoned before a valid nonce was found.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cancelled;
+
+/// Turn the result of a scan into the result of a cancellable seal. A scan only comes back empty
+/// handed when it was cancelled, or after trying every nonce in vain.
+fn cancelled_or_found(found: Option<u64>, cancel: &AtomicBool) -> Result<u64, Cancelled> {
+	match found {
+		Some(nonce) => Ok(nonce),
+		None if cancel.load(AtomicOrdering::Relaxed) => Err(Cancelled),
+		None => panic!("no nonce makes the header valid; is the threshold zero?"),
+	}
+}
+
+impl ProofOfWork {
+	/// Scan the nonce space for a digest that makes the given header valid, starting at the given
+	/// nonce and wrapping around after `u64::MAX`. Returns `None` once every nonce has been tried.
+	///
+	/// Every nonce is tried at most once, and nothing is kept around between tries, so this works
+	/// however low the threshold is. It just takes longer.
+	pub fn search(&self, partial_header: &Header, start: u64) -> Option<u64> {
+		self.scan(partial_header, start, start.wrapping_sub(1), || false)
+	}
+
+	/// Like `seal`, but gives up as soon as the given flag is raised. A node raises it when it
+	/// hears of a better block, so that it can start mining on top of that one instead.
+	pub fn seal_cancellable(
+		&self,
+		partial_header: &Header,
+		cancel: &AtomicBool,
+	) -> Result<u64, Cancelled> {
+		let start = thread_rng().gen();
+		let stop = || cancel.load(AtomicOrdering::Relaxed);
+		cancelled_or_found(self.scan(partial_header, start, start.wrapping_sub(1), stop), cancel)
+	}
+
+	/// Scan the nonces from `first` to `last` inclusive, wrapping around after `u64::MAX`, until
+	/// one makes the given header valid or `stop` says to give up.
+	fn scan(
+		&self,
+		partial_header: &Header,
+		first: u64,
+		last: u64,
+		stop: impl Fn() -> bool,
+	) -> Option<u64> {
+		let mut header = partial_header.clone();
+		let mut nonce = first;
+		while !stop() {
+			header.consensus_digest = ConsensusDigest::Pow { nonce };
+			if self.validate(&header) {
+				return Some(nonce)
+			}
+			if nonce == last {
+				break
+			}
+			nonce = nonce.wrapping_add(1);
+		}
+		None
+	}
+}
+
+impl Engine for ProofOfWork {
+	/// Scan from a random nonce, so that miners working on the same header don't all try the same
+	/// nonces in the same order.
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		let nonce = self
+			.seal_cancellable(partial_header, &AtomicBool::new(false))
+			.expect("nothing cancels this search");
+		ConsensusDigest::Pow { nonce }
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		matches!(header.consensus_digest, ConsensusDigest::Pow { .. }) &&
+			hash_header(header) < header.threshold
+	}
+}
+
+/// Proof of work mined by several threads at once. The nonce space is split into one range per
+/// thread, and as soon as one thread finds a valid nonce the others stop. Headers are valid by
+/// exactly the same rule as `ProofOfWork`, so the two engines can be mixed on one chain.
+pub struct ParallelProofOfWork {
+	/// How many threads to mine with. Zero is treated as one.
+	pub threads: usize,
+}
+
+impl ParallelProofOfWork {
+	/// Like `seal`, but every thread gives up as soon as the given flag is raised.
+	pub fn seal_cancellable(
+		&self,
+		partial_header: &Header,
+		cancel: &AtomicBool,
+	) -> Result<u64, Cancelled> {
+		let threads = self.threads.max(1) as u64;
+		let chunk = u64::MAX / threads;
+		let start: u64 = thread_rng().gen();
+		let stop = AtomicBool::new(false);
+
+		let found = thread::scope(|scope| {
+			let workers: Vec<_> = (0..threads)
+				.map(|i| {
+					let first = start.wrapping_add(i * chunk);
+					// The last worker takes whatever is left, up to where the first one started.
+					let last = if i + 1 == threads {
+						start.wrapping_sub(1)
+					} else {
+						first.wrapping_add(chunk - 1)
+					};
+					let stop = &stop;
+					let halt = move || {
+						stop.load(AtomicOrdering::Relaxed) || cancel.load(AtomicOrdering::Relaxed)
+					};
+					scope.spawn(move || {
+						let found = ProofOfWork.scan(partial_header, first, last, halt);
+						if found.is_some() {
+							stop.store(true, AtomicOrdering::Relaxed);
+						}
+						found
+					})
+				})
+				.collect();
+			workers
+				.into_iter()
+				.find_map(|worker| worker.join().expect("miner thread panicked"))
+		});
+		cancelled_or_found(found, cancel)
+	}
+}
+
+impl Engine for ParallelProofOfWork {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		let nonce = self
+			.seal_cancellable(partial_header, &AtomicBool::new(false))
+			.expect("nothing cancels this search");
+		ConsensusDigest::Pow { nonce }
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		ProofOfWork.validate(header)
+	}
+}
+
+/// Create and return a valid child of the given parent, mined with the given number of threads.
+pub fn mine_parallel(parent: &Header, extrinsic: u64, threads: usize) -> Header {
+	parent.child_with(extrinsic, &ParallelProofOfWork { threads })
+}
+
+/// Create and return a valid child of the given parent, mined with the given number of threads,
+/// unless the given flag is raised first.
+pub fn mine_cancellable(
+	parent: &Header,
+	extrinsic: u64,
+	threads: usize,
+	cancel: &AtomicBool,
+) -> Result<Header, Cancelled> {
+	let partial = parent.child_unsealed(extrinsic);
+	let nonce = ParallelProofOfWork { threads }.seal_cancellable(&partial, cancel)?;
+	Ok(Header { consensus_digest: ConsensusDigest::Pow { nonce }, ..partial })
+}
+
+#[test]
+fn bc_3_search_starts_at_given_nonce() {
+	let easy = Header::genesis().child_builder(1).threshold(u64::MAX).build();
+	let found = ProofOfWork.search(&easy, 42).unwrap();
+
+	// Only a hash of exactly u64::MAX is invalid, so the first nonce almost certainly works.
+	assert!(found == 42 || found == 43);
+	assert!(ProofOfWork
+		.validate(&Header { consensus_digest: ConsensusDigest::Pow { nonce: found }, ..easy }));
+}
+
+#[test]
+fn bc_3_search_wraps_around() {
+	let half = Header::genesis().child_builder(1).threshold(u64::MAX / 2).build();
+	let found = ProofOfWork.search(&half, u64::MAX).unwrap();
+
+	// Half of all nonces are valid, so one is found soon after wrapping to zero, if not before.
+	assert!(found == u64::MAX || found < 100);
+	assert!(ProofOfWork
+		.validate(&Header { consensus_digest: ConsensusDigest::Pow { nonce: found }, ..half }));
+}
+
+#[test]
+fn bc_3_mining_gets_harder_without_running_out_of_stack() {
+	use super::{ChainSpec, TARGET_BLOCK_TIME};
+	use crate::c1_state_machine::clock::SystemClock;
+	// Each step doubles the expected work, up to about a million hashes.
+	for difficulty in [1, 4, 8, 12, 16, 20] {
+		let spec = ChainSpec { threshold: u64::MAX >> difficulty, ..ChainSpec::default() };
+		let g = spec.genesis();
+		let b1 = spec.child_at(&g, 1, TARGET_BLOCK_TIME, &ProofOfWork);
+
+		assert!(hash_header(&b1) < u64::MAX >> difficulty);
+		assert!(spec.verify_sub_chain(&g, &[b1], &ProofOfWork, &SystemClock::new()));
+	}
+}
+
+#[test]
+fn bc_3_mine_parallel_makes_valid_children() {
+	let g = Header::genesis();
+	let b1 = mine_parallel(&g, 1, 4);
+	let b2 = mine_parallel(&b1, 2, 1);
+	let b3 = b2.child(3);
+
+	assert_eq!(b2.state, 3);
+	assert!(ParallelProofOfWork { threads: 2 }.validate(&b1));
+	assert!(g.verify_sub_chain(&[b1, b2, b3]));
+}
+
+#[test]
+fn bc_3_mine_parallel_at_higher_difficulty() {
+	use super::{ChainSpec, TARGET_BLOCK_TIME};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec { threshold: u64::MAX >> 20, ..ChainSpec::default() };
+	let g = spec.genesis();
+	let engine = ParallelProofOfWork { threads: 8 };
+	let b1 = spec.child_at(&g, 1, TARGET_BLOCK_TIME, &engine);
+
+	assert!(spec.verify_sub_chain(&g, &[b1], &ProofOfWork, &SystemClock::new()));
+}
+
+#[test]
+fn bc_3_zero_threads_still_mines() {
+	let b1 = mine_parallel(&Header::genesis(), 1, 0);
+
+	assert!(ProofOfWork.validate(&b1));
+}
+
+#[test]
+fn bc_3_mine_cancellable_finishes_when_not_cancelled() {
+	let g = Header::genesis();
+	let b1 = mine_cancellable(&g, 1, 2, &AtomicBool::new(false)).unwrap();
+
+	assert!(g.verify_sub_chain(&[b1]));
+}
+
+#[test]
+fn bc_3_cancelled_before_mining_starts() {
+	use super::ChainSpec;
+	// Nothing is below a threshold of zero, so without cancelling this would mine forever.
+	let hopeless = ChainSpec { threshold: 0, ..ChainSpec::default() }.genesis();
+	let cancel = AtomicBool::new(true);
+
+	assert_eq!(mine_cancellable(&hopeless, 1, 2, &cancel), Err(Cancelled));
+	let partial = hopeless.child_unsealed(1);
+	assert_eq!(ProofOfWork.seal_cancellable(&partial, &cancel), Err(Cancelled));
+}
+
+#[test]
+fn bc_3_cancelled_while_mining() {
+	use super::ChainSpec;
+	let hopeless = ChainSpec { threshold: 0, ..ChainSpec::default() }.genesis();
+	let cancel = AtomicBool::new(false);
+
+	let result = thread::scope(|scope| {
+		let miner = scope.spawn(|| mine_cancellable(&hopeless, 1, 4, &cancel));
+		thread::sleep(std::time::Duration::from_millis(50));
+		cancel.store(true, AtomicOrdering::Relaxed);
+		miner.join().unwrap()
+	});
+	assert_eq!(result, Err(Cancelled));
+}
@@ -0,0 +1,331 @@
+//! Political rules, and the forks and signalled deployments that switch them on.
+
+use super::{ConsensusDigest, Engine, Header, VerifyError};
+use std::collections::VecDeque;
+
+/// A political rule decides which headers are acceptable, whatever the consensus engine thinks of
+/// them. Any function from a header to a bool is a rule, so a new contentious fork only takes a
+/// closure.
+pub trait PoliticalRule {
+	/// Whether the given header follows this rule
+	fn permits(&self, header: &Header) -> bool;
+}
+
+impl<F: Fn(&Header) -> bool> PoliticalRule for F {
+	fn permits(&self, header: &Header) -> bool {
+		self(header)
+	}
+}
+
+/// Whether a number is even or odd. As a rule, the state must have this parity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+	Even,
+	Odd,
+}
+
+impl PoliticalRule for Parity {
+	fn permits(&self, header: &Header) -> bool {
+		let parity = if header.state.is_multiple_of(2) { Parity::Even } else { Parity::Odd };
+		parity == *self
+	}
+}
+
+/// The rules a block must follow beyond those of the consensus engine. Blocks in different eras
+/// of the chain follow different rule sets, and forks are where one era ends and the next begins.
+///
+/// The consensus engine can't change the state, so it seals blocks the same way whatever the rules
+/// are. Whoever authors must pick extrinsics that the rules allow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RuleSet {
+	/// The parity every state must have, if any
+	pub parity: Option<Parity>,
+	/// The largest extrinsic allowed, if any
+	pub max_extrinsic: Option<u64>,
+}
+
+impl RuleSet {
+	/// Only the given parity of state is allowed. Nothing else is restricted.
+	pub fn parity(parity: Parity) -> Self {
+		RuleSet { parity: Some(parity), ..RuleSet::default() }
+	}
+}
+
+impl PoliticalRule for RuleSet {
+	fn permits(&self, header: &Header) -> bool {
+		self.parity.is_none_or(|parity| parity.permits(header)) &&
+			self.max_extrinsic.is_none_or(|max| header.extrinsic <= max)
+	}
+}
+
+/// A political rule layered on top of another engine. After the fork height, every header must
+/// follow the rule. Before that, only the inner engine's rules apply.
+///
+/// This is the quickest way to try out a rule. Rules that should be part of a chain's spec belong
+/// in its fork schedule instead.
+pub struct PoliticalFork<C, R> {
+	/// The engine whose rules apply before and after the fork
+	pub engine: C,
+	/// The rule every header after the fork must follow
+	pub rule: R,
+	/// The last height before the rule applies
+	pub fork_height: u64,
+}
+
+impl<C: Engine, R: PoliticalRule> Engine for PoliticalFork<C, R> {
+	fn seal(&self, partial_header: &Header) -> ConsensusDigest {
+		self.engine.seal(partial_header)
+	}
+
+	fn validate(&self, header: &Header) -> bool {
+		self.engine.validate(header) &&
+			(header.height <= self.fork_height || self.rule.permits(header))
+	}
+}
+
+/// A change of rules. Every block above the given height follows the new rules, up to the next
+/// fork.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fork {
+	/// The last height of the previous era
+	pub height: u64,
+	/// The rules of the new era
+	pub rules: RuleSet,
+}
+
+/// A rule change that activates once enough authors signal for it, rather than at a height fixed in
+/// advance. Once `required` of the last `window` blocks have at least the given version, the
+/// deployment is active for good. Every later block must have at least that version and follow
+/// the deployment's rules. This is how Bitcoin activated several of its soft forks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Deployment {
+	/// The version blocks signal with, and must have once the deployment is active
+	pub version: u32,
+	/// How many of the most recent blocks are counted
+	pub window: usize,
+	/// How many of them must signal for the deployment to activate
+	pub required: usize,
+	/// The rules that apply once the deployment is active
+	pub rules: RuleSet,
+}
+
+/// The signalling seen so far while walking a chain, and which deployments it has activated
+pub(super) struct Signalling<'a> {
+	/// The deployments of the chain
+	deployments: &'a [Deployment],
+	/// Whether each deployment is active
+	active: Vec<bool>,
+	/// The versions of the most recent blocks, oldest first
+	recent: VecDeque<u32>,
+}
+
+impl<'a> Signalling<'a> {
+	/// Start tracking the given deployments, none of which are active yet
+	pub(super) fn new(deployments: &'a [Deployment]) -> Self {
+		Signalling { deployments, active: vec![false; deployments.len()], recent: VecDeque::new() }
+	}
+
+	/// Check the given block at the given height against every active deployment.
+	pub(super) fn check(&self, block: &Header, height: u64) -> Result<(), VerifyError> {
+		let active = self.deployments.iter().zip(&self.active).filter(|(_, active)| **active);
+		for (deployment, _) in active {
+			if block.version < deployment.version {
+				return Err(VerifyError::Outdated { height })
+			}
+			if !deployment.rules.permits(block) {
+				return Err(VerifyError::BrokenRules { height })
+			}
+		}
+		Ok(())
+	}
+
+	/// Count the given block's signal, and activate any deployment it tips over the line.
+	pub(super) fn record(&mut self, block: &Header) {
+		let longest = self.deployments.iter().map(|d| d.window).max().unwrap_or(0);
+		self.recent.push_back(block.version);
+		if self.recent.len() > longest {
+			self.recent.pop_front();
+		}
+		for (deployment, active) in self.deployments.iter().zip(&mut self.active) {
+			let window = self.recent.iter().rev().take(deployment.window);
+			let signals = window.filter(|version| **version >= deployment.version).count();
+			*active |= self.recent.len() >= deployment.window && signals >= deployment.required;
+		}
+	}
+}
+
+#[test]
+fn bc_3_fork_rules_apply_with_any_engine() {
+	use super::{AlwaysValid, ChainSpec, FORK_HEIGHT};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default().with_fork(FORK_HEIGHT, RuleSet::parity(Parity::Even));
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+	let b1 = g.child_with(1, &AlwaysValid); // 1, before the fork
+	let b2 = b1.child_with(2, &AlwaysValid); // 3, before the fork
+	let b3 = b2.child_with(1, &AlwaysValid); // 4
+
+	assert!(spec.verify_sub_chain(&g, &[b1.clone(), b2.clone(), b3], &AlwaysValid, &clock));
+	let b3_odd = b2.child_with(2, &AlwaysValid); // 5
+	assert!(!spec.verify_sub_chain(&g, &[b1, b2, b3_odd], &AlwaysValid, &clock));
+}
+
+#[test]
+fn bc_3_chain_spec_changes_fork_height() {
+	use super::{ChainSpec, ProofOfWork};
+	use crate::c1_state_machine::clock::SystemClock;
+	let early = ChainSpec::default().with_fork(0, RuleSet::parity(Parity::Even));
+	let g = early.genesis();
+	let b1 = early.child_at(&g, 1, 10, &ProofOfWork); // 1
+
+	let clock = SystemClock::new();
+	assert!(!early.verify_sub_chain(&g, std::slice::from_ref(&b1), &ProofOfWork, &clock));
+	assert!(g.verify_sub_chain_even(&[b1]));
+}
+
+#[test]
+fn bc_3_fork_schedule_is_kept_in_order() {
+	use super::ChainSpec;
+	let capped = RuleSet { max_extrinsic: Some(5), ..RuleSet::default() };
+	let spec = ChainSpec::default()
+		.with_fork(4, RuleSet::parity(Parity::Odd))
+		.with_fork(1, capped);
+
+	assert_eq!(spec.forks.iter().map(|f| f.height).collect::<Vec<_>>(), vec![1, 4]);
+	assert_eq!(spec.rules_at(1), RuleSet::default());
+	assert_eq!(spec.rules_at(2), capped);
+	assert_eq!(spec.rules_at(4), capped);
+	assert_eq!(spec.rules_at(5), RuleSet::parity(Parity::Odd));
+}
+
+#[test]
+fn bc_3_each_era_follows_its_own_rules() {
+	use super::{AlwaysValid, ChainSpec};
+	use crate::c1_state_machine::clock::SystemClock;
+	// Extrinsics are capped after block 1, and states must be odd after block 3, with no cap.
+	let capped = RuleSet { max_extrinsic: Some(5), ..RuleSet::default() };
+	let spec = ChainSpec::default()
+		.with_fork(1, capped)
+		.with_fork(3, RuleSet::parity(Parity::Odd));
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+	let b1 = g.child_with(10, &AlwaysValid); // 10, no rules yet
+	let b2 = b1.child_with(4, &AlwaysValid); // 14
+	let b3 = b2.child_with(5, &AlwaysValid); // 19
+	let b4 = b3.child_with(10, &AlwaysValid); // 29
+
+	let chain = [b1.clone(), b2.clone(), b3.clone(), b4];
+	assert!(spec.verify_sub_chain(&g, &chain, &AlwaysValid, &clock));
+
+	// A big extrinsic was fine before the first fork, but not in the capped era.
+	let b2_big = b1.child_with(10, &AlwaysValid); // 20
+	assert!(!spec.verify_sub_chain(&b1, &[b2_big], &AlwaysValid, &clock));
+
+	// An even state was fine in the capped era, but not in the odd one.
+	let b4_even = b3.child_with(1, &AlwaysValid); // 20
+	assert!(!spec.verify_sub_chain(&b3, &[b4_even], &AlwaysValid, &clock));
+}
+
+#[test]
+fn bc_3_closures_are_political_rules() {
+	let small_extrinsics = |header: &Header| header.extrinsic < 10;
+	let g = Header::genesis();
+	let b1 = g.child(20); // before the fork
+	let b2 = b1.child(5);
+	let b2_big = b1.child(10);
+
+	assert!(g.verify_sub_chain_with_rule(&[b1.clone(), b2], small_extrinsics, 1));
+	assert!(!g.verify_sub_chain_with_rule(&[b1.clone(), b2_big.clone()], small_extrinsics, 1));
+	assert!(g.verify_sub_chain_with_rule(&[b1, b2_big], small_extrinsics, 2));
+}
+
+#[test]
+fn bc_3_political_fork_wraps_any_engine() {
+	use super::AlwaysValid;
+	let odd = PoliticalFork { engine: AlwaysValid, rule: Parity::Odd, fork_height: 0 };
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &odd); // 1
+	let b2 = b1.child_with(1, &odd); // 2
+
+	assert!(g.verify_sub_chain_with(std::slice::from_ref(&b1), &odd));
+	assert!(!g.verify_sub_chain_with(&[b1, b2], &odd));
+	assert!(RuleSet::parity(Parity::Odd).permits(&g.child(3)));
+}
+
+#[cfg(test)]
+/// A deployment that requires even states once three of the last four blocks signal version 1
+fn even_deployment() -> Deployment {
+	Deployment { version: 1, window: 4, required: 3, rules: RuleSet::parity(Parity::Even) }
+}
+
+#[test]
+fn bc_3_children_inherit_version() {
+	use super::AlwaysValid;
+	let g = Header::genesis();
+	let b1 = g.child_unsealed(1).signalling(3).seal(&AlwaysValid);
+	let b2 = b1.child_with(2, &AlwaysValid);
+
+	assert_eq!(g.version, 0);
+	assert_eq!(b2.version, 3);
+}
+
+#[test]
+fn bc_3_deployment_activates_after_enough_signals() {
+	use super::{AlwaysValid, ChainSpec};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default().with_deployment(even_deployment());
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+	let b1 = g.child_unsealed(1).seal(&AlwaysValid); // 1
+	let b2 = b1.child_unsealed(1).signalling(1).seal(&AlwaysValid); // 2
+	let b3 = b2.child_with(1, &AlwaysValid); // 3
+	let b4 = b3.child_with(1, &AlwaysValid); // 4, the third signal activates the deployment
+
+	// Odd states were fine while signalling.
+	let chain = vec![b1, b2, b3, b4.clone()];
+	assert!(spec.verify_sub_chain(&g, &chain, &AlwaysValid, &clock));
+
+	let b5_odd = b4.child_with(1, &AlwaysValid); // 5
+	let mut with_odd = chain.clone();
+	with_odd.push(b5_odd);
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &with_odd, &AlwaysValid, &clock),
+		Err(VerifyError::BrokenRules { height: 5 })
+	);
+
+	let b5_old = b4.child_unsealed(2).signalling(0).seal(&AlwaysValid); // 6, but outdated
+	let mut with_old = chain.clone();
+	with_old.push(b5_old);
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &with_old, &AlwaysValid, &clock),
+		Err(VerifyError::Outdated { height: 5 })
+	);
+
+	let b5 = b4.child_with(2, &AlwaysValid); // 6
+	let b6 = b5.child_unsealed(2).signalling(0).seal(&AlwaysValid); // 8, but outdated
+	let mut full = chain;
+	full.push(b5);
+	assert!(spec.verify_sub_chain(&g, &full, &AlwaysValid, &clock));
+	// The deployment stays active even though the last block stopped signalling.
+	full.push(b6);
+	assert!(!spec.verify_sub_chain(&g, &full, &AlwaysValid, &clock));
+}
+
+#[test]
+fn bc_3_deployment_needs_signals_within_window() {
+	use super::{AlwaysValid, ChainSpec};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default().with_deployment(even_deployment());
+	let clock = SystemClock::new();
+	let g = spec.genesis();
+
+	// Signals alternate, so no window of four blocks ever holds three.
+	let mut chain = Vec::new();
+	let mut tip = g.clone();
+	for height in 1..=8 {
+		tip = tip.child_unsealed(1).signalling((height % 2) as u32).seal(&AlwaysValid);
+		chain.push(tip.clone());
+	}
+
+	assert!(spec.verify_sub_chain(&g, &chain, &AlwaysValid, &clock));
+}
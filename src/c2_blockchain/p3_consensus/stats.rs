@@ -0,0 +1,87 @@
+//! Statistics about chains. The fork choice rules that pick among several chains live in
+//! `fork_choice`.
+
+use super::{fork_choice::total_work, Header};
+
+/// Statistics about how quickly a chain of timestamped headers was produced. Handy for graphing
+/// how a chain behaves while tuning its threshold and retargeting.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainStats {
+	/// The mean time between blocks, in seconds
+	pub mean_interval: f64,
+	/// The median time between blocks, in seconds
+	pub median_interval: f64,
+	/// The longest time between two blocks, in seconds
+	pub longest_gap: u64,
+	/// Roughly how many hashes per second went into the chain, judged by the thresholds of its
+	/// blocks. A block with threshold `t` takes `2^64 / t` hashes on average.
+	pub hashrate: f64,
+}
+
+impl ChainStats {
+	/// Work out the statistics of the given chain, oldest header first. Only the time from the
+	/// first header onward counts, so there is nothing to say about fewer than two headers.
+	pub fn new(chain: &[Header]) -> Option<Self> {
+		let (first, last) = (chain.first()?, chain.last()?);
+		let mut intervals: Vec<u64> = chain
+			.windows(2)
+			.map(|pair| pair[1].timestamp.saturating_sub(pair[0].timestamp))
+			.collect();
+		if intervals.is_empty() {
+			return None
+		}
+		intervals.sort_unstable();
+
+		let count = intervals.len();
+		let elapsed = last.timestamp.saturating_sub(first.timestamp) as f64;
+		let middle = intervals[count / 2] as f64;
+		let median_interval = if count.is_multiple_of(2) {
+			(intervals[count / 2 - 1] as f64 + middle) / 2.0
+		} else {
+			middle
+		};
+		let hashes = total_work(&chain[1..]) as f64;
+
+		Some(ChainStats {
+			mean_interval: elapsed / count as f64,
+			median_interval,
+			longest_gap: intervals[count - 1],
+			hashrate: hashes / elapsed,
+		})
+	}
+}
+
+#[test]
+fn bc_3_chain_stats_intervals() {
+	use super::{AlwaysValid, ChainSpec};
+	let spec = ChainSpec::default();
+	let mut chain = vec![spec.genesis()];
+	for timestamp in [10, 20, 50, 60] {
+		let child = spec.child_at(chain.last().unwrap(), 1, timestamp, &AlwaysValid);
+		chain.push(child);
+	}
+	let stats = ChainStats::new(&chain).unwrap();
+
+	assert_eq!(stats.mean_interval, 15.0);
+	assert_eq!(stats.median_interval, 10.0);
+	assert_eq!(stats.longest_gap, 30);
+	assert_eq!(ChainStats::new(&chain[..3]).unwrap().median_interval, 10.0);
+	assert_eq!(ChainStats::new(&chain[1..4]).unwrap().median_interval, 20.0);
+}
+
+#[test]
+fn bc_3_chain_stats_hashrate() {
+	use super::AlwaysValid;
+	// On time blocks keep the threshold at 1 in 100, so that is 100 hashes every 10 seconds.
+	let g = Header::genesis();
+	let mut chain = vec![g.clone()];
+	for _ in 0..4 {
+		let child = chain.last().unwrap().child_with(1, &AlwaysValid);
+		chain.push(child);
+	}
+	let stats = ChainStats::new(&chain).unwrap();
+
+	assert!((stats.hashrate - 10.0).abs() < 0.001);
+	assert_eq!(ChainStats::new(&[g]), None);
+	assert_eq!(ChainStats::new(&[]), None);
+}
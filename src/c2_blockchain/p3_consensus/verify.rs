@@ -0,0 +1,556 @@
+//! Verifying chains against a chain spec, and the errors verification can find.
+
+use super::{rules::Signalling, ChainSpec, Engine, Hash, Header, PoliticalRule};
+use crate::{c1_state_machine::clock::Clock, hash_header};
+use std::collections::{HashMap, HashSet};
+
+/// The reasons a header chain may be invalid. Each carries the height of the first offending
+/// block, counted from its parent, so it is right even when the block claims another height.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum VerifyError {
+	/// The block's height doesn't follow its parent's.
+	BadHeight { height: u64 },
+	/// The block's parent hash isn't the hash of the previous block.
+	BadParent { height: u64 },
+	/// The block's state isn't its parent's state plus its extrinsic and uncle rewards.
+	BadState { height: u64 },
+	/// The block isn't later than its parent.
+	TooEarly { height: u64 },
+	/// The block is too far ahead of our clock.
+	TooLate { height: u64 },
+	/// The block's threshold isn't retargeted correctly from its parent's.
+	BadThreshold { height: u64 },
+	/// The block breaks the rules of its era, or of an active deployment.
+	BrokenRules { height: u64 },
+	/// The block's version is older than an active deployment's.
+	Outdated { height: u64 },
+	/// The block ends an epoch without committing to the next one, or commits to one mid-epoch.
+	BadEpochChange { height: u64 },
+	/// The block includes an uncle that isn't a valid and recent competing block, or that was
+	/// included before.
+	BadUncle { height: u64 },
+	/// The block is at the height of a trusted checkpoint, but doesn't have its hash.
+	BadCheckpoint { height: u64 },
+	/// The chain doesn't start with this chain's genesis header.
+	BadGenesis,
+	/// The consensus engine rejected the block. For proof of work, there isn't enough work.
+	InsufficientWork { height: u64 },
+}
+
+/// How much of a header chain is valid. Whoever imports the chain can keep the valid prefix
+/// instead of throwing the whole chain away.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Verified {
+	/// How many headers at the start of the chain are valid
+	pub valid: usize,
+	/// What is wrong with the first invalid header, if there is one
+	pub error: Option<VerifyError>,
+	/// The total work of the valid headers. See `total_work`.
+	pub work: u128,
+}
+
+impl ChainSpec {
+	/// Verify that all the given headers form a valid chain from the given header to the tip,
+	/// where the given engine decides what is valid at the consensus level, each block follows
+	/// the rules of its era, and no block is too far ahead of the given clock.
+	pub fn verify_sub_chain<C: Engine, K: Clock>(
+		&self,
+		from: &Header,
+		chain: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> bool {
+		self.try_verify_sub_chain(from, chain, engine, clock).is_ok()
+	}
+
+	/// Verify that the given headers form a valid chain, starting from genesis.
+	pub fn verify_chain<C: Engine, K: Clock>(
+		&self,
+		chain: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> Result<(), VerifyError> {
+		self.verify_chain_with_uncles(chain, &[], engine, clock)
+	}
+
+	/// Like `verify_chain`, but for a chain that includes uncles. The given uncle headers are
+	/// looked up by hash, so they may come in any order.
+	///
+	/// Each uncle must be a valid child of a block in the chain, but not in the chain itself, and
+	/// at most the maximum uncle depth below the block including it. No uncle may be included
+	/// twice.
+	pub fn verify_chain_with_uncles<C: Engine, K: Clock>(
+		&self,
+		chain: &[Header],
+		uncles: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> Result<(), VerifyError> {
+		match chain.split_first() {
+			Some((genesis, rest)) if self.is_genesis(genesis) => self
+				.verify_detailed(genesis, rest, uncles, engine, clock)
+				.error
+				.map_or(Ok(()), Err),
+			_ => Err(VerifyError::BadGenesis),
+		}
+	}
+
+	/// Like `verify_sub_chain`, but says what is wrong with the first invalid block.
+	pub fn try_verify_sub_chain<C: Engine, K: Clock>(
+		&self,
+		from: &Header,
+		chain: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> Result<(), VerifyError> {
+		self.verify_sub_chain_detailed(from, chain, engine, clock)
+			.error
+			.map_or(Ok(()), Err)
+	}
+
+	/// Like `verify_sub_chain`, but says how many blocks are valid before the first invalid one,
+	/// and what is wrong with it.
+	///
+	/// Deployments are tracked from the given header onward, since the signalling before it isn't
+	/// known. Verifying from genesis is exact. Verifying from later on may notice an activation
+	/// late, or not at all, so it never rejects a block that verifying from genesis would accept.
+	///
+	/// Blocks up to the latest checkpoint in the chain are only checked for their hash links. They
+	/// are only known to be valid once the checkpoint is reached, so if anything is wrong on the
+	/// way there, none of the chain counts as valid.
+	///
+	/// A block that includes uncles is rewarded for them in its state, so it is only valid once
+	/// its uncles are checked. That takes the uncle headers, so use `verify_chain_with_uncles`.
+	pub fn verify_sub_chain_detailed<C: Engine, K: Clock>(
+		&self,
+		from: &Header,
+		chain: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> Verified {
+		self.verify_detailed(from, chain, &[], engine, clock)
+	}
+
+	/// Like `verify_sub_chain_detailed`, but with the uncle headers the chain's blocks include.
+	fn verify_detailed<C: Engine, K: Clock>(
+		&self,
+		from: &Header,
+		chain: &[Header],
+		uncles: &[Header],
+		engine: &C,
+		clock: &K,
+	) -> Verified {
+		let latest = clock.now().as_secs() + self.max_future_drift;
+		let tip = from.height + chain.len() as u64;
+		let trusted = self.checkpoints.range(from.height + 1..=tip).next_back().map_or(0, |c| *c.0);
+		let uncles: HashMap<Hash, &Header> =
+			uncles.iter().map(|uncle| (hash_header(uncle), uncle)).collect();
+		let mut included = HashSet::new();
+		let mut signalling = Signalling::new(&self.deployments);
+		let mut ancestors = vec![from];
+		let mut work = 0;
+		for (valid, block) in chain.iter().enumerate() {
+			let prev = ancestors[ancestors.len() - 1];
+			let height = prev.height + 1;
+			let checked = if height <= trusted {
+				included.extend(&block.uncles);
+				self.check_linked(block, prev)
+					.and_then(|()| self.check_checkpoint(block, height))
+			} else {
+				let threshold = self.next_threshold(prev, block.timestamp);
+				self.check_block(block, prev, latest, engine, threshold)
+					.and_then(|()| signalling.check(block, height))
+					.and_then(|()| {
+						self.check_uncles(block, &ancestors, &uncles, &mut included, engine)
+					})
+			};
+			match checked {
+				Err(error) if height <= trusted =>
+					return Verified { valid: 0, error: Some(error), work: 0 },
+				Err(error) => return Verified { valid, error: Some(error), work },
+				Ok(()) => (),
+			}
+			signalling.record(block);
+			work += block.work();
+			ancestors.push(block);
+		}
+		Verified { valid: chain.len(), error: None, work }
+	}
+
+	/// Check the uncles the given block includes, given its ancestors, the known uncle headers by
+	/// hash, and the uncles included so far. Only the given ancestors count, so uncles of blocks
+	/// before them can't be checked.
+	fn check_uncles<C: Engine>(
+		&self,
+		block: &Header,
+		ancestors: &[&Header],
+		uncles: &HashMap<Hash, &Header>,
+		included: &mut HashSet<Hash>,
+		engine: &C,
+	) -> Result<(), VerifyError> {
+		let height = block.height;
+		if block.uncles.len() > self.max_uncles {
+			return Err(VerifyError::BadUncle { height })
+		}
+		for hash in &block.uncles {
+			let valid = included.insert(*hash) &&
+				uncles
+					.get(hash)
+					.is_some_and(|uncle| self.is_uncle(uncle, *hash, ancestors, height, engine));
+			if !valid {
+				return Err(VerifyError::BadUncle { height })
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether the given header is a valid uncle for a block at the given height, whose ancestors
+	/// are given
+	fn is_uncle<C: Engine>(
+		&self,
+		uncle: &Header,
+		hash: Hash,
+		ancestors: &[&Header],
+		height: u64,
+		engine: &C,
+	) -> bool {
+		let recent = uncle.height < height && height - uncle.height <= self.max_uncle_depth;
+		let at_height = |h| ancestors.iter().find(|ancestor| ancestor.height == h);
+		let (Some(parent), Some(sibling)) =
+			(at_height(uncle.height.wrapping_sub(1)), at_height(uncle.height))
+		else {
+			return false
+		};
+		let threshold = self.next_threshold(parent, uncle.timestamp);
+		recent &&
+			hash_header(sibling) != hash &&
+			self.check_child(uncle, parent, threshold).is_ok() &&
+			engine.validate(uncle)
+	}
+
+	/// Check a single block against its parent, the rules of its era and the consensus engine.
+	/// The block must record the given threshold, and must not be authored after `latest`.
+	pub(super) fn check_block<C: Engine>(
+		&self,
+		block: &Header,
+		prev: &Header,
+		latest: u64,
+		engine: &C,
+		threshold: u64,
+	) -> Result<(), VerifyError> {
+		let height = prev.height + 1;
+		self.check_child(block, prev, threshold)?;
+		if block.timestamp > latest {
+			Err(VerifyError::TooLate { height })
+		} else if !self.rules_at(height).permits(block) {
+			Err(VerifyError::BrokenRules { height })
+		} else if !engine.validate(block) {
+			Err(VerifyError::InsufficientWork { height })
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Check the rules every block must follow whatever the consensus engine, namely that it
+	/// builds on its parent, executes its extrinsic correctly, comes after its parent, and uses
+	/// the given threshold.
+	fn check_child(
+		&self,
+		block: &Header,
+		prev: &Header,
+		threshold: u64,
+	) -> Result<(), VerifyError> {
+		let height = prev.height + 1;
+		self.check_linked(block, prev)?;
+		if block.state != self.state_after(prev, block) {
+			Err(VerifyError::BadState { height })
+		} else if block.timestamp <= prev.timestamp {
+			Err(VerifyError::TooEarly { height })
+		} else if block.threshold != threshold {
+			Err(VerifyError::BadThreshold { height })
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Check that the given block links up to the given previous block, and nothing else.
+	fn check_linked(&self, block: &Header, prev: &Header) -> Result<(), VerifyError> {
+		let height = prev.height + 1;
+		if block.height != height {
+			Err(VerifyError::BadHeight { height })
+		} else if block.parent != hash_header(prev) {
+			Err(VerifyError::BadParent { height })
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Check the given block against the checkpoint at the given height, if there is one.
+	fn check_checkpoint(&self, block: &Header, height: u64) -> Result<(), VerifyError> {
+		match self.checkpoints.get(&height) {
+			Some(hash) if *hash != hash_header(block) => Err(VerifyError::BadCheckpoint { height }),
+			_ => Ok(()),
+		}
+	}
+}
+
+#[test]
+fn bc_3_verify_errors_name_the_problem() {
+	use super::ProofOfWork;
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	let b2 = b1.child(2);
+	let cases = [
+		(b1.child_builder(2).height(7), VerifyError::BadHeight { height: 2 }),
+		(b1.child_builder(2).parent(0), VerifyError::BadParent { height: 2 }),
+		(b1.child_builder(2).state(9), VerifyError::BadState { height: 2 }),
+		(b1.child_builder(2).timestamp(5), VerifyError::TooEarly { height: 2 }),
+		(b1.child_builder(2).threshold(u64::MAX), VerifyError::BadThreshold { height: 2 }),
+	];
+
+	assert_eq!(g.try_verify_sub_chain(&[b1.clone(), b2]), Ok(()));
+	for (bad, error) in cases {
+		let bad = bad.seal(&ProofOfWork);
+		assert_eq!(g.try_verify_sub_chain(&[b1.clone(), bad]), Err(error));
+	}
+}
+
+#[test]
+fn bc_3_verify_errors_for_work_rules_and_time() {
+	use super::{AlwaysValid, ConsensusDigest, Parity, ProofOfWork, RuleSet, MAX_FUTURE_DRIFT};
+	use crate::c1_state_machine::clock::MockClock;
+
+	let spec = ChainSpec::default().with_fork(1, RuleSet::parity(Parity::Even));
+	let clock = MockClock::new();
+	let g = spec.genesis();
+	let b1 = spec.child_at(&g, 1, 10, &AlwaysValid);
+
+	let mut unmined = Header { consensus_digest: ConsensusDigest::Pow { nonce: 0 }, ..b1.clone() };
+	while ProofOfWork.validate(&unmined) {
+		unmined.consensus_digest = ConsensusDigest::Pow { nonce: rand::random() };
+	}
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &[unmined], &ProofOfWork, &clock),
+		Err(VerifyError::InsufficientWork { height: 1 })
+	);
+
+	let b2_odd = spec.child_at(&b1, 2, 20, &AlwaysValid); // 3
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &[b1.clone(), b2_odd], &AlwaysValid, &clock),
+		Err(VerifyError::BrokenRules { height: 2 })
+	);
+
+	let future = spec.child_at(&g, 1, MAX_FUTURE_DRIFT + 1, &AlwaysValid);
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &[future], &AlwaysValid, &clock),
+		Err(VerifyError::TooLate { height: 1 })
+	);
+}
+
+#[test]
+fn bc_3_detailed_verification_counts_valid_prefix() {
+	use super::ProofOfWork;
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	let b2 = b1.child(2);
+	let b3 = b2.child(3);
+	let bad_b3 = b2.child_builder(3).state(0).seal(&ProofOfWork);
+	let b4 = bad_b3.child(4);
+
+	let all_good = g.verify_sub_chain_detailed(&[b1.clone(), b2.clone(), b3]);
+	assert_eq!(all_good, Verified { valid: 3, error: None, work: 300 });
+
+	let chain = [b1, b2, bad_b3, b4];
+	let verified = g.verify_sub_chain_detailed(&chain);
+	assert_eq!(
+		verified,
+		Verified { valid: 2, error: Some(VerifyError::BadState { height: 3 }), work: 200 }
+	);
+	assert!(g.verify_sub_chain(&chain[..verified.valid]));
+}
+
+#[test]
+fn bc_3_detailed_verification_of_empty_and_hopeless_chains() {
+	let g = Header::genesis();
+	let orphan = Header::genesis().child(1).child(2);
+
+	assert_eq!(g.verify_sub_chain_detailed(&[]), Verified { valid: 0, error: None, work: 0 });
+	assert_eq!(
+		g.verify_sub_chain_detailed(&[orphan]),
+		Verified { valid: 0, error: Some(VerifyError::BadHeight { height: 1 }), work: 0 }
+	);
+}
+
+/// A chain from genesis of the given length, where every block from height 1 up has a competing
+/// sibling mined on the same parent. Returns the chain and the siblings.
+#[cfg(test)]
+fn chain_with_siblings(length: u64) -> (Vec<Header>, Vec<Header>) {
+	use super::{ProofOfWork, TARGET_BLOCK_TIME};
+	let spec = ChainSpec::default();
+	let mut chain = vec![spec.genesis()];
+	let mut siblings = Vec::new();
+	for height in 1..length {
+		let parent = chain.last().unwrap();
+		let timestamp = height * TARGET_BLOCK_TIME;
+		siblings.push(spec.child_at(parent, 100, timestamp, &ProofOfWork));
+		chain.push(spec.child_at(parent, 1, timestamp, &ProofOfWork));
+	}
+	(chain, siblings)
+}
+
+#[test]
+fn bc_3_uncles_are_rewarded() {
+	use super::{ProofOfWork, UNCLE_REWARD};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default();
+	let (mut chain, siblings) = chain_with_siblings(3);
+	let tip = spec
+		.child_unsealed_with_uncles(&chain[2], 1, 30, &siblings[..2])
+		.seal(&ProofOfWork);
+
+	assert_eq!(tip.state, chain[2].state + 1 + 2 * UNCLE_REWARD);
+	chain.push(tip);
+	let clock = SystemClock::new();
+	assert_eq!(spec.verify_chain_with_uncles(&chain, &siblings, &ProofOfWork, &clock), Ok(()));
+	// Without the uncle headers, the reward can't be checked.
+	assert_eq!(
+		spec.verify_chain(&chain, &ProofOfWork, &clock),
+		Err(VerifyError::BadUncle { height: 3 })
+	);
+
+	// Claiming the reward without including any uncles breaks the state.
+	let greedy = chain[2].child_builder(1).state(chain[3].state).seal(&ProofOfWork);
+	assert_eq!(
+		chain[0].try_verify_sub_chain(&[chain[1].clone(), chain[2].clone(), greedy]),
+		Err(VerifyError::BadState { height: 3 })
+	);
+}
+
+#[test]
+fn bc_3_bad_uncles() {
+	use super::{AlwaysValid, ProofOfWork};
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default();
+	let (chain, siblings) = chain_with_siblings(9);
+	let tip = chain.last().unwrap();
+	let with_uncles = |uncles: &[Header]| {
+		let block = spec.child_unsealed_with_uncles(tip, 1, 90, uncles).seal(&ProofOfWork);
+		[chain.clone(), vec![block]].concat()
+	};
+	let unsealed = spec.child_at(&chain[7], 100, 80, &AlwaysValid);
+	let nephew = spec.child_at(&siblings[6], 1, 90, &ProofOfWork);
+	let clock = SystemClock::new();
+	let verify = |uncles: &[Header]| {
+		let known = [&siblings[..], &[unsealed.clone(), nephew.clone()]].concat();
+		spec.verify_chain_with_uncles(&with_uncles(uncles), &known, &ProofOfWork, &clock)
+	};
+
+	let bad = Err(VerifyError::BadUncle { height: 9 });
+	assert_eq!(verify(&siblings[2..3]), Ok(()));
+	// Too old
+	assert_eq!(verify(&siblings[1..2]), bad);
+	// Already in the chain
+	assert_eq!(verify(&chain[8..9]), bad);
+	// Not mined
+	assert_eq!(verify(std::slice::from_ref(&unsealed)), bad);
+	// Not a child of the chain
+	assert_eq!(verify(std::slice::from_ref(&nephew)), bad);
+	// Too many, or the same one twice
+	assert_eq!(verify(&siblings[5..8]), bad);
+	assert_eq!(verify(&[siblings[7].clone(), siblings[7].clone()]), bad);
+	// Unknown
+	assert_eq!(spec.verify_chain(&with_uncles(&siblings[7..]), &ProofOfWork, &clock), bad);
+}
+
+#[test]
+fn bc_3_forged_uncles_are_not_rewarded() {
+	use super::{ProofOfWork, TARGET_BLOCK_TIME};
+	let spec = ChainSpec::default();
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	// Nobody has ever seen these uncles, so the reward for them is made up.
+	let forged = [b1.child(2), b1.child(3)];
+	let b2 = spec
+		.child_unsealed_with_uncles(&b1, 4, b1.timestamp + TARGET_BLOCK_TIME, &forged)
+		.seal(&ProofOfWork);
+
+	assert_eq!(g.try_verify_sub_chain(&[b1, b2]), Err(VerifyError::BadUncle { height: 2 }));
+}
+
+#[test]
+fn bc_3_uncles_are_only_rewarded_once() {
+	use super::ProofOfWork;
+	use crate::c1_state_machine::clock::SystemClock;
+	let spec = ChainSpec::default();
+	let (mut chain, siblings) = chain_with_siblings(3);
+	for timestamp in [30, 40] {
+		let block = spec
+			.child_unsealed_with_uncles(chain.last().unwrap(), 1, timestamp, &siblings[..1])
+			.seal(&ProofOfWork);
+		chain.push(block);
+	}
+
+	let clock = SystemClock::new();
+	assert_eq!(spec.verify_chain_with_uncles(&chain[..4], &siblings, &ProofOfWork, &clock), Ok(()));
+	assert_eq!(
+		spec.verify_chain_with_uncles(&chain, &siblings, &ProofOfWork, &clock),
+		Err(VerifyError::BadUncle { height: 4 })
+	);
+}
+
+#[test]
+fn bc_3_checkpoint_skips_verifying_below_it() {
+	use super::{AlwaysValid, ProofOfWork};
+	use crate::c1_state_machine::clock::SystemClock;
+	let g = Header::genesis();
+	let b1 = g.child(1);
+	// Nobody mined these, but the checkpoint vouches for them.
+	let b2 = b1.child_with(2, &AlwaysValid);
+	let b3 = b2.child_with(3, &AlwaysValid);
+	let b4 = b3.child(4);
+	let chain = [b1, b2, b3.clone(), b4];
+
+	let spec = ChainSpec::default().with_checkpoint(3, hash_header(&b3));
+	let clock = SystemClock::new();
+	assert_eq!(spec.try_verify_sub_chain(&g, &chain, &ProofOfWork, &clock), Ok(()));
+	assert!(!g.verify_sub_chain(&chain));
+
+	// Blocks after the checkpoint are verified as usual.
+	let b5 = chain[3].child_with(5, &AlwaysValid);
+	let longer = [&chain[..], &[b5]].concat();
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &longer, &ProofOfWork, &clock),
+		Err(VerifyError::InsufficientWork { height: 5 })
+	);
+
+	// A chain too short to reach the checkpoint is verified as usual too.
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &chain[..2], &ProofOfWork, &clock),
+		Err(VerifyError::InsufficientWork { height: 2 })
+	);
+}
+
+#[test]
+fn bc_3_checkpoint_still_checks_links() {
+	use super::AlwaysValid;
+	use crate::c1_state_machine::clock::SystemClock;
+	let g = Header::genesis();
+	let b1 = g.child_with(1, &AlwaysValid);
+	let b2 = b1.child_with(2, &AlwaysValid);
+	let other_b2 = b1.child_with(3, &AlwaysValid);
+	let spec = ChainSpec::default().with_checkpoint(2, hash_header(&b2));
+	let clock = SystemClock::new();
+
+	let verified =
+		spec.verify_sub_chain_detailed(&g, &[b1.clone(), other_b2], &AlwaysValid, &clock);
+	assert_eq!(
+		verified,
+		Verified { valid: 0, error: Some(VerifyError::BadCheckpoint { height: 2 }), work: 0 }
+	);
+
+	let orphan = g.child_with(9, &AlwaysValid);
+	assert_eq!(
+		spec.try_verify_sub_chain(&g, &[b1, orphan], &AlwaysValid, &clock),
+		Err(VerifyError::BadHeight { height: 2 })
+	);
+}
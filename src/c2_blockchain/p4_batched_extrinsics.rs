@@ -1,22 +1,202 @@
 //! Until now, each block has contained just a single extrinsic. Really we would prefer to batch
 //! them. Now, we stop relying solely on headers, and instead, create complete blocks.
-use crate::hash;
+//!
+//! The chain code doesn't care what the extrinsics and the state are, as long as there is a state
+//! transition function to execute the extrinsics with. Headers and blocks are generic over both,
+//! and the chain of `u64`s added together that we have used so far is just the default
+//! instantiation.
+use crate::{
+	c1_state_machine::TryStateMachine,
+	crypto::{Pair, Public, SchnorrPair, Signature},
+	hash, hash_header,
+};
 use rand::{thread_rng, Rng};
+use std::{hash::Hash as StdHash, marker::PhantomData};
 type Hash = u64;
 
+/// A state transition function, or STF. It executes the extrinsics of a block on top of the
+/// parent's state, and returns the post-state, or the reason the extrinsics can't be executed.
+///
+/// Blocks can be built and verified with any STF, so the same chain code can drive any runtime.
+/// The STF is a value, so it can carry whatever the chain is configured with, such as the genesis
+/// hash that extrinsics are signed for.
+///
+/// The STF also sets how much work a block may contain. Each extrinsic has a weight, roughly how
+/// long it takes to execute, and the weights of the extrinsics in a block may not add up to more
+/// than the budget. Otherwise an author could make a block that takes everybody else ages to
+/// verify. Like the genesis hash, the budget may be chosen when the chain is launched, see
+/// `Budgeted`.
+pub trait Stf {
+	/// The extrinsics in the block bodies
+	type Extrinsic;
+
+	/// The state stored in the headers
+	type State;
+
+	/// The reasons a block may be rejected
+	type Error;
+
+	/// The most weight a single block may contain. Unless the STF says otherwise, there is no
+	/// limit.
+	fn max_block_weight(&self) -> u64 {
+		u64::MAX
+	}
+
+	/// Execute the given extrinsics in order on top of the given pre-state.
+	fn apply(
+		&self,
+		pre_state: &Self::State,
+		extrinsics: &[Self::Extrinsic],
+	) -> Result<Self::State, Self::Error>;
+
+	/// The weight of the given extrinsic. Unless the STF says otherwise, every extrinsic weighs
+	/// the same, so the budget limits how many extrinsics fit in a block.
+	fn weight(&self, _extrinsic: &Self::Extrinsic) -> u64 {
+		1
+	}
+
+	/// The total weight of the given extrinsics
+	fn total_weight(&self, extrinsics: &[Self::Extrinsic]) -> u64 {
+		extrinsics
+			.iter()
+			.map(|extrinsic| self.weight(extrinsic))
+			.fold(0, u64::saturating_add)
+	}
+}
+
+/// The STF of the chains so far. The state is the sum of all the extrinsics.
+pub struct Adder;
+
+/// The sum of the extrinsics no longer fits in a `u64`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Overflow;
+
+impl Stf for Adder {
+	type Extrinsic = u64;
+	type State = u64;
+	type Error = Overflow;
+
+	fn apply(&self, pre_state: &u64, extrinsics: &[u64]) -> Result<u64, Overflow> {
+		extrinsics
+			.iter()
+			.try_fold(*pre_state, |state, extrinsic| state.checked_add(*extrinsic))
+			.ok_or(Overflow)
+	}
+}
+
+/// Any state machine from the first chapter is an STF. A block is rejected as soon as any of its
+/// transitions is.
+impl<M: TryStateMachine> Stf for M
+where
+	M::State: Clone,
+{
+	type Extrinsic = M::Transition;
+	type State = M::State;
+	type Error = M::Error;
+
+	fn apply(
+		&self,
+		pre_state: &M::State,
+		extrinsics: &[M::Transition],
+	) -> Result<M::State, M::Error> {
+		extrinsics
+			.iter()
+			.try_fold(pre_state.clone(), |state, extrinsic| M::try_next_state(&state, extrinsic))
+	}
+}
+
+/// A state that extrinsics of type `E` can be executed on. This is the runtime of the chain.
+pub trait Execute<E>: Sized {
+	/// Why an extrinsic can't be executed. Runtimes that accept every extrinsic use `Infallible`.
+	type Error;
+
+	/// The state after executing the given extrinsic on top of this one.
+	fn execute(&self, extrinsic: &E) -> Result<Self, Self::Error>;
+}
+
+/// The canonical runtime. The state is a number, and each extrinsic is added to it, just like
+/// `Adder` does.
+impl Execute<u64> for u64 {
+	type Error = Overflow;
+
+	fn execute(&self, extrinsic: &u64) -> Result<u64, Overflow> {
+		self.checked_add(*extrinsic).ok_or(Overflow)
+	}
+}
+
+/// The STF of any state that knows how to execute its extrinsics one at a time. A block is
+/// rejected as soon as any of its extrinsics can't be executed.
+pub struct Execution<E, S>(PhantomData<(E, S)>);
+
+impl<E, S: Execute<E> + Clone> Stf for Execution<E, S> {
+	type Extrinsic = E;
+	type State = S;
+	type Error = S::Error;
+
+	fn apply(&self, pre_state: &S, extrinsics: &[E]) -> Result<S, S::Error> {
+		extrinsics
+			.iter()
+			.try_fold(pre_state.clone(), |state, extrinsic| state.execute(extrinsic))
+	}
+}
+
+/// Any STF, with a weight budget chosen when the chain is launched rather than by the STF itself
+pub struct Budgeted<F> {
+	/// The STF that executes the extrinsics
+	pub inner: F,
+	/// The most weight a single block may contain
+	pub max_block_weight: u64,
+}
+
+impl<F: Stf> Stf for Budgeted<F> {
+	type Extrinsic = F::Extrinsic;
+	type State = F::State;
+	type Error = F::Error;
+
+	fn max_block_weight(&self) -> u64 {
+		self.max_block_weight
+	}
+
+	fn apply(
+		&self,
+		pre_state: &F::State,
+		extrinsics: &[F::Extrinsic],
+	) -> Result<F::State, F::Error> {
+		self.inner.apply(pre_state, extrinsics)
+	}
+
+	fn weight(&self, extrinsic: &F::Extrinsic) -> u64 {
+		self.inner.weight(extrinsic)
+	}
+}
+
+/// The reasons a child block can't be built
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BuildError<E> {
+	/// The extrinsics weigh more than a block may contain.
+	Overweight,
+	/// The extrinsics can't be executed.
+	Invalid(E),
+}
+
 /// The header no longer contains an extrinsic directly. Rather a vector of extrinsics will be
 /// stored in the block body. We are still storing the state in the header for now. This will change
 /// in an upcoming lesson as well.
+///
+/// `E` is the type of the extrinsics in the body, and `S` the type of the state.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct Header {
+pub struct Header<E = u64, S = u64> {
 	parent: Hash,
 	height: u64,
 	// We now switch from storing an extrinsic directly, to storing an extrinsic root.
 	// This is basically a concise cryptographic commitment to the complete list of extrinsics.
 	// For example, a hash or a Merkle root.
 	extrinsics_root: Hash,
-	state: u64,
+	state: S,
 	pub consensus_digest: u64,
+	// The extrinsics aren't stored in the header, but a header only ever belongs to blocks of one
+	// kind of extrinsic.
+	extrinsics: PhantomData<E>,
 }
 
 // Methods for creating and verifying headers.
@@ -28,24 +208,68 @@ pub struct Header {
 impl Header {
 	/// Returns a new valid genesis header.
 	pub fn genesis() -> Self {
-		Header { parent: 0, height: 0, extrinsics_root: 0, state: 0, consensus_digest: 0 }
+		Header::genesis_with_state(0)
+	}
+}
+
+impl<E: StdHash, S: StdHash> Header<E, S> {
+	/// Returns a new valid genesis header committing to the given genesis state. The chains in
+	/// this lesson always start from zero, but other runtimes do not.
+	pub fn genesis_with_state(state: S) -> Self {
+		Header {
+			parent: 0,
+			height: 0,
+			extrinsics_root: 0,
+			state,
+			consensus_digest: 0,
+			extrinsics: PhantomData,
+		}
 	}
 
 	/// Create and return a valid child header.
 	/// Without the extrinsics themselves, we cannot calculate the final state
 	/// so that information is passed in.
-	pub fn child(&self, extrinsics_root: Hash, state: u64) -> Self {
+	pub fn child(&self, extrinsics_root: Hash, state: S) -> Self {
 		let mut rng = thread_rng();
 
 		Header {
-			parent: hash(self),
+			parent: hash_header(self),
 			height: self.height + 1,
 			extrinsics_root,
 			state,
 			consensus_digest: rng.gen::<u64>(),
+			extrinsics: PhantomData,
 		}
 	}
 
+	/// The hash of this header. This is what a child stores as its parent.
+	pub fn hash(&self) -> Hash {
+		hash_header(self)
+	}
+
+	/// The hash of this header's parent.
+	pub fn parent(&self) -> Hash {
+		self.parent
+	}
+
+	/// How many blocks come before this one.
+	pub fn height(&self) -> u64 {
+		self.height
+	}
+
+	/// The commitment to the extrinsics in this block.
+	pub fn extrinsics_root(&self) -> Hash {
+		self.extrinsics_root
+	}
+
+	/// The state after executing this block.
+	pub fn state(&self) -> S
+	where
+		S: Clone,
+	{
+		self.state.clone()
+	}
+
 	/// Verify a single child header.
 	///
 	/// This is a slightly different interface from the previous units. Rather
@@ -53,8 +277,8 @@ impl Header {
 	/// This is useful because checking the header can now be thought of as a
 	/// subtask of checking an entire block. So it doesn't make sense to check
 	/// the entire header chain at once if the chain may be invalid at the second block.
-	fn verify_child(&self, child: &Header) -> bool {
-		child.parent == hash(self) && child.height == self.height + 1
+	pub(crate) fn verify_child(&self, child: &Header<E, S>) -> bool {
+		child.parent == hash_header(self) && child.height == self.height + 1
 	}
 
 	/// Verify that all the given headers form a valid chain from this header to the tip.
@@ -64,8 +288,8 @@ impl Header {
 	///  - with a loop
 	///  - with head recursion
 	///  - with tail recursion
-	fn verify_sub_chain(&self, chain: &[Header]) -> bool {
-		let mut parent: &Header = self;
+	fn verify_sub_chain(&self, chain: &[Header<E, S>]) -> bool {
+		let mut parent: &Header<E, S> = self;
 		for child in chain {
 			if !parent.verify_child(child) {
 				return false;
@@ -77,11 +301,84 @@ impl Header {
 	}
 }
 
+/// An extrinsic signed by the account that sent it.
+///
+/// The signature covers the payload and the hash of the genesis block of the chain it was meant
+/// for. Otherwise a transaction sent on one chain could be replayed on any other chain where the
+/// sender has an account, such as a testnet, or both sides of a chain split.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SignedExtrinsic<P> {
+	/// What the sender wants done
+	pub payload: P,
+	/// The sender's account
+	pub signer: Public,
+	/// The sender's signature over the genesis hash and the payload
+	pub signature: Signature,
+}
+
+impl<P: StdHash> SignedExtrinsic<P> {
+	/// Sign the given payload for the chain with the given genesis hash.
+	pub fn new(pair: &SchnorrPair, genesis: Hash, payload: P) -> Self {
+		let signature = pair.sign(&(genesis, &payload));
+		SignedExtrinsic { payload, signer: pair.public(), signature }
+	}
+
+	/// Whether the signer signed this payload for the chain with the given genesis hash.
+	pub fn verify(&self, genesis: Hash) -> bool {
+		SchnorrPair::verify(&self.signature, &(genesis, &self.payload), &self.signer)
+	}
+}
+
+/// The STF of a chain whose extrinsics are signed. Every extrinsic must have been signed for the
+/// chain with the given genesis hash, or the whole block is rejected. The payloads are then
+/// executed with the inner STF, just as if they had never been signed.
+pub struct Signed<F> {
+	/// The hash of the genesis block of this chain
+	pub genesis: Hash,
+	/// The STF that executes the payloads
+	pub inner: F,
+}
+
+/// The reasons a block of signed extrinsics may be rejected
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SignedError<E> {
+	/// An extrinsic wasn't signed by its signer, or was signed for another chain.
+	BadSignature,
+	/// The payloads can't be executed.
+	Payload(E),
+}
+
+impl<F: Stf> Stf for Signed<F>
+where
+	F::Extrinsic: StdHash + Clone,
+{
+	type Extrinsic = SignedExtrinsic<F::Extrinsic>;
+	type State = F::State;
+	type Error = SignedError<F::Error>;
+
+	fn apply(
+		&self,
+		pre_state: &F::State,
+		extrinsics: &[SignedExtrinsic<F::Extrinsic>],
+	) -> Result<F::State, SignedError<F::Error>> {
+		if !extrinsics.iter().all(|extrinsic| extrinsic.verify(self.genesis)) {
+			return Err(SignedError::BadSignature)
+		}
+		let payloads: Vec<F::Extrinsic> =
+			extrinsics.iter().map(|extrinsic| extrinsic.payload.clone()).collect();
+		self.inner.apply(pre_state, &payloads).map_err(SignedError::Payload)
+	}
+
+	fn weight(&self, extrinsic: &SignedExtrinsic<F::Extrinsic>) -> u64 {
+		self.inner.weight(&extrinsic.payload)
+	}
+}
+
 /// A complete Block is a header and the extrinsics.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
-pub struct Block {
-	pub(crate) header: Header,
-	pub(crate) body: Vec<u64>,
+pub struct Block<E = u64, S = u64> {
+	pub(crate) header: Header<E, S>,
+	pub(crate) body: Vec<E>,
 }
 
 // Methods for creating and verifying blocks.
@@ -94,28 +391,73 @@ impl Block {
 	pub fn genesis() -> Self {
 		Block { header: Header::genesis(), body: vec![] }
 	}
+}
 
-	/// Create and return a valid child block.
-	/// The extrinsics are batched now, so we need to execute each of them.
-	pub fn child(&self, extrinsics: Vec<u64>) -> Self {
-		let state = extrinsics.iter().fold(self.header.state, |acc, extrinsic| acc + extrinsic);
+impl<E: StdHash, S: StdHash + Clone + Eq> Block<E, S> {
+	/// Returns a new valid genesis block committing to the given genesis state. By convention this
+	/// block has no extrinsics either.
+	pub fn genesis_with_state(state: S) -> Self {
+		Block { header: Header::genesis_with_state(state), body: vec![] }
+	}
+
+	/// This block's header.
+	pub fn header(&self) -> &Header<E, S> {
+		&self.header
+	}
+
+	/// The extrinsics in this block.
+	pub fn body(&self) -> &[E] {
+		&self.body
+	}
+
+	/// Create and return a child block, executing the extrinsics with the given STF.
+	///
+	/// The extrinsics must fit in the weight budget of the STF, or the block would be invalid.
+	/// Choosing extrinsics that do is up to the author, see the transaction pool.
+	pub fn child_with<F: Stf<Extrinsic = E, State = S>>(
+		&self,
+		stf: &F,
+		extrinsics: Vec<E>,
+	) -> Result<Self, BuildError<F::Error>> {
+		if stf.total_weight(&extrinsics) > stf.max_block_weight() {
+			return Err(BuildError::Overweight)
+		}
+		let state = stf.apply(&self.header.state, &extrinsics).map_err(BuildError::Invalid)?;
 		let extrinsics_root = hash(&extrinsics);
-		Block { header: self.header.child(extrinsics_root, state), body: extrinsics }
+		Ok(Block { header: self.header.child(extrinsics_root, state), body: extrinsics })
 	}
 
-	/// Verify that all the given blocks form a valid chain from this block to the tip.
+	/// Verify this block as a child of the given parent header, executing the extrinsics with the
+	/// given STF. The header must be a valid child, it must commit to the body through its
+	/// extrinsics root, the body must fit in the weight budget, and executing the body on top of
+	/// the parent's state must succeed and give the state in the header.
+	///
+	/// Without the root check, anybody could swap the body of a block for another one with the
+	/// same total, and the header would still vouch for it.
+	pub fn verify_with<F: Stf<Extrinsic = E, State = S>>(
+		&self,
+		stf: &F,
+		parent: &Header<E, S>,
+	) -> bool {
+		parent.verify_child(&self.header) &&
+			self.header.extrinsics_root == hash(&self.body) &&
+			stf.total_weight(&self.body) <= stf.max_block_weight() &&
+			stf.apply(&parent.state, &self.body)
+				.is_ok_and(|state| state == self.header.state)
+	}
+
+	/// Verify that all the given blocks form a valid chain from this block to the tip, executing
+	/// the extrinsics with the given STF.
 	///
 	/// We need to verify the headers as well as execute all transactions and check the final state.
-	pub fn verify_sub_chain(&self, chain: &[Block]) -> bool {
-		let mut parent: &Block = self;
+	pub fn verify_sub_chain_with<F: Stf<Extrinsic = E, State = S>>(
+		&self,
+		stf: &F,
+		chain: &[Block<E, S>],
+	) -> bool {
+		let mut parent: &Block<E, S> = self;
 		for child in chain {
-			if !parent.header.verify_child(&child.header)
-				|| child
-					.body
-					.iter()
-					.fold(parent.header.state, |state, extrinsic| state + extrinsic)
-					!= child.header.state
-			{
+			if !child.verify_with(stf, &parent.header) {
 				return false;
 			} else {
 				parent = child;
@@ -125,6 +467,32 @@ impl Block {
 	}
 }
 
+// The same methods again for states that know how to execute their own extrinsics, so that the
+// STF doesn't have to be spelled out.
+impl<E: StdHash, S: StdHash + Clone + Eq + Execute<E>> Block<E, S> {
+	/// Create and return a valid child block.
+	/// The extrinsics are batched now, so we need to execute each of them.
+	///
+	/// Panics if the extrinsics can't be executed, for example because they overflow the canonical
+	/// runtime. Use `child_with` to handle that instead.
+	pub fn child(&self, extrinsics: Vec<E>) -> Self {
+		let Ok(block) = self.child_with(&Execution(PhantomData), extrinsics) else {
+			panic!("the extrinsics can't be executed on top of this block")
+		};
+		block
+	}
+
+	/// Verify this block as a child of the given parent header.
+	pub fn verify(&self, parent: &Header<E, S>) -> bool {
+		self.verify_with(&Execution(PhantomData), parent)
+	}
+
+	/// Verify that all the given blocks form a valid chain from this block to the tip.
+	pub fn verify_sub_chain(&self, chain: &[Block<E, S>]) -> bool {
+		self.verify_sub_chain_with(&Execution(PhantomData), chain)
+	}
+}
+
 /// Create an invalid child block of the given block. Although the child block is invalid,
 /// the header should be valid.
 ///
@@ -161,7 +529,7 @@ fn bc_4_child_block_empty() {
 	let b1 = b0.child(vec![]);
 
 	assert_eq!(b1.header.height, 1);
-	assert_eq!(b1.header.parent, hash(&b0.header));
+	assert_eq!(b1.header.parent, hash_header(&b0.header));
 	assert_eq!(b1, Block { header: b1.header.clone(), body: vec![] });
 }
 
@@ -171,7 +539,7 @@ fn bc_4_child_block() {
 	let b1 = b0.child(vec![1, 2, 3, 4, 5]);
 
 	assert_eq!(b1.header.height, 1);
-	assert_eq!(b1.header.parent, hash(&b0.header));
+	assert_eq!(b1.header.parent, hash_header(&b0.header));
 	assert_eq!(b1, Block { header: b1.header.clone(), body: vec![1, 2, 3, 4, 5] });
 }
 
@@ -181,14 +549,14 @@ fn bc_4_child_header() {
 	let h1 = g.child(hash(&[1, 2, 3]), 6);
 
 	assert_eq!(h1.height, 1);
-	assert_eq!(h1.parent, hash(&g));
+	assert_eq!(h1.parent, hash_header(&g));
 	assert_eq!(h1.extrinsics_root, hash(&[1, 2, 3]));
 	assert_eq!(h1.state, 6);
 
 	let h2 = h1.child(hash(&[10, 20]), 36);
 
 	assert_eq!(h2.height, 2);
-	assert_eq!(h2.parent, hash(&h1));
+	assert_eq!(h2.parent, hash_header(&h1));
 	assert_eq!(h2.extrinsics_root, hash(&[10, 20]));
 	assert_eq!(h2.state, 36);
 }
@@ -205,7 +573,14 @@ fn bc_4_verify_three_blocks() {
 #[test]
 fn bc_4_invalid_header_does_not_check() {
 	let g = Header::genesis();
-	let h1 = Header { parent: 0, height: 100, extrinsics_root: 0, state: 100, consensus_digest: 0 };
+	let h1 = Header {
+		parent: 0,
+		height: 100,
+		extrinsics_root: 0,
+		state: 100,
+		consensus_digest: 0,
+		extrinsics: PhantomData,
+	};
 
 	assert!(!g.verify_child(&h1));
 }
@@ -242,3 +617,98 @@ fn bc_4_student_invalid_block_really_is_invalid() {
 	// Make sure that the block is not valid when executed.
 	assert!(!gb.verify_sub_chain(&[b1]));
 }
+
+#[test]
+fn bc_4_block_commits_to_its_body() {
+	let b0 = Block::genesis();
+	let b1 = b0.child(vec![1, 2, 3]);
+	assert!(b1.verify(&b0.header));
+
+	// The same total, but not the extrinsics the header committed to.
+	let swapped = Block { body: vec![3, 2, 1], ..b1.clone() };
+	assert!(!swapped.verify(&b0.header));
+	assert!(!b0.verify_sub_chain(&[swapped]));
+
+	// Nor may a block claim any other parent.
+	assert!(!b1.child(vec![]).verify(&b0.header));
+}
+
+#[test]
+fn bc_4_signed_extrinsics() {
+	let alice = SchnorrPair::from_seed(1);
+	let g = Block::<SignedExtrinsic<u64>, u64>::genesis_with_state(0);
+	let stf = Signed { genesis: g.header.hash(), inner: Adder };
+
+	let b1 = g.child_with(&stf, vec![SignedExtrinsic::new(&alice, stf.genesis, 5)]).unwrap();
+	assert_eq!(b1.header.state, 5);
+	assert!(b1.verify_with(&stf, &g.header));
+	assert!(g.verify_sub_chain_with(&stf, std::slice::from_ref(&b1)));
+
+	// Nobody else can change what Alice signed.
+	let mut tampered = b1.body[0].clone();
+	tampered.payload = 50;
+	assert_eq!(
+		g.child_with(&stf, vec![tampered.clone()]),
+		Err(BuildError::Invalid(SignedError::BadSignature))
+	);
+	let body = vec![tampered];
+	let forged = Block { header: g.header.child(hash(&body), 50), body };
+	assert!(!forged.verify_with(&stf, &g.header));
+	assert!(!g.verify_sub_chain_with(&stf, &[forged]));
+}
+
+#[test]
+fn bc_4_signed_extrinsics_cannot_be_replayed_on_other_chains() {
+	let alice = SchnorrPair::from_seed(1);
+	let g = Block::<SignedExtrinsic<u64>, u64>::genesis_with_state(0);
+	let other = Block::<SignedExtrinsic<u64>, u64>::genesis_with_state(100);
+	let stf = Signed { genesis: other.header.hash(), inner: Adder };
+	let extrinsic = SignedExtrinsic::new(&alice, g.header.hash(), 5);
+
+	assert_eq!(
+		other.child_with(&stf, vec![extrinsic.clone()]),
+		Err(BuildError::Invalid(SignedError::BadSignature))
+	);
+	let body = vec![extrinsic];
+	let replayed = Block { header: other.header.child(hash(&body), 105), body };
+	assert!(!replayed.verify_with(&stf, &other.header));
+}
+
+#[test]
+fn bc_4_adder_stf() {
+	let b0 = Block::genesis();
+	let b1 = b0.child_with(&Adder, vec![1, 2, 3]).unwrap();
+
+	assert_eq!(b1.header.state, 6);
+	assert!(b0.verify_sub_chain_with(&Adder, std::slice::from_ref(&b1)));
+	assert!(b0.verify_sub_chain(&[b1]));
+
+	// Blocks that overflow can't be built, and don't check out whatever state they claim.
+	let body = vec![u64::MAX, 1];
+	assert_eq!(b0.child_with(&Adder, body.clone()), Err(BuildError::Invalid(Overflow)));
+	let wrapped = Block { header: b0.header.child(hash(&body), 0), body };
+	assert!(!wrapped.verify_with(&Adder, &b0.header));
+	// The canonical runtime rejects them too, rather than panicking or wrapping around.
+	assert!(!wrapped.verify(&b0.header));
+	assert!(!b0.verify_sub_chain(&[wrapped]));
+}
+
+#[test]
+#[should_panic]
+fn bc_4_canonical_child_refuses_to_overflow() {
+	Block::genesis().child(vec![u64::MAX, 1]);
+}
+
+#[test]
+fn bc_4_weight_budget_is_chosen_at_launch() {
+	let b0 = Block::genesis();
+	let small = Budgeted { inner: Adder, max_block_weight: 2 };
+	let b1 = b0.child_with(&small, vec![1, 2]).unwrap();
+	assert!(b1.verify_with(&small, &b0.header));
+
+	// The same extrinsics fit one chain's blocks but not another's.
+	assert_eq!(b0.child_with(&small, vec![1, 2, 3]), Err(BuildError::Overweight));
+	let heavy = b0.child_with(&Adder, vec![1, 2, 3]).unwrap();
+	assert!(heavy.verify_with(&Adder, &b0.header));
+	assert!(!heavy.verify_with(&small, &b0.header));
+}
@@ -7,7 +7,7 @@
 //! Since we have nothing to add to the Block or Header data structures in this lesson,
 //! we will import them from the previous lesson.
 #[allow(unused, unused_variables)]
-use crate::hash;
+use crate::{hash, hash_header};
 use super::p4_batched_extrinsics::{Block, Header};
 
 const THRESHOLD: u64 = u64::max_value() / 100;
@@ -67,6 +67,9 @@ pub struct HeaviestChainRule;
 /// This will be useful for exploring the heaviest chain rule. The expected
 /// usage is that you create a block using the normal `Block.child()` method
 /// and then pass the block to this helper for additional mining.
+///
+/// The proof of work is measured against `crate::hash_header` of the header, not `crate::hash`,
+/// just like the parent links, so mine against that.
 fn mine_extra_hard(block: &mut Block, threshold: u64) {
 	todo!("Exercise 4")
 }
@@ -113,12 +116,42 @@ impl ForkChoice for MostBlocksWithEvenHash {
 //
 // I've omitted GHOST from here because it requires information about blocks that
 // are _not_ in the chain to decide which chain is best. Therefore it does't work
-// well with this relatively simple trait definition. We will return to the GHOST
-// rule later when we have written a full blockchain client
+// well with this relatively simple trait definition. The block tree module returns
+// to the GHOST rule, with every known block at hand.
 //
 // The GHOST rule was first published in 2013 by Yonatan Sompolinsky and Aviv Zohar.
 // Learn more at https://eprint.iacr.org/2013/881.pdf
 
+/// A safeguard that some nodes layer on top of their fork choice rule: never abandon more than
+/// `max_depth` blocks of the chain currently considered best, no matter how much better the
+/// alternative looks. This is sometimes sold as "finality by depth".
+///
+/// It is a tempting idea, but it is also dangerous. The rule depends on which chain a node
+/// happened to see first, and on a number each node operator picks for themselves. Two honest
+/// nodes with different `max_depth` values can look at exactly the same blocks and permanently
+/// disagree about which chain is real. See the `bc_5_differing_max_reorg_depths_split` test.
+pub struct MaxReorgDepth {
+	/// The deepest reorg this node is willing to perform.
+	pub max_depth: u64,
+}
+
+impl MaxReorgDepth {
+	/// How many blocks of the `current` chain would be retracted by switching to `candidate`.
+	/// That is the number of blocks in `current` that are not also part of `candidate`.
+	pub fn reorg_depth(current: &[Header], candidate: &[Header]) -> u64 {
+		let candidate_hashes: Vec<u64> = candidate.iter().map(hash_header).collect();
+		current.iter().filter(|h| !candidate_hashes.contains(&hash_header(h))).count() as u64
+	}
+
+	/// Decide whether a node that currently considers `current` best should switch to
+	/// `candidate`. The candidate must be better according to the fork choice rule `F` AND
+	/// switching must not retract more than `max_depth` blocks.
+	pub fn should_switch<F: ForkChoice>(&self, current: &[Header], candidate: &[Header]) -> bool {
+		Self::reorg_depth(current, candidate) <= self.max_depth &&
+			F::first_chain_is_better(candidate, current)
+	}
+}
+
 //
 
 /// Build and return two different chains with a common prefix.
@@ -161,7 +194,7 @@ fn bc_5_mine_to_custom_difficulty() {
 	let custom_threshold = u64::max_value() / 1000;
 	mine_extra_hard(&mut b1, custom_threshold);
 
-	assert!(hash(&b1.header) < custom_threshold);
+	assert!(hash_header(&b1.header) < custom_threshold);
 }
 
 #[test]
@@ -172,7 +205,7 @@ fn bc_5_heaviest_chain() {
 	let h_a1 = loop {
 		let header = g.child(hash(&[i]), i);
 		// Extrinsics root hash must be higher than threshold (less work done)
-		if hash(&header) > THRESHOLD {
+		if hash_header(&header) > THRESHOLD {
 			break header
 		}
 		i += 1;
@@ -182,7 +215,7 @@ fn bc_5_heaviest_chain() {
 	let h_b1 = loop {
 		let header = g.child(hash(&[i]), i);
 		// Extrinsics root hash must be lower than threshold (more work done)
-		if hash(&header) < THRESHOLD {
+		if hash_header(&header) < THRESHOLD {
 			break header
 		}
 		i += 1;
@@ -201,14 +234,14 @@ fn bc_5_most_even_blocks() {
 	let mut h_a1 = g.child(2, 0);
 	for i in 0..u64::max_value() {
 		h_a1 = g.child(2, i);
-		if hash(&h_a1) % 2 == 0 {
+		if hash_header(&h_a1) % 2 == 0 {
 			break
 		}
 	}
 	let mut h_a2 = g.child(2, 0);
 	for i in 0..u64::max_value() {
 		h_a2 = h_a1.child(2, i);
-		if hash(&h_a2) % 2 == 0 {
+		if hash_header(&h_a2) % 2 == 0 {
 			break
 		}
 	}
@@ -217,14 +250,14 @@ fn bc_5_most_even_blocks() {
 	let mut h_b1 = g.child(2, 0);
 	for i in 0..u64::max_value() {
 		h_b1 = g.child(2, i);
-		if hash(&h_b1) % 2 != 0 {
+		if hash_header(&h_b1) % 2 != 0 {
 			break
 		}
 	}
 	let mut h_b2 = g.child(2, 0);
 	for i in 0..u64::max_value() {
 		h_b2 = h_b1.child(2, i);
-		if hash(&h_b2) % 2 != 0 {
+		if hash_header(&h_b2) % 2 != 0 {
 			break
 		}
 	}
@@ -249,3 +282,59 @@ fn bc_5_longest_vs_heaviest() {
 
 	assert_eq!(HeaviestChainRule::best_chain(&[&longest_chain, &pow_chain]), &pow_chain);
 }
+
+#[test]
+fn bc_5_reorg_depth() {
+	let g = Header::genesis();
+	let a1 = g.child(hash(&[1]), 1);
+	let a2 = a1.child(hash(&[2]), 2);
+	let a3 = a2.child(hash(&[3]), 3);
+	let b2 = a1.child(hash(&[4]), 4);
+
+	let current = [g.clone(), a1.clone(), a2, a3];
+	let candidate = [g, a1, b2];
+
+	assert_eq!(MaxReorgDepth::reorg_depth(&current, &candidate), 2);
+	assert_eq!(MaxReorgDepth::reorg_depth(&candidate, &current), 1);
+	assert_eq!(MaxReorgDepth::reorg_depth(&current, &current), 0);
+}
+
+#[test]
+fn bc_5_differing_max_reorg_depths_split() {
+	// For this scenario we judge chains by their length alone, independent of the exercises above.
+	struct Length;
+	impl ForkChoice for Length {
+		fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
+			chain_1.len() > chain_2.len()
+		}
+	}
+
+	// Two honest nodes that differ only in their configured max reorg depth.
+	let cautious = MaxReorgDepth { max_depth: 2 };
+	let relaxed = MaxReorgDepth { max_depth: 5 };
+
+	// Both nodes follow the same chain for a while. Then, after a network partition heals, a
+	// longer chain that forked off three blocks back shows up.
+	let g = Header::genesis();
+	let mut a = vec![g.clone(), g.child(hash(&[1]), 1)];
+	let mut b = a.clone();
+	for i in 0..3 {
+		a.push(a.last().unwrap().child(hash(&[10 + i]), 10 + i));
+	}
+	for i in 0..5 {
+		b.push(b.last().unwrap().child(hash(&[20 + i]), 20 + i));
+	}
+
+	assert!(!cautious.should_switch::<Length>(&a, &b));
+	assert!(relaxed.should_switch::<Length>(&a, &b));
+
+	// From here on the two nodes keep building on different chains. No matter how far ahead the
+	// relaxed node's chain gets, the cautious node will never reorg to it. The split is permanent.
+	let cautious_chain = a;
+	let relaxed_chain = &mut b;
+	for i in 0..10 {
+		relaxed_chain.push(relaxed_chain.last().unwrap().child(hash(&[30 + i]), 30 + i));
+		assert!(!cautious.should_switch::<Length>(&cautious_chain, relaxed_chain));
+		assert!(!relaxed.should_switch::<Length>(relaxed_chain, &cautious_chain));
+	}
+}
@@ -13,7 +13,7 @@
 //! This notion of state may sound familiar from our previous work on state machines. Indeed this
 //! naming coincidence foreshadows a key abstraction that we will make in a coming chapter.
 #[allow(unused, unused_variables)]
-use crate::hash;
+use crate::{hash, hash_header};
 type Hash = u64;
 
 /// In this section we will use sum and product together to be our state. While this is only a
@@ -25,6 +25,13 @@ pub struct State {
 	product: u64,
 }
 
+impl State {
+	/// A state with the given sum and product.
+	pub fn new(sum: u64, product: u64) -> Self {
+		State { sum, product }
+	}
+}
+
 /// The header no longer contains the state directly, but rather, it contains a hash of
 /// the complete state. This hash will allow block verifiers to cryptographically confirm
 /// that they got the same state as the author without having a complete copy of the
@@ -55,11 +62,17 @@ impl Header {
 	/// Create and return a valid child header.
 	///
 	/// The state root is passed in similarly to how the complete state
-	/// was in the previous section.
+	/// was in the previous section. The parent link is `crate::hash_header` of this header, not
+	/// `crate::hash`, because that is what the tests and the other lessons check against.
 	fn child(&self, extrinsics_root: Hash, state_root: Hash) -> Self {
 		todo!("Exercise 2")
 	}
 
+	/// The commitment to the complete post state of this block.
+	pub fn state_root(&self) -> Hash {
+		self.state_root
+	}
+
 	/// Verify a single child header.
 	fn verify_child(&self, child: &Header) -> bool {
 		todo!("Exercise 3")
@@ -151,7 +164,7 @@ fn bc_6_child_block_empty() {
 	let b1 = b0.child(&state, vec![]);
 
 	assert_eq!(b1.header.height, 1);
-	assert_eq!(b1.header.parent, hash(&b0.header));
+	assert_eq!(b1.header.parent, hash_header(&b0.header));
 	assert_eq!(b1, Block { header: b1.header.clone(), body: vec![] });
 }
 
@@ -162,7 +175,7 @@ fn bc_6_child_block() {
 	let b1 = b0.child(&state, vec![1, 2, 3, 4, 5]);
 
 	assert_eq!(b1.header.height, 1);
-	assert_eq!(b1.header.parent, hash(&b0.header));
+	assert_eq!(b1.header.parent, hash_header(&b0.header));
 	assert_eq!(b1, Block { header: b1.header.clone(), body: vec![1, 2, 3, 4, 5] });
 }
 
@@ -179,7 +192,7 @@ fn bc_6_child_header() {
 	let h1 = g.child(hash(&extrinsics), hash(&state_1));
 
 	assert_eq!(h1.height, 1);
-	assert_eq!(h1.parent, hash(&g));
+	assert_eq!(h1.parent, hash_header(&g));
 	assert_eq!(h1.extrinsics_root, hash(&extrinsics));
 	assert_eq!(h1.state_root, hash(&state_1));
 
@@ -193,7 +206,7 @@ fn bc_6_child_header() {
 	let h2 = h1.child(hash(&extrinsics), hash(&state_2));
 
 	assert_eq!(h2.height, 2);
-	assert_eq!(h2.parent, hash(&h1));
+	assert_eq!(h2.parent, hash_header(&h1));
 	assert_eq!(h2.extrinsics_root, hash(&extrinsics));
 	assert_eq!(h2.state_root, hash(&state_2));
 }
@@ -259,3 +272,12 @@ fn bc_6_student_invalid_block_really_is_invalid() {
 	// Make sure that the block is not valid when executed.
 	assert!(!gb.verify_sub_chain(&state, &[b1]));
 }
+
+#[test]
+fn bc_6_chain_matches_the_replay() {
+	use super::replay::Replay;
+
+	let replay = Replay::parse(include_str!("../../replays/sum_and_product.replay")).unwrap();
+
+	assert!(replay.grade());
+}
@@ -0,0 +1,312 @@
+//! In the first chapter we built state machines, and in this one we built blockchains whose state
+//! is updated by executing extrinsics. Those are the same idea. The state transition function of a
+//! blockchain is just a state machine, and the extrinsics are its transitions.
+//!
+//! This module makes that connection concrete. `StateMachineRuntime` plugs any of the machines from
+//! the first chapter into the batched-extrinsics blocks from part 4. The extrinsics in each block
+//! are the machine's transitions, and the `state` in each header is the hash of the machine's
+//! state, much like the state root from part 6.
+//!
+//! The currencies from the first chapter go one step further. Their states implement `Execute`,
+//! so they can be stored in the headers of the part 4 blocks directly, and their transfers can wait
+//! in a transaction pool like any other extrinsic.
+//!
+//! The connection works the other way too. `StfMachine` turns any STF back into a state machine
+//! whose transitions are single extrinsics, so runtimes get the tooling from the first chapter,
+//! like traces, model checking and state graphs.
+
+use super::{
+	p4_batched_extrinsics::{Execute, Header, Stf},
+	transaction_pool::PoolExtrinsic,
+};
+use crate::{
+	c1_state_machine::{
+		p4_accounted_currency::{AccountedCurrency, AccountingTransaction, Ledger},
+		p5_digital_cash::{CashTransaction, DigitalCashSystem, State},
+		EnumerableTransitions, StateMachine, TryStateMachine,
+	},
+	hash,
+};
+use std::{convert::Infallible, hash::Hash as StdHash, marker::PhantomData};
+type Hash = u64;
+
+/// Executes blocks of transitions with the state machine `SM`.
+pub struct StateMachineRuntime<SM>(PhantomData<SM>);
+
+impl<SM: StateMachine> StateMachineRuntime<SM>
+where
+	SM::State: Clone + StdHash,
+{
+	/// Execute the given extrinsics on top of the pre-state, and return the post-state.
+	pub fn execute(pre_state: &SM::State, extrinsics: &[SM::Transition]) -> SM::State {
+		SM::apply_all(pre_state, extrinsics)
+	}
+
+	/// The commitment to the given state that is stored in headers.
+	pub fn state_root(state: &SM::State) -> Hash {
+		hash(state)
+	}
+}
+
+/// A complete block whose extrinsics are transitions of the state machine `SM`.
+pub struct Block<SM: StateMachine> {
+	pub header: Header,
+	pub body: Vec<SM::Transition>,
+}
+
+/// Methods for creating and verifying blocks.
+///
+/// As in part 6, the state is not stored in the blocks, so a pre-state is passed explicitly.
+impl<SM: StateMachine> Block<SM>
+where
+	SM::State: Clone + StdHash,
+	SM::Transition: StdHash,
+{
+	/// Returns a new valid genesis block committing to the given genesis state. By convention this
+	/// block has no extrinsics.
+	pub fn genesis(genesis_state: &SM::State) -> Self {
+		Block {
+			header: Header::genesis_with_state(StateMachineRuntime::<SM>::state_root(
+				genesis_state,
+			)),
+			body: vec![],
+		}
+	}
+
+	/// Create and return a valid child block.
+	pub fn child(&self, pre_state: &SM::State, extrinsics: Vec<SM::Transition>) -> Self {
+		let post_state = StateMachineRuntime::<SM>::execute(pre_state, &extrinsics);
+		Block {
+			header: self
+				.header
+				.child(hash(&extrinsics), StateMachineRuntime::<SM>::state_root(&post_state)),
+			body: extrinsics,
+		}
+	}
+
+	/// Verify that all the given blocks form a valid chain from this block to the tip, starting
+	/// from the given pre-state.
+	pub fn verify_sub_chain(&self, pre_state: &SM::State, chain: &[Block<SM>]) -> bool {
+		if StateMachineRuntime::<SM>::state_root(pre_state) != self.header.state() {
+			return false
+		}
+
+		let mut parent = self;
+		let mut state = pre_state.clone();
+		for child in chain {
+			state = StateMachineRuntime::<SM>::execute(&state, &child.body);
+			if !parent.header.verify_child(&child.header) ||
+				child.header.extrinsics_root() != hash(&child.body) ||
+				child.header.state() != StateMachineRuntime::<SM>::state_root(&state)
+			{
+				return false
+			}
+			parent = child;
+		}
+		true
+	}
+}
+
+/// The STF `F` seen as a state machine. Each transition executes a single extrinsic, and an
+/// extrinsic the STF rejects leaves the state as it was.
+///
+/// State machines aren't values, so the STF is made fresh from its default for every transition.
+pub struct StfMachine<F>(PhantomData<F>);
+
+impl<F: Stf + Default> StateMachine for StfMachine<F>
+where
+	F::State: Clone,
+{
+	type State = F::State;
+	type Transition = F::Extrinsic;
+
+	fn next_state(starting_state: &F::State, t: &F::Extrinsic) -> F::State {
+		Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+	}
+}
+
+impl<F: Stf + Default> TryStateMachine for StfMachine<F>
+where
+	F::State: Clone,
+{
+	type Error = F::Error;
+
+	fn try_next_state(starting_state: &F::State, t: &F::Extrinsic) -> Result<F::State, F::Error> {
+		F::default().apply(starting_state, std::slice::from_ref(t))
+	}
+}
+
+/// An STF whose extrinsics can be listed, like the transitions of an `EnumerableTransitions`
+/// machine. Those that carry arbitrary data may list a representative sample instead.
+pub trait EnumerableExtrinsics: Stf {
+	/// Every extrinsic this STF can execute
+	fn extrinsics() -> Vec<Self::Extrinsic>;
+}
+
+impl<F: EnumerableExtrinsics + Default> EnumerableTransitions for StfMachine<F>
+where
+	F::State: Clone,
+{
+	fn transitions() -> Vec<F::Extrinsic> {
+		F::extrinsics()
+	}
+}
+
+/// An accounted currency can be the runtime of a blockchain, with transactions as extrinsics.
+impl Execute<AccountingTransaction> for Ledger {
+	type Error = Infallible;
+
+	fn execute(&self, extrinsic: &AccountingTransaction) -> Result<Ledger, Infallible> {
+		Ok(AccountedCurrency::next_state(self, extrinsic))
+	}
+}
+
+/// Transfers are prioritised by their fees. Two transfers from the same sender with the same nonce
+/// can't both be included, so only one of them may wait in a transaction pool.
+impl PoolExtrinsic for AccountingTransaction {
+	fn priority(&self) -> u64 {
+		match self {
+			AccountingTransaction::Transfer { fee, .. } => *fee,
+			_ => 0,
+		}
+	}
+
+	fn replacement_tag(&self) -> Option<u64> {
+		match self {
+			AccountingTransaction::Transfer { sender, nonce, .. } =>
+				Some(crate::hash(&(sender, nonce))),
+			_ => None,
+		}
+	}
+}
+
+/// A digital cash system can be the runtime of a blockchain, with transactions as extrinsics.
+impl Execute<CashTransaction> for State {
+	type Error = Infallible;
+
+	fn execute(&self, extrinsic: &CashTransaction) -> Result<State, Infallible> {
+		Ok(DigitalCashSystem::next_state(self, extrinsic))
+	}
+}
+
+#[test]
+fn bc_runtime_genesis_commits_to_state() {
+	use crate::c1_state_machine::p1_switches::LightSwitch;
+
+	let g = Block::<LightSwitch>::genesis(&true);
+
+	assert_eq!(g.header.height(), 0);
+	assert_eq!(g.header.state(), hash(&true));
+	assert!(g.body.is_empty());
+}
+
+#[test]
+fn bc_runtime_builds_valid_chain() {
+	use crate::c1_state_machine::p2_laundry_machine::{
+		ClothesAction::*, ClothesMachine, ClothesState,
+	};
+
+	let genesis_state = ClothesState::Clean(10);
+	let g = Block::<ClothesMachine>::genesis(&genesis_state);
+	let b1 = g.child(&genesis_state, vec![Wear, Wear]);
+	let s1 = ClothesState::Dirty(8);
+	let b2 = b1.child(&s1, vec![Wash, Dry]);
+
+	assert_eq!(b2.header.state(), hash(&ClothesState::Clean(6)));
+	assert!(g.verify_sub_chain(&genesis_state, &[b1, b2]));
+}
+
+#[test]
+fn bc_runtime_rejects_wrong_pre_state() {
+	use crate::c1_state_machine::p1_switches::LightSwitch;
+
+	let g = Block::<LightSwitch>::genesis(&false);
+	let b1 = g.child(&false, vec![(), ()]);
+
+	assert!(!g.verify_sub_chain(&true, &[b1]));
+}
+
+#[test]
+fn bc_runtime_rejects_wrong_state_and_extrinsics() {
+	use crate::c1_state_machine::p1_switches::{Toggle, TwoSwitches, WeirdSwitchMachine};
+
+	let genesis_state = TwoSwitches::default();
+	let g = Block::<WeirdSwitchMachine>::genesis(&genesis_state);
+
+	// Claims to toggle the second switch, but commits to the state after toggling the first.
+	let wrong_state = Block::<WeirdSwitchMachine> {
+		header: g.header.child(
+			hash(&vec![Toggle::SecondSwitch]),
+			hash(&WeirdSwitchMachine::next_state(&genesis_state, &Toggle::FirstSwitch)),
+		),
+		body: vec![Toggle::SecondSwitch],
+	};
+	assert!(!g.verify_sub_chain(&genesis_state, &[wrong_state]));
+
+	// A valid header, but the body was swapped out afterwards.
+	let mut swapped_body = g.child(&genesis_state, vec![Toggle::FirstSwitch]);
+	swapped_body.body = vec![Toggle::SecondSwitch];
+	assert!(!g.verify_sub_chain(&genesis_state, &[swapped_body]));
+}
+
+#[test]
+fn bc_runtime_transfers_are_pooled_by_fee_and_nonce() {
+	use crate::c1_state_machine::User;
+
+	let cheap = AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 1, 0);
+	let bumped = AccountingTransaction::signed_transfer(User::Alice, User::Charlie, 10, 5, 0);
+	let next = AccountingTransaction::signed_transfer(User::Alice, User::Bob, 10, 1, 1);
+	let mint = AccountingTransaction::Mint { minter: User::Alice, amount: 10 };
+
+	assert_eq!((cheap.priority(), bumped.priority(), mint.priority()), (1, 5, 0));
+	assert_eq!(cheap.replacement_tag(), bumped.replacement_tag());
+	assert_ne!(cheap.replacement_tag(), next.replacement_tag());
+	assert_eq!(mint.replacement_tag(), None);
+}
+
+/// A clock face with five hours. Each extrinsic moves the hand forward by so many hours.
+#[cfg(test)]
+#[derive(Default)]
+struct ClockFace;
+
+#[cfg(test)]
+impl Stf for ClockFace {
+	type Extrinsic = u8;
+	type State = u8;
+	type Error = ();
+
+	fn apply(&self, pre_state: &u8, extrinsics: &[u8]) -> Result<u8, ()> {
+		extrinsics.iter().try_fold(*pre_state, |hour, hours| match hours {
+			1..=4 => Ok((hour + hours) % 5),
+			_ => Err(()),
+		})
+	}
+}
+
+#[cfg(test)]
+impl EnumerableExtrinsics for ClockFace {
+	fn extrinsics() -> Vec<u8> {
+		vec![2, 3]
+	}
+}
+
+#[test]
+fn bc_runtime_stf_as_state_machine() {
+	// The extrinsic that would move the hand by seven hours is rejected, so nothing happens.
+	assert_eq!(StfMachine::<ClockFace>::trace(&0, &[2, 7, 4]), vec![0, 2, 2, 1]);
+	assert_eq!(StfMachine::<ClockFace>::try_next_state(&2, &7), Err(()));
+}
+
+#[test]
+fn bc_runtime_stf_gets_chapter_1_tooling() {
+	use crate::c1_state_machine::{model_checker, state_graph};
+
+	assert_eq!(model_checker::check::<StfMachine<ClockFace>>(&0, 4, |hour| *hour < 5), Ok(5));
+	let counterexample =
+		model_checker::check::<StfMachine<ClockFace>>(&0, 4, |hour| *hour != 1).unwrap_err();
+	assert_eq!(counterexample.states.last(), Some(&1));
+
+	let graph = state_graph::explore::<StfMachine<ClockFace>>(&0, 10);
+	assert_eq!(graph.states().len(), 5);
+	assert_eq!(graph.edges().len(), 10);
+}
@@ -0,0 +1,383 @@
+//! Users don't author blocks themselves. They hand their extrinsics to a node, which keeps them in
+//! its transaction pool until it gets to author a block. Then it fills the block from the pool.
+//!
+//! Not every extrinsic that is submitted can go into a block. Some are invalid, like transfers
+//! that spend more than the sender has, and those are turned away right away. Others are only
+//! valid after some other extrinsic, or stop being valid once another one is included. So the pool
+//! checks each extrinsic against the state it will actually be executed on, both when it is
+//! submitted and again when a block is authored.
+//!
+//! There is usually more on offer than fits in the weight budget of a block, so the pool puts the
+//! extrinsics that pay the most first, and stops once the block is full.
+//!
+//! A node runs for a long time, and extrinsics keep coming whether or not they ever make it into
+//! a block. So the pool only holds so many. Once it is full, a new extrinsic has to pay more than
+//! the cheapest one waiting, which is then evicted. Likewise, a sender who wants to change an
+//! extrinsic that is still waiting, say because it pays too little to ever be included, may
+//! replace it, but only by paying more. Otherwise anyone could keep the pool busy for free.
+
+use super::p4_batched_extrinsics::{Block, Stf};
+use std::{cmp::Reverse, hash::Hash as StdHash};
+type Hash = u64;
+
+/// How many extrinsics a pool holds unless told otherwise
+pub const DEFAULT_POOL_LIMIT: usize = 1024;
+
+/// What the pool needs to know about an extrinsic
+pub trait PoolExtrinsic {
+	/// How eager the author should be to include this extrinsic. Usually the fee it pays.
+	fn priority(&self) -> u64;
+
+	/// Extrinsics with the same tag can't both be included, so only one of them may wait in the
+	/// pool. The tag is usually made from the sender and their nonce. Extrinsics without a tag
+	/// never replace each other.
+	fn replacement_tag(&self) -> Option<Hash> {
+		None
+	}
+}
+
+/// The reasons the pool may turn an extrinsic away
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PoolError<E> {
+	/// The very same extrinsic is already waiting in the pool.
+	AlreadyPooled,
+	/// The extrinsic can't be executed on top of the best state.
+	Invalid(E),
+	/// An extrinsic with the same replacement tag is waiting, and this one doesn't pay more.
+	Underpriced,
+	/// The pool is full, and this extrinsic doesn't pay more than any of the ones waiting.
+	PoolFull,
+	/// The extrinsic weighs more than a whole block may, so it could never be included.
+	Overweight,
+}
+
+/// Extrinsics waiting to be included in a block, executed with the STF `F`
+pub struct TransactionPool<F: Stf> {
+	/// The STF the extrinsics are executed with
+	stf: F,
+	/// The waiting extrinsics, in the order they were submitted
+	pending: Vec<F::Extrinsic>,
+	/// How many extrinsics may wait at once
+	limit: usize,
+}
+
+impl<F: Stf> TransactionPool<F>
+where
+	F::Extrinsic: PoolExtrinsic + Clone + PartialEq,
+	F::State: Clone,
+{
+	/// An empty pool for the given STF that holds up to `DEFAULT_POOL_LIMIT` extrinsics
+	pub fn new(stf: F) -> Self {
+		Self::with_limit(stf, DEFAULT_POOL_LIMIT)
+	}
+
+	/// An empty pool for the given STF that holds up to the given number of extrinsics
+	pub fn with_limit(stf: F, limit: usize) -> Self {
+		TransactionPool { stf, pending: Vec::new(), limit }
+	}
+
+	/// How many extrinsics are waiting
+	pub fn len(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Whether no extrinsics are waiting
+	pub fn is_empty(&self) -> bool {
+		self.pending.is_empty()
+	}
+
+	/// The waiting extrinsics, in the order they were submitted
+	pub fn pending(&self) -> &[F::Extrinsic] {
+		&self.pending
+	}
+
+	/// Add the given extrinsic to the pool if it can be executed on top of the best state.
+	///
+	/// If an extrinsic with the same replacement tag is waiting, the new one replaces it as long
+	/// as it pays more. If the pool is full, the new one evicts the lowest priority extrinsic as
+	/// long as it pays more. Of several extrinsics with the lowest priority, the one submitted
+	/// last is evicted.
+	pub fn submit(
+		&mut self,
+		extrinsic: F::Extrinsic,
+		best_state: &F::State,
+	) -> Result<(), PoolError<F::Error>> {
+		if self.pending.contains(&extrinsic) {
+			return Err(PoolError::AlreadyPooled)
+		}
+		if self.stf.weight(&extrinsic) > self.stf.max_block_weight() {
+			return Err(PoolError::Overweight)
+		}
+		self.stf
+			.apply(best_state, std::slice::from_ref(&extrinsic))
+			.map_err(PoolError::Invalid)?;
+
+		let priority = extrinsic.priority();
+		let replaced = extrinsic.replacement_tag().and_then(|tag| {
+			self.pending.iter().position(|pending| pending.replacement_tag() == Some(tag))
+		});
+		if let Some(index) = replaced {
+			if self.pending[index].priority() >= priority {
+				return Err(PoolError::Underpriced)
+			}
+			self.pending.remove(index);
+		} else if self.pending.len() >= self.limit {
+			let lowest = self
+				.pending
+				.iter()
+				.enumerate()
+				.min_by_key(|(index, pending)| (pending.priority(), Reverse(*index)))
+				.map(|(index, pending)| (index, pending.priority()));
+			match lowest {
+				Some((index, lowest)) if lowest < priority => {
+					self.pending.remove(index);
+				},
+				_ => return Err(PoolError::PoolFull),
+			}
+		}
+		self.pending.push(extrinsic);
+		Ok(())
+	}
+
+	/// The extrinsics to include in a block on top of the given state, highest priority first.
+	/// Extrinsics of equal priority stay in the order they were submitted.
+	///
+	/// Each extrinsic is executed on top of the ones before it, and those that fail are left out,
+	/// so the whole batch is sure to execute. So are those that don't fit in what is left of the
+	/// weight budget, though lighter ones after them may still fit.
+	pub fn ready(&self, state: &F::State) -> Vec<F::Extrinsic> {
+		let mut candidates: Vec<&F::Extrinsic> = self.pending.iter().collect();
+		candidates.sort_by_key(|extrinsic| Reverse(extrinsic.priority()));
+
+		let mut state = state.clone();
+		let mut batch = Vec::new();
+		let mut room = self.stf.max_block_weight();
+		for extrinsic in candidates {
+			let weight = self.stf.weight(extrinsic);
+			if weight > room {
+				continue
+			}
+			if let Ok(next) = self.stf.apply(&state, std::slice::from_ref(extrinsic)) {
+				state = next;
+				room -= weight;
+				batch.push(extrinsic.clone());
+			}
+		}
+		batch
+	}
+
+	/// Forget the given extrinsics, usually because they were included in a block.
+	pub fn remove(&mut self, included: &[F::Extrinsic]) {
+		self.pending.retain(|extrinsic| !included.contains(extrinsic));
+	}
+
+	/// Forget every extrinsic that can no longer be executed on top of the new best state.
+	pub fn revalidate(&mut self, best_state: &F::State) {
+		let stf = &self.stf;
+		self.pending
+			.retain(|extrinsic| stf.apply(best_state, std::slice::from_ref(extrinsic)).is_ok());
+	}
+
+	/// Author a child of the given block from the ready extrinsics, and remove them from the
+	/// pool.
+	pub fn author<E, S>(&mut self, parent: &Block<E, S>) -> Block<E, S>
+	where
+		F: Stf<Extrinsic = E, State = S>,
+		E: StdHash,
+		S: StdHash + Clone + Eq,
+	{
+		let batch = self.ready(&parent.header().state());
+		self.remove(&batch);
+		let Ok(block) = parent.child_with(&self.stf, batch) else {
+			unreachable!(
+				"the batch fits in the budget and every extrinsic in it was executed successfully"
+			)
+		};
+		block
+	}
+}
+
+/// A payment out of a single wallet, along with the fee it offers the author. The pool tests, and
+/// other tests that need a pool, bring their own runtime, whose state is the balance of the wallet,
+/// and in which each payment weighs as much as it pays.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Payment {
+	pub(crate) amount: u64,
+	pub(crate) fee: u64,
+	pub(crate) nonce: Option<u64>,
+}
+
+#[cfg(test)]
+impl PoolExtrinsic for Payment {
+	fn priority(&self) -> u64 {
+		self.fee
+	}
+
+	fn replacement_tag(&self) -> Option<Hash> {
+		self.nonce
+	}
+}
+
+#[cfg(test)]
+pub(crate) struct Wallet;
+
+/// The wallet can't pay for a payment and its fee.
+#[cfg(test)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Insufficient;
+
+#[cfg(test)]
+impl Stf for Wallet {
+	type Extrinsic = Payment;
+	type State = u64;
+	type Error = Insufficient;
+
+	fn max_block_weight(&self) -> u64 {
+		10
+	}
+
+	fn apply(&self, balance: &u64, payments: &[Payment]) -> Result<u64, Insufficient> {
+		payments.iter().try_fold(*balance, |balance, payment| {
+			balance.checked_sub(payment.amount + payment.fee).ok_or(Insufficient)
+		})
+	}
+
+	fn weight(&self, payment: &Payment) -> u64 {
+		payment.amount
+	}
+}
+
+#[cfg(test)]
+pub(crate) fn pay(amount: u64, fee: u64) -> Payment {
+	Payment { amount, fee, nonce: None }
+}
+
+#[test]
+fn bc_pool_turns_away_invalid_and_duplicate_extrinsics() {
+	let mut pool = TransactionPool::new(Wallet);
+
+	assert_eq!(pool.submit(pay(5, 1), &10), Ok(()));
+	assert_eq!(pool.submit(pay(5, 1), &10), Err(PoolError::AlreadyPooled));
+	assert_eq!(pool.submit(pay(10, 1), &10), Err(PoolError::Invalid(Insufficient)));
+	assert_eq!(pool.pending(), &[pay(5, 1)]);
+}
+
+#[test]
+fn bc_pool_orders_by_priority() {
+	let mut pool = TransactionPool::new(Wallet);
+	for payment in [pay(1, 1), pay(2, 5), pay(3, 1), pay(4, 3)] {
+		pool.submit(payment, &100).unwrap();
+	}
+
+	assert_eq!(pool.ready(&100), vec![pay(2, 5), pay(4, 3), pay(1, 1), pay(3, 1)]);
+}
+
+#[test]
+fn bc_pool_batch_executes_as_a_whole() {
+	let mut pool = TransactionPool::new(Wallet);
+	for payment in [pay(4, 1), pay(6, 2), pay(2, 0)] {
+		pool.submit(payment, &10).unwrap();
+	}
+
+	// Each payment fits on its own, but not all of them together.
+	assert_eq!(pool.ready(&10), vec![pay(6, 2), pay(2, 0)]);
+}
+
+#[test]
+fn bc_pool_authors_blocks() {
+	let mut pool = TransactionPool::new(Wallet);
+	let g = Block::<Payment, u64>::genesis_with_state(10);
+	for payment in [pay(4, 1), pay(6, 2), pay(1, 0)] {
+		pool.submit(payment, &10).unwrap();
+	}
+
+	let b1 = pool.author(&g);
+	assert_eq!(b1.body(), &[pay(6, 2), pay(1, 0)]);
+	assert_eq!(b1.header().state(), 1);
+	assert!(g.verify_sub_chain_with(&Wallet, std::slice::from_ref(&b1)));
+
+	// The leftover payment no longer fits, so it is dropped once the new block is best.
+	assert_eq!(pool.pending(), &[pay(4, 1)]);
+	pool.revalidate(&b1.header().state());
+	assert!(pool.is_empty());
+	assert!(pool.author(&b1).body().is_empty());
+}
+
+#[test]
+fn bc_pool_full_evicts_lowest_priority() {
+	let mut pool = TransactionPool::with_limit(Wallet, 3);
+	for payment in [pay(1, 2), pay(2, 1), pay(3, 1)] {
+		pool.submit(payment, &100).unwrap();
+	}
+
+	// Paying no more than the cheapest extrinsic isn't enough to get in.
+	assert_eq!(pool.submit(pay(4, 1), &100), Err(PoolError::PoolFull));
+	assert_eq!(pool.submit(pay(4, 0), &100), Err(PoolError::PoolFull));
+
+	// Paying more evicts the cheapest, most recently submitted extrinsic.
+	assert_eq!(pool.submit(pay(5, 3), &100), Ok(()));
+	assert_eq!(pool.pending(), &[pay(1, 2), pay(2, 1), pay(5, 3)]);
+	assert_eq!(pool.submit(pay(6, 2), &100), Ok(()));
+	assert_eq!(pool.pending(), &[pay(1, 2), pay(5, 3), pay(6, 2)]);
+	assert_eq!(pool.len(), 3);
+
+	assert_eq!(
+		TransactionPool::with_limit(Wallet, 0).submit(pay(1, 9), &100),
+		Err(PoolError::PoolFull)
+	);
+}
+
+#[test]
+fn bc_pool_replace_by_fee() {
+	let mut pool = TransactionPool::with_limit(Wallet, 2);
+	let first = Payment { amount: 5, fee: 1, nonce: Some(0) };
+	pool.submit(first, &100).unwrap();
+	pool.submit(pay(1, 1), &100).unwrap();
+
+	let same_fee = Payment { amount: 6, ..first };
+	assert_eq!(pool.submit(same_fee, &100), Err(PoolError::Underpriced));
+
+	// Replacing doesn't need any room, even though the pool is full.
+	let bumped = Payment { fee: 2, ..first };
+	assert_eq!(pool.submit(bumped, &100), Ok(()));
+	assert_eq!(pool.pending(), &[pay(1, 1), bumped]);
+
+	let next_nonce = Payment { nonce: Some(1), fee: 5, ..first };
+	assert_eq!(pool.submit(next_nonce, &100), Ok(()));
+	assert_eq!(pool.pending(), &[bumped, next_nonce]);
+}
+
+#[test]
+fn bc_pool_fills_blocks_up_to_the_weight_budget() {
+	let mut pool = TransactionPool::new(Wallet);
+	assert_eq!(pool.submit(pay(11, 9), &100), Err(PoolError::Overweight));
+	for payment in [pay(6, 3), pay(5, 2), pay(4, 1), pay(1, 0)] {
+		pool.submit(payment, &100).unwrap();
+	}
+
+	// The second payment doesn't fit next to the first, but the lighter ones after it do.
+	let g = Block::<Payment, u64>::genesis_with_state(100);
+	let b1 = pool.author(&g);
+	assert_eq!(b1.body(), &[pay(6, 3), pay(4, 1)]);
+	assert_eq!(pool.pending(), &[pay(5, 2), pay(1, 0)]);
+	assert!(b1.verify_with(&Wallet, g.header()));
+}
+
+#[test]
+fn bc_pool_overweight_blocks_do_not_check() {
+	use super::p4_batched_extrinsics::BuildError;
+
+	let g = Block::<Payment, u64>::genesis_with_state(100);
+	let body = vec![pay(6, 0), pay(5, 0)];
+	assert_eq!(Wallet.total_weight(&body), 11);
+	assert_eq!(g.child_with(&Wallet, body.clone()), Err(BuildError::Overweight));
+
+	// Nor does a block that somebody else authored anyway.
+	let heavy = Block { header: g.header().child(crate::hash(&body), 89), body };
+	assert!(!heavy.verify_with(&Wallet, g.header()));
+	assert!(g
+		.child_with(&Wallet, vec![pay(6, 0), pay(4, 0)])
+		.unwrap()
+		.verify_with(&Wallet, g.header()));
+}
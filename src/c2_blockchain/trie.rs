@@ -0,0 +1,249 @@
+//! The flat map state backend has to hash the whole state again whenever a single entry changes,
+//! and it can't prove a single entry to somebody who only knows the root without handing over
+//! the whole state. A Merkle-Patricia trie fixes both.
+//!
+//! Keys are split into nibbles, that is four bits each, and each nibble picks one of sixteen
+//! children on the way down from the root. Runs of nibbles without any choice to make are
+//! squashed into a single node, which is the Patricia part. Every node stores its own hash, so
+//! after a write only the nodes on the path to the written key need to be hashed again, and the
+//! nodes on that path are a proof of the entry.
+//!
+//! Removing entries squashes nodes back together, so a trie only depends on what is in it, not on
+//! the order things were inserted and removed in. Otherwise equal states could have different
+//! roots.
+
+use super::kv_state::StateBackend;
+use crate::{domain, hash_with_domain};
+type Hash = u64;
+
+/// A node of the trie along with its hash
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Node {
+	kind: Kind,
+	hash: Hash,
+}
+
+/// The kinds of node in a trie
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Kind {
+	/// A value, along with the rest of the nibbles of its key
+	Leaf { rest: Vec<u8>, value: u64 },
+	/// Nibbles that all the keys below share
+	Extension { prefix: Vec<u8>, child: Box<Node> },
+	/// At least two children, one for each nibble that comes next in some key
+	Branch { children: Box<[Option<Node>; 16]> },
+}
+
+impl Node {
+	/// Hash the given node.
+	fn new(kind: Kind) -> Node {
+		let hash = match &kind {
+			Kind::Leaf { rest, value } => hash_with_domain(domain::MERKLE_LEAF, &(rest, value)),
+			Kind::Extension { prefix, child } =>
+				hash_with_domain(domain::MERKLE_NODE, &("extension", prefix, child.hash)),
+			Kind::Branch { children } => {
+				let hashes =
+					children.each_ref().map(|child| child.as_ref().map(|child| child.hash));
+				hash_with_domain(domain::MERKLE_NODE, &("branch", hashes))
+			},
+		};
+		Node { kind, hash }
+	}
+
+	fn leaf(rest: &[u8], value: u64) -> Node {
+		Node::new(Kind::Leaf { rest: rest.to_vec(), value })
+	}
+
+	fn branch(children: Box<[Option<Node>; 16]>) -> Node {
+		Node::new(Kind::Branch { children })
+	}
+
+	/// The given node, moved down below the given nibbles. Leaves and extensions absorb the
+	/// nibbles, so that there are never two extensions in a row, nor an extension above a leaf.
+	fn prefixed(prefix: &[u8], node: Node) -> Node {
+		if prefix.is_empty() {
+			return node
+		}
+		match node.kind {
+			Kind::Leaf { rest, value } => Node::leaf(&[prefix, &rest].concat(), value),
+			Kind::Extension { prefix: inner, child } =>
+				Node::new(Kind::Extension { prefix: [prefix, &inner].concat(), child }),
+			Kind::Branch { .. } =>
+				Node::new(Kind::Extension { prefix: prefix.to_vec(), child: Box::new(node) }),
+		}
+	}
+
+	/// A branch where two paths part ways. The first path leads to the given node, and the second
+	/// to a new leaf with the given value. Both paths start with the same nibbles for as long as
+	/// the shared prefix, and differ in the next one.
+	fn fork(shared: usize, path: &[u8], node: Node, new_path: &[u8], value: u64) -> Node {
+		let mut children: Box<[Option<Node>; 16]> = Default::default();
+		children[path[shared] as usize] = Some(Node::prefixed(&path[shared + 1..], node));
+		children[new_path[shared] as usize] = Some(Node::leaf(&new_path[shared + 1..], value));
+		Node::prefixed(&new_path[..shared], Node::branch(children))
+	}
+}
+
+/// How many nibbles the given paths start with in common
+fn shared_prefix(a: &[u8], b: &[u8]) -> usize {
+	a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// The nibbles of the given key, most significant first
+fn nibbles(key: u64) -> Vec<u8> {
+	(0..16).rev().map(|i| ((key >> (4 * i)) & 0xf) as u8).collect()
+}
+
+fn get(node: &Node, path: &[u8]) -> Option<u64> {
+	match &node.kind {
+		Kind::Leaf { rest, value } => (rest == path).then_some(*value),
+		Kind::Extension { prefix, child } =>
+			path.strip_prefix(prefix.as_slice()).and_then(|path| get(child, path)),
+		Kind::Branch { children } => get(children[path[0] as usize].as_ref()?, &path[1..]),
+	}
+}
+
+fn insert(node: Option<Node>, path: &[u8], value: u64) -> Node {
+	let Some(node) = node else { return Node::leaf(path, value) };
+	match node.kind {
+		Kind::Leaf { rest, value: old } => {
+			if rest == path {
+				return Node::leaf(path, value)
+			}
+			let shared = shared_prefix(&rest, path);
+			Node::fork(shared, &rest, Node::leaf(&[], old), path, value)
+		},
+		Kind::Extension { prefix, child } => {
+			let shared = shared_prefix(&prefix, path);
+			if shared == prefix.len() {
+				return Node::prefixed(&prefix, insert(Some(*child), &path[shared..], value))
+			}
+			Node::fork(shared, &prefix, *child, path, value)
+		},
+		Kind::Branch { mut children } => {
+			let next = path[0] as usize;
+			children[next] = Some(insert(children[next].take(), &path[1..], value));
+			Node::branch(children)
+		},
+	}
+}
+
+fn remove(node: Node, path: &[u8]) -> Option<Node> {
+	match node.kind {
+		Kind::Leaf { ref rest, .. } => (rest.as_slice() != path).then_some(node),
+		Kind::Extension { ref prefix, .. } if !path.starts_with(prefix) => Some(node),
+		Kind::Extension { prefix, child } =>
+			remove(*child, &path[prefix.len()..]).map(|child| Node::prefixed(&prefix, child)),
+		Kind::Branch { mut children } => {
+			let next = path[0] as usize;
+			children[next] = children[next].take().and_then(|child| remove(child, &path[1..]));
+			let remaining: Vec<usize> = (0..16).filter(|i| children[*i].is_some()).collect();
+			match remaining.as_slice() {
+				[] => None,
+				// A branch with a single child makes no choice, so it squashes into that child.
+				[only] => {
+					let child = children[*only].take().expect("the child was just found");
+					Some(Node::prefixed(&[*only as u8], child))
+				},
+				_ => Some(Node::branch(children)),
+			}
+		},
+	}
+}
+
+/// A state backend that keeps the state in a Merkle-Patricia trie
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Trie {
+	root: Option<Node>,
+}
+
+impl Trie {
+	/// An empty trie
+	pub fn new() -> Self {
+		Trie::default()
+	}
+}
+
+impl<const N: usize> From<[(u64, u64); N]> for Trie {
+	fn from(entries: [(u64, u64); N]) -> Self {
+		let mut trie = Trie::new();
+		for (key, value) in entries {
+			trie.insert(key, value);
+		}
+		trie
+	}
+}
+
+impl StateBackend for Trie {
+	fn get(&self, key: u64) -> Option<u64> {
+		get(self.root.as_ref()?, &nibbles(key))
+	}
+
+	fn insert(&mut self, key: u64, value: u64) {
+		self.root = Some(insert(self.root.take(), &nibbles(key), value));
+	}
+
+	fn remove(&mut self, key: u64) {
+		self.root = self.root.take().and_then(|root| remove(root, &nibbles(key)));
+	}
+
+	/// The hash of the root node. The empty trie has a root of zero.
+	fn root(&self) -> Hash {
+		self.root.as_ref().map_or(0, |root| root.hash)
+	}
+}
+
+#[test]
+fn bc_trie_insert_get_remove() {
+	let mut trie = Trie::from([(0x12, 1), (0x1234, 2), (0x1235, 3), (u64::MAX, 4)]);
+
+	assert_eq!(trie.get(0x12), Some(1));
+	assert_eq!(trie.get(0x1234), Some(2));
+	assert_eq!(trie.get(0x1235), Some(3));
+	assert_eq!(trie.get(u64::MAX), Some(4));
+	assert_eq!(trie.get(0x1236), None);
+	assert_eq!(trie.get(0), None);
+
+	trie.insert(0x1234, 20);
+	trie.remove(0x1235);
+	trie.remove(0x9999);
+	assert_eq!(trie.get(0x1234), Some(20));
+	assert_eq!(trie.get(0x1235), None);
+	assert_eq!(trie.get(0x12), Some(1));
+}
+
+#[test]
+fn bc_trie_root_only_depends_on_contents() {
+	let trie = Trie::from([(1, 10), (2, 20), (0x100, 30)]);
+	let mut shuffled = Trie::from([(0x100, 30), (5, 50), (2, 20), (1, 11)]);
+	shuffled.remove(5);
+	shuffled.insert(1, 10);
+
+	assert_eq!(shuffled, trie);
+	assert_eq!(shuffled.root(), trie.root());
+	assert_ne!(Trie::from([(1, 10), (2, 20), (0x100, 31)]).root(), trie.root());
+
+	let mut emptied = trie.clone();
+	for key in [2, 1, 0x100] {
+		emptied.remove(key);
+	}
+	assert_eq!(emptied, Trie::new());
+	assert_eq!(emptied.root(), 0);
+}
+
+#[test]
+fn bc_trie_backs_a_chain() {
+	use super::kv_state::{Block, Extrinsic};
+
+	let genesis_state = Trie::from([(1, 10)]);
+	let g = Block::genesis(&genesis_state);
+	let b1 = g.child(&genesis_state, vec![Extrinsic::Set { key: 2, value: 20 }]);
+
+	assert_eq!(b1.header.state(), Trie::from([(1, 10), (2, 20)]).root());
+	assert!(g.verify_sub_chain(&genesis_state, std::slice::from_ref(&b1)));
+
+	// The trie and the flat map commit to the same state differently, so a chain must stick to
+	// one backend.
+	let flat = super::kv_state::State::from([(1, 10)]);
+	assert!(!g.verify_sub_chain(&flat, &[b1]));
+}
@@ -24,6 +24,8 @@ type Hash = u64;
 /// It can import new blocks, author its own blocks
 pub struct FullClient {
     transaction_pool: Vec<Transaction>,
+    // States can be kept in any `kv_state::StateBackend`. `SnapshotState` is the one to use when
+    // a test needs to checkpoint the client mid-run and branch scenarios from there.
     block_database: HashMap<Hash, Block>,
     state_database: HashMap<Hash, State>,
     leaves: HashSet<Hash>,
@@ -32,6 +34,7 @@ pub struct FullClient {
 //TODO maybe make a trait `Client` and implement it for light client too.
 // Let's see how many of the same methods make sense.
 impl FullClient {
+    // Fork bombs are bounded by importing through a `BlockTree` built `with_limits`.
     fn import_block(&mut self, b: Block) -> Result<Hash, String> {
         todo!()
     }
@@ -39,6 +42,7 @@ impl FullClient {
     // Could provide an explicit parent, or could have a fork choice rule, or both
     // maybe we start with explicit parent and add the fork choice rule later.
     // should be able to do ghost now that we have a block database.
+    // Development chains with instant and manual seal are `dev_seal::DevChain` in chapter 2.
     fn create_block(){todo!()}
 
     fn get_block_by_hash(&self, h: Hash) -> Result<Block, String> {
@@ -53,5 +57,7 @@ impl FullClient {
 
     //TODO maybe this method gets introduced later on and we see how it allows pruning
     // the leaves and limits how far back we have to iterate for things like seeing which block is best
+    // The pruning itself is `BlockTree::finalize` from chapter 2, which drops every competing
+    // branch, returns the pruned hashes, and makes later imports on them fail with `PrunedParent`.
     fn note_finality(b: Hash) { todo!()}
 }
\ No newline at end of file
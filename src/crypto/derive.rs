@@ -0,0 +1,142 @@
+//! Hierarchical key derivation lets a single secret phrase control an entire tree of key pairs.
+//!
+//! A key is described by a "secret URI" made of a phrase followed by a derivation path, for example
+//! `"correct horse battery staple//validator/0"`. Each step in the path is a junction. A hard
+//! junction (`//`) derives a child from the parent's secret, so nobody without the secret can
+//! learn anything about the child. A soft junction (`/`) derives a child in a way that lets anybody
+//! who knows the parent's public key also compute the child's public key, which is handy for
+//! wallets that want to watch many accounts without holding any secrets.
+
+use super::Seed;
+use crate::{domain, hash_with_domain};
+use rand::{seq::SliceRandom, thread_rng};
+
+/// A single step in a derivation path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeriveJunction {
+	/// A child that can only be derived with knowledge of the parent's secret.
+	Hard(u64),
+	/// A child whose public key can be derived from the parent's public key alone.
+	Soft(u64),
+}
+
+impl DeriveJunction {
+	/// Build a junction from its textual form. Numeric junctions are used as-is so that
+	/// `/0`, `/1`, `/2` make a nice sequence of accounts. Anything else is hashed.
+	fn new(text: &str, hard: bool) -> Self {
+		let index = text
+			.parse::<u64>()
+			.unwrap_or_else(|_| hash_with_domain(domain::KEY_DERIVATION, &text));
+		if hard {
+			DeriveJunction::Hard(index)
+		} else {
+			DeriveJunction::Soft(index)
+		}
+	}
+}
+
+/// Split a secret URI into the seed it starts from and the junctions that follow.
+///
+/// The phrase is everything before the first `/`. A phrase of the form `0x<hex>` is interpreted
+/// directly as a raw seed. Any other phrase is hashed to produce the seed. An empty phrase or
+/// an empty junction makes the whole URI invalid.
+pub fn parse_suri(suri: &str) -> Option<(Seed, Vec<DeriveJunction>)> {
+	let (phrase, path) = match suri.find('/') {
+		Some(i) => suri.split_at(i),
+		None => (suri, ""),
+	};
+	let phrase = phrase.trim();
+	if phrase.is_empty() {
+		return None
+	}
+	let seed = match phrase.strip_prefix("0x") {
+		Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+		None => hash_with_domain(domain::KEY_DERIVATION, &phrase),
+	};
+
+	Some((seed, parse_path(path)?))
+}
+
+/// Split a derivation path such as `"//validator/0"` into its junctions. Every junction must be
+/// introduced by `/` or `//` and must not be empty, otherwise the path is invalid.
+pub fn parse_path(mut path: &str) -> Option<Vec<DeriveJunction>> {
+	let mut junctions = Vec::new();
+	while !path.is_empty() {
+		let slashes = path.len() - path.trim_start_matches('/').len();
+		let hard = match slashes {
+			1 => false,
+			2 => true,
+			_ => return None,
+		};
+		path = &path[slashes..];
+		let end = path.find('/').unwrap_or(path.len());
+		if end == 0 {
+			return None
+		}
+		junctions.push(DeriveJunction::new(&path[..end], hard));
+		path = &path[end..];
+	}
+
+	Some(junctions)
+}
+
+/// The words that randomly generated phrases are built from.
+const WORDS: [&str; 64] = [
+	"apple", "arrow", "badge", "basin", "berry", "blade", "brick", "cabin", "camel", "cedar",
+	"chalk", "cider", "clock", "coral", "crane", "delta", "drift", "eagle", "ember", "fable",
+	"fern", "flint", "frost", "grape", "gravel", "harbor", "hazel", "honey", "ivory", "jelly",
+	"kettle", "lemon", "lilac", "maple", "meadow", "mango", "noble", "oasis", "olive", "orbit",
+	"otter", "pearl", "pepper", "pilot", "quartz", "raven", "river", "saddle", "shadow", "spruce",
+	"storm", "tiger", "topaz", "tulip", "umber", "velvet", "violet", "walnut", "willow", "yarrow",
+	"zebra", "zephyr", "canyon", "lantern",
+];
+
+/// Generate a random twelve word phrase from which a whole tree of keys can be derived.
+pub fn generate_phrase() -> String {
+	let mut rng = thread_rng();
+	(0..12).map(|_| *WORDS.choose(&mut rng).unwrap()).collect::<Vec<_>>().join(" ")
+}
+
+#[test]
+fn crypto_parse_plain_phrase() {
+	assert_eq!(
+		parse_suri("hello world"),
+		Some((hash_with_domain(domain::KEY_DERIVATION, &"hello world"), vec![]))
+	);
+}
+
+#[test]
+fn crypto_parse_hex_seed() {
+	assert_eq!(parse_suri("0x2a"), Some((42, vec![])));
+}
+
+#[test]
+fn crypto_parse_derivation_path() {
+	let (_, junctions) = parse_suri("hello//validator/0//1").unwrap();
+
+	assert_eq!(
+		junctions,
+		vec![
+			DeriveJunction::Hard(hash_with_domain(domain::KEY_DERIVATION, &"validator")),
+			DeriveJunction::Soft(0),
+			DeriveJunction::Hard(1),
+		]
+	);
+}
+
+#[test]
+fn crypto_parse_invalid_suris() {
+	assert_eq!(parse_suri(""), None);
+	assert_eq!(parse_suri("//alice"), None);
+	assert_eq!(parse_suri("hello///alice"), None);
+	assert_eq!(parse_suri("hello/"), None);
+	assert_eq!(parse_suri("0xnothex"), None);
+}
+
+#[test]
+fn crypto_generated_phrase_has_twelve_words() {
+	let phrase = generate_phrase();
+
+	assert_eq!(phrase.split(' ').count(), 12);
+	assert!(parse_suri(&phrase).is_some());
+}
@@ -0,0 +1,340 @@
+//! A keystore keeps track of the secret keys that a node or a wallet owns.
+//!
+//! Keys are grouped by a `KeyTypeId` which describes what the key is used for, for example
+//! authoring Proof of Authority blocks, or controlling a user account. The keystore itself does
+//! not care which signature scheme a key belongs to. Callers choose the scheme through the `Pair`
+//! type parameter when they ask the keystore to create or look up a key.
+//!
+//! The keystore can live purely in memory, which is what tests will usually want, or it can be
+//! backed by a directory on disk so that keys survive restarts.
+//!
+//! Rather than raw secrets, the keystore remembers the secret URI each key was created from. That
+//! way a key derived from another key can always be re-derived from the same phrase and path.
+
+use super::{
+	derive::{generate_phrase, parse_path, parse_suri},
+	Pair, Seed,
+};
+use std::{
+	collections::HashMap,
+	fs,
+	hash::Hash,
+	io,
+	path::{Path, PathBuf},
+};
+
+/// A four byte identifier describing what a key is used for.
+pub type KeyTypeId = [u8; 4];
+
+/// Keys used to author Proof of Authority blocks.
+pub const POA: KeyTypeId = *b"poa_";
+
+/// Keys used to author Proof of Stake blocks.
+pub const POS: KeyTypeId = *b"pos_";
+
+/// Keys that control a user account.
+pub const ACCOUNT: KeyTypeId = *b"acct";
+
+/// Stores secret URIs for key pairs, and looks them up by their public key.
+#[derive(Debug, Default)]
+pub struct Keystore {
+	/// Every known secret URI, indexed by the key type and the public key it produces.
+	keys: HashMap<(KeyTypeId, u64), String>,
+	/// The directory that keys are persisted to, if any.
+	path: Option<PathBuf>,
+}
+
+impl Keystore {
+	/// Create an empty keystore that only lives in memory.
+	pub fn in_memory() -> Self {
+		Self::default()
+	}
+
+	/// Open a keystore backed by the given directory, loading any keys that are already there.
+	/// The directory is created if it does not exist yet.
+	///
+	/// Each key is stored in its own file. The file name is the hex-encoded key type followed by
+	/// the hex-encoded public key, and the file contains the secret URI.
+	pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+		let path = path.into();
+		fs::create_dir_all(&path)?;
+
+		let mut keys = HashMap::new();
+		for entry in fs::read_dir(&path)? {
+			let entry = entry?;
+			let Some((key_type, public)) = entry.file_name().to_str().and_then(parse_file_name)
+			else {
+				continue
+			};
+			let suri = fs::read_to_string(entry.path())?.trim().to_string();
+			if parse_suri(&suri).is_none() {
+				return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed secret URI"))
+			}
+			keys.insert((key_type, public), suri);
+		}
+
+		Ok(Keystore { keys, path: Some(path) })
+	}
+
+	/// Generate a brand new key pair of the given type and return its public key.
+	pub fn generate<P: Pair>(&mut self, key_type: KeyTypeId) -> io::Result<P::Public> {
+		let (_, seed) = P::generate();
+		self.insert::<P>(key_type, seed)
+	}
+
+	/// Generate a brand new phrase, insert the key pair it describes, and return both. The phrase
+	/// is the only backup of the key and everything derived from it, so it is up to the caller to
+	/// show it to the user.
+	pub fn generate_with_phrase<P: Pair>(
+		&mut self,
+		key_type: KeyTypeId,
+	) -> io::Result<(String, P::Public)> {
+		let phrase = generate_phrase();
+		let public = self.insert_suri::<P>(key_type, &phrase)?;
+		Ok((phrase, public))
+	}
+
+	/// Insert the key pair created from an existing seed and return its public key.
+	pub fn insert<P: Pair>(&mut self, key_type: KeyTypeId, seed: Seed) -> io::Result<P::Public> {
+		self.insert_suri::<P>(key_type, &format!("0x{:016x}", seed))
+	}
+
+	/// Insert the key pair described by a secret URI, such as a phrase followed by a derivation
+	/// path, and return its public key.
+	pub fn insert_suri<P: Pair>(
+		&mut self,
+		key_type: KeyTypeId,
+		suri: &str,
+	) -> io::Result<P::Public> {
+		let public = P::from_string(suri)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "malformed secret URI"))?
+			.public();
+		if let Some(path) = &self.path {
+			fs::write(key_file(path, key_type, public.into()), suri)?;
+		}
+		self.keys.insert((key_type, public.into()), suri.to_string());
+		Ok(public)
+	}
+
+	/// Derive a child of a key that is already in the keystore by following the given derivation
+	/// path, for example `"//validator/0"`. The child is stored under the same key type and its
+	/// public key is returned. The path must hold at least one junction, each starting with `/` or
+	/// `//`, so that it can't run on into the parent's phrase.
+	pub fn derive<P: Pair>(
+		&mut self,
+		key_type: KeyTypeId,
+		parent: &P::Public,
+		path: &str,
+	) -> io::Result<P::Public> {
+		if parse_path(path).is_none_or(|junctions| junctions.is_empty()) {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "malformed derivation path"))
+		}
+		let parent_suri = self
+			.keys
+			.get(&(key_type, (*parent).into()))
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown parent key"))?;
+		let suri = format!("{}{}", parent_suri, path);
+		self.insert_suri::<P>(key_type, &suri)
+	}
+
+	/// Forget the key pair with the given public key. Returns whether a key was actually removed.
+	pub fn remove<P: Pair>(&mut self, key_type: KeyTypeId, public: &P::Public) -> io::Result<bool> {
+		let removed = self.keys.remove(&(key_type, (*public).into())).is_some();
+		if let (true, Some(path)) = (removed, &self.path) {
+			fs::remove_file(key_file(path, key_type, (*public).into()))?;
+		}
+		Ok(removed)
+	}
+
+	/// Whether the keystore holds the secret for the given public key.
+	pub fn has_key<P: Pair>(&self, key_type: KeyTypeId, public: &P::Public) -> bool {
+		self.keys.contains_key(&(key_type, (*public).into()))
+	}
+
+	/// Look up the full key pair belonging to the given public key.
+	pub fn key_pair<P: Pair>(&self, key_type: KeyTypeId, public: &P::Public) -> Option<P> {
+		self.keys
+			.get(&(key_type, (*public).into()))
+			.and_then(|suri| P::from_string(suri))
+	}
+
+	/// All of the public keys of the given type that this keystore holds secrets for.
+	pub fn public_keys<P: Pair>(&self, key_type: KeyTypeId) -> Vec<P::Public> {
+		let mut publics: Vec<u64> = self
+			.keys
+			.keys()
+			.filter(|(t, _)| *t == key_type)
+			.map(|(_, public)| *public)
+			.collect();
+		publics.sort();
+		publics.into_iter().map(Into::into).collect()
+	}
+
+	/// Sign a message with the key belonging to the given public key, if we have it.
+	pub fn sign<P: Pair, M: Hash>(
+		&self,
+		key_type: KeyTypeId,
+		public: &P::Public,
+		message: &M,
+	) -> Option<P::Signature> {
+		self.key_pair::<P>(key_type, public).map(|pair| pair.sign(message))
+	}
+}
+
+/// The file that a particular key is stored in.
+fn key_file(dir: &Path, key_type: KeyTypeId, public: u64) -> PathBuf {
+	let key_type: String = key_type.iter().map(|b| format!("{:02x}", b)).collect();
+	dir.join(format!("{}{:016x}", key_type, public))
+}
+
+/// Recover the key type and public key from a key file's name.
+fn parse_file_name(name: &str) -> Option<(KeyTypeId, u64)> {
+	if name.len() != 24 || !name.is_ascii() {
+		return None
+	}
+	let mut key_type = [0u8; 4];
+	for (i, byte) in key_type.iter_mut().enumerate() {
+		*byte = u8::from_str_radix(&name[2 * i..2 * i + 2], 16).ok()?;
+	}
+	let public = u64::from_str_radix(&name[8..], 16).ok()?;
+	Some((key_type, public))
+}
+
+#[cfg(test)]
+use super::SchnorrPair;
+
+#[test]
+fn keystore_generate_and_look_up() {
+	let mut keystore = Keystore::in_memory();
+	let public = keystore.generate::<SchnorrPair>(POA).unwrap();
+
+	assert!(keystore.has_key::<SchnorrPair>(POA, &public));
+	assert_eq!(keystore.key_pair::<SchnorrPair>(POA, &public).unwrap().public(), public);
+}
+
+#[test]
+fn keystore_keys_are_separated_by_type() {
+	let mut keystore = Keystore::in_memory();
+	let public = keystore.insert::<SchnorrPair>(POA, 1).unwrap();
+
+	assert!(!keystore.has_key::<SchnorrPair>(POS, &public));
+	assert!(keystore.public_keys::<SchnorrPair>(ACCOUNT).is_empty());
+	assert_eq!(keystore.public_keys::<SchnorrPair>(POA), vec![public]);
+}
+
+#[test]
+fn keystore_signs_with_stored_key() {
+	let mut keystore = Keystore::in_memory();
+	let public = keystore.insert::<SchnorrPair>(ACCOUNT, 5).unwrap();
+	let signature = keystore.sign::<SchnorrPair, _>(ACCOUNT, &public, b"transfer").unwrap();
+
+	assert!(SchnorrPair::verify(&signature, b"transfer", &public));
+}
+
+#[test]
+fn keystore_unknown_key_cannot_sign() {
+	let keystore = Keystore::in_memory();
+	let public = SchnorrPair::from_seed(5).public();
+
+	assert!(keystore.sign::<SchnorrPair, _>(ACCOUNT, &public, b"transfer").is_none());
+}
+
+#[test]
+fn keystore_remove_key() {
+	let mut keystore = Keystore::in_memory();
+	let public = keystore.insert::<SchnorrPair>(POA, 9).unwrap();
+
+	assert!(keystore.remove::<SchnorrPair>(POA, &public).unwrap());
+	assert!(!keystore.has_key::<SchnorrPair>(POA, &public));
+	assert!(!keystore.remove::<SchnorrPair>(POA, &public).unwrap());
+}
+
+#[test]
+fn keystore_file_backed_keys_survive_reopening() {
+	let dir = std::env::temp_dir().join(format!("bfs-keystore-{}", rand::random::<u64>()));
+
+	let mut keystore = Keystore::open(&dir).unwrap();
+	let public = keystore.generate::<SchnorrPair>(POS).unwrap();
+	let removed = keystore.insert::<SchnorrPair>(POS, 3).unwrap();
+	keystore.remove::<SchnorrPair>(POS, &removed).unwrap();
+	drop(keystore);
+
+	let reopened = Keystore::open(&dir).unwrap();
+	assert_eq!(reopened.public_keys::<SchnorrPair>(POS), vec![public]);
+	assert!(reopened.key_pair::<SchnorrPair>(POS, &public).is_some());
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keystore_derives_many_accounts_from_one_phrase() {
+	let mut keystore = Keystore::in_memory();
+	let root = keystore.insert_suri::<SchnorrPair>(ACCOUNT, "apple river storm").unwrap();
+	let accounts: Vec<_> = (0..5)
+		.map(|i| keystore.derive::<SchnorrPair>(ACCOUNT, &root, &format!("/{}", i)).unwrap())
+		.collect();
+
+	// Every account is distinct, and another keystore with the same phrase gets the same ones.
+	let mut other = Keystore::in_memory();
+	for (i, account) in accounts.iter().enumerate() {
+		assert!(accounts[..i].iter().all(|a| a != account));
+		let suri = format!("apple river storm/{}", i);
+		assert_eq!(other.insert_suri::<SchnorrPair>(ACCOUNT, &suri).unwrap(), *account);
+	}
+	assert_eq!(keystore.public_keys::<SchnorrPair>(ACCOUNT).len(), 6);
+}
+
+#[test]
+fn keystore_derive_from_unknown_parent_fails() {
+	let mut keystore = Keystore::in_memory();
+	let stranger = SchnorrPair::from_seed(1).public();
+
+	assert!(keystore.derive::<SchnorrPair>(ACCOUNT, &stranger, "//child").is_err());
+}
+
+#[test]
+fn keystore_derive_rejects_malformed_paths() {
+	let mut keystore = Keystore::in_memory();
+	let root = keystore.insert_suri::<SchnorrPair>(ACCOUNT, "correct horse").unwrap();
+
+	for path in ["", "battery", " staple//0", "///0", "//", "/0/"] {
+		let err = keystore.derive::<SchnorrPair>(ACCOUNT, &root, path).unwrap_err();
+		assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{:?}", path);
+	}
+	assert_eq!(keystore.public_keys::<SchnorrPair>(ACCOUNT), vec![root]);
+}
+
+#[test]
+fn keystore_rejects_malformed_suri() {
+	let mut keystore = Keystore::in_memory();
+
+	assert!(keystore.insert_suri::<SchnorrPair>(ACCOUNT, "//no phrase").is_err());
+}
+
+#[test]
+fn keystore_file_backed_derived_keys_survive_reopening() {
+	let dir = std::env::temp_dir().join(format!("bfs-keystore-{}", rand::random::<u64>()));
+
+	let mut keystore = Keystore::open(&dir).unwrap();
+	let root = keystore.insert_suri::<SchnorrPair>(POA, "cedar ember frost").unwrap();
+	let child = keystore.derive::<SchnorrPair>(POA, &root, "//authority//0").unwrap();
+	drop(keystore);
+
+	let reopened = Keystore::open(&dir).unwrap();
+	let pair = reopened.key_pair::<SchnorrPair>(POA, &child).unwrap();
+	assert_eq!(pair, SchnorrPair::from_string("cedar ember frost//authority//0").unwrap());
+
+	fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn keystore_generated_phrase_restores_keys() {
+	let mut keystore = Keystore::in_memory();
+	let (phrase, root) = keystore.generate_with_phrase::<SchnorrPair>(ACCOUNT).unwrap();
+	let account = keystore.derive::<SchnorrPair>(ACCOUNT, &root, "/0").unwrap();
+
+	// Somebody who wrote the phrase down gets the same keys back.
+	let mut restored = Keystore::in_memory();
+	assert_eq!(restored.insert_suri::<SchnorrPair>(ACCOUNT, &phrase).unwrap(), root);
+	assert_eq!(restored.derive::<SchnorrPair>(ACCOUNT, &root, "/0").unwrap(), account);
+}
@@ -0,0 +1,73 @@
+//! A tiny cryptography layer for signing things.
+//!
+//! Up until now we have avoided real cryptography entirely, and simply attached an identity (like
+//! a `ConsensusAuthority`) wherever a signature would go. That is fine for learning consensus, but
+//! some later lessons need signatures that can actually be checked by somebody who does not know
+//! the secret.
+//!
+//! The scheme here is real Schnorr signing logic, but over a comically small group so that every
+//! number involved fits in a `u64`, just like the rest of our hashes. It is absolutely NOT secure.
+//! Brute forcing a secret key takes seconds. Use it to learn, never to protect anything.
+
+use std::hash::Hash;
+
+mod derive;
+mod keystore;
+mod schnorr;
+
+pub use derive::{generate_phrase, DeriveJunction};
+pub use keystore::{KeyTypeId, Keystore, ACCOUNT, POA, POS};
+pub use schnorr::{Public, SchnorrPair, Signature};
+
+/// The secret material from which a key pair is deterministically derived.
+pub type Seed = u64;
+
+/// A cryptographic key pair that is able to sign messages.
+///
+/// Multiple signature schemes can implement this trait. Code that only needs to sign and verify,
+/// like the keystore or a consensus engine, should be written against this trait rather than a
+/// specific scheme.
+pub trait Pair: Sized + Clone {
+	/// The public half of the key pair. It must fit in a `u64` so that it can be stored and looked
+	/// up as easily as our hashes.
+	type Public: Copy + Eq + Hash + core::fmt::Debug + Into<u64> + From<u64>;
+
+	/// A signature made by this kind of key pair.
+	type Signature: Copy + Eq + Hash + core::fmt::Debug;
+
+	/// Deterministically create a key pair from the given seed.
+	fn from_seed(seed: Seed) -> Self;
+
+	/// Create a brand new key pair from a random seed. The seed is returned as well so that
+	/// the caller can store it and recreate the pair later.
+	fn generate() -> (Self, Seed) {
+		let seed = rand::random::<Seed>();
+		(Self::from_seed(seed), seed)
+	}
+
+	/// The public key of this pair.
+	fn public(&self) -> Self::Public;
+
+	/// Sign the given message.
+	fn sign<M: Hash>(&self, message: &M) -> Self::Signature;
+
+	/// Check that the signature was made over the message by the owner of the public key.
+	fn verify<M: Hash>(signature: &Self::Signature, message: &M, public: &Self::Public) -> bool;
+
+	/// Derive a child key pair by following the given path of junctions from this pair.
+	fn derive<I: IntoIterator<Item = DeriveJunction>>(&self, path: I) -> Self;
+
+	/// Derive a child public key from a parent public key without knowing any secret. This is
+	/// only possible when every junction in the path is soft, so `None` is returned otherwise.
+	fn derive_public<I: IntoIterator<Item = DeriveJunction>>(
+		public: &Self::Public,
+		path: I,
+	) -> Option<Self::Public>;
+
+	/// Create a key pair from a secret URI such as `"my secret phrase//hard/soft/0"`. Returns
+	/// `None` if the URI is malformed.
+	fn from_string(suri: &str) -> Option<Self> {
+		let (seed, path) = derive::parse_suri(suri)?;
+		Some(Self::from_seed(seed).derive(path))
+	}
+}
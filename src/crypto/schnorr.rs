@@ -0,0 +1,254 @@
+//! Schnorr signatures in the multiplicative group of integers modulo the prime 2^31 - 1.
+//!
+//! The secret key is an exponent `x` and the public key is `g^x`. To sign, the signer commits to a
+//! random-looking exponent `k` by publishing `r = g^k`, derives a challenge `e` by hashing `r`
+//! together with the message, and responds with `s = k + e * x`. Anyone can check that
+//! `g^s * y^-e` reproduces the commitment `r` without ever learning `x`.
+
+use super::{DeriveJunction, Pair, Seed};
+use crate::{domain, hash_with_domain};
+use std::hash::Hash;
+
+/// The prime modulus of our group.
+const MODULUS: u64 = (1 << 31) - 1;
+
+/// The number of elements in the group. Exponents are reduced modulo this number.
+const ORDER: u64 = MODULUS - 1;
+
+/// A generator of the entire group.
+const GENERATOR: u64 = 7;
+
+/// Calculate `base ^ exponent` in our group using square-and-multiply.
+fn pow(mut base: u64, mut exponent: u64) -> u64 {
+	let mut result = 1;
+	base %= MODULUS;
+	while exponent > 0 {
+		if exponent & 1 == 1 {
+			result = result * base % MODULUS;
+		}
+		base = base * base % MODULUS;
+		exponent >>= 1;
+	}
+	result
+}
+
+/// Turn an arbitrary hash into a valid, non-zero secret exponent.
+fn secret_from_hash(h: u64) -> u64 {
+	h % (ORDER - 1) + 1
+}
+
+/// A Schnorr public key. This is the group element `g^x`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Public(u64);
+
+impl From<Public> for u64 {
+	fn from(public: Public) -> u64 {
+		public.0
+	}
+}
+
+impl From<u64> for Public {
+	fn from(raw: u64) -> Public {
+		Public(raw)
+	}
+}
+
+/// A Schnorr signature. Both halves are smaller than 2^31, so the whole signature can be packed
+/// into a single `u64` wherever a chain has room for exactly that much.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signature {
+	challenge: u64,
+	response: u64,
+}
+
+impl From<Signature> for u64 {
+	fn from(signature: Signature) -> u64 {
+		(signature.challenge << 32) | signature.response
+	}
+}
+
+impl From<u64> for Signature {
+	fn from(packed: u64) -> Signature {
+		Signature { challenge: packed >> 32, response: packed & u32::MAX as u64 }
+	}
+}
+
+/// A Schnorr key pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchnorrPair {
+	secret: u64,
+	public: Public,
+}
+
+impl SchnorrPair {
+	/// Create the key pair belonging to a specific secret exponent.
+	fn from_secret(secret: u64) -> Self {
+		SchnorrPair { secret, public: Public(pow(GENERATOR, secret)) }
+	}
+}
+
+/// The amount by which a soft junction shifts the secret exponent. It depends only on public
+/// information, which is exactly what makes soft derivation of public keys possible.
+fn soft_tweak(public: &Public, index: u64) -> u64 {
+	hash_with_domain(domain::KEY_DERIVATION, &("soft", public, index)) % ORDER
+}
+
+/// The challenge that binds a commitment to a particular message and signer.
+fn challenge(commitment: u64, public: &Public, message_hash: u64) -> u64 {
+	hash_with_domain(domain::SIGNATURE, &(commitment, public, message_hash)) % ORDER
+}
+
+impl Pair for SchnorrPair {
+	type Public = Public;
+	type Signature = Signature;
+
+	fn from_seed(seed: Seed) -> Self {
+		Self::from_secret(secret_from_hash(hash_with_domain(domain::KEY_DERIVATION, &seed)))
+	}
+
+	fn public(&self) -> Public {
+		self.public
+	}
+
+	fn sign<M: Hash>(&self, message: &M) -> Signature {
+		let message_hash = hash_with_domain(domain::SIGNATURE, message);
+		// Deriving the nonce from the secret and the message means we never need a source of
+		// randomness, and never accidentally reuse a nonce across two different messages.
+		let nonce =
+			secret_from_hash(hash_with_domain(domain::SIGNATURE, &(self.secret, message_hash)));
+		let commitment = pow(GENERATOR, nonce);
+		let challenge = challenge(commitment, &self.public, message_hash);
+		let response = (nonce as u128 + challenge as u128 * self.secret as u128) % ORDER as u128;
+
+		Signature { challenge, response: response as u64 }
+	}
+
+	fn verify<M: Hash>(signature: &Signature, message: &M, public: &Public) -> bool {
+		if signature.challenge >= ORDER ||
+			signature.response >= ORDER ||
+			public.0 == 0 ||
+			public.0 >= MODULUS
+		{
+			return false
+		}
+
+		// Reconstruct the commitment as g^s * y^-e. Because y^ORDER = 1, y^-e = y^(ORDER - e).
+		let commitment = pow(GENERATOR, signature.response) *
+			pow(public.0, ORDER - signature.challenge) %
+			MODULUS;
+
+		challenge(commitment, public, hash_with_domain(domain::SIGNATURE, message)) ==
+			signature.challenge
+	}
+
+	fn derive<I: IntoIterator<Item = DeriveJunction>>(&self, path: I) -> Self {
+		path.into_iter().fold(self.clone(), |parent, junction| match junction {
+			DeriveJunction::Hard(index) => Self::from_secret(secret_from_hash(hash_with_domain(
+				domain::KEY_DERIVATION,
+				&("hard", parent.secret, index),
+			))),
+			// Adding the tweak to the secret multiplies the public key by g^tweak.
+			DeriveJunction::Soft(index) =>
+				Self::from_secret((parent.secret + soft_tweak(&parent.public, index)) % ORDER),
+		})
+	}
+
+	fn derive_public<I: IntoIterator<Item = DeriveJunction>>(
+		public: &Public,
+		path: I,
+	) -> Option<Public> {
+		path.into_iter().try_fold(*public, |parent, junction| match junction {
+			DeriveJunction::Hard(_) => None,
+			DeriveJunction::Soft(index) =>
+				Some(Public(parent.0 * pow(GENERATOR, soft_tweak(&parent, index)) % MODULUS)),
+		})
+	}
+}
+
+#[test]
+fn crypto_sign_and_verify() {
+	let pair = SchnorrPair::from_seed(42);
+	let signature = pair.sign(b"hello");
+
+	assert!(SchnorrPair::verify(&signature, b"hello", &pair.public()));
+}
+
+#[test]
+fn crypto_wrong_message_does_not_verify() {
+	let pair = SchnorrPair::from_seed(42);
+	let signature = pair.sign(b"hello");
+
+	assert!(!SchnorrPair::verify(&signature, b"goodbye", &pair.public()));
+}
+
+#[test]
+fn crypto_wrong_signer_does_not_verify() {
+	let alice = SchnorrPair::from_seed(1);
+	let bob = SchnorrPair::from_seed(2);
+	let signature = alice.sign(b"hello");
+
+	assert!(!SchnorrPair::verify(&signature, b"hello", &bob.public()));
+}
+
+#[test]
+fn crypto_seed_is_deterministic() {
+	assert_eq!(SchnorrPair::from_seed(7), SchnorrPair::from_seed(7));
+	assert_ne!(SchnorrPair::from_seed(7).public(), SchnorrPair::from_seed(8).public());
+}
+
+#[test]
+fn crypto_signature_packs_into_u64() {
+	let pair = SchnorrPair::from_seed(3);
+	let signature = pair.sign(&1234u64);
+	let packed: u64 = signature.into();
+
+	assert_eq!(Signature::from(packed), signature);
+	assert!(SchnorrPair::verify(&Signature::from(packed), &1234u64, &pair.public()));
+}
+
+#[test]
+fn crypto_derivation_is_deterministic() {
+	let a = SchnorrPair::from_string("wallet phrase//stash/0").unwrap();
+	let b = SchnorrPair::from_string("wallet phrase//stash/0").unwrap();
+	let c = SchnorrPair::from_string("wallet phrase//stash/1").unwrap();
+
+	assert_eq!(a, b);
+	assert_ne!(a.public(), c.public());
+}
+
+#[test]
+fn crypto_derived_keys_differ_from_parent() {
+	let parent = SchnorrPair::from_string("wallet phrase").unwrap();
+	let hard = parent.derive([DeriveJunction::Hard(0)]);
+	let soft = parent.derive([DeriveJunction::Soft(0)]);
+
+	assert_ne!(parent.public(), hard.public());
+	assert_ne!(parent.public(), soft.public());
+	assert_ne!(hard.public(), soft.public());
+}
+
+#[test]
+fn crypto_derived_keys_can_sign() {
+	let pair = SchnorrPair::from_string("wallet phrase//validator/3").unwrap();
+	let signature = pair.sign(b"block");
+
+	assert!(SchnorrPair::verify(&signature, b"block", &pair.public()));
+}
+
+#[test]
+fn crypto_soft_derivation_of_public_keys() {
+	let parent = SchnorrPair::from_string("wallet phrase//accounts").unwrap();
+	let path = [DeriveJunction::Soft(0), DeriveJunction::Soft(7)];
+
+	assert_eq!(
+		SchnorrPair::derive_public(&parent.public(), path),
+		Some(parent.derive(path).public())
+	);
+}
+
+#[test]
+fn crypto_hard_derivation_of_public_keys_is_impossible() {
+	let parent = SchnorrPair::from_string("wallet phrase").unwrap();
+
+	assert_eq!(SchnorrPair::derive_public(&parent.public(), [DeriveJunction::Hard(0)]), None);
+}
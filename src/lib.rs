@@ -5,10 +5,36 @@ use std::{
 	hash::{Hash, Hasher},
 };
 
-mod c1_state_machine;
-mod c2_blockchain;
-mod c3_consensus;
+pub mod c1_state_machine;
+pub mod c2_blockchain;
+pub mod c3_consensus;
 mod c4_framework;
+pub mod crypto;
+
+pub use prelude::*;
+
+/// The teaching-stable API. Companion crates that build on this one should import from here.
+///
+/// Everything re-exported here keeps its name and meaning from one lesson to the next. Other
+/// public items are reachable through their chapter modules, but they exist to support particular
+/// lessons and may change as the lessons do.
+///
+/// The runtime of a chain is its state transition function, `Stf`. Until the client chapter is
+/// written there is no full node to export, and the keystore is all there is of a wallet.
+pub mod prelude {
+	pub use crate::{
+		c1_state_machine::{
+			EnumerableTransitions, Invariant, MealyStateMachine, ReversibleStateMachine,
+			StateMachine, TryStateMachine,
+		},
+		c2_blockchain::{
+			p4_batched_extrinsics::{Block, Header, Stf},
+			p5_fork_choice::ForkChoice,
+		},
+		c3_consensus::{Consensus, ConsensusAuthority},
+		crypto::{Keystore, Pair},
+	};
+}
 
 // Simple helper to do some hashing.
 fn hash<T: Hash>(t: &T) -> u64 {
@@ -16,3 +42,68 @@ fn hash<T: Hash>(t: &T) -> u64 {
 	t.hash(&mut s);
 	s.finish()
 }
+
+/// Hash a value within a specific domain.
+///
+/// The same hash function is used all over this crate, from ATM PINs to block headers. If two of
+/// those uses ever hashed identical bytes, a hash made for one purpose could be passed off as a
+/// hash made for the other. Prefixing every hash with a tag describing its purpose rules that out.
+fn hash_with_domain<T: Hash>(domain: &[u8], t: &T) -> u64 {
+	let mut s = DefaultHasher::new();
+	domain.hash(&mut s);
+	t.hash(&mut s);
+	s.finish()
+}
+
+/// Hash a block header. This is the hash that children use to point at their parent, and the
+/// hash that proof of work is measured against.
+fn hash_header<T: Hash>(header: &T) -> u64 {
+	hash_with_domain(domain::HEADER, header)
+}
+
+/// The domain tags used with `hash_with_domain`. Every distinct purpose gets its own tag.
+mod domain {
+	/// Block headers.
+	pub const HEADER: &[u8] = b"header";
+	/// PINs keyed in at the ATM.
+	pub const ATM_PIN: &[u8] = b"atm-pin";
+	/// Messages that are signed, and the commitments made while signing them.
+	pub const SIGNATURE: &[u8] = b"signature";
+	/// Seeds, phrases, and paths used to derive key pairs.
+	pub const KEY_DERIVATION: &[u8] = b"key-derivation";
+	/// Leaves of a state Merkle tree, that is single key-value pairs.
+	pub const MERKLE_LEAF: &[u8] = b"merkle-leaf";
+	/// Inner nodes of a state Merkle tree, that is pairs of child hashes.
+	pub const MERKLE_NODE: &[u8] = b"merkle-node";
+}
+
+#[test]
+fn hash_domains_separate_identical_payloads() {
+	let payload = (1u64, 2u64, 3u64);
+
+	assert_ne!(
+		hash_with_domain(domain::HEADER, &payload),
+		hash_with_domain(domain::ATM_PIN, &payload)
+	);
+	assert_ne!(hash_with_domain(domain::HEADER, &payload), hash(&payload));
+	assert_eq!(hash_with_domain(domain::HEADER, &payload), hash_header(&payload));
+}
+
+#[test]
+fn hash_domain_is_not_confused_with_payload() {
+	// The domain is length-prefixed, so moving bytes between the domain and the payload changes
+	// the hash.
+	assert_ne!(hash_with_domain(b"ab", &b"c".to_vec()), hash_with_domain(b"a", &b"bc".to_vec()));
+}
+
+#[test]
+fn prelude_is_enough_to_build_a_chain() {
+	use crate::prelude::*;
+
+	let genesis = Block::genesis();
+	let b1 = genesis.child(vec![1, 2]);
+	let b2 = b1.child(vec![3]);
+
+	assert!(genesis.verify_sub_chain(&[b1, b2.clone()]));
+	assert_eq!(b2.header().state(), 6);
+}